@@ -12,7 +12,10 @@ pub trait Bound {
 }
 
 /// The rectangle that bounds an object
-#[derive(Copy, Clone, PartialEq, Default, Eq, Debug)]
+///
+/// The default is `BoundingRect::INVALID`, which bounds nothing. This is distinct from
+/// a genuine degenerate rect like `new_x_y_w_h(5, 5, 0, 0)`, which still has a position.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct BoundingRect {
     pub left: i32,
     pub top: i32,
@@ -27,7 +30,9 @@ pub struct BoundingRectF64 {
 }
 
 /// Statistics over a collection of objects with `Bound` trait
-#[derive(Debug)]
+///
+/// All fields are 0 for an empty collection.
+#[derive(Debug, Default)]
 pub struct BoundStat {
     pub average_area: i32,
     pub average_width: i32,
@@ -38,6 +43,9 @@ pub struct BoundStat {
 
 impl BoundStat {
     pub fn calculate<B: Bound>(bs: &[B]) -> Self {
+        if bs.is_empty() {
+            return Self::default();
+        }
         let mut sum_area   = 0;
         let mut sum_width  = 0;
         let mut sum_height = 0;
@@ -66,9 +74,64 @@ impl BoundStat {
             min_height,
         }
     }
+
+    /// Like `calculate`, but averages are weighted by `weights` (e.g. cluster areas).
+    /// Minimums are taken over all bounds regardless of weight.
+    /// Panics if `bs` and `weights` differ in length.
+    pub fn calculate_weighted<B: Bound>(bs: &[B], weights: &[i32]) -> Self {
+        assert_eq!(bs.len(), weights.len());
+        if bs.is_empty() {
+            return Self::default();
+        }
+        let mut sum_area:   i64 = 0;
+        let mut sum_width:  i64 = 0;
+        let mut sum_height: i64 = 0;
+        let mut sum_weight: i64 = 0;
+        let mut min_width  = i32::MAX;
+        let mut min_height = i32::MAX;
+
+        for (b, &w) in bs.iter().zip(weights.iter()) {
+            let b      = b.bound();
+            let width  = b.width();
+            let height = b.height();
+            let w      = w as i64;
+
+            sum_area   += w * (width * height) as i64;
+            sum_width  += w * width as i64;
+            sum_height += w * height as i64;
+            sum_weight += w;
+            min_width   = min(min_width, width);
+            min_height  = min(min_height, height);
+        }
+
+        // all weights being zero leaves nothing to average
+        let average = |sum: i64| if sum_weight != 0 { (sum / sum_weight) as i32 } else { 0 };
+
+        Self {
+            average_area:   average(sum_area),
+            average_width:  average(sum_width),
+            average_height: average(sum_height),
+            min_width,
+            min_height,
+        }
+    }
+}
+
+impl Default for BoundingRect {
+    fn default() -> Self {
+        Self::INVALID
+    }
 }
 
 impl BoundingRect {
+    /// Bounds nothing; adding a point or merging a rect into it yields that point or rect
+    pub const INVALID: Self = Self {
+        left: i32::MAX,
+        top: i32::MAX,
+        right: i32::MIN,
+        bottom: i32::MIN,
+    };
+
     // assume top-left origin
     pub fn new_x_y_w_h(x: i32, y: i32, w: i32, h: i32) -> Self {
         Self {
@@ -79,22 +142,32 @@ impl BoundingRect {
         }
     }
 
+    /// 0 for invalid rects, so that cropping an image or rendering a cluster
+    /// with an invalid rect yields an empty image. Saturates at `i32::MAX`.
     pub fn width(self) -> i32 {
-        self.right - self.left
+        if self.right > self.left { self.right.saturating_sub(self.left) } else { 0 }
     }
 
+    /// 0 for invalid rects. Saturates at `i32::MAX`.
     pub fn height(self) -> i32 {
-        self.bottom - self.top
+        if self.bottom > self.top { self.bottom.saturating_sub(self.top) } else { 0 }
     }
 
+    /// Returns true if the rect has no size, which includes invalid rects
     pub fn is_empty(self) -> bool {
         self.width() == 0 && self.height() == 0
     }
 
+    /// Returns false for `INVALID` and any rect with right < left or bottom < top
+    pub fn is_valid(self) -> bool {
+        self.left <= self.right && self.top <= self.bottom
+    }
+
+    /// The midpoint, rounded down; exact for any coordinates
     pub fn center(self) -> PointI32 {
         PointI32 {
-            x: (self.left + self.right) >> 1,
-            y: (self.top + self.bottom) >> 1,
+            x: midpoint(self.left, self.right),
+            y: midpoint(self.top, self.bottom),
         }
     }
 
@@ -128,10 +201,17 @@ impl BoundingRect {
         PointI32::new(self.right, self.bottom)
     }
 
-    /// Calculates the squared distance betweeen the center of two `BoundingRect`s.
+    /// Calculates the squared distance betweeen the center of two `BoundingRect`s,
+    /// saturating at `i32::MAX`; see `sq_dist_i64` for rects far apart.
     pub fn sq_dist(self, other: Self) -> i32 {
-        let diff = self.center() - other.center();
-        diff.dot(diff)
+        std::cmp::min(self.sq_dist_i64(other), i32::MAX as i64) as i32
+    }
+
+    /// Like `sq_dist`, but in i64, saturating only for centers more than about 2^31 apart
+    pub fn sq_dist_i64(self, other: Self) -> i64 {
+        let (a, b) = (self.center(), other.center());
+        let (dx, dy) = (a.x as i64 - b.x as i64, a.y as i64 - b.y as i64);
+        dx.saturating_mul(dx).saturating_add(dy.saturating_mul(dy))
     }
 
     pub fn aspect_ratio(self) -> f64 {
@@ -139,12 +219,17 @@ impl BoundingRect {
             / std::cmp::min(self.width(), self.height()) as f64
     }
 
+    /// Twice the aspect ratio, rounded down; `i32::MAX` if it does not fit or the rect is empty in a dimension
     pub fn aspect_ratio_doubled(self) -> i32 {
-        2 * std::cmp::max(self.width(), self.height()) / std::cmp::min(self.width(), self.height())
+        let shorter = std::cmp::min(self.width(), self.height()) as i64;
+        if shorter == 0 {
+            return i32::MAX;
+        }
+        std::cmp::min(2 * std::cmp::max(self.width(), self.height()) as i64 / shorter, i32::MAX as i64) as i32
     }
 
     pub fn add_x_y(&mut self, x: i32, y: i32) {
-        if self.is_empty() {
+        if !self.is_valid() {
             self.left = x;
             self.right = x + 1;
             self.top = y;
@@ -164,14 +249,11 @@ impl BoundingRect {
     }
 
     pub fn merge(&mut self, other: Self) {
-        if other.is_empty() {
+        if !other.is_valid() {
             return;
         }
-        if self.is_empty() {
-            self.left = other.left;
-            self.right = other.right;
-            self.top = other.top;
-            self.bottom = other.bottom;
+        if !self.is_valid() {
+            *self = other;
             return;
         }
         self.left = std::cmp::min(self.left, other.left);
@@ -181,10 +263,7 @@ impl BoundingRect {
     }
 
     pub fn clear(&mut self) {
-        self.left = 0;
-        self.right = 0;
-        self.top = 0;
-        self.bottom = 0;
+        *self = Self::INVALID;
     }
 
     pub fn hit(self, other: Self) -> bool {
@@ -211,17 +290,33 @@ impl BoundingRect {
         }
     }
 
+    /// The square of the longer side centered on this rect,
+    /// shifted if needed to stay within the range of i32
     pub fn squared(self) -> Self {
-        let size = std::cmp::max(self.width(), self.height());
-        Self::new_x_y_w_h(
-            self.left - ((size - self.width()) >> 1),
-            self.top - ((size - self.height()) >> 1),
-            size,
-            size,
-        )
+        if !self.is_valid() {
+            return self;
+        }
+        let width = self.right as i64 - self.left as i64;
+        let height = self.bottom as i64 - self.top as i64;
+        let size = std::cmp::max(width, height);
+        // the start of a span of `size` placed at `start`, kept in range
+        let place = |start: i64| -> i64 {
+            start.clamp(i32::MIN as i64, i32::MAX as i64 - size)
+        };
+        let left = place(self.left as i64 - ((size - width) >> 1));
+        let top = place(self.top as i64 - ((size - height) >> 1));
+        Self {
+            left: left as i32,
+            top: top as i32,
+            right: (left + size) as i32,
+            bottom: (top + size) as i32,
+        }
     }
 
     pub fn translate(&mut self, p: PointI32) {
+        if !self.is_valid() {
+            return;
+        }
         self.left += p.x;
         self.top += p.y;
         self.right += p.x;
@@ -341,14 +436,17 @@ impl BoundingRect {
 
 impl Default for BoundingRectF64 {
     fn default() -> Self {
-        Self {
-            left_top: PointF64::new(f64::MAX, f64::MAX),
-            right_bottom: PointF64::new(f64::MIN, f64::MIN),
-        }
+        Self::INVALID
     }
 }
 
 impl BoundingRectF64 {
+    /// Bounds nothing, see `BoundingRect::INVALID`
+    pub const INVALID: Self = Self {
+        left_top: PointF64::new(f64::MAX, f64::MAX),
+        right_bottom: PointF64::new(f64::MIN, f64::MIN),
+    };
+
     pub fn new(left_top: PointF64, right_bottom: PointF64) -> Self {
         Self { left_top, right_bottom }
     }
@@ -367,11 +465,9 @@ impl BoundingRectF64 {
         }
     }
 
+    /// Returns true for `INVALID` and any rect with right < left or bottom < top
     pub fn is_empty(self) -> bool {
-        self.left_top.x == f64::MAX &&
-        self.left_top.y == f64::MAX &&
-        self.right_bottom.x == f64::MIN &&
-        self.right_bottom.y == f64::MIN
+        !(self.left_top.x <= self.right_bottom.x && self.left_top.y <= self.right_bottom.y)
     }
 
     pub fn right_top(&self) -> PointF64 {
@@ -406,6 +502,10 @@ impl BoundingRectF64 {
     }
 
     pub fn add_point(&mut self, p: PointF64) {
+        if self.is_empty() {
+            *self = Self::new(p, p);
+            return;
+        }
         self.left_top.x = self.left_top.x.min(p.x);
         self.left_top.y = self.left_top.y.min(p.y);
         self.right_bottom.x = self.right_bottom.x.max(p.x);
@@ -434,7 +534,11 @@ impl Bound for BoundingRectF64 {
     }
 }
 
+/// Returns 0 for an empty slice
 pub fn average_width<B: Bound>(bs: &[B]) -> i32 {
+    if bs.is_empty() {
+        return 0;
+    }
     let sum: i32 = bs
         .iter()
         .map(|b| b.bound().width())
@@ -443,7 +547,11 @@ pub fn average_width<B: Bound>(bs: &[B]) -> i32 {
     sum / (bs.len() as i32)
 }
 
+/// Returns 0 for an empty slice
 pub fn average_height<B: Bound>(bs: &[B]) -> i32 {
+    if bs.is_empty() {
+        return 0;
+    }
     let sum: i32 = bs
         .iter()
         .map(|b| b.bound().height())
@@ -472,19 +580,142 @@ pub fn merge_expand<B: Bound>(items: Vec<B>, expand_x: i32, expand_y: i32) -> Ve
     )
 }
 
+/// Grows `b` by `expand_x` on the left and right and by `expand_y` on the top and bottom,
+/// with the edges clamped to the range of i32
 pub fn expand(b: BoundingRect, expand_x: i32, expand_y: i32) -> BoundingRect {
-    BoundingRect::new_x_y_w_h(
-        b.left - expand_x,
-        b.top - expand_y,
-        b.width() + 2 * expand_x,
-        b.height() + 2 * expand_y
-    )
+    let clamp = |v: i64| v.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    let (left, top) = (b.left as i64 - expand_x as i64, b.top as i64 - expand_y as i64);
+    BoundingRect {
+        left: clamp(left),
+        top: clamp(top),
+        right: clamp(left + b.width() as i64 + 2 * expand_x as i64),
+        bottom: clamp(top + b.height() as i64 + 2 * expand_y as i64),
+    }
+}
+
+/// `(a + b) / 2` rounded down, without overflowing
+fn midpoint(a: i32, b: i32) -> i32 {
+    ((a as i64 + b as i64) >> 1) as i32
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn bounding_rect_invalid() {
+        let rect = BoundingRect::default();
+        assert_eq!(rect, BoundingRect::INVALID);
+        assert!(!rect.is_valid());
+        assert!(rect.is_empty());
+        assert_eq!(rect.width(), 0);
+        assert_eq!(rect.height(), 0);
+
+        let degenerate = BoundingRect::new_x_y_w_h(5, 5, 0, 0);
+        assert!(degenerate.is_valid());
+        assert!(degenerate.is_empty());
+
+        let mut merged = BoundingRect::default();
+        merged.merge(degenerate);
+        assert_eq!(merged, degenerate);
+        merged.merge(BoundingRect::INVALID);
+        assert_eq!(merged, degenerate);
+
+        let mut translated = BoundingRect::INVALID;
+        translated.translate(PointI32::new(1, 1));
+        assert_eq!(translated, BoundingRect::INVALID);
+    }
+
+    #[test]
+    fn bounding_rect_origin_pixel() {
+        // a cluster covering only pixel (0,0)
+        let mut origin = BoundingRect::default();
+        origin.add_x_y(0, 0);
+        assert_eq!(origin, BoundingRect::new_x_y_w_h(0, 0, 1, 1));
+
+        let mut other = BoundingRect::default();
+        other.add_x_y(3, 3);
+        assert_eq!(enclosing_bound(&[origin, other]), BoundingRect::new_x_y_w_h(0, 0, 4, 4));
+        assert_eq!(enclosing_bound(&[other, origin]), BoundingRect::new_x_y_w_h(0, 0, 4, 4));
+
+        let mut cleared = origin;
+        cleared.clear();
+        assert!(!cleared.is_valid());
+        cleared.add_x_y(0, 0);
+        assert_eq!(cleared, origin);
+        cleared.clear();
+        other.merge(cleared);
+        assert_eq!(other, BoundingRect::new_x_y_w_h(3, 3, 1, 1));
+
+        let mut degenerate = BoundingRect::new_x_y_w_h(5, 5, 0, 0);
+        degenerate.merge(origin);
+        assert_eq!(degenerate, BoundingRect::new_x_y_w_h(0, 0, 5, 5));
+    }
+
+    #[test]
+    fn bounding_rect_f64_empty() {
+        let mut rect = BoundingRectF64::default();
+        assert!(rect.is_empty());
+        rect.add_point(PointF64::new(1.0, 2.0));
+        assert!(!rect.is_empty());
+        assert_eq!(rect, BoundingRectF64::new(PointF64::new(1.0, 2.0), PointF64::new(1.0, 2.0)));
+        rect.merge(BoundingRectF64::new_x_y_w_h(0.0, 0.0, 4.0, 4.0));
+        // shrink until inverted
+        rect.right_bottom.x = -1.0;
+        assert!(rect.is_empty());
+        rect.add_point(PointF64::new(3.0, 3.0));
+        assert_eq!(rect, BoundingRectF64::new(PointF64::new(3.0, 3.0), PointF64::new(3.0, 3.0)));
+        let mut other = BoundingRectF64::new_x_y_w_h(0.0, 0.0, 1.0, 1.0);
+        other.merge(BoundingRectF64::new(PointF64::new(5.0, 5.0), PointF64::new(4.0, 4.0)));
+        assert_eq!(other, BoundingRectF64::new_x_y_w_h(0.0, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn bounding_rect_invalid_size() {
+        let inverted = BoundingRect { left: 5, top: 5, right: 2, bottom: 3 };
+        assert_eq!((inverted.width(), inverted.height()), (0, 0));
+        assert_eq!((BoundingRect::INVALID.width(), BoundingRect::INVALID.height()), (0, 0));
+
+        let rects = [BoundingRect::INVALID, BoundingRect::new_x_y_w_h(0, 0, 2, 4)];
+        let stat = BoundStat::calculate(&rects);
+        assert_eq!(stat.average_area, 4);
+        assert_eq!(stat.min_width, 0);
+        assert_eq!(enclosing_bound(&rects), BoundingRect::new_x_y_w_h(0, 0, 2, 4));
+    }
+
+    #[test]
+    fn bound_average_empty() {
+        let empty: [BoundingRect; 0] = [];
+        assert_eq!(average_width(&empty), 0);
+        assert_eq!(average_height(&empty), 0);
+        let stat = BoundStat::calculate(&empty);
+        assert_eq!(stat.average_area, 0);
+        assert_eq!(stat.min_width, 0);
+        let stat = BoundStat::calculate_weighted(&[BoundingRect::new_x_y_w_h(0, 0, 2, 3)], &[0]);
+        assert_eq!(stat.average_width, 0);
+        assert_eq!(stat.min_width, 2);
+    }
+
+    #[test]
+    fn bound_stat_weighted() {
+        let rects = [
+            BoundingRect::new_x_y_w_h(0, 0, 2, 2),
+            BoundingRect::new_x_y_w_h(0, 0, 2, 2),
+            BoundingRect::new_x_y_w_h(0, 0, 20, 10),
+        ];
+        let stat = BoundStat::calculate(&rects);
+        assert_eq!(stat.average_width, 8);
+        let stat = BoundStat::calculate_weighted(&rects, &[1, 1, 1]);
+        assert_eq!(stat.average_width, 8);
+        assert_eq!(stat.average_height, 4);
+        let stat = BoundStat::calculate_weighted(&rects, &[1, 1, 98]);
+        assert_eq!(stat.average_width, 19);
+        assert_eq!(stat.average_height, 9);
+        assert_eq!(stat.average_area, 196);
+        assert_eq!(stat.min_width, 2);
+        assert_eq!(stat.min_height, 2);
+    }
+
     #[test]
     fn bounding_rect_1x1() {
         let mut rect = BoundingRect::default();
@@ -518,6 +749,56 @@ mod tests {
         assert_eq!(rect.aspect_ratio_doubled(), 4);
     }
 
+    #[test]
+    fn bounding_rect_extreme_coordinates() {
+        let far = BoundingRect::new_x_y_w_h(-60_000, -60_000, 10, 10);
+        let near = BoundingRect::new_x_y_w_h(60_000, 50_000, 20, 10);
+        let reference = |a: BoundingRect, b: BoundingRect| {
+            let center = |r: BoundingRect| ((r.left as f64 + r.right as f64) / 2.0, (r.top as f64 + r.bottom as f64) / 2.0);
+            let (a, b) = (center(a), center(b));
+            (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)
+        };
+        assert_eq!(far.sq_dist_i64(near) as f64, reference(far, near));
+        assert_eq!(far.sq_dist(near), i32::MAX);
+        let a = BoundingRect::new_x_y_w_h(-1_000_000_000, 1_000_000_000, 7, 7);
+        let b = BoundingRect::new_x_y_w_h(1_000_000_000, -1_000_000_000, 3, 3);
+        assert!(((a.sq_dist_i64(b) as f64 - reference(a, b)) / reference(a, b)).abs() < 1e-12);
+        let extremes = BoundingRect { left: i32::MIN, top: i32::MIN, right: i32::MIN + 2, bottom: i32::MIN + 2 };
+        let opposite = BoundingRect { left: i32::MAX - 2, top: i32::MAX - 2, right: i32::MAX, bottom: i32::MAX };
+        assert_eq!(extremes.sq_dist_i64(opposite), i64::MAX);
+
+        assert_eq!(opposite.center(), PointI32::new(i32::MAX - 1, i32::MAX - 1));
+        assert_eq!(BoundingRect { left: i32::MIN, top: -3, right: i32::MAX, bottom: 0 }.center(), PointI32::new(-1, -2));
+        assert_eq!(BoundingRect::new_x_y_w_h(-5, -5, 3, 4).center(), PointI32::new(-4, -3));
+
+        let flat = BoundingRect::new_x_y_w_h(0, 0, 1 << 30, 1);
+        assert_eq!(flat.aspect_ratio_doubled(), i32::MAX);
+        assert_eq!(BoundingRect::new_x_y_w_h(0, 0, 5, 0).aspect_ratio_doubled(), i32::MAX);
+        assert_eq!(BoundingRect::new_x_y_w_h(0, 0, 1 << 29, 3).aspect_ratio_doubled() as f64, (2.0 * (1 << 29) as f64 / 3.0).floor());
+
+        let squared = BoundingRect { left: i32::MAX - 10, top: 0, right: i32::MAX, bottom: 40 }.squared();
+        assert_eq!(squared, BoundingRect { left: i32::MAX - 40, top: 0, right: i32::MAX, bottom: 40 });
+        let squared = BoundingRect { left: i32::MIN, top: 0, right: i32::MIN + 10, bottom: 40 }.squared();
+        assert_eq!(squared, BoundingRect { left: i32::MIN, top: 0, right: i32::MIN + 40, bottom: 40 });
+
+        let full = BoundingRect { left: i32::MIN, top: i32::MIN, right: i32::MAX, bottom: i32::MAX };
+        assert_eq!((full.width(), full.height()), (i32::MAX, i32::MAX));
+        assert!(!full.is_empty());
+        assert_eq!(full.squared(), full);
+        let wide = BoundingRect { left: i32::MIN, top: -5, right: i32::MAX, bottom: 5 };
+        assert_eq!((wide.width(), wide.height()), (i32::MAX, 10));
+        assert_eq!(wide.squared(), full);
+        let tall = BoundingRect { left: 0, top: i32::MIN + 1, right: 1, bottom: i32::MAX };
+        assert_eq!(tall.width(), 1);
+        assert_eq!(tall.height(), i32::MAX);
+        let squared = tall.squared();
+        assert_eq!((squared.top, squared.bottom), (tall.top, tall.bottom));
+        assert_eq!(squared.right as i64 - squared.left as i64, tall.bottom as i64 - tall.top as i64);
+
+        let expanded = expand(BoundingRect { left: i32::MIN + 1, top: 0, right: 0, bottom: i32::MAX - 1 }, 5, 5);
+        assert_eq!(expanded, BoundingRect { left: i32::MIN, top: -5, right: 5, bottom: i32::MAX });
+    }
+
     #[test]
     fn bounding_rect_clip() {
         let mut rect = BoundingRect::default();
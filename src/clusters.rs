@@ -1,6 +1,6 @@
 //! Algorithm to cluster a binary image
 
-use crate::{BinaryImage, BoundingRect, CompoundPath, MonoImage, MonoImageItem, PathI32, PathSimplifyMode, PointI32, Shape, Spline};
+use crate::{BinaryImage, BoundingRect, CompatFlags, CompoundPath, MonoImage, MonoImageItem, PathI32, PathSimplifyMode, PointI32, Shape, Spline};
 
 /// A cluster of binary image pixels
 #[derive(Default)]
@@ -88,7 +88,7 @@ impl Cluster {
         splice_threshold: f64
     ) -> CompoundPath {
         match mode {
-            PathSimplifyMode::None | PathSimplifyMode::Polygon => {
+            PathSimplifyMode::None | PathSimplifyMode::Polygon | PathSimplifyMode::Corner { .. } => {
                 let paths = Self::image_to_paths(image, mode);
                 let mut group = CompoundPath::new();
                 for mut path in paths.into_iter() {
@@ -110,15 +110,15 @@ impl Cluster {
     }
 
     pub fn image_to_paths(image: &BinaryImage, mode: PathSimplifyMode) -> Vec<PathI32> {
+        Self::image_to_paths_with_border(image, mode, true)
+    }
+
+    /// Like `image_to_paths`, which counts the pixels beyond the border of `image` as background.
+    /// If not `border_is_background`, they count as foreground, as for a subject running off the edge:
+    /// the outer path then runs along the border and every cluster of unset pixels is traced as a hole.
+    pub fn image_to_paths_with_border(image: &BinaryImage, mode: PathSimplifyMode, border_is_background: bool) -> Vec<PathI32> {
         let mut boundaries = vec![(image.clone(), PointI32 { x: 0, y: 0 })];
-        let holes = image.negative().to_clusters(false);
-        for hole in holes.iter() {
-            if  hole.rect.left as usize == 0 ||
-                hole.rect.top as usize == 0 ||
-                hole.rect.right as usize == image.width ||
-                hole.rect.bottom as usize == image.height {
-                continue;
-            }
+        for hole in image.holes(false, border_is_background) {
             for p in hole.points.iter() {
                 boundaries[0].0.set_pixel(p.x as usize, p.y as usize, true);
             }
@@ -144,15 +144,20 @@ impl Cluster {
     const OUTSET_RATIO: f64 = 8.0;
 
     pub fn image_to_splines(image: &BinaryImage, corner_threshold: f64, segment_length: f64, max_iterations:usize, splice_threshold: f64) -> Vec<Spline> {
+        Self::image_to_splines_with_border(image, corner_threshold, segment_length, max_iterations, splice_threshold, true)
+    }
+
+    /// Like `image_to_splines`, with the pixels beyond the border of `image` as in `image_to_paths_with_border`
+    pub fn image_to_splines_with_border(
+        image: &BinaryImage,
+        corner_threshold: f64,
+        segment_length: f64,
+        max_iterations: usize,
+        splice_threshold: f64,
+        border_is_background: bool,
+    ) -> Vec<Spline> {
         let mut boundaries = vec![(image.clone(), PointI32 { x: 0, y: 0 })];
-        let holes = image.negative().to_clusters(false);
-        for hole in holes.iter() {
-            if  hole.rect.left as usize == 0 ||
-                hole.rect.top as usize == 0 ||
-                hole.rect.right as usize == image.width ||
-                hole.rect.bottom as usize == image.height {
-                continue;
-            }
+        for hole in image.holes(false, border_is_background) {
             for p in hole.points.iter() {
                 boundaries[0].0.set_pixel(p.x as usize, p.y as usize, true);
             }
@@ -185,12 +190,71 @@ impl Cluster {
 
     const BREAK_AT_LEAST: usize = 5;
 
+    /// Breaks the cluster at staircase patterns (see below) into pieces, recursively,
+    /// as long as every piece has at least `BREAK_AT_LEAST` pixels and `DEFAULT_MAX_BREAK_DEPTH` is not exceeded.
+    ///
+    /// Of the patterns in the topmost row having any, the one nearest the horizontal center is broken.
+    /// Either middle pixel with a set neighbour beyond it may be removed; the one leaving the larger
+    /// smallest piece is, or the one below the empty top cell if that is a tie. Of two patterns equally near,
+    /// the one whose cut leaves an image preceding the mirror image of what the other's cut leaves is broken,
+    /// in raster order with set pixels first; if these are the same, the cluster is symmetric and the left one is.
+    /// Mirroring the cluster horizontally thus mirrors the pieces, unless it is symmetric itself.
     pub fn break_cluster_recursive(cluster: Cluster, output: &mut Clusters) {
+        Self::break_cluster_into(cluster, output, None);
+    }
+
+    /// How many times a piece may be cut out of the original cluster by `break_cluster_recursive`
+    /// and `break_cluster_into`; use `break_cluster_to_depth` for another limit.
+    /// `max_pieces` of `break_cluster_into` bounds the total work, this bounds the cuts along any one chain of pieces.
+    pub const DEFAULT_MAX_BREAK_DEPTH: usize = 256;
+
+    /// Like `break_cluster_recursive`, but stops breaking once there would be more than `max_pieces` pieces,
+    /// adding the remaining ones unbroken. Returns the number of pieces added to `output`.
+    ///
+    /// The pieces are worked off an explicit stack rather than by recursion, so long chains of necks,
+    /// as in dotted lines, do not overflow small stacks. Each cut re-clusters the whole piece being cut,
+    /// which costs time proportional to its area: once for the cut, and once more for either middle pixel
+    /// of each pattern where both may be removed. With two patterns equally near the center, that is up to
+    /// five times per cut, plus a scan comparing the piece with its mirror image.
+    pub fn break_cluster_into(cluster: Cluster, output: &mut Clusters, max_pieces: Option<usize>) -> usize {
+        Self::break_pieces(cluster, output, max_pieces.unwrap_or(usize::MAX), Self::DEFAULT_MAX_BREAK_DEPTH)
+    }
+
+    /// Like `break_cluster_recursive`, but pieces which took `max_depth` cuts to separate
+    /// are added unbroken. Returns the number of pieces added to `output`.
+    pub fn break_cluster_to_depth(cluster: Cluster, output: &mut Clusters, max_depth: usize) -> usize {
+        Self::break_pieces(cluster, output, usize::MAX, max_depth)
+    }
+
+    fn break_pieces(cluster: Cluster, output: &mut Clusters, max_pieces: usize, max_depth: usize) -> usize {
+        let mut added = 0;
+        // (piece yet to be broken, cuts it took), the next one last
+        let mut stack = vec![(cluster, 0)];
+        while let Some((cluster, depth)) = stack.pop() {
+            match (depth < max_depth).then(|| Self::break_once(&cluster)).flatten() {
+                Some(pieces) if added + stack.len() + pieces.len() <= max_pieces => {
+                    stack.extend(pieces.into_iter().rev().map(|piece| (piece, depth + 1)));
+                },
+                _ => {
+                    output.add_cluster(cluster);
+                    added += 1;
+                },
+            }
+        }
+        added
+    }
+
+    /// Cuts the cluster once, returning the pieces in parent coordinates,
+    /// or `None` if there is no pattern to cut at or a piece would be too small
+    fn break_once(cluster: &Cluster) -> Option<Vec<Cluster>> {
         let mut image = cluster.to_binary_image();
         let (w, h) = (2, 3);
         let mut broke = false;
         if image.width >= w && image.height >= h {
-            'outer: for y in 0..(image.height-h+1) {
+            for y in 0..(image.height-h+1) {
+                // removable middle pixels in order of preference, of the one or two patterns nearest the center
+                let mut nearest: Vec<Vec<usize>> = Vec::new();
+                let mut nearest_off_center = usize::MAX;
                 for x in 0..(image.width-w+1) {
                     if  image.get_pixel(x, y)   != image.get_pixel(x+1, y) &&
                         image.get_pixel(x, y+1) && image.get_pixel(x+1, y+1) &&
@@ -201,37 +265,161 @@ impl Cluster {
                             **          **
                             -*          *-
                         */
-                        if x < image.width - 2 && image.get_pixel(x + 2, y + 1) {
-                            image.set_pixel(x + 1, y + 1, false);
-                            broke = true;
-                            break 'outer;
-                        } else if x > 0 && image.get_pixel(x - 1, y + 1) {
-                            image.set_pixel(x, y + 1, false);
-                            broke = true;
-                            break 'outer;
+                        let right = (x + 2 < image.width && image.get_pixel(x + 2, y + 1)).then_some(x + 1);
+                        let left = (x > 0 && image.get_pixel(x - 1, y + 1)).then_some(x);
+                        let options: Vec<usize> = if image.get_pixel(x, y) { [right, left] } else { [left, right] }
+                            .into_iter().flatten().collect();
+                        let off_center = (2 * x + 1).abs_diff(image.width - 1);
+                        if options.is_empty() || off_center > nearest_off_center {
+                            continue;
                         }
+                        if off_center < nearest_off_center {
+                            nearest.clear();
+                            nearest_off_center = off_center;
+                        }
+                        nearest.push(options);
                     }
                 }
+                if !nearest.is_empty() {
+                    let smallest_piece = |remove: usize| {
+                        let mut image = image.clone();
+                        image.set_pixel(remove, y + 1, false);
+                        image.to_clusters(false).iter().map(|cc| cc.size()).min().unwrap_or(0)
+                    };
+                    let cuts: Vec<usize> = nearest.iter().map(|options| {
+                        if options.len() > 1 && smallest_piece(options[1]) > smallest_piece(options[0]) {
+                            options[1]
+                        } else {
+                            options[0]
+                        }
+                    }).collect();
+                    let remove = if cuts.len() > 1 && !Self::cut_precedes_mirrored(&image, y + 1, cuts[0], cuts[1]) {
+                        cuts[1]
+                    } else {
+                        cuts[0]
+                    };
+                    image.set_pixel(remove, y + 1, false);
+                    broke = true;
+                    break;
+                }
             }
         }
+        if !broke {
+            return None;
+        }
         let clusters = image.to_clusters(false);
-        if broke {
-            let min = clusters.iter().map(|cc| cc.size()).min().unwrap();
-            if min < Self::BREAK_AT_LEAST {
-                broke = false;
+        if clusters.iter().map(|cc| cc.size()).min()? < Self::BREAK_AT_LEAST {
+            return None;
+        }
+        Some(clusters.clusters.into_iter().map(|mut cc| {
+            cc.offset(PointI32 {
+                x: cluster.rect.left,
+                y: cluster.rect.top,
+            });
+            cc
+        }).collect())
+    }
+
+    /// Whether `image` without pixel (a, y) comes before the mirror image of `image` without pixel (b, y),
+    /// in raster order with set pixels first. Swapping the cuts and mirroring `image` reverses the answer,
+    /// unless the two are the same, which happens only if `image` is symmetric and the cuts mirror each other.
+    fn cut_precedes_mirrored(image: &BinaryImage, y: usize, a: usize, b: usize) -> bool {
+        let w = image.width;
+        for yy in 0..image.height {
+            for x in 0..w {
+                let cut_a = image.get_pixel(x, yy) && !(yy == y && x == a);
+                let mirrored_cut_b = image.get_pixel(w - 1 - x, yy) && !(yy == y && w - 1 - x == b);
+                if cut_a != mirrored_cut_b {
+                    return cut_a;
+                }
             }
         }
-        if broke {
-            for mut cc in clusters.clusters.into_iter() {
-                cc.offset(PointI32 {
-                    x: cluster.rect.left,
-                    y: cluster.rect.top,
-                });
-                Self::break_cluster_recursive(cc, output);
+        true
+    }
+
+    /// Pieces smaller than this are merged into their nearest neighbour by `split_by_projection`
+    const SPLIT_AT_LEAST: usize = 5;
+
+    /// Splits touching glyphs apart by cutting vertically at the thinnest columns.
+    ///
+    /// Cuts are made at local minima of the column ink profile, which must dip below the
+    /// thickest column within `expected_piece_width * 0.6` and be at least that far apart
+    /// from each other and from the edges. Thinnest columns are cut first, making at most
+    /// `max_pieces` slabs. Each slab is then re-clustered, and pieces smaller than a minimal
+    /// area are merged into the nearest remaining piece.
+    ///
+    /// Returns a copy of the original cluster alone if no acceptable cut is found.
+    pub fn split_by_projection(&self, expected_piece_width: usize, max_pieces: usize) -> Clusters {
+        let image = self.to_binary_image();
+        let profile: Vec<usize> = (0..image.width)
+            .map(|x| (0..image.height).filter(|&y| image.get_pixel(x, y)).count())
+            .collect();
+        let spacing = std::cmp::max((expected_piece_width as f64 * 0.6).ceil() as usize, 1);
+
+        let mut candidates: Vec<usize> = (spacing..image.width.saturating_sub(spacing - 1))
+            .filter(|&x| profile[x] <= profile[x - 1] && (x + 1 == image.width || profile[x] <= profile[x + 1]))
+            .filter(|&x| {
+                let window = &profile[x - spacing..std::cmp::min(x + spacing + 1, image.width)];
+                profile[x] < *window.iter().max().unwrap()
+            })
+            .collect();
+        candidates.sort_by_key(|&x| (profile[x], x));
+
+        let mut cuts: Vec<usize> = Vec::new();
+        for x in candidates {
+            if cuts.len() + 1 >= max_pieces {
+                break;
+            }
+            if image.width - x >= spacing && cuts.iter().all(|&c| c.abs_diff(x) >= spacing) {
+                cuts.push(x);
             }
-        } else {
-            output.add_cluster(cluster);
         }
+        if cuts.is_empty() {
+            return self.split_unchanged();
+        }
+        cuts.sort_unstable();
+
+        let mut pieces = Vec::new();
+        let mut bounds = vec![0];
+        bounds.extend(cuts);
+        bounds.push(image.width);
+        for slab in bounds.windows(2) {
+            let rect = BoundingRect::new_x_y_w_h(slab[0] as i32, 0, (slab[1] - slab[0]) as i32, image.height as i32);
+            for mut piece in image.crop_with_rect(rect).to_clusters(false).into_iter() {
+                piece.offset(PointI32::new(self.rect.left + slab[0] as i32, self.rect.top));
+                pieces.push(piece);
+            }
+        }
+
+        let (mut large, small): (Vec<Cluster>, Vec<Cluster>) =
+            pieces.into_iter().partition(|piece| piece.size() >= Self::SPLIT_AT_LEAST);
+        if large.len() < 2 {
+            return self.split_unchanged();
+        }
+        for piece in small {
+            let center = piece.rect.center();
+            let nearest = (0..large.len())
+                .min_by_key(|&i| {
+                    let d = large[i].rect.center() - center;
+                    d.dot(d)
+                })
+                .unwrap();
+            for &p in piece.points.iter() {
+                large[nearest].add(p);
+            }
+        }
+
+        let mut clusters = Clusters::default();
+        for piece in large {
+            clusters.add_cluster(piece);
+        }
+        clusters
+    }
+
+    fn split_unchanged(&self) -> Clusters {
+        let mut clusters = Clusters::default();
+        clusters.add_cluster(Cluster { points: self.points.clone(), rect: self.rect });
+        clusters
     }
 }
 
@@ -256,6 +444,35 @@ impl Clusters {
         self.rect.merge(cluster.rect);
         self.clusters.push(cluster);
     }
+
+    /// Keeps only the `n` clusters with the most points, preserving their order,
+    /// and discards the rest. Ties are resolved in favour of the earlier cluster.
+    pub fn keep_largest(&mut self, n: usize) {
+        if self.clusters.len() <= n {
+            return;
+        }
+        let mut ranked: Vec<usize> = (0..self.clusters.len()).collect();
+        ranked.sort_by_key(|&i| std::cmp::Reverse(self.clusters[i].size()));
+        let mut keep = vec![false; self.clusters.len()];
+        for &i in ranked.iter().take(n) {
+            keep[i] = true;
+        }
+        let mut keep = keep.into_iter();
+        self.clusters.retain(|_| keep.next().unwrap());
+        self.rect = BoundingRect::default();
+        for cluster in self.clusters.iter() {
+            self.rect.merge(cluster.rect);
+        }
+    }
+
+    /// Sorts the clusters by their first pixel in raster order (top to bottom, then left to right),
+    /// the order `BinaryImage::to_clusters` returns. Empty clusters go last, in their current order.
+    pub fn sort_by_first_pixel(&mut self) {
+        self.clusters.sort_by_cached_key(|cluster| {
+            let first = cluster.points.iter().min_by_key(|p| (p.y, p.x));
+            (first.is_none(), first.map(|p| (p.y, p.x)))
+        });
+    }
 }
 
 impl IntoIterator for Clusters {
@@ -268,7 +485,16 @@ impl IntoIterator for Clusters {
 }
 
 impl BinaryImage {
+    /// Groups the set pixels into connected clusters, 4-connected unless `diagonal`.
+    ///
+    /// The clusters are ordered by their first pixel in raster order, see `Clusters::sort_by_first_pixel`;
+    /// the points of a cluster are in no particular order.
     pub fn to_clusters(&self, diagonal: bool) -> Clusters {
+        self.to_clusters_with(diagonal, CompatFlags::default())
+    }
+
+    /// Like `to_clusters`, ordered as in the release `compat` was resolved from, see `CompatFlags::raster_order`
+    pub fn to_clusters_with(&self, diagonal: bool, compat: CompatFlags) -> Clusters {
         let mut clusters = Vec::<Cluster>::new();
         let mut rect = BoundingRect::default();
         let mut clustermap = MonoImage::new_w_h(self.width, self.height);
@@ -344,7 +570,60 @@ impl BinaryImage {
 
         let clusters = clusters.into_iter().filter(|c| c.size() != 0).collect();
 
-        Clusters { clusters, rect }
+        // clusters are created as they are first seen, but merging frees and reuses slots
+        let mut clusters = Clusters { clusters, rect };
+        if compat.raster_order {
+            clusters.sort_by_first_pixel();
+        }
+        clusters
+    }
+
+    /// Counts the holes, i.e. the clusters of unset pixels not touching the border of the image,
+    /// 4-connected unless `diagonal`, without tracing them
+    pub fn count_holes(&self, diagonal: bool) -> usize {
+        self.holes(diagonal, true).count()
+    }
+
+    /// Returns the number of clusters minus the number of holes, counting clusters 4-connected unless `diagonal`
+    /// (holes are then connected the other way).
+    ///
+    /// Computed in a single pass by counting 2x2 pixel patterns (Gray's bit-quads), without labeling.
+    pub fn euler_number(&self, diagonal: bool) -> i32 {
+        let (mut one, mut three, mut crossed) = (0i32, 0i32, 0i32);
+        // quads overlapping the image, the pixels outside unset
+        for y in -1..self.height as i32 {
+            for x in -1..self.width as i32 {
+                let quad = [
+                    self.get_pixel_safe(x, y),
+                    self.get_pixel_safe(x + 1, y),
+                    self.get_pixel_safe(x, y + 1),
+                    self.get_pixel_safe(x + 1, y + 1),
+                ];
+                match quad.iter().filter(|&&v| v).count() {
+                    1 => one += 1,
+                    3 => three += 1,
+                    2 if quad[0] == quad[3] => crossed += 1,
+                    _ => (),
+                }
+            }
+        }
+        if diagonal {
+            (one - three - 2 * crossed) / 4
+        } else {
+            (one - three + 2 * crossed) / 4
+        }
+    }
+
+    /// The clusters of unset pixels, except those touching the border of the image if `border_is_background`
+    pub(crate) fn holes(&self, diagonal: bool, border_is_background: bool) -> impl Iterator<Item = Cluster> + '_ {
+        self.negative().to_clusters(diagonal).into_iter().filter(move |hole| {
+            !border_is_background || (
+                hole.rect.left > 0 &&
+                hole.rect.top > 0 &&
+                hole.rect.right as usize != self.width &&
+                hole.rect.bottom as usize != self.height
+            )
+        })
     }
 }
 
@@ -352,6 +631,12 @@ impl BinaryImage {
 mod tests {
     use super::*;
 
+    #[test]
+    fn empty_cluster_to_binary_image() {
+        let image = Cluster::default().to_binary_image();
+        assert_eq!((image.width, image.height), (0, 0));
+    }
+
     #[test]
     fn clusters_3x3() {
         let size = 3;
@@ -376,6 +661,24 @@ mod tests {
         assert_eq!(bin.get_pixel(0, 0), true);
     }
 
+    #[test]
+    fn clusters_keep_largest() {
+        let image = BinaryImage::from_string(&(
+            "**-****-*\n".to_owned()+
+            "**-****--\n"+
+            "---------\n"+
+            "***-**-*-\n"+
+            "***-**---\n"+
+            "***------\n"));
+        let mut clusters = image.to_clusters(false);
+        assert_eq!(clusters.len(), 6);
+        clusters.keep_largest(3);
+        assert_eq!(clusters.len(), 3);
+        let sizes: Vec<usize> = clusters.iter().map(|c| c.size()).collect();
+        assert_eq!(sizes, vec![4, 8, 9]);
+        assert_eq!(clusters.rect, BoundingRect::new_x_y_w_h(0, 0, 7, 6));
+    }
+
     #[test]
     fn clusters_3x3_diagonal() {
         let size = 3;
@@ -456,22 +759,69 @@ mod tests {
             "******\n"+
             "***---\n"));
         let clusters = Cluster::break_cluster(image.to_clusters(false).clusters.remove(0));
-        for c in clusters.iter() {
-            println!("{}", c.to_binary_image());
-        }
-        assert_eq!(clusters.len(), 2); 
+        assert_eq!(clusters.len(), 2);
         assert_eq!(clusters.get_cluster(0).to_binary_image().to_string(),
             "***\n".to_owned()+
-            "-**\n");
+            "***\n");
         assert_eq!(clusters.get_cluster(0).rect.left, 3);
         assert_eq!(clusters.get_cluster(0).rect.top, 0);
         assert_eq!(clusters.get_cluster(1).to_binary_image().to_string(),
-            "***\n".to_owned()+
+            "**-\n".to_owned()+
             "***\n");
         assert_eq!(clusters.get_cluster(1).rect.left, 0);
         assert_eq!(clusters.get_cluster(1).rect.top, 1);
     }
 
+    /// Breaks the cluster drawn by `rows` and its mirror image, asserts the pieces are mirror images
+    /// and returns their number
+    fn break_mirrored(rows: &[&str]) -> usize {
+        let mirror = |s: &str| s.chars().rev().collect::<String>();
+        let image = BinaryImage::from_string(&rows.iter().map(|row| format!("{}\n", row)).collect::<String>());
+        let mirrored = BinaryImage::from_string(&rows.iter().map(|row| format!("{}\n", mirror(row))).collect::<String>());
+
+        // each piece drawn on the whole image, in a canonical order
+        let pieces = |image: &BinaryImage, flip: bool| {
+            let clusters = Cluster::break_cluster(image.to_clusters(false).clusters.remove(0));
+            let mut pieces: Vec<String> = clusters.iter().map(|cluster| {
+                let mut canvas = BinaryImage::new_w_h(image.width, image.height);
+                for p in cluster.iter() {
+                    let x = if flip { image.width - 1 - p.x as usize } else { p.x as usize };
+                    canvas.set_pixel(x, p.y as usize, true);
+                }
+                canvas.to_string()
+            }).collect();
+            pieces.sort();
+            pieces
+        };
+        let original = pieces(&image, false);
+        assert_eq!(original, pieces(&mirrored, true));
+        original.len()
+    }
+
+    #[test]
+    fn break_cluster_mirrored() {
+        assert_eq!(break_mirrored(&[
+            "***-------",
+            "*******---",
+            "---*******",
+            "-------***",
+        ]), 3);
+    }
+
+    #[test]
+    fn break_cluster_mirrored_asymmetric() {
+        // broke differently from its mirror image while the left of two equally near patterns was broken first
+        assert!(break_mirrored(&[
+            "***-*---*-*-",
+            "*-***-******",
+            "*-********-*",
+            "****--*-*-**",
+            "*-*****-*-**",
+            "-**-**---***",
+            "**--*----*-*",
+        ]) > 1);
+    }
+
     #[test]
     fn break_cluster_cant_break() {
         let image_string = 
@@ -523,4 +873,222 @@ mod tests {
         assert_eq!(clusters.get_cluster(2).rect.left, 3);
         assert_eq!(clusters.get_cluster(2).rect.top, 1);
     }
+
+    #[test]
+    fn split_by_projection_touching() {
+        let mut image = BinaryImage::new_w_h(20, 12);
+        for y in 0..12 {
+            for x in (1..9).chain(10..18) {
+                image.set_pixel(x, y, true);
+            }
+        }
+        image.set_pixel(9, 5, true);
+        image.set_pixel(9, 6, true);
+        let cluster = image.to_clusters(false).clusters.remove(0);
+        let clusters = cluster.split_by_projection(8, 4);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters.get_cluster(0).rect, BoundingRect::new_x_y_w_h(1, 0, 8, 12));
+        assert_eq!(clusters.get_cluster(1).rect, BoundingRect::new_x_y_w_h(9, 0, 9, 12));
+        assert_eq!(clusters.get_cluster(0).size() + clusters.get_cluster(1).size(), cluster.size());
+    }
+
+    #[test]
+    fn split_by_projection_solid() {
+        let mut image = BinaryImage::new_w_h(10, 12);
+        for y in 2..12 {
+            for x in 2..10 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let cluster = image.to_clusters(false).clusters.remove(0);
+        let clusters = cluster.split_by_projection(8, 4);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters.get_cluster(0).rect, cluster.rect);
+        assert_eq!(clusters.get_cluster(0).points, cluster.points);
+    }
+
+    #[test]
+    fn to_clusters_raster_order() {
+        // the left arm of the U is started first, then the blob, then the thicker right arm,
+        // into which the left arm merges at the bottom, leaving the U in the slot after the blob
+        let image = BinaryImage::from_string(&(
+            "*----*--\n".to_owned() +
+            "*-**-*--\n" +
+            "*-**----\n" +
+            "*-**----\n" +
+            "****----\n" +
+            "--------\n" +
+            "-----**-\n"
+        ));
+        let clusters = image.to_clusters(false);
+        let firsts: Vec<(i32, i32)> = clusters.iter()
+            .map(|cluster| cluster.points.iter().map(|p| (p.y, p.x)).min().unwrap())
+            .collect();
+        assert_eq!(firsts, vec![(0, 0), (0, 5), (6, 5)]);
+        assert_eq!(clusters.iter().map(|c| c.size()).collect::<Vec<_>>(), vec![14, 2, 2]);
+        for cluster in clusters.iter() {
+            let mut restored = BinaryImage::new_w_h(image.width, image.height);
+            for p in cluster.iter() {
+                restored.set_pixel(p.x as usize, p.y as usize, true);
+            }
+            assert_eq!(
+                restored.crop_with_rect(cluster.rect).to_string(),
+                cluster.to_binary_image().to_string()
+            );
+            assert!(cluster.iter().all(|p| image.get_pixel(p.x as usize, p.y as usize)));
+        }
+
+        let mut shuffled = Clusters::default();
+        for cluster in image.to_clusters(false).into_iter().rev() {
+            shuffled.add_cluster(cluster);
+        }
+        shuffled.add_cluster(Cluster::default());
+        shuffled.sort_by_first_pixel();
+        assert_eq!(shuffled.iter().map(|c| c.size()).collect::<Vec<_>>(), vec![14, 2, 2, 0]);
+    }
+
+    #[test]
+    fn count_holes_figure_eight() {
+        let image = BinaryImage::from_string(&(
+            "-------\n".to_owned() +
+            "-*****-\n" +
+            "-*---*-\n" +
+            "-*****-\n" +
+            "-*---*-\n" +
+            "-*****-\n" +
+            "-------\n"
+        ));
+        assert_eq!(image.count_holes(false), 2);
+        assert_eq!(image.count_holes(true), 2);
+        assert_eq!(Cluster::image_to_paths(&image, PathSimplifyMode::None).len(), 3);
+
+        let blob = BinaryImage::from_string(&(
+            "-***-\n".to_owned() +
+            "*****\n" +
+            "-***-\n"
+        ));
+        assert_eq!(blob.count_holes(false), 0);
+        assert_eq!(blob.count_holes(true), 0);
+    }
+
+    /// A staircase of `steps` rows 6 pixels wide, each overlapping the one above by 3 pixels
+    fn staircase(steps: usize) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(3 * steps + 3, steps);
+        for y in 0..steps {
+            for x in 3 * y..3 * y + 6 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn break_cluster_long_staircase() {
+        let pieces = std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(|| {
+                let image = staircase(101);
+                let mut output = Clusters::default();
+                let added = Cluster::break_cluster_into(image.to_clusters(false).clusters.remove(0), &mut output, None);
+                assert_eq!(added, output.len());
+                output.len()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(pieces > 50, "{}", pieces);
+    }
+
+    #[test]
+    fn break_cluster_max_pieces() {
+        let image = staircase(101);
+        let unlimited = Cluster::break_cluster(image.to_clusters(false).clusters.remove(0));
+        let mut output = Clusters::default();
+        let added = Cluster::break_cluster_into(image.to_clusters(false).clusters.remove(0), &mut output, Some(10));
+        assert!(added <= 10 && added < unlimited.len(), "{}", added);
+        assert_eq!(added, output.len());
+    }
+
+    #[test]
+    fn break_cluster_max_depth() {
+        let image = staircase(101);
+        let unlimited = Cluster::break_cluster(image.to_clusters(false).clusters.remove(0));
+        let mut output = Clusters::default();
+        let added = Cluster::break_cluster_to_depth(image.to_clusters(false).clusters.remove(0), &mut output, 3);
+        assert_eq!(added, output.len());
+        assert!(added < unlimited.len(), "{} {}", added, unlimited.len());
+        assert!(added <= 1 << 3, "{}", added);
+
+        // the default depth is not reached here
+        let mut output = Clusters::default();
+        let added = Cluster::break_cluster_to_depth(image.to_clusters(false).clusters.remove(0), &mut output, Cluster::DEFAULT_MAX_BREAK_DEPTH);
+        assert_eq!(added, unlimited.len());
+
+        let mut output = Clusters::default();
+        assert_eq!(Cluster::break_cluster_to_depth(image.to_clusters(false).clusters.remove(0), &mut output, 0), 1);
+        assert_eq!(output.get_cluster(0).size(), image.to_clusters(false).clusters[0].size());
+    }
+
+    #[test]
+    fn euler_number_disks() {
+        let disks = |disks: &[(f64, f64, f64)], holes: &[(f64, f64, f64)]| {
+            let mut image = BinaryImage::new_w_h(40, 20);
+            let inside = |x: usize, y: usize, &(cx, cy, r): &(f64, f64, f64)| (x as f64 - cx).hypot(y as f64 - cy) <= r;
+            for y in 0..20 {
+                for x in 0..40 {
+                    image.set_pixel(x, y, disks.iter().any(|d| inside(x, y, d)) && !holes.iter().any(|d| inside(x, y, d)));
+                }
+            }
+            image
+        };
+        for diagonal in [false, true] {
+            assert_eq!(disks(&[(10.0, 10.0, 8.0)], &[]).euler_number(diagonal), 1);
+            assert_eq!(disks(&[(10.0, 10.0, 8.0)], &[(10.0, 10.0, 3.0)]).euler_number(diagonal), 0);
+            assert_eq!(disks(&[(10.0, 10.0, 8.0), (30.0, 10.0, 6.0)], &[]).euler_number(diagonal), 2);
+        }
+        assert_eq!(BinaryImage::new_w_h(3, 3).euler_number(false), 0);
+
+        // diagonally touching pixels around an unset center
+        let ring = BinaryImage::from_string(&(
+            "-*-\n".to_owned() +
+            "*-*\n" +
+            "-*-\n"
+        ));
+        assert_eq!(ring.euler_number(false), 4);
+        assert_eq!(ring.euler_number(true), 0);
+        assert_eq!(ring.to_clusters(false).len() as i32 - ring.count_holes(true) as i32, 4);
+    }
+
+    #[test]
+    fn image_to_paths_border() {
+        // a cup touching the top edge
+        let image = BinaryImage::from_string(&(
+            "-*---*-\n".to_owned() +
+            "-*---*-\n" +
+            "-*****-\n" +
+            "-------\n"
+        ));
+        let closed = |paths: &[PathI32]| paths.iter().all(|path| path.len() > 4 && path[0] == path[path.len() - 1]);
+
+        let paths = Cluster::image_to_paths_with_border(&image, PathSimplifyMode::None, true);
+        assert_eq!(paths.len(), 1);
+        assert!(closed(&paths));
+        assert_eq!(paths[0].iter().map(|p| p.y).min(), Some(0));
+
+        // the inside of the cup and the surroundings become holes within the frame
+        let paths = Cluster::image_to_paths_with_border(&image, PathSimplifyMode::None, false);
+        assert_eq!(paths.len(), 3);
+        assert!(closed(&paths));
+        // the (left, top, right, bottom) extent of the pixel corners a path runs through
+        let corners = |path: &PathI32| {
+            let (xs, ys) = (path.iter().map(|p| p.x), path.iter().map(|p| p.y));
+            (xs.clone().min().unwrap(), ys.clone().min().unwrap(), xs.max().unwrap(), ys.max().unwrap())
+        };
+        assert_eq!(corners(&paths[0]), (0, 0, 7, 4));
+        assert!(paths[1..].iter().any(|path| corners(path) == (2, 0, 5, 2)));
+        assert_eq!(
+            Cluster::image_to_splines_with_border(&image, 1.0, 4.0, 10, 1.0, false).len(),
+            3
+        );
+    }
 }
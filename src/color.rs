@@ -1,3 +1,5 @@
+use crate::{ColorImage, GrayImage, ScalerField};
+
 pub trait ColorType {
     type ValueType;
 
@@ -5,7 +7,9 @@ pub trait ColorType {
 }
 
 /// RGBA; each channel is 8 bit unsigned
-#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+///
+/// Ordered by r, then g, then b, then a.
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -13,6 +17,26 @@ pub struct Color {
     pub a: u8,
 }
 
+/// Error from parsing a `Color` from a string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseColorError {
+    /// Not of the form `#RGB`, `#RRGGBB` or `#RRGGBBAA`
+    InvalidHex,
+    /// Not of the form `rgba(r,g,b,a)` or `rgb(r,g,b)`
+    InvalidRgba,
+}
+
+impl std::fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHex => write!(f, "invalid hex color, expected #RGB, #RRGGBB or #RRGGBBAA"),
+            Self::InvalidRgba => write!(f, "invalid rgba color, expected rgba(r,g,b,a) or rgb(r,g,b)"),
+        }
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
 /// Color names
 pub enum ColorName {
     Black,
@@ -47,7 +71,7 @@ pub struct ColorSum {
 }
 
 /// HSV; each channel is 64 bit float
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct ColorHsv {
     pub h: f64,
     pub s: f64,
@@ -96,10 +120,90 @@ impl Color {
         )
     }
 
+    /// Packs the channels as `r | g << 8 | b << 16 | a << 24`, so that `to_le_bytes` gives them
+    /// in the order of a pixel in `ColorImage::pixels`
+    pub fn to_rgba_u32(&self) -> u32 {
+        u32::from_le_bytes([self.r, self.g, self.b, self.a])
+    }
+
+    /// Inverse of `to_rgba_u32`
+    pub fn from_rgba_u32(rgba: u32) -> Self {
+        let [r, g, b, a] = rgba.to_le_bytes();
+        Self { r, g, b, a }
+    }
+
+    /// Luma with the weights of `GrayImage::LUMA_WEIGHTS`, rounded; alpha is ignored
+    pub fn luminance(&self) -> u8 {
+        let [r, g, b] = GrayImage::LUMA_WEIGHTS;
+        (r * self.r as f64 + g * self.g as f64 + b * self.b as f64).round().min(255.0) as u8
+    }
+
+    /// Whether every channel, alpha included, differs from `other` by at most `tolerance`
+    pub fn approx_eq(&self, other: &Color, tolerance: u8) -> bool {
+        self.r.abs_diff(other.r) <= tolerance && self.g.abs_diff(other.g) <= tolerance &&
+        self.b.abs_diff(other.b) <= tolerance && self.a.abs_diff(other.a) <= tolerance
+    }
+
+    /// Sum of the squared differences of the channels, alpha included
+    pub fn distance_sq(&self, other: &Color) -> u32 {
+        [(self.r, other.r), (self.g, other.g), (self.b, other.b), (self.a, other.a)].iter()
+            .map(|&(a, b)| (a.abs_diff(b) as u32).pow(2))
+            .sum()
+    }
+
     pub fn to_hex_string(&self) -> String {
         format!("#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
     }
 
+    pub fn to_hex_string_rgba(&self) -> String {
+        format!("#{:02X}{:02X}{:02X}{:02X}", self.r, self.g, self.b, self.a)
+    }
+
+    /// Parses `#RGB`, `#RRGGBB` or `#RRGGBBAA`, case-insensitive and with optional '#'
+    pub fn from_hex_str(s: &str) -> Result<Self, ParseColorError> {
+        let s = s.trim();
+        let s = s.strip_prefix('#').unwrap_or(s);
+        if !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseColorError::InvalidHex);
+        }
+        let digit = |i: usize| u8::from_str_radix(&s[i..i + 1], 16).unwrap();
+        let byte = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).unwrap();
+        match s.len() {
+            3 => Ok(Self::new(digit(0) * 17, digit(1) * 17, digit(2) * 17)),
+            6 => Ok(Self::new(byte(0), byte(2), byte(4))),
+            8 => Ok(Self::new_rgba(byte(0), byte(2), byte(4), byte(6))),
+            _ => Err(ParseColorError::InvalidHex),
+        }
+    }
+
+    /// Parses `rgba(r,g,b,a)` as produced by `to_color_string`, where alpha is within 0 to 1,
+    /// or `rgb(r,g,b)`
+    pub fn from_rgba_str(s: &str) -> Result<Self, ParseColorError> {
+        let s = s.trim().to_ascii_lowercase();
+        let (inner, has_alpha) = if let Some(inner) = s.strip_prefix("rgba(") {
+            (inner, true)
+        } else if let Some(inner) = s.strip_prefix("rgb(") {
+            (inner, false)
+        } else {
+            return Err(ParseColorError::InvalidRgba);
+        };
+        let inner = inner.strip_suffix(')').ok_or(ParseColorError::InvalidRgba)?;
+        let parts: Vec<&str> = inner.split(',').map(|p| p.trim()).collect();
+        if parts.len() != if has_alpha { 4 } else { 3 } {
+            return Err(ParseColorError::InvalidRgba);
+        }
+        let channel = |p: &str| p.parse::<u8>().map_err(|_| ParseColorError::InvalidRgba);
+        let alpha = if has_alpha {
+            match parts[3].parse::<f64>() {
+                Ok(a) if (0.0..=1.0).contains(&a) => (a * 255.0).round() as u8,
+                _ => return Err(ParseColorError::InvalidRgba),
+            }
+        } else {
+            255
+        };
+        Ok(Self::new_rgba(channel(parts[0])?, channel(parts[1])?, channel(parts[2])?, alpha))
+    }
+
     pub fn to_color_i32(&self) -> ColorI32 {
         ColorI32::new(self)
     }
@@ -174,6 +278,19 @@ impl ColorType for Color {
     }
 }
 
+impl std::str::FromStr for Color {
+    type Err = ParseColorError;
+
+    /// Parses either format of `Color::from_rgba_str` or `Color::from_hex_str`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim_start().to_ascii_lowercase().starts_with("rgb") {
+            Self::from_rgba_str(s)
+        } else {
+            Self::from_hex_str(s)
+        }
+    }
+}
+
 impl ColorI32 {
     pub fn new(color: &Color) -> Self {
         Self {
@@ -229,6 +346,32 @@ impl ColorHsv {
     pub fn new(h: f64, s: f64, v: f64) -> Self {
         Self { h, s, v }
     }
+
+    /// Converts back to an opaque RGB color, the inverse of `Color::to_hsv`.
+    /// Hue wraps around; saturation and value are clamped to [0, 1].
+    pub fn to_color(&self) -> Color {
+        let h = self.h.rem_euclid(1.0) * 6.0;
+        let (s, v) = (self.s.clamp(0.0, 1.0), self.v.clamp(0.0, 1.0));
+        let sector = (h.floor() as usize) % 6;
+        let f = h - h.floor();
+        let (p, q, t) = (v * (1.0 - s), v * (1.0 - f * s), v * (1.0 - (1.0 - f) * s));
+        let (r, g, b) = match sector {
+            0 => (v, t, p),
+            1 => (q, v, p),
+            2 => (p, v, t),
+            3 => (p, q, v),
+            4 => (t, p, v),
+            _ => (v, p, q),
+        };
+        let channel = |c: f64| (c * 255.0).round() as u8;
+        Color::new(channel(r), channel(g), channel(b))
+    }
+
+    /// Distance between the hues around the color wheel, from 0 to 0.5, so 0.99 is 0.02 from 0.01
+    pub fn hue_distance(&self, other: &ColorHsv) -> f64 {
+        let d = (self.h - other.h).abs().rem_euclid(1.0);
+        d.min(1.0 - d)
+    }
 }
 
 impl ColorSum {
@@ -269,3 +412,251 @@ impl ColorSum {
         self.counter = 0;
     }
 }
+
+/// Number of grid cells along each channel of a `PaletteIndex`
+const PALETTE_GRID_CELLS: usize = 16;
+/// Number of channel values spanned by a grid cell
+const PALETTE_CELL_SIZE: usize = 256 / PALETTE_GRID_CELLS;
+
+/// Exact nearest color lookup in a palette, by RGB distance (alpha is ignored).
+///
+/// The RGB cube is divided into a grid of cells, each listing the palette entries that can be nearest
+/// to a color in the cell: those that are not farther from the cell than the farthest point of the cell
+/// is from the best single entry. A query then only scans the few candidates of its cell.
+#[derive(Debug, Clone)]
+pub struct PaletteIndex {
+    palette: Vec<Color>,
+    cells: Vec<Vec<usize>>,
+}
+
+impl PaletteIndex {
+    /// Panics if `palette` is empty
+    pub fn build(palette: &[Color]) -> PaletteIndex {
+        assert!(!palette.is_empty(), "palette is empty");
+        let mut cells = Vec::with_capacity(PALETTE_GRID_CELLS.pow(3));
+        for r in 0..PALETTE_GRID_CELLS {
+            for g in 0..PALETTE_GRID_CELLS {
+                for b in 0..PALETTE_GRID_CELLS {
+                    let lows = [r, g, b].map(|i| (i * PALETTE_CELL_SIZE) as i32);
+                    let bounds: Vec<(u32, u32)> = palette.iter()
+                        .map(|color| cell_sq_dist_bounds(*color, lows))
+                        .collect();
+                    let farthest = bounds.iter().map(|&(_, max)| max).min().unwrap();
+                    cells.push((0..palette.len()).filter(|&i| bounds[i].0 <= farthest).collect());
+                }
+            }
+        }
+        PaletteIndex { palette: palette.to_vec(), cells }
+    }
+
+    pub fn palette(&self) -> &[Color] {
+        &self.palette
+    }
+
+    /// The index of the palette entry nearest to `c` (the first among ties, as a linear scan would find)
+    /// and its squared distance
+    pub fn nearest(&self, c: Color) -> (usize, u32) {
+        let cell = |v: u8| v as usize / PALETTE_CELL_SIZE;
+        let candidates = &self.cells[(cell(c.r) * PALETTE_GRID_CELLS + cell(c.g)) * PALETTE_GRID_CELLS + cell(c.b)];
+        let mut best = (candidates[0], u32::MAX);
+        for &i in candidates.iter() {
+            let d = rgb_sq_dist(self.palette[i], c);
+            if d < best.1 {
+                best = (i, d);
+            }
+        }
+        best
+    }
+
+    /// The index of the nearest palette entry of every pixel. Panics if the palette has more than 256 entries.
+    pub fn map_image(&self, image: &ColorImage) -> ScalerField<u8> {
+        assert!(self.palette.len() <= 256, "palette of {} entries does not fit in u8", self.palette.len());
+        let mut field = ScalerField::<u8>::new_w_h(image.width, image.height);
+        // images tend to repeat colors, so remember the last one
+        let mut last: Option<(Color, u8)> = None;
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let color = image.get_pixel(x, y);
+                let index = match last {
+                    Some((c, index)) if c.r == color.r && c.g == color.g && c.b == color.b => index,
+                    _ => self.nearest(color).0 as u8,
+                };
+                last = Some((color, index));
+                field.set_pixel(x, y, index);
+            }
+        }
+        field
+    }
+}
+
+fn rgb_sq_dist(a: Color, b: Color) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)
+}
+
+/// The least and greatest squared distance from `color` to the grid cell with the lowest values `lows`
+fn cell_sq_dist_bounds(color: Color, lows: [i32; 3]) -> (u32, u32) {
+    let (mut min, mut max) = (0, 0);
+    for (v, low) in [color.r as i32, color.g as i32, color.b as i32].into_iter().zip(lows) {
+        let high = low + PALETTE_CELL_SIZE as i32 - 1;
+        let near = if v < low { low - v } else if v > high { v - high } else { 0 };
+        let far = std::cmp::max((v - low).abs(), (v - high).abs());
+        min += (near * near) as u32;
+        max += (far * far) as u32;
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_hex_round_trip() {
+        let color = Color::new_rgba(18, 171, 205, 128);
+        assert_eq!(color.to_hex_string(), "#12ABCD");
+        assert_eq!(color.to_hex_string_rgba(), "#12ABCD80");
+        assert_eq!(Color::from_hex_str(&color.to_hex_string()), Ok(Color::new(18, 171, 205)));
+        assert_eq!(Color::from_hex_str(&color.to_hex_string_rgba()), Ok(color));
+        assert_eq!(Color::from_hex_str("12abcd80"), Ok(color));
+        assert_eq!(Color::from_hex_str(" #f0A "), Ok(Color::new(255, 0, 170)));
+    }
+
+    #[test]
+    fn color_order_and_u32() {
+        let mut colors = [
+            Color::new_rgba(1, 2, 3, 4), Color::new(1, 2, 3), Color::new(0, 255, 255),
+            Color::new(1, 0, 9), Color::new_rgba(1, 2, 3, 4), Color::new(1, 2, 2),
+        ];
+        colors.sort();
+        let keys: Vec<(u8, u8, u8, u8)> = colors.iter().map(|c| (c.r, c.g, c.b, c.a)).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        assert_eq!(colors[0], Color::new(0, 255, 255));
+        assert_eq!(colors[5], Color::new(1, 2, 3));
+
+        let mut image = ColorImage::new_w_h(1, 1);
+        for &color in colors.iter() {
+            assert_eq!(Color::from_rgba_u32(color.to_rgba_u32()), color);
+            image.set_pixel(0, 0, &color);
+            assert_eq!(image.pixels[..4], color.to_rgba_u32().to_le_bytes());
+        }
+
+        assert_eq!(Color::new(255, 255, 255).luminance(), 255);
+        assert_eq!(Color::new(0, 0, 0).luminance(), 0);
+        assert!(Color::new(0, 255, 0).luminance() > Color::new(255, 0, 0).luminance());
+    }
+
+    #[test]
+    fn color_approx_eq() {
+        let (a, b) = (Color::new(10, 10, 10), Color::new(12, 11, 9));
+        assert!(a.approx_eq(&b, 2) && b.approx_eq(&a, 2));
+        assert!(!a.approx_eq(&b, 1));
+        assert!(!a.approx_eq(&Color::new_rgba(10, 10, 10, 250), 2));
+        assert_eq!(a.distance_sq(&b), 6);
+        assert_eq!(Color::new(0, 0, 0).distance_sq(&Color::new_rgba(255, 255, 255, 0)), 4 * 255 * 255);
+    }
+
+    #[test]
+    fn color_rgba_round_trip() {
+        for &color in [Color::new_rgba(18, 171, 205, 128), Color::new(0, 0, 0), Color::new_rgba(1, 2, 3, 0)].iter() {
+            assert_eq!(Color::from_rgba_str(&color.to_color_string()), Ok(color));
+            assert_eq!(color.to_color_string().parse::<Color>(), Ok(color));
+            assert_eq!(color.to_hex_string_rgba().parse::<Color>(), Ok(color));
+        }
+        assert_eq!(Color::from_rgba_str("RGB( 1, 2 ,3 )"), Ok(Color::new(1, 2, 3)));
+        assert_eq!(Color::from_rgba_str("rgba(1,2,3,0.5)"), Ok(Color::new_rgba(1, 2, 3, 128)));
+    }
+
+    #[test]
+    fn color_parse_errors() {
+        for s in ["", "#", "#12", "#1234", "#12345", "#1234567", "#12345G", "#+12"].iter() {
+            assert_eq!(Color::from_hex_str(s), Err(ParseColorError::InvalidHex), "{}", s);
+        }
+        for s in ["rgba(1,2,3)", "rgb(1,2,3,1)", "rgba(1,2,3,1.5)", "rgb(256,0,0)", "rgb(1,2,3", "rgb(-1,2,3)", "hsl(1,2,3)"].iter() {
+            assert_eq!(Color::from_rgba_str(s), Err(ParseColorError::InvalidRgba), "{}", s);
+        }
+        assert_eq!("rgb(a,b,c)".parse::<Color>(), Err(ParseColorError::InvalidRgba));
+        assert_eq!("not a color".parse::<Color>(), Err(ParseColorError::InvalidHex));
+    }
+
+    #[test]
+    fn color_parse_as_key() {
+        let builder = crate::color_clusters::Builder::new().key("#FF0000".parse().unwrap());
+        assert_eq!(builder.conf.key, Color::new(255, 0, 0));
+    }
+
+    fn random_colors(seed: &mut u32, n: usize) -> Vec<Color> {
+        (0..n).map(|_| {
+            *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let v = *seed >> 8;
+            Color::new(v as u8, (v >> 8) as u8, (v >> 16) as u8)
+        }).collect()
+    }
+
+    #[test]
+    fn palette_index_exact() {
+        let mut seed = 7;
+        for n in [1, 2, 5, 16, 64, 256] {
+            let mut palette = random_colors(&mut seed, n);
+            // a duplicate entry, so ties must go to the first
+            palette.push(palette[0]);
+            let index = PaletteIndex::build(&palette);
+            let mut queries = random_colors(&mut seed, 2000);
+            queries.extend([Color::new(0, 0, 0), Color::new(255, 255, 255), Color::new(15, 16, 255)]);
+            for c in queries {
+                let brute = palette.iter().enumerate()
+                    .map(|(i, p)| (rgb_sq_dist(*p, c), i))
+                    .min()
+                    .unwrap();
+                assert_eq!(index.nearest(c), (brute.1, brute.0), "palette of {} at {:?}", n, c);
+            }
+        }
+    }
+
+    #[test]
+    fn palette_index_map_image() {
+        let mut seed = 11;
+        let palette = random_colors(&mut seed, 256);
+        let index = PaletteIndex::build(&palette);
+        let mut image = ColorImage::new_w_h(512, 512);
+        for y in 0..512 {
+            for x in 0..512 {
+                image.set_pixel(x, y, &Color::new((x / 2) as u8, (y / 2) as u8, ((x + y) / 4) as u8));
+            }
+        }
+        let mapped = index.map_image(&image);
+        assert_eq!((mapped.width(), mapped.height()), (512, 512));
+        for (x, y) in [(0, 0), (511, 511), (100, 400), (301, 7)] {
+            assert_eq!(mapped.get_pixel(x, y) as usize, index.nearest(image.get_pixel(x, y)).0);
+        }
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let color = Color::new(r as u8, g as u8, b as u8);
+                    let back = color.to_hsv().to_color();
+                    assert!(
+                        (back.r as i32 - r).abs() <= 1 && (back.g as i32 - g).abs() <= 1 && (back.b as i32 - b).abs() <= 1,
+                        "{:?} {:?} {:?}", color, color.to_hsv(), back
+                    );
+                }
+            }
+        }
+        assert_eq!(ColorHsv::new(1.0 / 3.0, 1.0, 1.0).to_color(), Color::new(0, 255, 0));
+        assert_eq!(ColorHsv::new(-1.0 / 6.0, 1.0, 1.0).to_color(), Color::new(255, 0, 255));
+    }
+
+    #[test]
+    fn hsv_hue_distance() {
+        let hue = |h: f64| ColorHsv { h, ..Default::default() };
+        assert!((hue(0.99).hue_distance(&hue(0.01)) - 0.02).abs() < 1e-9);
+        assert!((hue(0.01).hue_distance(&hue(0.99)) - 0.02).abs() < 1e-9);
+        assert!((hue(0.2).hue_distance(&hue(0.7)) - 0.5).abs() < 1e-9);
+        assert_eq!(hue(0.3).hue_distance(&hue(0.3)), 0.0);
+    }
+}
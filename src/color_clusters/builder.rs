@@ -1,6 +1,7 @@
-use std::collections::HashMap;
-use crate::{Color, ColorImage};
-use super::{Cluster, Clusters, ClustersView, container::ClusterIndex, container::ClusterIndexElem};
+use std::collections::{HashMap, HashSet};
+use crate::disjoint_sets::Forests;
+use crate::{BoundingRect, Color, ColorImage, CompatFlags, CompatLevel};
+use super::{Cluster, Clusters, ClustersSnapshot, ClustersView, ClusterWorkspace, container::ClusterIndex, container::ClusterIndexElem};
 
 // Describes what to do with pixels that match the key color
 #[derive(Default, Clone, Copy)]
@@ -10,6 +11,42 @@ pub enum KeyingAction {
     Discard,
 }
 
+/// A problem that did not stop clustering, reported in `Clusters::warnings`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuilderWarning {
+    /// The key color matched more than `max_keyed_fraction` of the pixels clustered
+    KeyedFractionExceeded { fraction: f64, max: f64 },
+}
+
+/// A problem that stopped clustering, see `Builder::try_run`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuilderError {
+    /// The key color matched more than `max_keyed_fraction` of the pixels clustered, with `abort_on_max_keyed` set
+    KeyedFractionExceeded { fraction: f64, max: f64 },
+}
+
+impl std::fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::KeyedFractionExceeded { fraction, max } => write!(
+                f, "key color matched {:.1}% of the pixels, more than the maximum of {:.1}%",
+                fraction * 100.0, max * 100.0
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuilderError {}
+
+/// Progress of an `IncrementalBuilder`, see `IncrementalBuilder::progress_detail`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProgressDetail {
+    /// Percentage, same as `IncrementalBuilder::progress`
+    pub progress: u32,
+    /// Pixels matching the key color so far
+    pub keyed_pixels: u32,
+}
+
 #[derive(Clone)]
 pub struct BuilderConfig {
     pub(crate) diagonal: bool,
@@ -17,6 +54,25 @@ pub struct BuilderConfig {
     pub(crate) batch_size: u32,
     pub(crate) key: Color,
     pub(crate) keying_action: KeyingAction,
+    /// Region of interest; an invalid rect (the default) means the whole image
+    pub(crate) roi: BoundingRect,
+    /// Maximum number of output clusters; the smallest are merged into their neighbours
+    pub(crate) max_clusters: usize,
+    /// Whether pixels differing only in alpha may be clustered together
+    pub(crate) ignore_alpha: bool,
+    /// Unless `ignore_alpha`, the largest difference in alpha of pixels clustered together in stage 1
+    pub(crate) alpha_tolerance: u8,
+    /// Maximum fraction of the clustered pixels that may match the key color, see `BuilderWarning::KeyedFractionExceeded`
+    pub(crate) max_keyed_fraction: Option<f64>,
+    /// Whether exceeding `max_keyed_fraction` stops clustering with an error instead of a warning
+    pub(crate) abort_on_max_keyed: bool,
+    /// Behaviours pinned across upgrades, see `CompatLevel`
+    pub(crate) compat: CompatFlags,
+    /// How much a short shared boundary penalizes a merge target, see `NeighbourInfo::shared_boundary`
+    pub(crate) boundary_weight: f64,
+    /// Whether stage 1 records the clusters to combine in a union-find pass and combines them at the end,
+    /// instead of combining them while labeling; the resulting clusters are the same
+    pub(crate) skip_stage1_merge: bool,
 }
 
 impl Default for BuilderConfig {
@@ -27,19 +83,102 @@ impl Default for BuilderConfig {
             batch_size: 10000,
             key: Color::default(),
             keying_action: KeyingAction::default(),
+            roi: BoundingRect::default(),
+            max_clusters: usize::MAX,
+            ignore_alpha: true,
+            alpha_tolerance: 0,
+            max_keyed_fraction: None,
+            abort_on_max_keyed: false,
+            compat: CompatFlags::default(),
+            boundary_weight: 0.0,
+            skip_stage1_merge: false,
         }
     }
 }
 
+macro_rules! field_setter {
+    ($name:ident, $t:ty) => {
+        pub fn $name(mut self, $name: $t) -> Self {
+            self.$name = $name;
+            self
+        }
+    };
+}
+
+impl BuilderConfig {
+    field_setter!(diagonal, bool);
+    field_setter!(hierarchical, u32);
+    field_setter!(batch_size, u32);
+    field_setter!(key, Color);
+    field_setter!(keying_action, KeyingAction);
+    field_setter!(roi, BoundingRect);
+    field_setter!(max_clusters, usize);
+    field_setter!(ignore_alpha, bool);
+    field_setter!(alpha_tolerance, u8);
+    field_setter!(max_keyed_fraction, Option<f64>);
+    field_setter!(abort_on_max_keyed, bool);
+    field_setter!(boundary_weight, f64);
+    field_setter!(skip_stage1_merge, bool);
+
+    /// Pins the clustering behaviours of `level`, see `CompatLevel`
+    pub fn compat(mut self, level: CompatLevel) -> Self {
+        self.compat = level.into();
+        self
+    }
+}
+
 pub struct NeighbourInfo {
     pub index: ClusterIndex,
     pub diff: i32,
+    pub(crate) shared_boundary: u32,
+}
+
+impl NeighbourInfo {
+    /// Number of adjacent pixel pairs between the cluster and this neighbour.
+    ///
+    /// With a positive `boundary_weight`, the merge target is the neighbour with the lowest
+    /// `diff * (1 + boundary_weight * (1 - shared_boundary / total))`, where `total` is the sum of
+    /// `shared_boundary` over all neighbours; so a neighbour along most of the boundary is preferred
+    /// over a slightly closer one touching at a few pixels. `diff` itself is left as is.
+    pub fn shared_boundary(&self) -> u32 {
+        self.shared_boundary
+    }
+}
+
+/// The neighbourhood of a pixel being labeled in stage 1, see `Builder::connectivity_hook`.
+///
+/// Neighbours outside the image or the region of interest are `None`.
+pub struct PixelContext {
+    pub x: i32,
+    pub y: i32,
+    pub color: Color,
+    pub up: Option<Color>,
+    pub left: Option<Color>,
+    pub upleft: Option<Color>,
+    /// The pixel two rows up
+    pub up2: Option<Color>,
+    /// The pixel two columns left
+    pub left2: Option<Color>,
+}
+
+/// What a `Builder::connectivity_hook` decides for a pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Compare with the neighbours using `Builder::same`, as without a hook
+    Default,
+    /// Join the cluster of the pixel above
+    Up,
+    /// Join the cluster of the pixel to the left
+    Left,
+    /// Join the cluster of the pixel above to the left
+    UpLeft,
 }
 
 type Cmp = Box<dyn Fn(Color, Color) -> bool>;
 type Diff = Box<dyn Fn(Color, Color) -> i32>;
 type Deepen = Box<dyn Fn(&BuilderImpl, &Cluster, &[NeighbourInfo]) -> bool>;
 type Hollow = Box<dyn Fn(&BuilderImpl, &Cluster, &[NeighbourInfo]) -> bool>;
+type ConnectivityHook = Box<dyn Fn(&PixelContext) -> Connectivity>;
 
 /// the 0th cluster is reserved for internal use
 pub const ZERO: ClusterIndex = ClusterIndex(0);
@@ -52,6 +191,7 @@ pub struct Builder {
     pub(crate) diff: Option<Diff>,
     pub(crate) deepen: Option<Deepen>,
     pub(crate) hollow: Option<Hollow>,
+    pub(crate) connectivity_hook: Option<ConnectivityHook>,
     pub(crate) image: Option<ColorImage>,
 }
 
@@ -87,26 +227,72 @@ impl Builder {
         self
     }
 
+    /// Panics if clustering is aborted, see `try_run`
     pub fn run(self) -> Clusters {
         let mut bimpl = BuilderImpl::from(self);
         while !bimpl.tick() {}
         bimpl.result()
     }
 
+    /// Same as `run`, but returns an error if clustering is aborted, e.g. by `abort_on_max_keyed`
+    pub fn try_run(self) -> Result<Clusters, BuilderError> {
+        let mut bimpl = BuilderImpl::from(self);
+        while !bimpl.tick() {}
+        bimpl.try_result()
+    }
+
     pub fn start(self) -> IncrementalBuilder {
         IncrementalBuilder::new(BuilderImpl::from(self))
     }
 
+    /// Same as `run`, but takes the large buffers from `ws` instead of allocating them.
+    ///
+    /// The returned `Clusters` owns those buffers; hand it back with `ClusterWorkspace::recycle`
+    /// before the next run, otherwise that run allocates afresh. Results are identical to `run`.
+    pub fn run_with_workspace(self, ws: &mut ClusterWorkspace) -> Clusters {
+        let mut bimpl = BuilderImpl::with_workspace(self, ws);
+        while !bimpl.tick() {}
+        ws.cluster_areas = std::mem::take(&mut bimpl.cluster_areas);
+        ws.spare = std::mem::take(&mut bimpl.spare);
+        bimpl.result()
+    }
+
     config_setter!(diagonal, bool);
     config_setter!(hierarchical, u32);
     config_setter!(batch_size, u32);
     config_setter!(key, Color);
     config_setter!(keying_action, KeyingAction);
+    config_setter!(roi, BoundingRect);
+    config_setter!(max_clusters, usize);
+    config_setter!(ignore_alpha, bool);
+    config_setter!(alpha_tolerance, u8);
+    config_setter!(max_keyed_fraction, Option<f64>);
+    config_setter!(abort_on_max_keyed, bool);
+    config_setter!(boundary_weight, f64);
+    config_setter!(skip_stage1_merge, bool);
+
+    /// Pins the clustering behaviours of `level`, see `CompatLevel`
+    pub fn compat(mut self, level: CompatLevel) -> Self {
+        self.conf = self.conf.compat(level);
+        self
+    }
 
     closure_setter!(same, Fn(Color, Color) -> bool);
     closure_setter!(diff, Fn(Color, Color) -> i32);
     closure_setter!(deepen, Fn(&BuilderImpl, &Cluster, &[NeighbourInfo]) -> bool);
     closure_setter!(hollow, Fn(&BuilderImpl, &Cluster, &[NeighbourInfo]) -> bool);
+
+    /// Lets stage 1 join a pixel to a neighbouring cluster regardless of `same`, e.g. to bridge thin gaps
+    /// of a "don't care" color by looking one pixel further. Returning `Connectivity::Default` keeps the
+    /// usual comparison; without a hook, stage 1 does not build a `PixelContext` at all.
+    ///
+    /// Pixels are labeled row by row, so the hook should decide from the colors alone, and agree with
+    /// itself for mirrored situations; otherwise the clusters depend on the scan order.
+    /// Keyed pixels never reach the hook, and joining a keyed neighbour falls back to `Connectivity::Default`.
+    pub fn connectivity_hook(mut self, hook: impl Fn(&PixelContext) -> Connectivity + 'static) -> Self {
+        self.connectivity_hook = Some(Box::new(hook));
+        self
+    }
 }
 
 impl IncrementalBuilder {
@@ -124,10 +310,21 @@ impl IncrementalBuilder {
         self.builder_impl.as_ref().unwrap().view()
     }
 
+    /// Panics if clustering is aborted, see `try_result`
     pub fn result(&mut self) -> Clusters {
         self.builder_impl.take().unwrap().result()
     }
 
+    pub fn try_result(&mut self) -> Result<Clusters, BuilderError> {
+        self.builder_impl.take().unwrap().try_result()
+    }
+
+    /// Takes an owned copy of the current state, optionally with a preview map downsampled by `preview_scale`
+    pub fn snapshot(&self, preview_scale: Option<u32>) -> ClustersSnapshot {
+        let builder = self.builder_impl.as_ref().unwrap();
+        ClustersSnapshot::new(&builder.view(), builder.is_done(), preview_scale)
+    }
+
     pub fn progress(&self) -> u32 {
         match &self.builder_impl {
             None => {
@@ -138,75 +335,218 @@ impl IncrementalBuilder {
             }
         }
     }
+
+    pub fn progress_detail(&self) -> ProgressDetail {
+        match &self.builder_impl {
+            None => ProgressDetail::default(),
+            Some(builder) => ProgressDetail {
+                progress: builder.progress(),
+                keyed_pixels: builder.keyed_pixels,
+            },
+        }
+    }
 }
 
-struct Area {
+#[derive(Clone)]
+pub(crate) struct Area {
     pub area: usize,
     pub count: usize,
 }
 
 pub struct BuilderImpl {
-    diagonal: bool,
+    pub(crate) diagonal: bool,
     hierarchical: u32,
     batch_size: u32,
     key: Color,
     keying_action: KeyingAction,
+    roi: BoundingRect,         // clipped to the image; pixels outside are never clustered
+    max_clusters: usize,
+    ignore_alpha: bool,
+    alpha_tolerance: u8,
+    max_keyed_fraction: Option<f64>,
+    abort_on_max_keyed: bool,
+    compat: CompatFlags,
+    boundary_weight: f64,
+    skip_stage1_merge: bool,
+    labels: Forests<ClusterIndex>, // clusters to combine at the end of stage 1, with `skip_stage1_merge`
     same: Cmp,
     diff: Diff,
     deepen: Deepen,
     hollow: Hollow,
+    connectivity_hook: Option<ConnectivityHook>,
     pub(crate) width: u32,
     pub(crate) height: u32,
     pixels: Vec<u8>,           // raw bytes from getImageData; 4 bytes as a pixel
     clusters: Vec<Cluster>,    // array of clusters
+    spare: Vec<Cluster>,       // recycled clusters whose buffers can be reused
     pub(crate) cluster_indices: Vec<ClusterIndex>, // the cluster index each pixel belongs to
     cluster_areas: Vec<Area>,  // uniquely sorted array of cluster sizes
     clusters_output: Vec<ClusterIndex>, // indices of good clusters
     stage: u32,
     iteration: u32,
     next_index: ClusterIndex,
+    keyed_pixels: u32,         // pixels matching the key color
+    warnings: Vec<BuilderWarning>,
+    error: Option<BuilderError>,
+}
+
+/// A `Builder::same` predicate: colors are the same if their hue, saturation and value (all from 0 to 1, see `Color::to_hsv`)
+/// differ by at most `h_tol`, `s_tol` and `v_tol`. Hue is compared around the color wheel, so 0.99 is 0.02 from 0.01.
+///
+/// Grays all have hue 0, so a tight `h_tol` also tells apart a gray from a faint tint of any color but red.
+pub fn same_by_hsv(h_tol: f64, s_tol: f64, v_tol: f64) -> impl Fn(Color, Color) -> bool {
+    move |a: Color, b: Color| {
+        let (a, b) = (a.to_hsv(), b.to_hsv());
+        a.hue_distance(&b) <= h_tol && (a.s - b.s).abs() <= s_tol && (a.v - b.v).abs() <= v_tol
+    }
+}
+
+/// A `Builder::diff` measure: the differences in hue (around the color wheel, doubled to range from 0 to 1),
+/// saturation and value, weighted by `weights` in that order and scaled by 255 to be comparable with RGB differences
+pub fn diff_by_hsv(weights: [f64; 3]) -> impl Fn(Color, Color) -> i32 {
+    move |a: Color, b: Color| {
+        let (a, b) = (a.to_hsv(), b.to_hsv());
+        let diff = weights[0] * 2.0 * a.hue_distance(&b) + weights[1] * (a.s - b.s).abs() + weights[2] * (a.v - b.v).abs();
+        (diff * 255.0).round() as i32
+    }
 }
 
 impl From<Builder> for BuilderImpl {
 
-    fn from(mut b: Builder) -> Self {
+    fn from(b: Builder) -> Self {
+        Self::with_workspace(b, &mut ClusterWorkspace::default())
+    }
+}
+
+impl BuilderImpl {
+    fn with_workspace(mut b: Builder, ws: &mut ClusterWorkspace) -> Self {
         let im = b.image.unwrap();
         let len = im.pixels.len();
-
-        Self {
+        let full = BoundingRect::new_x_y_w_h(0, 0, im.width as i32, im.height as i32);
+        let roi = if !b.conf.roi.is_valid() {
+            full
+        } else {
+            let mut roi = b.conf.roi;
+            roi.clip(full);
+            roi.right = roi.right.max(roi.left);
+            roi.bottom = roi.bottom.max(roi.top);
+            roi
+        };
+
+        let mut cluster_indices = std::mem::take(&mut ws.cluster_indices);
+        cluster_indices.clear();
+        cluster_indices.resize(len / 4, ZERO);
+        let mut clusters = std::mem::take(&mut ws.clusters);
+        clusters.clear();
+        let spare = std::mem::take(&mut ws.spare);
+        let mut cluster_areas = std::mem::take(&mut ws.cluster_areas);
+        cluster_areas.clear();
+        let mut clusters_output = std::mem::take(&mut ws.clusters_output);
+        clusters_output.clear();
+
+        let mut bimpl = Self {
             diagonal: b.conf.diagonal,
             hierarchical: b.conf.hierarchical,
             batch_size: b.conf.batch_size,
             key: b.conf.key,
             keying_action: b.conf.keying_action,
+            roi,
+            max_clusters: b.conf.max_clusters,
+            ignore_alpha: b.conf.ignore_alpha,
+            alpha_tolerance: b.conf.alpha_tolerance,
+            max_keyed_fraction: b.conf.max_keyed_fraction,
+            abort_on_max_keyed: b.conf.abort_on_max_keyed,
+            compat: b.conf.compat,
+            boundary_weight: b.conf.boundary_weight,
+            skip_stage1_merge: b.conf.skip_stage1_merge,
+            labels: Forests::new(),
             same: b.same.take().unwrap(),
             diff: b.diff.take().unwrap(),
             deepen: b.deepen.take().unwrap(),
             hollow: b.hollow.take().unwrap(),
+            connectivity_hook: b.connectivity_hook.take(),
             width: im.width as u32,
             height: im.height as u32,
             pixels: im.pixels,
-            clusters: vec![Cluster::new()],
-            cluster_indices: vec![Default::default(); len / 4],
-            cluster_areas: Vec::new(),
-            clusters_output: Vec::new(),
+            clusters,
+            spare,
+            cluster_indices,
+            cluster_areas,
+            clusters_output,
             stage: 1,
             iteration: 0,
             next_index: ClusterIndex(1),
+            keyed_pixels: 0,
+            warnings: Vec::new(),
+            error: None,
+        };
+        let zero = bimpl.new_cluster();
+        bimpl.clusters.push(zero);
+        if bimpl.skip_stage1_merge {
+            bimpl.labels.make_set(ZERO);
         }
+        bimpl
     }
 }
 
 impl BuilderImpl {
+    /// A copy of the current state, continuing with the closures of `b` (its image and config are ignored)
+    pub(crate) fn fork(&self, mut b: Builder) -> Self {
+        Self {
+            diagonal: self.diagonal,
+            hierarchical: self.hierarchical,
+            batch_size: self.batch_size,
+            key: self.key,
+            keying_action: self.keying_action,
+            roi: self.roi,
+            max_clusters: self.max_clusters,
+            ignore_alpha: self.ignore_alpha,
+            alpha_tolerance: self.alpha_tolerance,
+            max_keyed_fraction: self.max_keyed_fraction,
+            abort_on_max_keyed: self.abort_on_max_keyed,
+            compat: self.compat,
+            boundary_weight: self.boundary_weight,
+            skip_stage1_merge: self.skip_stage1_merge,
+            labels: Forests::new(),
+            same: b.same.take().unwrap(),
+            diff: b.diff.take().unwrap(),
+            deepen: b.deepen.take().unwrap(),
+            hollow: b.hollow.take().unwrap(),
+            connectivity_hook: b.connectivity_hook.take(),
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.clone(),
+            clusters: self.clusters.clone(),
+            spare: Vec::new(),
+            cluster_indices: self.cluster_indices.clone(),
+            cluster_areas: self.cluster_areas.clone(),
+            clusters_output: self.clusters_output.clone(),
+            stage: self.stage,
+            iteration: self.iteration,
+            next_index: self.next_index,
+            keyed_pixels: self.keyed_pixels,
+            warnings: self.warnings.clone(),
+            error: self.error.clone(),
+        }
+    }
+
+    /// Whether stage 1 is done, see `fork`
+    pub(crate) fn is_past_stage_1(&self) -> bool {
+        self.stage > 1
+    }
+
     pub fn tick(&mut self) -> bool {
         match self.stage {
             1 => {
                 if self.stage_1() {
-                    if self.hierarchical != 0 {
+                    if !self.check_keyed_fraction() {
+                        self.stage = 3;
+                    } else if self.hierarchical != 0 {
                         self.stage += 1;
                         self.iteration = 0;
                     } else {
                         self.stage_1_output();
+                        self.keep_largest_outputs();
                         self.stage += 2;
                     }
                 }
@@ -215,6 +555,7 @@ impl BuilderImpl {
             2 => {
                 for _i in 0..std::cmp::max(1, self.iteration / 16) {
                     if self.stage_2() {
+                        self.keep_largest_outputs();
                         self.stage += 1;
                         self.iteration = 0;
                         break;
@@ -234,14 +575,51 @@ impl BuilderImpl {
         &mut self.clusters[index.0 as usize]
     }
 
+    /// Panics if clustering is aborted, see `try_result`
     pub fn result(self) -> Clusters {
-        Clusters {
+        match self.try_result() {
+            Ok(clusters) => clusters,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Same as `result`, but returns an error if clustering was aborted, e.g. by `abort_on_max_keyed`
+    pub fn try_result(self) -> Result<Clusters, BuilderError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let clustered_pixels = self.roi_area();
+        Ok(Clusters {
             width: self.width,
             height: self.height,
+            diagonal: self.diagonal,
             pixels: self.pixels,
             clusters: self.clusters,
             cluster_indices: self.cluster_indices,
             clusters_output: self.clusters_output,
+            keyed_pixels: self.keyed_pixels,
+            clustered_pixels,
+            warnings: self.warnings,
+        })
+    }
+
+    /// Records a warning, or an error to abort with, if too many pixels matched the key color.
+    /// Returns whether clustering should continue.
+    fn check_keyed_fraction(&mut self) -> bool {
+        let max = match self.max_keyed_fraction {
+            Some(max) => max,
+            None => return true,
+        };
+        let fraction = self.keyed_pixels as f64 / std::cmp::max(self.roi_area(), 1) as f64;
+        if fraction <= max {
+            return true;
+        }
+        if self.abort_on_max_keyed {
+            self.error = Some(BuilderError::KeyedFractionExceeded { fraction, max });
+            false
+        } else {
+            self.warnings.push(BuilderWarning::KeyedFractionExceeded { fraction, max });
+            true
         }
     }
 
@@ -249,6 +627,7 @@ impl BuilderImpl {
         ClustersView {
             width: self.width,
             height: self.height,
+            diagonal: self.diagonal,
             pixels: &self.pixels,
             clusters: &self.clusters,
             cluster_indices: &self.cluster_indices,
@@ -256,13 +635,17 @@ impl BuilderImpl {
         }
     }
 
+    pub(crate) fn is_done(&self) -> bool {
+        self.stage > 2
+    }
+
     pub fn progress(&self) -> u32 {
         match self.stage {
             1 => {
-                50 * self.iteration / self.cluster_indices.len() as u32
+                50 * self.iteration / self.roi_area().max(1)
             },
             2 => {
-                50 + 50 * self.iteration / self.cluster_areas.len() as u32
+                50 + 50 * self.iteration / (self.cluster_areas.len() as u32).max(1)
             },
             _ => {
                 100
@@ -276,12 +659,14 @@ impl BuilderImpl {
         let key = self.key;
         let keying_action = self.keying_action;
         let has_key = key != Color::default();
-        let len = self.cluster_indices.len();
+        let len = self.roi_area();
+        let roi_width = self.roi.width() as u32;
 
-        for i in (self.iteration..(self.iteration + batch_size)).take_while(|&i| (i as usize) < len)
+        for j in (self.iteration..(self.iteration + batch_size)).take_while(|&j| j < len)
         {
-            let x = (i % self.width) as i32;
-            let y = (i / self.width) as i32;
+            let x = self.roi.left + (j % roi_width) as i32;
+            let y = self.roi.top + (j / roi_width) as i32;
+            let i = y as u32 * self.width + x as u32;
 
             let color = self.pixel_at(x, y);
             let up = self.pixel_at(x, y - 1);
@@ -310,7 +695,9 @@ impl BuilderImpl {
                 self.is_same(color, left) &&
                 self.is_same(color, up))
             {
-                if self.get_cluster(cluster_left).area() <= self.get_cluster(cluster_up).area() {
+                if self.skip_stage1_merge {
+                    self.labels.union(&cluster_left, &cluster_up);
+                } else if self.get_cluster(cluster_left).area() <= self.get_cluster(cluster_up).area() {
                     self.combine_clusters(cluster_left, cluster_up);
                     if cluster_left.0 == self.next_index.0 - 1
                         && self.next_index.0 as usize == self.clusters.len()
@@ -328,10 +715,14 @@ impl BuilderImpl {
             let c = color.unwrap();
 
             if has_key && c == key {
+                self.keyed_pixels += 1;
                 match keying_action {
                     KeyingAction::Keep => self.get_cluster_mut(ZERO).add(i, &c, x, y),
                     KeyingAction::Discard => {},
                 }
+            } else if let Some(hooked) = self.hooked_cluster(x, y, [cluster_up, cluster_left, cluster_upleft]) {
+                self.cluster_indices[i as usize] = hooked;
+                self.get_cluster_mut(hooked).add(i, &c, x, y);
             } else if self.is_same(color, up) && self.is_same(color, upleft) {
                 self.cluster_indices[i as usize] = cluster_up;
                 self.get_cluster_mut(cluster_up).add(i, &c, x, y);
@@ -342,7 +733,7 @@ impl BuilderImpl {
                 self.cluster_indices[i as usize] = cluster_upleft;
                 self.get_cluster_mut(cluster_upleft).add(i, &c, x, y);
             } else {
-                let mut new_cluster = Cluster::new();
+                let mut new_cluster = self.new_cluster();
                 new_cluster.add(i, &c, x, y);
                 if (self.next_index.0 as usize) < self.clusters.len() {
                     self.clusters[self.next_index.0 as usize] = new_cluster;
@@ -350,12 +741,18 @@ impl BuilderImpl {
                     self.clusters.push(new_cluster);
                 }
                 self.cluster_indices[i as usize] = self.next_index;
+                if self.skip_stage1_merge {
+                    self.labels.make_set(self.next_index);
+                }
                 self.next_index.0 += 1;
             }
         }
 
         self.iteration += batch_size;
-        if self.iteration as usize >= self.cluster_indices.len() {
+        if self.iteration >= len {
+            if self.skip_stage1_merge {
+                self.combine_labels();
+            }
             self.prepare_stage_2();
             true
         } else {
@@ -363,6 +760,57 @@ impl BuilderImpl {
         }
     }
 
+    /// The cluster `connectivity_hook` joins pixel (x, y) to, if any, given the clusters above, left and above left
+    fn hooked_cluster(&self, x: i32, y: i32, [cluster_up, cluster_left, cluster_upleft]: [ClusterIndex; 3]) -> Option<ClusterIndex> {
+        let hook = self.connectivity_hook.as_ref()?;
+        let context = PixelContext {
+            x,
+            y,
+            color: self.pixel_at(x, y)?,
+            up: self.pixel_at(x, y - 1),
+            left: self.pixel_at(x - 1, y),
+            upleft: self.pixel_at(x - 1, y - 1),
+            up2: self.pixel_at(x, y - 2),
+            left2: self.pixel_at(x - 2, y),
+        };
+        let hooked = match hook(&context) {
+            Connectivity::Default => None,
+            Connectivity::Up => context.up.map(|_| cluster_up),
+            Connectivity::Left => context.left.map(|_| cluster_left),
+            Connectivity::UpLeft => context.upleft.map(|_| cluster_upleft),
+        };
+        // keyed neighbours are in no cluster to join
+        hooked.filter(|&index| index != ZERO)
+    }
+
+    /// Combines each set of clusters recorded in `labels` into its lowest index
+    fn combine_labels(&mut self) {
+        let indices: Vec<ClusterIndex> = (0..self.next_index.0).map(ClusterIndex).collect();
+        for group in self.labels.group_items(&indices) {
+            let to = indices[group[0]];
+            for &from in group[1..].iter() {
+                self.combine_clusters(indices[from], to);
+            }
+        }
+        self.labels = Forests::new();
+    }
+
+    /// Returns an empty cluster, reusing the buffers of a spare one if available
+    fn new_cluster(&mut self) -> Cluster {
+        match self.spare.pop() {
+            Some(mut spare) => {
+                spare.indices.clear();
+                spare.holes.clear();
+                Cluster {
+                    indices: spare.indices,
+                    holes: spare.holes,
+                    ..Cluster::default()
+                }
+            },
+            None => Cluster::new(),
+        }
+    }
+
     fn stage_1_output(&mut self) {
         let mut output = Vec::new();
         for index in 0..self.clusters.len() {
@@ -372,10 +820,100 @@ impl BuilderImpl {
                 output.push((index, area));
             }
         }
-        output.sort_by_key(|c| c.1 as u64 * 65535 + c.0.0 as u64);
+        sort_outputs(&mut output, self.compat.packed_sort_keys);
         output.iter().for_each(|c| self.clusters_output.push(c.0));
     }
 
+    /// Keeps the `max_clusters` output clusters with the largest visible area,
+    /// merging the pixels of each of the others into the output cluster it shares the longest border with.
+    /// Smaller clusters are merged first, so they may end up passing through a larger removed cluster.
+    fn keep_largest_outputs(&mut self) {
+        if self.clusters_output.len() <= self.max_clusters {
+            return;
+        }
+
+        // the topmost output claiming each pixel, as rendered by `to_color_image`
+        let mut owner = vec![ZERO; self.cluster_indices.len()];
+        let mut visible = HashMap::new();
+        for &index in self.clusters_output.iter().rev() {
+            let cluster = self.get_cluster(index);
+            let holes: HashSet<u32> = cluster.holes.iter().copied().collect();
+            for &i in cluster.indices.iter().filter(|i| !holes.contains(i)) {
+                owner[i as usize] = index;
+            }
+        }
+        for &index in owner.iter().filter(|&&o| o != ZERO) {
+            *visible.entry(index).or_insert(0) += 1;
+        }
+
+        let mut ranked = self.clusters_output.clone();
+        ranked.sort_by_key(|index| std::cmp::Reverse(visible.get(index).copied().unwrap_or(0)));
+        let mut alive: HashSet<ClusterIndex> = ranked.iter().copied().collect();
+        let mut merged_into = HashMap::new();
+        let (width, height) = (self.width as i32, self.height as i32);
+
+        for &from in ranked[self.max_clusters..].iter().rev() {
+            alive.remove(&from);
+            let pixels: Vec<u32> = self.get_cluster(from).indices.iter()
+                .copied()
+                .filter(|&i| owner[i as usize] == from)
+                .collect();
+
+            let mut borders: HashMap<ClusterIndex, usize> = HashMap::new();
+            for &i in pixels.iter() {
+                let (x, y) = ((i % self.width) as i32, (i / self.width) as i32);
+                for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+                    let other = owner[(ny * width + nx) as usize];
+                    if alive.contains(&other) {
+                        *borders.entry(other).or_insert(0) += 1;
+                    }
+                }
+            }
+            let to = borders
+                .into_iter()
+                .max_by_key(|&(index, count)| (count, std::cmp::Reverse(index)))
+                .map(|(index, _)| index)
+                .or_else(|| ranked.first().copied().filter(|_| self.max_clusters > 0));
+            let to = match to {
+                Some(to) => to,
+                None => continue,
+            };
+            merged_into.insert(from, to);
+
+            let transferred: HashSet<u32> = pixels.iter().copied().collect();
+            let num_holes = self.get_cluster(to).holes.len();
+            self.get_cluster_mut(to).holes.retain(|i| !transferred.contains(i));
+            let refilled = num_holes != self.get_cluster(to).holes.len();
+            // refilled holes, or pixels `from` covered after being deepened from `to`
+            let existing: HashSet<u32> = self.get_cluster(to).indices.iter().copied().collect();
+            for &i in pixels.iter() {
+                owner[i as usize] = to;
+                self.cluster_indices[i as usize] = to;
+                if !existing.contains(&i) {
+                    let color = self.get_pixel(i).unwrap_or_default();
+                    let (x, y) = ((i % self.width) as i32, (i / self.width) as i32);
+                    self.get_cluster_mut(to).add(i, &color, x, y);
+                }
+            }
+            if refilled {
+                let cluster = self.get_cluster_mut(to);
+                cluster.num_holes = cluster.num_holes.saturating_sub(1);
+            }
+        }
+
+        // the remaining pixels of a removed cluster, e.g. those hidden under another output,
+        // go to the cluster it merged into, or the one that merged into in turn
+        for index in self.cluster_indices.iter_mut() {
+            while let Some(&to) = merged_into.get(index) {
+                *index = to;
+            }
+        }
+        self.clusters_output.retain(|index| alive.contains(index));
+    }
+
     fn prepare_stage_2(&mut self) {
         for c in self.clusters.iter_mut() {
             c.residue_sum = c.sum;
@@ -403,6 +941,9 @@ impl BuilderImpl {
     }
 
     fn stage_2(&mut self) -> bool {
+        if self.cluster_areas.is_empty() {
+            return true;
+        }
         if self.cluster_areas[self.iteration as usize].count == 0 {
             self.iteration += 1;
             if self.iteration as usize == self.cluster_areas.len() {
@@ -432,9 +973,10 @@ impl BuilderImpl {
             let mut infos: Vec<_> = mycluster
                 .neighbours_internal(self)
                 .iter()
-                .map(|other| NeighbourInfo {
-                    index: *other,
-                    diff: (self.diff)(mycolor, self.get_cluster(*other).color()),
+                .map(|&(other, shared_boundary)| NeighbourInfo {
+                    index: other,
+                    diff: self.color_diff(mycolor, self.get_cluster(other).color()),
+                    shared_boundary,
                 })
                 .collect();
 
@@ -446,7 +988,11 @@ impl BuilderImpl {
                 continue;
             }
 
-            infos.sort_by_key(|info| info.diff as i64 * 65535 + info.index.0 as i64);
+            if self.boundary_weight > 0.0 {
+                sort_neighbours_by_boundary(&mut infos, self.boundary_weight);
+            } else {
+                sort_neighbours(&mut infos, self.compat.packed_sort_keys);
+            }
 
             let target = infos[0].index;
 
@@ -494,6 +1040,7 @@ impl BuilderImpl {
             let residue_sum = self.clusters[from.0 as usize].residue_sum;
             self.clusters[to.0 as usize].residue_sum.merge(&residue_sum);
             self.combine_clusters(from, to);
+            self.clusters[from.0 as usize].merged_into = to;
         } else {
             self.combine_clusters_clone(from, to);
 
@@ -504,6 +1051,7 @@ impl BuilderImpl {
             }
 
             self.clusters[from.0 as usize].merged_into = to;
+            self.clusters[from.0 as usize].deepened_into = Some(to);
             self.clusters[to.0 as usize].depth += 1;
         }
     }
@@ -535,16 +1083,36 @@ impl BuilderImpl {
         self.clusters[from.0 as usize].rect.clear();
     }
 
+    fn compares_alpha(&self) -> bool {
+        !self.ignore_alpha && self.compat.compare_alpha
+    }
+
+    // unless ignored, alpha is compared on its own, within `alpha_tolerance`
     fn is_same(&self, left: Option<Color>, right: Option<Color>) -> bool {
         if let (Some(l), Some(r)) = (left, right) {
-            (self.same)(l, r)
+            (self.same)(l, r) && (!self.compares_alpha() || l.a.abs_diff(r.a) <= self.alpha_tolerance)
         } else {
             false
         }
     }
 
+    // unless ignored, a difference in alpha counts as much as the same difference in color
+    fn color_diff(&self, left: Color, right: Color) -> i32 {
+        let diff = (self.diff)(left, right);
+        if self.compares_alpha() {
+            std::cmp::max(diff, left.a.abs_diff(right.a) as i32)
+        } else {
+            diff
+        }
+    }
+
+    /// Number of pixels to be clustered
+    fn roi_area(&self) -> u32 {
+        (self.roi.width() * self.roi.height()) as u32
+    }
+
     fn pixel_at(&self, x: i32, y: i32) -> Option<Color> {
-        if x < 0 || y < 0 {
+        if x < self.roi.left || y < self.roi.top || x >= self.roi.right || y >= self.roi.bottom {
             return None;
         }
 
@@ -565,3 +1133,340 @@ impl BuilderImpl {
         }
     }
 }
+
+/// Orders (index, area) pairs by ascending area, then index; or as 0.8 did if `packed`, see `CompatFlags::packed_sort_keys`
+fn sort_outputs(output: &mut [(ClusterIndex, usize)], packed: bool) {
+    if packed {
+        output.sort_by_key(|&(index, area)| area as u64 * 65535 + index.0 as u64);
+    } else {
+        output.sort_by_key(|&(index, area)| (area, index));
+    }
+}
+
+/// Orders neighbours by ascending diff, then index; or as 0.8 did if `packed`
+fn sort_neighbours(infos: &mut [NeighbourInfo], packed: bool) {
+    if packed {
+        infos.sort_by_key(|info| info.diff as i64 * 65535 + info.index.0 as i64);
+    } else {
+        infos.sort_by_key(|info| (info.diff, info.index));
+    }
+}
+
+/// Orders by the diff scaled by the fraction of the boundary not shared, see `NeighbourInfo::shared_boundary`
+fn sort_neighbours_by_boundary(infos: &mut [NeighbourInfo], weight: f64) {
+    let total: u32 = infos.iter().map(|info| info.shared_boundary).sum();
+    let key = |info: &NeighbourInfo| {
+        let fraction = info.shared_boundary as f64 / total.max(1) as f64;
+        info.diff as f64 * (1.0 + weight * (1.0 - fraction))
+    };
+    infos.sort_by(|a, b| key(a).total_cmp(&key(b)).then((a.diff, a.index).cmp(&(b.diff, b.index))));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::{PathSimplifyMode, PointF64};
+    use crate::color_clusters::{Runner, RunnerConfig};
+
+    #[test]
+    fn sort_keys_beyond_u16() {
+        // packed as diff * 65535 + index, (1, 0) and (0, 65535) would collide and (0, 65536) would sort after (1, 0)
+        let mut infos: Vec<NeighbourInfo> = (0..70000)
+            .map(|i| NeighbourInfo { index: ClusterIndex(i), diff: [i32::MAX, 1, 0][i as usize % 3], shared_boundary: 0 })
+            .collect();
+        infos.reverse();
+        sort_neighbours(&mut infos, false);
+        assert!(infos.windows(2).all(|pair| (pair[0].diff, pair[0].index) < (pair[1].diff, pair[1].index)));
+        assert_eq!(infos[0].index, ClusterIndex(2));
+        assert_eq!(infos.last().unwrap().index, ClusterIndex(69999));
+
+        let mut output: Vec<(ClusterIndex, usize)> = (0..70000)
+            .map(|i| (ClusterIndex(i), if i >= 65535 { 1 } else { 2 }))
+            .collect();
+        sort_outputs(&mut output, false);
+        assert_eq!(output[0], (ClusterIndex(65535), 1));
+        assert_eq!(output[4464], (ClusterIndex(69999), 1));
+        assert_eq!(output[4465], (ClusterIndex(0), 2));
+        assert!(output.windows(2).all(|pair| (pair[0].1, pair[0].0) < (pair[1].1, pair[1].0)));
+    }
+
+    #[test]
+    fn same_by_hsv_hue_wraparound() {
+        let same = same_by_hsv(0.05, 0.1, 0.1);
+        // hues of about 0.99 and 0.01
+        let (a, b) = (Color::new(204, 41, 51), Color::new(204, 51, 41));
+        assert!(a.to_hsv().h > 0.98 && b.to_hsv().h < 0.02);
+        assert!(same(a, b) && same(b, a));
+        assert!(!same(a, Color::new(41, 204, 51)));
+        assert!(!same(b, Color::new(102, 25, 20)));
+
+        // a checkerboard of the two reds beside a green block
+        let mut image = ColorImage::new_w_h(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                let color = if x >= 4 { Color::new(41, 204, 51) } else if (x + y) % 2 == 0 { a } else { b };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let clusters = Builder::new()
+            .from(image)
+            .hierarchical(0)
+            .same(same_by_hsv(0.05, 0.1, 0.1))
+            .diff(|_, _| 0)
+            .deepen(|_, _, _| false)
+            .hollow(|_, _, _| false)
+            .run();
+        // both reds in one cluster (the corner pixels start their own, having nothing above)
+        let reds = clusters.iter_output().map(|(_, cluster)| cluster).max_by_key(|cluster| (cluster.area(), -cluster.rect.left)).unwrap();
+        assert_eq!((reds.rect, reds.area()), (BoundingRect::new_x_y_w_h(0, 0, 4, 4), 15));
+    }
+
+    #[test]
+    fn diff_by_hsv_weights() {
+        let diff = diff_by_hsv([1.0, 0.0, 0.0]);
+        let (red, green) = (Color::new(255, 0, 0), Color::new(0, 255, 0));
+        // a third of the way around the wheel
+        assert_eq!(diff(red, green), 170);
+        assert_eq!(diff(green, red), 170);
+        assert_eq!(diff(Color::new(204, 41, 51), Color::new(204, 51, 41)), diff(Color::new(204, 51, 41), Color::new(204, 41, 51)));
+        assert!(diff(Color::new(204, 41, 51), Color::new(204, 51, 41)) < 20);
+        assert_eq!(diff_by_hsv([0.0, 0.0, 1.0])(red, Color::new(0, 0, 0)), 255);
+        assert_eq!(diff_by_hsv([0.0, 0.0, 1.0])(red, green), 0);
+    }
+
+    #[test]
+    fn boundary_weight_prefers_long_side() {
+        // A: 8x4 block; a 1px sliver below it, touching B (2x5, slightly closer in color) at its last pixel
+        let (a, sliver, b) = (Color::new(100, 0, 0), Color::new(120, 0, 0), Color::new(125, 0, 0));
+        let mut image = ColorImage::new_w_h(10, 5);
+        for y in 0..5 {
+            for x in 0..10 {
+                let color = if x >= 8 { b } else if y == 4 { sliver } else { a };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let areas = |weight: f64| {
+            let clusters = Builder::new()
+                .from(image.clone())
+                .diagonal(false)
+                .hierarchical(9)
+                .boundary_weight(weight)
+                .same(|a, b| a == b)
+                .diff(|a, b| (a.r as i32 - b.r as i32).abs())
+                .deepen(|_, _, _| false)
+                .hollow(|_, _, _| false)
+                .run();
+            let mut areas: Vec<usize> = clusters.iter_output().map(|(_, cluster)| cluster.area()).collect();
+            areas.sort();
+            areas
+        };
+        assert_eq!(areas(0.0), vec![18, 32]);
+        assert_eq!(areas(10.0), vec![10, 40]);
+    }
+
+    #[test]
+    fn compact_clusters() {
+        let mut image = ColorImage::new_w_h(48, 40);
+        let mut seed = 777u32;
+        for y in 0..40 {
+            for x in 0..48 {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = (seed >> 16) as i32 % 24;
+                let channel = |base: i32| (base + noise).clamp(0, 255) as u8;
+                image.set_pixel(x, y, &Color::new(channel(x as i32 * 5), channel(y as i32 * 6), channel(((x + y) % 7) as i32 * 30)));
+            }
+        }
+        let builder = || Runner::new(RunnerConfig {
+            good_min_area: 4,
+            deepen_diff: 16,
+            ..RunnerConfig::default()
+        }, image.clone()).builder();
+        let run = || {
+            let mut bimpl = BuilderImpl::from(builder());
+            while !bimpl.tick() {}
+            bimpl.try_result().unwrap()
+        };
+        let (mut compacted, uncompacted) = (run(), run());
+        let remap = compacted.compact();
+        assert_eq!(remap.len(), uncompacted.clusters.len());
+        for (old, new) in uncompacted.clusters_output.iter().zip(compacted.clusters_output.iter()) {
+            assert_eq!(remap[old.0 as usize], Some(*new));
+        }
+
+        assert_eq!(compacted.view().to_color_image().pixels, uncompacted.view().to_color_image().pixels);
+        // pixels and color of each output cluster, in render order
+        let outputs = |clusters: &Clusters| clusters.output_in_render_order().iter().map(|&index| {
+            let cluster = &clusters.clusters[index.0 as usize];
+            (cluster.indices.clone(), cluster.holes.clone(), cluster.residue_color())
+        }).collect::<Vec<_>>();
+        assert_eq!(outputs(&compacted), outputs(&uncompacted));
+        for (a, b) in compacted.iter_output().zip(uncompacted.iter_output()) {
+            assert_eq!(
+                a.1.to_compound_path(&compacted.view(), false, PathSimplifyMode::Polygon, 0.0, 0.0, 0, 0.0).to_svg_string(true, PointF64::default(), None),
+                b.1.to_compound_path(&uncompacted.view(), false, PathSimplifyMode::Polygon, 0.0, 0.0, 0, 0.0).to_svg_string(true, PointF64::default(), None),
+            );
+        }
+
+        // every cluster but ZERO has pixels, is output, or is referenced by another
+        let referenced: HashSet<ClusterIndex> = compacted.clusters.iter()
+            .flat_map(|cluster| std::iter::once(cluster.merged_into).chain(cluster.deepened_into))
+            .collect();
+        let live = compacted.clusters.iter().enumerate().skip(1).filter(|&(i, cluster)| {
+            let index = ClusterIndex(i as ClusterIndexElem);
+            cluster.area() > 0 || compacted.clusters_output.contains(&index) || referenced.contains(&index)
+        }).count();
+        assert_eq!(compacted.clusters.len(), live + 1);
+        assert!(compacted.clusters.len() < uncompacted.clusters.len());
+        assert!(compacted.memory_footprint() < uncompacted.memory_footprint());
+    }
+
+    #[test]
+    fn connectivity_hook_bridges_gap() {
+        let (red, gray) = (Color::new(255, 0, 0), Color::new(128, 128, 128));
+        let mut image = ColorImage::new_w_h(7, 4);
+        for y in 0..4 {
+            for x in 0..7 {
+                image.set_pixel(x, y, if x == 3 { &gray } else { &red });
+            }
+        }
+        // whether the leftmost and rightmost pixels of the third row end up in the same cluster
+        let connected = |hook: bool| {
+            let mut builder = Builder::new()
+                .from(image.clone())
+                .hierarchical(0)
+                .same(|a, b| a == b)
+                .diff(|_, _| 0)
+                .deepen(|_, _, _| false)
+                .hollow(|_, _, _| false);
+            if hook {
+                builder = builder.connectivity_hook(move |p: &PixelContext| {
+                    let red_or_gray = |c: Option<Color>| c == Some(red) || c == Some(gray);
+                    if red_or_gray(Some(p.color)) && red_or_gray(p.left) && p.left2 == Some(red) && p.left != Some(p.color) {
+                        Connectivity::Left
+                    } else {
+                        Connectivity::Default
+                    }
+                });
+            }
+            let clusters = builder.run();
+            let cluster_of = |i: u32| clusters.iter_output().find(|(_, cluster)| cluster.iter().any(|&j| j == i)).unwrap().0;
+            cluster_of(14) == cluster_of(20)
+        };
+        assert!(connected(true));
+        assert!(!connected(false));
+    }
+
+    #[test]
+    fn max_clusters_removes_root() {
+        // the root shows fewer pixels of its own than the blue cluster deepened into it
+        let (root, islands, big) = (Color::new(255, 0, 0), Color::new(250, 0, 0), Color::new(0, 0, 255));
+        let mut image = ColorImage::new_w_h(25, 10);
+        for y in 0..10 {
+            for x in 0..25 {
+                let island = [1, 5, 9].iter().any(|&left| (left..left + 4).contains(&x)) && (2..7).contains(&y);
+                image.set_pixel(x, y, if x >= 14 { &big } else if island { &islands } else { &root });
+            }
+        }
+        let run = |max_clusters: usize| Builder::new()
+            .from(image.clone())
+            .max_clusters(max_clusters)
+            .same(|a, b| a == b)
+            .diff(|a, b| (a.r as i32 - b.r as i32).abs() + (a.b as i32 - b.b as i32).abs())
+            .deepen(|_, _, _| true)
+            .hollow(|_, _, _| false)
+            .run();
+        let all = run(usize::MAX);
+        assert!(all.output_len() > 1);
+        let limited = run(1);
+        assert_eq!(limited.output_len(), 1);
+        let kept = limited.clusters_output[0];
+        // every pixel pointed to the removed root, and none is counted twice
+        assert_eq!(limited.view().get_cluster(kept).area(), 250);
+        assert!(limited.cluster_indices.iter().all(|&index| index == kept));
+    }
+
+    #[test]
+    fn connectivity_hook_keyed_neighbour() {
+        let (key, red) = (Color::new(0, 255, 0), Color::new(255, 0, 0));
+        let mut image = ColorImage::new_w_h(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                image.set_pixel(x, y, if x == 0 { &key } else { &red });
+            }
+        }
+        let clusters = Builder::new()
+            .from(image)
+            .hierarchical(0)
+            .key(key)
+            .keying_action(KeyingAction::Keep)
+            .same(|a, b| a == b)
+            .diff(|_, _| 0)
+            .deepen(|_, _, _| false)
+            .hollow(|_, _, _| false)
+            .connectivity_hook(|_: &PixelContext| Connectivity::Left)
+            .run();
+        for i in 0..6 {
+            assert_eq!(clusters.cluster_indices[i] == ZERO, i % 3 == 0, "{}", i);
+        }
+    }
+
+    #[test]
+    fn skip_stage1_merge_same_clusters() {
+        // pixel indices of each output cluster, in a canonical order
+        let partition = |image: &ColorImage, diagonal: bool, key: Color, skip: bool| {
+            let clusters = Builder::new()
+                .from(image.clone())
+                .diagonal(diagonal)
+                .hierarchical(0)
+                .key(key)
+                .batch_size(7)
+                .skip_stage1_merge(skip)
+                .same(|a, b| a == b)
+                .diff(|_, _| 0)
+                .deepen(|_, _, _| false)
+                .hollow(|_, _, _| false)
+                .run();
+            let mut sets: Vec<Vec<u32>> = clusters.iter_output().map(|(_, cluster)| {
+                let mut indices: Vec<u32> = cluster.iter().copied().collect();
+                indices.sort();
+                indices
+            }).collect();
+            sets.sort();
+            sets
+        };
+
+        let palette = [Color::new(255, 0, 0), Color::new(0, 255, 0), Color::new(0, 0, 255)];
+        let mut seed = 12345u32;
+        let mut images = Vec::new();
+        for &(width, height, colors) in [(16, 12, 2), (23, 9, 3), (7, 31, 2)].iter() {
+            let mut image = ColorImage::new_w_h(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    image.set_pixel(x, y, &palette[(seed >> 16) as usize % colors]);
+                }
+            }
+            images.push(image);
+        }
+        // a comb, whose teeth start as separate labels and meet only at the bottom
+        let mut comb = ColorImage::new_w_h(12, 8);
+        for y in 0..8 {
+            for x in 0..12 {
+                let teeth = x % 2 == 0 || y == 7;
+                comb.set_pixel(x, y, &palette[if teeth { 0 } else { 1 }]);
+            }
+        }
+        images.push(comb);
+
+        for image in images.iter() {
+            for &diagonal in [false, true].iter() {
+                for &key in [Color::default(), palette[1]].iter() {
+                    let expected = partition(image, diagonal, key, false);
+                    assert!(expected.len() > 1);
+                    assert_eq!(partition(image, diagonal, key, true), expected);
+                }
+            }
+        }
+    }
+}
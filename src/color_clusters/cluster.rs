@@ -1,5 +1,5 @@
-use std::collections::HashSet;
-use crate::{BinaryImage, BoundingRect, Color, ColorImage, ColorSum, CompoundPath, PointI32, PathSimplifyMode, Shape};
+use std::collections::HashMap;
+use crate::{BinaryImage, BoundingRect, Color, ColorImage, ColorSum, CompoundPath, Field, PointI32, PathSimplifyMode, Shape};
 use crate::clusters::Cluster as BinaryCluster;
 use super::container::{ClusterIndex, ClustersView};
 use super::builder::{BuilderImpl, ZERO};
@@ -14,6 +14,7 @@ pub struct Cluster {
     pub residue_sum: ColorSum,
     pub rect: BoundingRect,
     pub merged_into: ClusterIndex,
+    pub(crate) deepened_into: Option<ClusterIndex>,
 }
 
 impl Cluster {
@@ -21,6 +22,11 @@ impl Cluster {
         Self::default()
     }
 
+    /// The cluster this one was deepened into by `Builder::deepen`, if any
+    pub fn deepened_into(&self) -> Option<ClusterIndex> {
+        self.deepened_into
+    }
+
     pub fn add(&mut self, i: u32, color: &Color, x: i32, y: i32) {
         self.indices.push(i);
         self.sum.add(color);
@@ -53,6 +59,16 @@ impl Cluster {
     pub fn to_image(&self, parent: &ClustersView) -> BinaryImage {
         self.to_image_with_hole(parent.width, true)
     }
+    /// Like `to_image`, but on an image the size of the parent, with the cluster at its original position
+    pub fn to_full_frame_image(&self, parent: &ClustersView) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(parent.width as usize, parent.height as usize);
+        self.render_to_binary_image(parent, &mut image);
+        for &i in self.holes.iter() {
+            image.set_pixel_index(i as usize, false);
+        }
+        image
+    }
+
     fn to_image_internal(&self, internal: &BuilderImpl) -> BinaryImage {
         self.to_image_with_hole(internal.width, true)
     }
@@ -100,6 +116,55 @@ impl Cluster {
         }
     }
 
+    /// Returns the fraction of each pixel of the parent image covered by this cluster (holes excluded).
+    ///
+    /// The cluster mask is averaged onto pixel corners and contoured at 0.5 by marching squares;
+    /// the coverage of a pixel is the area of its cell enclosed by the contour.
+    /// Pixels along horizontal and vertical edges stay fully in or out, while diagonal edges become fractional.
+    pub fn coverage(&self, parent: &ClustersView) -> Field<f32> {
+        let (width, height) = (parent.width as i32, parent.height as i32);
+        let mut field = Field::with_initial(width as usize, height as usize, 0.0);
+        if !self.rect.is_valid() {
+            return field;
+        }
+
+        let mask = self.to_image_with_hole(parent.width, true);
+        let inside = |x: i32, y: i32| -> f32 {
+            let (x, y) = (x - self.rect.left, y - self.rect.top);
+            if mask.get_pixel_safe(x, y) { 1.0 } else { 0.0 }
+        };
+        let corner = |x: i32, y: i32| -> f32 {
+            (inside(x - 1, y - 1) + inside(x, y - 1) + inside(x - 1, y) + inside(x, y)) / 4.0
+        };
+
+        for y in std::cmp::max(self.rect.top - 1, 0)..std::cmp::min(self.rect.bottom + 1, height) {
+            for x in std::cmp::max(self.rect.left - 1, 0)..std::cmp::min(self.rect.right + 1, width) {
+                let value = cell_coverage([corner(x, y), corner(x + 1, y), corner(x + 1, y + 1), corner(x, y + 1)]);
+                field.set(field.index_at(x as usize, y as usize), &value);
+            }
+        }
+        field
+    }
+
+    /// Blends the residue color into `image`, weighting each pixel by `coverage` (see `Cluster::coverage`)
+    pub fn render_antialiased(&self, image: &mut ColorImage, coverage: &Field<f32>) {
+        let color = self.residue_color();
+        let blend = |src: u8, dst: u8, t: f32| (src as f32 * t + dst as f32 * (1.0 - t)).round() as u8;
+        for i in 0..std::cmp::min(coverage.width() * coverage.height(), image.width * image.height) {
+            let t = coverage.get(i).unwrap_or(0.0).clamp(0.0, 1.0);
+            if t <= 0.0 {
+                continue;
+            }
+            let dst = image.get_pixel_at(i);
+            image.set_pixel_at(i, &Color::new_rgba(
+                blend(color.r, dst.r, t),
+                blend(color.g, dst.g, t),
+                blend(color.b, dst.b, t),
+                blend(color.a, dst.a, t),
+            ));
+        }
+    }
+
     pub fn to_shape(&self, parent: &ClustersView) -> Shape {
         self.to_image(parent).into()
     }
@@ -115,6 +180,7 @@ impl Cluster {
         splice_threshold: f64
     ) -> CompoundPath {
         let mut paths = CompoundPath::new();
+        // split into 4-connected parts even in diagonal mode, as the path walker cannot trace diagonal connections
         for cluster in self.to_image_with_hole(parent.width, hole).to_clusters(false).iter() {
             paths.append(
                 BinaryCluster::image_to_compound_path(&PointI32 {
@@ -127,59 +193,124 @@ impl Cluster {
         paths
     }
 
-    pub fn neighbours(&self, parent: &ClustersView) -> Vec<ClusterIndex> {
-        let myself = parent.get_cluster_at(*self.indices.first().unwrap());
-        let mut neighbours = HashSet::new();
-
-        for &i in self.iter() {
-            let x = i % parent.width;
-            let y = i / parent.width;
+    /// Decomposes the pixels of this cluster (holes excluded) into non-overlapping axis-aligned rects
+    /// covering each pixel exactly once, in parent coordinates, e.g. for crisp pixel art export.
+    ///
+    /// Each row is split into runs, and a run extends the rect above it if that rect spans the same columns.
+    /// The rects are ordered by top, then left.
+    pub fn to_rects(&self, parent: &ClustersView) -> Vec<BoundingRect> {
+        let image = self.to_image(parent);
+        let mut rects = Vec::new();
+        // rects still open at the previous row, ordered by left
+        let mut open: Vec<BoundingRect> = Vec::new();
+        for y in 0..image.height {
+            let mut runs = Vec::new();
+            let mut x = 0;
+            while x < image.width {
+                if !image.get_pixel(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < image.width && image.get_pixel(x, y) {
+                    x += 1;
+                }
+                runs.push((start as i32, x as i32));
+            }
 
-            for k in 0..4 {
-                let index = match k {
-                    0 => if y > 0 { parent.cluster_indices[(parent.width * (y - 1) + x) as usize] } else { ZERO },
-                    1 => if y < parent.height - 1 { parent.cluster_indices[(parent.width * (y + 1) + x) as usize] } else { ZERO },
-                    2 => if x > 0 { parent.cluster_indices[(parent.width * y + (x - 1)) as usize] } else { ZERO },
-                    3 => if x < parent.width - 1 { parent.cluster_indices[(parent.width * y + (x + 1)) as usize] } else { ZERO },
-                    _ => unreachable!(),
-                };
-                if index != ZERO && index != myself {
-                    neighbours.insert(index);
+            let mut next = Vec::with_capacity(runs.len());
+            let mut previous = open.into_iter().peekable();
+            for (left, right) in runs {
+                while let Some(rect) = previous.next_if(|rect| rect.left < left || (rect.left == left && rect.right != right)) {
+                    rects.push(rect);
                 }
+                let mut rect = match previous.next_if(|rect| rect.left == left && rect.right == right) {
+                    Some(rect) => rect,
+                    None => BoundingRect { left, top: y as i32, right, bottom: y as i32 },
+                };
+                rect.bottom += 1;
+                next.push(rect);
             }
+            rects.extend(previous);
+            open = next;
         }
+        rects.extend(open);
 
-        let mut list: Vec<ClusterIndex> = neighbours.into_iter().collect();
-        list.sort();
-        list
+        for rect in rects.iter_mut() {
+            rect.translate(self.rect.left_top());
+        }
+        rects.sort_by_key(|rect| (rect.top, rect.left));
+        rects
+    }
+
+    /// Returns the clusters adjacent to this one, including diagonally adjacent ones
+    /// if the clusters were built with `diagonal`
+    pub fn neighbours(&self, parent: &ClustersView) -> Vec<ClusterIndex> {
+        self.neighbours_in(parent.cluster_indices, parent.width, parent.height, parent.diagonal)
+            .into_iter().map(|(index, _)| index).collect()
     }
 
     /// Equivalent to [`neighbours()`] but operates on `BuilderImpl` directly, 
-    /// removing the overhead of constructing a `ClustersView`
-    pub(crate) fn neighbours_internal(&self, internal: &BuilderImpl) -> Vec<ClusterIndex> {
-        let myself = internal.cluster_indices[*self.indices.first().unwrap() as usize];
-        let mut neighbours = HashSet::new();
+    /// removing the overhead of constructing a `ClustersView`;
+    /// also returns the number of adjacent pixel pairs shared with each neighbour
+    pub(crate) fn neighbours_internal(&self, internal: &BuilderImpl) -> Vec<(ClusterIndex, u32)> {
+        self.neighbours_in(&internal.cluster_indices, internal.width, internal.height, internal.diagonal)
+    }
+
+    fn neighbours_in(&self, cluster_indices: &[ClusterIndex], width: u32, height: u32, diagonal: bool) -> Vec<(ClusterIndex, u32)> {
+        const OFFSETS: [(i32, i32); 8] = [(0, -1), (0, 1), (-1, 0), (1, 0), (-1, -1), (1, -1), (-1, 1), (1, 1)];
+        let offsets = if diagonal { &OFFSETS[..] } else { &OFFSETS[..4] };
+        let myself = cluster_indices[*self.indices.first().unwrap() as usize];
+        let mut neighbours = HashMap::new();
 
         for &i in self.iter() {
-            let x = i % internal.width;
-            let y = i / internal.width;
-
-            for k in 0..4 {
-                let index = match k {
-                    0 => if y > 0 { internal.cluster_indices[(internal.width * (y - 1) + x) as usize] } else { ZERO },
-                    1 => if y < internal.height - 1 { internal.cluster_indices[(internal.width * (y + 1) + x) as usize] } else { ZERO },
-                    2 => if x > 0 { internal.cluster_indices[(internal.width * y + (x - 1)) as usize] } else { ZERO },
-                    3 => if x < internal.width - 1 { internal.cluster_indices[(internal.width * y + (x + 1)) as usize] } else { ZERO },
-                    _ => unreachable!(),
-                };
+            let x = (i % width) as i32;
+            let y = (i / width) as i32;
+
+            for &(dx, dy) in offsets {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let index = cluster_indices[(ny as u32 * width + nx as u32) as usize];
                 if index != ZERO && index != myself {
-                    neighbours.insert(index);
+                    *neighbours.entry(index).or_insert(0) += 1;
                 }
             }
         }
 
-        let mut list: Vec<ClusterIndex> = neighbours.into_iter().collect();
+        let mut list: Vec<(ClusterIndex, u32)> = neighbours.into_iter().collect();
         list.sort();
         list
     }
 }
+
+/// Area of the unit cell enclosed by the 0.5 contour, given the values at its corners
+/// in the order top-left, top-right, bottom-right, bottom-left
+fn cell_coverage(values: [f32; 4]) -> f32 {
+    const CORNERS: [(f32, f32); 4] = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+    const LEVEL: f32 = 0.5;
+
+    let mut polygon = Vec::with_capacity(8);
+    for i in 0..4 {
+        let j = (i + 1) % 4;
+        let (vi, vj) = (values[i], values[j]);
+        if vi >= LEVEL {
+            polygon.push(CORNERS[i]);
+        }
+        if (vi >= LEVEL) != (vj >= LEVEL) {
+            let t = (LEVEL - vi) / (vj - vi);
+            polygon.push((
+                CORNERS[i].0 + (CORNERS[j].0 - CORNERS[i].0) * t,
+                CORNERS[i].1 + (CORNERS[j].1 - CORNERS[i].1) * t,
+            ));
+        }
+    }
+
+    let mut area = 0.0;
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+    (area / 2.0).abs()
+}
@@ -1,29 +1,175 @@
-use crate::{Color, ColorImage, PointI32};
-use super::Cluster;
+use std::collections::{HashMap, HashSet};
+use crate::{Color, ColorImage, MonoImage, PointI32};
+use super::{BuilderWarning, Cluster, ZERO};
 
 pub struct Clusters {
     pub width: u32,
     pub height: u32,
+    pub(crate) diagonal: bool, // whether diagonally adjacent pixels are connected
     pub(crate) pixels: Vec<u8>,
     pub(crate) clusters: Vec<Cluster>,
     pub(crate) cluster_indices: Vec<ClusterIndex>,
     pub(crate) clusters_output: Vec<ClusterIndex>, // valid outputs. Valid outputs are clusters with at least one pixel.
+    pub(crate) keyed_pixels: u32,
+    pub(crate) clustered_pixels: u32, // pixels in the region of interest
+    pub(crate) warnings: Vec<BuilderWarning>,
 }
 
-#[derive(Copy, Clone, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
-pub struct ClusterIndex(pub ClusterIndexElem);
+/// Index of a cluster within `Clusters`. Index 0 is reserved (see `ZERO`): it holds the pixels
+/// that belong to no cluster, e.g. keyed pixels or those outside the region of interest.
+///
+/// The raw value is stable, so it can be stored or passed over FFI via `as_u32` and `ClusterIndex::new`;
+/// only `Clusters::compact` renumbers clusters, and it returns the mapping.
+#[derive(Copy, Clone, Debug, Default, Eq, Ord, Hash, PartialEq, PartialOrd)]
+pub struct ClusterIndex(pub(crate) ClusterIndexElem);
 
 pub type ClusterIndexElem = u32;
 
+impl ClusterIndex {
+    /// Returns `None` for the reserved index 0, use `ClusterIndex::zero` to get it explicitly
+    pub fn new(raw: ClusterIndexElem) -> Option<Self> {
+        if raw == 0 { None } else { Some(Self(raw)) }
+    }
+
+    /// The reserved index, same as `ZERO`
+    pub fn zero() -> Self {
+        ZERO
+    }
+
+    pub fn as_u32(&self) -> ClusterIndexElem {
+        self.0
+    }
+
+    pub fn is_reserved(&self) -> bool {
+        *self == ZERO
+    }
+}
+
+/// How many output clusters claim each pixel, see `Clusters::check_output_coverage`
+#[derive(Debug, Default)]
+pub struct CoverageReport {
+    pub covered_once: usize,
+    pub covered_multiple: usize,
+    pub uncovered: usize,
+    /// Pairs of output clusters claiming the same pixel, the earlier output first
+    pub offenders: Vec<(ClusterIndex, ClusterIndex)>,
+}
+
 impl Clusters {
     pub fn output_len(&self) -> usize {
         self.clusters_output.len()
     }
 
+    /// Returns the cluster, or `None` if the index is reserved or out of range
+    pub fn get_cluster_checked(&self, index: ClusterIndex) -> Option<&Cluster> {
+        if index.is_reserved() {
+            return None;
+        }
+        self.clusters.get(index.0 as usize)
+    }
+
+    /// The largest valid cluster index, or `None` if there are no clusters
+    pub fn max_index(&self) -> Option<ClusterIndex> {
+        ClusterIndex::new(self.clusters.len().saturating_sub(1) as ClusterIndexElem)
+    }
+
+    /// Number of pixels that matched the key color, whether kept or discarded
+    pub fn keyed_pixel_count(&self) -> u32 {
+        self.keyed_pixels
+    }
+
+    /// Fraction of the clustered pixels (those in the region of interest) that matched the key color
+    pub fn keyed_fraction(&self) -> f64 {
+        self.keyed_pixels as f64 / std::cmp::max(self.clustered_pixels, 1) as f64
+    }
+
+    /// Approximate number of bytes held, including the buffers of every cluster
+    pub fn memory_footprint(&self) -> usize {
+        use std::mem::size_of;
+        let clusters: usize = self.clusters.iter()
+            .map(|cluster| (cluster.indices.capacity() + cluster.holes.capacity()) * size_of::<u32>())
+            .sum();
+        size_of::<Self>() +
+            self.pixels.capacity() +
+            self.clusters.capacity() * size_of::<Cluster>() + clusters +
+            (self.cluster_indices.capacity() + self.clusters_output.capacity()) * size_of::<ClusterIndex>() +
+            self.warnings.capacity() * size_of::<BuilderWarning>()
+    }
+
+    /// Drops the slots of clusters merged away while building, which are empty and unreferenced,
+    /// renumbering the remaining clusters in their original order and shrinking all buffers to fit.
+    /// Returns the new index of each old one (by raw value), `None` for the dropped slots.
+    ///
+    /// A cluster is kept if it has pixels, is output, is `ZERO`, or is reachable through
+    /// `merged_into` or `deepened_into` from a kept cluster; so the hierarchy is unchanged.
+    /// Indices seen while building, e.g. through `IncrementalBuilder::view`, no longer match afterwards,
+    /// and the freed buffers can not be reused by `ClusterWorkspace::recycle`.
+    pub fn compact(&mut self) -> Vec<Option<ClusterIndex>> {
+        let len = self.clusters.len();
+        let mut keep = vec![false; len];
+        let mut stack: Vec<ClusterIndex> = self.clusters_output.clone();
+        stack.push(ZERO);
+        stack.extend((0..len).filter(|&i| self.clusters[i].area() > 0).map(|i| ClusterIndex(i as ClusterIndexElem)));
+        while let Some(index) = stack.pop() {
+            if std::mem::replace(&mut keep[index.0 as usize], true) {
+                continue;
+            }
+            let cluster = &self.clusters[index.0 as usize];
+            stack.push(cluster.merged_into);
+            stack.extend(cluster.deepened_into);
+        }
+
+        let mut remap = vec![None; len];
+        let mut next = 0;
+        for (new, &kept) in remap.iter_mut().zip(keep.iter()) {
+            if kept {
+                *new = Some(ClusterIndex(next));
+                next += 1;
+            }
+        }
+        // every index still referenced is kept
+        let new = |index: ClusterIndex| remap[index.0 as usize].unwrap_or(ZERO);
+        let clusters = std::mem::take(&mut self.clusters);
+        self.clusters = clusters.into_iter().zip(keep).filter_map(|(mut cluster, kept)| {
+            if !kept {
+                return None;
+            }
+            cluster.merged_into = new(cluster.merged_into);
+            cluster.deepened_into = cluster.deepened_into.map(new);
+            cluster.indices.shrink_to_fit();
+            cluster.holes.shrink_to_fit();
+            Some(cluster)
+        }).collect();
+        for index in self.cluster_indices.iter_mut().chain(self.clusters_output.iter_mut()) {
+            *index = new(*index);
+        }
+        self.pixels.shrink_to_fit();
+        self.cluster_indices.shrink_to_fit();
+        self.clusters_output.shrink_to_fit();
+        self.warnings.shrink_to_fit();
+        remap
+    }
+
+    /// Problems found while clustering, e.g. `BuilderWarning::KeyedFractionExceeded`
+    pub fn warnings(&self) -> &[BuilderWarning] {
+        &self.warnings
+    }
+
+    /// Iterates over the indices of the output clusters, in output order
+    pub fn output_indices(&self) -> impl Iterator<Item = ClusterIndex> + '_ {
+        self.clusters_output.iter().copied()
+    }
+
+    /// Iterates over the output clusters with their indices, in output order
+    pub fn iter_output(&self) -> impl Iterator<Item = (ClusterIndex, &Cluster)> + '_ {
+        self.clusters_output.iter().map(|&index| (index, &self.clusters[index.0 as usize]))
+    }
+
     pub fn view(&self) -> ClustersView {
         ClustersView {
             width: self.width,
             height: self.height,
+            diagonal: self.diagonal,
             pixels: &self.pixels,
             clusters: &self.clusters,
             cluster_indices: &self.cluster_indices,
@@ -31,6 +177,120 @@ impl Clusters {
         }
     }
 
+    /// Counts, per pixel, how many output clusters claim it after subtracting their holes.
+    ///
+    /// When a deepened cluster is also hollowed, its pixels are punched out of its parent,
+    /// so every pixel is claimed exactly once. Deepen without hollow is a stacking contract:
+    /// the child is output on top of its parent, which still claims the child's pixels.
+    /// Such pixels are reported as `covered_multiple`, which is fine for opaque layered
+    /// rendering but shows up as duplicates once the output is translucent.
+    pub fn check_output_coverage(&self) -> CoverageReport {
+        let mut claimant = vec![ZERO; self.cluster_indices.len()];
+        let mut counts = vec![0u32; self.cluster_indices.len()];
+        let mut offenders = Vec::new();
+        let mut seen = HashSet::new();
+
+        for &index in self.clusters_output.iter() {
+            let cluster = &self.clusters[index.0 as usize];
+            let holes: HashSet<u32> = cluster.holes.iter().copied().collect();
+            for &i in cluster.indices.iter().filter(|i| !holes.contains(i)) {
+                let i = i as usize;
+                if counts[i] > 0 && seen.insert((claimant[i], index)) {
+                    offenders.push((claimant[i], index));
+                }
+                claimant[i] = index;
+                counts[i] += 1;
+            }
+        }
+
+        let mut report = CoverageReport { offenders, ..Default::default() };
+        for count in counts {
+            match count {
+                0 => report.uncovered += 1,
+                1 => report.covered_once += 1,
+                _ => report.covered_multiple += 1,
+            }
+        }
+        report
+    }
+
+    /// Returns an image where each pixel holds 1 + the ordinal (in output order) of the output
+    /// cluster it belongs to, or 0 if no output cluster claims it. Holes are respected, and where
+    /// outputs are stacked the topmost one (as in `ClustersView::to_color_image`) wins.
+    /// Returns `None` if there are more than `u16::MAX` output clusters, which the labels can not tell apart.
+    pub fn label_image(&self) -> Option<MonoImage> {
+        if self.clusters_output.len() > u16::MAX as usize {
+            return None;
+        }
+        let mut image = MonoImage::new_w_h(self.width as usize, self.height as usize);
+        for (ordinal, &index) in self.clusters_output.iter().enumerate().rev() {
+            let label = (ordinal + 1) as u16;
+            let cluster = &self.clusters[index.0 as usize];
+            let holes: HashSet<u32> = cluster.holes.iter().copied().collect();
+            for &i in cluster.indices.iter().filter(|i| !holes.contains(i)) {
+                image.set_pixel(i as usize % image.width(), i as usize / image.width(), label);
+            }
+        }
+        Some(image)
+    }
+
+    /// Returns the output clusters ordered for painter's-algorithm rendering: every deepened
+    /// cluster comes after the output clusters it was deepened into, so children paint on top.
+    /// Clusters are ordered by nesting level, then by area descending, then by index.
+    pub fn output_in_render_order(&self) -> Vec<ClusterIndex> {
+        let outputs: HashSet<ClusterIndex> = self.clusters_output.iter().copied().collect();
+        let parents: HashMap<ClusterIndex, ClusterIndex> = self.clusters_output
+            .iter()
+            .filter_map(|&index| self.output_parent(index, &outputs).map(|parent| (index, parent)))
+            .collect();
+
+        let level = |mut index: ClusterIndex| {
+            let mut level = 0;
+            while let Some(&parent) = parents.get(&index) {
+                index = parent;
+                level += 1;
+                if level > parents.len() {
+                    break;
+                }
+            }
+            level
+        };
+
+        let mut order = self.clusters_output.clone();
+        order.sort_by_key(|&index| {
+            (level(index), std::cmp::Reverse(self.clusters[index.0 as usize].area()), index)
+        });
+        order
+    }
+
+    /// Returns the output clusters topmost first, the reverse of `output_in_render_order`
+    pub fn output_in_hit_test_order(&self) -> Vec<ClusterIndex> {
+        let mut order = self.output_in_render_order();
+        order.reverse();
+        order
+    }
+
+    /// The output cluster `index` was deepened into, following merges through non-output clusters
+    fn output_parent(&self, index: ClusterIndex, outputs: &HashSet<ClusterIndex>) -> Option<ClusterIndex> {
+        let mut current = self.clusters[index.0 as usize].deepened_into?;
+        for _ in 0..self.clusters.len() {
+            if outputs.contains(&current) {
+                return Some(current);
+            }
+            let cluster = &self.clusters[current.0 as usize];
+            current = match cluster.deepened_into {
+                Some(parent) => parent,
+                None if cluster.merged_into != ZERO && cluster.merged_into != current => cluster.merged_into,
+                None => return None,
+            };
+        }
+        None
+    }
+
+    pub fn to_color_image_on_background(&self, bg: Color) -> ColorImage {
+        self.view().to_color_image_on_background(bg)
+    }
+
     pub fn take_image(self) -> ColorImage {
         ColorImage {
             pixels: self.pixels,
@@ -43,6 +303,7 @@ impl Clusters {
 pub struct ClustersView<'a> {
     pub width: u32,
     pub height: u32,
+    pub(crate) diagonal: bool,
     pub pixels: &'a [u8],
     pub clusters: &'a [Cluster],
     pub cluster_indices: &'a [ClusterIndex],
@@ -56,6 +317,11 @@ pub struct ClustersOutputIterator<'a> {
 }
 
 impl ClustersView<'_> {
+    /// Whether diagonally adjacent pixels were connected when clustering
+    pub fn diagonal(&self) -> bool {
+        self.diagonal
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &Cluster> {
         ClustersOutputIterator {
             counter: 0,
@@ -65,9 +331,18 @@ impl ClustersView<'_> {
     }
 
     pub fn get_cluster(&self, index: ClusterIndex) -> &Cluster {
+        debug_assert!(!index.is_reserved(), "the reserved cluster index has no cluster");
         &self.clusters[index.0 as usize]
     }
 
+    /// Returns the cluster, or `None` if the index is reserved or out of range
+    pub fn get_cluster_checked(&self, index: ClusterIndex) -> Option<&Cluster> {
+        if index.is_reserved() {
+            return None;
+        }
+        self.clusters.get(index.0 as usize)
+    }
+
     pub fn get_cluster_at_point(&self, point: PointI32) -> ClusterIndex {
         let index = (point.y * self.width as i32 + point.x) as u32;
         self.get_cluster_at(index)
@@ -114,6 +389,39 @@ impl ClustersView<'_> {
 
         image
     }
+
+    /// Like `to_color_image`, but composites each cluster's color over `bg` according to its alpha,
+    /// giving an opaque image
+    pub fn to_color_image_on_background(&self, bg: Color) -> ColorImage {
+        let mut image = ColorImage::new_w_h(self.width as usize, self.height as usize);
+        let bg = Color::new(bg.r, bg.g, bg.b);
+        for i in 0..(self.width * self.height) as usize {
+            image.set_pixel_at(i, &bg);
+        }
+
+        self.clusters_output
+            .iter()
+            .rev()
+            .for_each(|&u| {
+                let cluster = self.get_cluster(u);
+                let color = cluster.residue_color();
+                let alpha = color.a as u32;
+                let blend = |src: u8, dst: u8| {
+                    ((src as u32 * alpha + dst as u32 * (255 - alpha) + 127) / 255) as u8
+                };
+                let holes: HashSet<u32> = cluster.holes.iter().copied().collect();
+                for &i in cluster.iter() {
+                    if holes.contains(&i) {
+                        continue;
+                    }
+                    let dst = image.get_pixel_at(i as usize);
+                    let blended = Color::new(blend(color.r, dst.r), blend(color.g, dst.g), blend(color.b, dst.b));
+                    image.set_pixel_at(i as usize, &blended);
+                }
+            });
+
+        image
+    }
 }
 
 impl<'a> Iterator for ClustersOutputIterator<'a> {
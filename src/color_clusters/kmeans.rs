@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use crate::{Color, ColorImage, MonoImage, MonoImageItem};
+
+impl ColorImage {
+    /// Reduces the image to at most `k` colors by k-means over RGB (alpha is ignored), as opposed to
+    /// the spatial clustering of `Builder`. Returns the palette and the index into it of every pixel.
+    ///
+    /// The centroids start from the most frequent color, then repeatedly the color farthest from the
+    /// centroids so far, so the result is deterministic. Iteration stops after `max_iter` rounds or once
+    /// no color changes centroid. Fewer than `k` colors are returned if the image has fewer distinct colors.
+    /// If `k` is 0, the palette is empty and every label is 0.
+    pub fn kmeans_colors(&self, k: usize, max_iter: usize) -> (Vec<Color>, MonoImage) {
+        if k == 0 {
+            return (Vec::new(), MonoImage::new_w_h(self.width, self.height));
+        }
+        let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+        for pixel in self.pixels.chunks_exact(4) {
+            *counts.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+        }
+        let mut colors: Vec<([u8; 3], u32)> = counts.into_iter().collect();
+        colors.sort();
+
+        let k = k.min(colors.len()).min(MonoImageItem::MAX as usize + 1);
+        let distance = |a: [f64; 3], b: [u8; 3]| (0..3).map(|c| (a[c] - b[c] as f64).powi(2)).sum::<f64>();
+        let to_f64 = |c: [u8; 3]| [c[0] as f64, c[1] as f64, c[2] as f64];
+
+        let mut centroids: Vec<[f64; 3]> = Vec::with_capacity(k);
+        if let Some(&(first, _)) = colors.iter().max_by_key(|&&(color, count)| (count, std::cmp::Reverse(color))) {
+            centroids.push(to_f64(first));
+        }
+        while centroids.len() < k {
+            let farthest = colors.iter()
+                .map(|&(color, _)| (color, centroids.iter().map(|&c| distance(c, color)).fold(f64::MAX, f64::min)))
+                .fold(None, |best: Option<([u8; 3], f64)>, (color, d)| match best {
+                    Some((_, best_d)) if best_d >= d => best,
+                    _ => Some((color, d)),
+                });
+            centroids.push(to_f64(farthest.unwrap().0));
+        }
+
+        let nearest = |centroids: &[[f64; 3]], color: [u8; 3]| {
+            (0..centroids.len())
+                .min_by(|&a, &b| distance(centroids[a], color).total_cmp(&distance(centroids[b], color)))
+                .unwrap_or(0)
+        };
+        let mut assignment = vec![usize::MAX; colors.len()];
+        for _ in 0..max_iter {
+            let mut changed = false;
+            for (i, &(color, _)) in colors.iter().enumerate() {
+                let label = nearest(&centroids, color);
+                changed |= assignment[i] != label;
+                assignment[i] = label;
+            }
+            if !changed {
+                break;
+            }
+            let mut sums = vec![([0.0; 3], 0.0); k];
+            for (&(color, count), &label) in colors.iter().zip(assignment.iter()) {
+                for (sum, &channel) in sums[label].0.iter_mut().zip(color.iter()) {
+                    *sum += channel as f64 * count as f64;
+                }
+                sums[label].1 += count as f64;
+            }
+            for (centroid, (sum, total)) in centroids.iter_mut().zip(sums) {
+                // a centroid left without colors stays where it is
+                if total > 0.0 {
+                    *centroid = [sum[0] / total, sum[1] / total, sum[2] / total];
+                }
+            }
+        }
+
+        let labels: HashMap<[u8; 3], usize> = colors.iter().map(|&(color, _)| (color, nearest(&centroids, color))).collect();
+        let mut image = MonoImage::new_w_h(self.width, self.height);
+        for (i, pixel) in self.pixels.chunks_exact(4).enumerate() {
+            image.set_pixel(i % self.width, i / self.width, labels[&[pixel[0], pixel[1], pixel[2]]] as MonoImageItem);
+        }
+        let palette = centroids.iter()
+            .map(|c| Color::new(c[0].round() as u8, c[1].round() as u8, c[2].round() as u8))
+            .collect();
+        (palette, image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kmeans_three_colors() {
+        let palette = [Color::new(200, 30, 30), Color::new(20, 180, 60), Color::new(40, 40, 220)];
+        let mut image = ColorImage::new_w_h(12, 9);
+        for y in 0..9 {
+            for x in 0..12 {
+                image.set_pixel(x, y, &palette[(x / 4 + y / 3) % 3]);
+            }
+        }
+        let (mut colors, labels) = image.kmeans_colors(3, 10);
+        let found = colors.clone();
+        colors.sort_by_key(|c| (c.r, c.g, c.b));
+        let mut expected = palette.to_vec();
+        expected.sort_by_key(|c| (c.r, c.g, c.b));
+        assert_eq!(colors, expected);
+        for y in 0..9 {
+            for x in 0..12 {
+                assert_eq!(found[labels.get_pixel(x, y) as usize], image.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn kmeans_zero_colors() {
+        let mut image = ColorImage::new_w_h(4, 3);
+        image.set_pixel(1, 1, &Color::new(200, 30, 30));
+        let (colors, labels) = image.kmeans_colors(0, 10);
+        assert!(colors.is_empty());
+        assert_eq!((labels.width(), labels.height()), (4, 3));
+        assert!((0..3).all(|y| (0..4).all(|x| labels.get_pixel(x, y) == 0)));
+    }
+}
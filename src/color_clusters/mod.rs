@@ -12,9 +12,22 @@
 mod builder;
 mod cluster;
 mod container;
+mod kmeans;
+mod posterize;
 mod runner;
+mod seeds;
+mod snapshot;
+mod sweep;
+mod tracking;
+mod workspace;
 
 pub use builder::*;
 pub use cluster::*;
 pub use container::*;
-pub use runner::*;
\ No newline at end of file
+pub use posterize::*;
+pub use runner::*;
+pub use seeds::*;
+pub use snapshot::*;
+pub use sweep::*;
+pub use tracking::*;
+pub use workspace::*;
\ No newline at end of file
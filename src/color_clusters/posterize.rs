@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::{BinaryImage, Color, ColorImage, ColorSum, GrayImage, PointI32};
+
+/// Maximum number of k-means iterations when choosing band boundaries
+const KMEANS_MAX_ITERATIONS: usize = 32;
+
+#[derive(Debug, Clone)]
+/// Options of `posterize_with_config`
+pub struct PosterizeConfig {
+    /// Number of bands (per channel if `per_channel`)
+    pub levels: usize,
+    /// Quantizes red, green and blue separately instead of the luminance,
+    /// giving up to `levels`^3 bands
+    pub per_channel: bool,
+    /// Chooses the band boundaries by k-means on the histogram instead of spacing them uniformly
+    pub kmeans: bool,
+    /// 4-connected patches of a band smaller than this are merged into the most common adjacent band
+    pub min_area: usize,
+}
+
+impl Default for PosterizeConfig {
+    fn default() -> Self {
+        Self {
+            levels: 4,
+            per_channel: false,
+            kmeans: false,
+            min_area: 4,
+        }
+    }
+}
+
+/// Quantizes the luminance of `image` into `levels` uniformly spaced bands, see `posterize_with_config`
+pub fn posterize(image: &ColorImage, levels: usize) -> Vec<(Color, BinaryImage)> {
+    posterize_with_config(image, &PosterizeConfig { levels, ..Default::default() })
+}
+
+/// Separates `image` into bands of similar luminance (or color, if `per_channel`), like screen-print layers.
+///
+/// Returns the average color and the mask of every non-empty band, ordered dark to light.
+/// The masks partition the image.
+pub fn posterize_with_config(image: &ColorImage, config: &PosterizeConfig) -> Vec<(Color, BinaryImage)> {
+    let levels = config.levels.max(1);
+    let channels: Vec<Vec<u8>> = if config.per_channel {
+        vec![
+            image.iter().map(|c| c.r).collect(),
+            image.iter().map(|c| c.g).collect(),
+            image.iter().map(|c| c.b).collect(),
+        ]
+    } else {
+        vec![GrayImage::from_color_image(image, GrayImage::LUMA_WEIGHTS).pixels]
+    };
+
+    let mut labels = vec![0; image.width * image.height];
+    for values in channels.iter() {
+        let thresholds = if config.kmeans {
+            kmeans_thresholds(values, levels)
+        } else {
+            (1..levels).map(|k| (k * 256 / levels) as u16).collect()
+        };
+        for (label, &v) in labels.iter_mut().zip(values.iter()) {
+            *label = *label * levels + thresholds.iter().filter(|&&t| t <= v as u16).count();
+        }
+    }
+    despeckle(&mut labels, image.width, image.height, config.min_area);
+
+    let mut bands = BTreeMap::<usize, (ColorSum, BinaryImage)>::new();
+    for (i, &label) in labels.iter().enumerate() {
+        let (sum, mask) = bands.entry(label)
+            .or_insert_with(|| (ColorSum::new(), BinaryImage::new_w_h(image.width, image.height)));
+        sum.add(&image.get_pixel_at(i));
+        mask.set_pixel_index(i, true);
+    }
+    let mut bands: Vec<(Color, BinaryImage)> = bands.into_values()
+        .map(|(sum, mask)| (sum.average(), mask))
+        .collect();
+    bands.sort_by(|a, b| luminance(&a.0).partial_cmp(&luminance(&b.0)).unwrap());
+    bands
+}
+
+fn luminance(color: &Color) -> f64 {
+    let [r, g, b] = GrayImage::LUMA_WEIGHTS;
+    r * color.r as f64 + g * color.g as f64 + b * color.b as f64
+}
+
+/// The `levels - 1` ascending boundaries between the clusters found by 1D k-means on the histogram of `values`,
+/// where a value belongs to the band above a boundary if it is not less than it
+fn kmeans_thresholds(values: &[u8], levels: usize) -> Vec<u16> {
+    let mut histogram = [0u64; 256];
+    for &v in values {
+        histogram[v as usize] += 1;
+    }
+    // start at the quantiles, so that every center starts among values
+    let mut centers = Vec::with_capacity(levels);
+    let (mut v, mut seen) = (0, 0);
+    for k in 0..levels {
+        let quantile = ((k as f64 + 0.5) * values.len() as f64 / levels as f64) as u64;
+        while v < 255 && seen + histogram[v] <= quantile {
+            seen += histogram[v];
+            v += 1;
+        }
+        centers.push(v as f64);
+    }
+    for _ in 0..KMEANS_MAX_ITERATIONS {
+        let mut sums = vec![(0.0, 0u64); levels];
+        for (v, &count) in histogram.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let nearest = (0..levels)
+                .min_by(|&a, &b| (centers[a] - v as f64).abs().partial_cmp(&(centers[b] - v as f64).abs()).unwrap())
+                .unwrap();
+            sums[nearest].0 += v as f64 * count as f64;
+            sums[nearest].1 += count;
+        }
+        let mut changed = false;
+        for (center, &(sum, count)) in centers.iter_mut().zip(sums.iter()) {
+            // a center without values stays where it is
+            if count > 0 && (sum / count as f64 - *center).abs() > 1e-6 {
+                *center = sum / count as f64;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    centers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    centers.windows(2).map(|pair| ((pair[0] + pair[1]) / 2.0).floor() as u16 + 1).collect()
+}
+
+/// Relabels each 4-connected patch smaller than `min_area` to the label most common around it
+/// (the smallest one among ties); patches without other labels around are kept
+fn despeckle(labels: &mut [usize], width: usize, height: usize, min_area: usize) {
+    let distinct: BTreeSet<usize> = labels.iter().copied().collect();
+    for label in distinct {
+        let mut mask = BinaryImage::new_w_h(width, height);
+        for (i, &l) in labels.iter().enumerate() {
+            if l == label {
+                mask.set_pixel_index(i, true);
+            }
+        }
+        for patch in mask.to_clusters(false).iter() {
+            if patch.size() >= min_area {
+                continue;
+            }
+            let mut around = BTreeMap::<usize, usize>::new();
+            for &p in patch.iter() {
+                for n in [PointI32::new(0, -1), PointI32::new(-1, 0), PointI32::new(1, 0), PointI32::new(0, 1)] {
+                    let q = p + n;
+                    if q.x < 0 || q.y < 0 || q.x >= width as i32 || q.y >= height as i32 {
+                        continue;
+                    }
+                    let other = labels[q.y as usize * width + q.x as usize];
+                    if other != label {
+                        *around.entry(other).or_default() += 1;
+                    }
+                }
+            }
+            // the first of the most common, as max_by_key returns the last
+            if let Some((&other, _)) = around.iter().rev().max_by_key(|(_, &count)| count) {
+                for &p in patch.iter() {
+                    labels[p.y as usize * width + p.x as usize] = other;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> ColorImage {
+        let mut image = ColorImage::new_w_h(64, 8);
+        for y in 0..8 {
+            for x in 0..64 {
+                let v = (x * 4) as u8;
+                image.set_pixel(x, y, &Color::new(v, v, v));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn posterize_gradient() {
+        let image = gradient();
+        let bands = posterize(&image, 3);
+        assert_eq!(bands.len(), 3);
+        // the masks partition the image
+        for i in 0..image.width * image.height {
+            assert_eq!(bands.iter().filter(|(_, mask)| mask.pixels.get(i).unwrap()).count(), 1);
+        }
+        assert!(bands.windows(2).all(|pair| pair[0].0.r < pair[1].0.r));
+        // each band is a column range
+        assert!(bands[0].1.get_pixel(0, 0) && bands[0].1.get_pixel(21, 7) && !bands[0].1.get_pixel(22, 0));
+
+        let config = PosterizeConfig { levels: 3, kmeans: true, ..Default::default() };
+        assert_eq!(posterize_with_config(&image, &config).len(), 3);
+    }
+
+    #[test]
+    fn posterize_kmeans_and_despeckle() {
+        // two dark tones and a light one, which uniform bands would lump together
+        let mut image = ColorImage::new_w_h(30, 10);
+        for y in 0..10 {
+            for x in 0..30 {
+                let v = match x { 0..=9 => 10, 10..=19 => 60, _ => 250 };
+                image.set_pixel(x, y, &Color::new(v, v, v));
+            }
+        }
+        image.set_pixel(25, 5, &Color::new(60, 60, 60));
+
+        let config = PosterizeConfig { levels: 3, ..Default::default() };
+        assert_eq!(posterize_with_config(&image, &config).len(), 2);
+        let config = PosterizeConfig { levels: 3, kmeans: true, ..Default::default() };
+        let bands = posterize_with_config(&image, &config);
+        assert_eq!(bands.iter().map(|(c, _)| c.r).collect::<Vec<_>>(), vec![10, 60, 248]);
+        // the speckle went to the light band
+        assert!(bands[2].1.get_pixel(25, 5));
+        assert_eq!(bands[1].1.area(), 100);
+
+        let config = PosterizeConfig { levels: 3, kmeans: true, min_area: 0, ..Default::default() };
+        assert_eq!(posterize_with_config(&image, &config)[1].1.area(), 101);
+    }
+}
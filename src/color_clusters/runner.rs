@@ -1,9 +1,10 @@
-use crate::{Color, ColorImage, ColorI32};
+use crate::{BoundingRect, Color, ColorImage, ColorI32};
 use super::*;
 
 pub struct Runner {
     config: RunnerConfig,
     image: ColorImage,
+    roi: BoundingRect,
 }
 
 pub struct RunnerConfig {
@@ -43,6 +44,7 @@ impl Default for Runner {
         Self {
             config: RunnerConfig::default(),
             image: ColorImage::new(),
+            roi: BoundingRect::default(),
         }
     }
 }
@@ -52,7 +54,18 @@ impl Runner {
     pub fn new(config: RunnerConfig, image: ColorImage) -> Self {
         Self {
             config,
-            image
+            image,
+            roi: BoundingRect::default(),
+        }
+    }
+
+    /// Only clusters pixels inside `roi` (clipped to the image).
+    /// Outputs remain in full-image coordinates.
+    pub fn new_with_roi(image: ColorImage, roi: BoundingRect, config: RunnerConfig) -> Self {
+        Self {
+            config,
+            image,
+            roi,
         }
     }
 
@@ -83,6 +96,7 @@ impl Runner {
             .hierarchical(hierarchical)
             .key(key_color)
             .keying_action(keying_action)
+            .roi(self.roi)
             .batch_size(batch_size as u32)
             .same(move |a: Color, b: Color| {
                 color_same(a, b, is_same_color_a, is_same_color_b)
@@ -144,3 +158,509 @@ fn patch_good(
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScalerField;
+
+    fn test_image() -> ColorImage {
+        let (width, height) = (24, 20);
+        let mut image = ColorImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = (((x / 5) * 7 + (y / 4) * 13) % 4) as u8 * 60;
+                image.set_pixel(x, y, &Color::new(c, 255 - c, c / 2));
+            }
+        }
+        image
+    }
+
+    fn config() -> RunnerConfig {
+        RunnerConfig {
+            good_min_area: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn runner_roi_full_image() {
+        let image = test_image();
+        let roi = BoundingRect::new_x_y_w_h(0, 0, image.width as i32, image.height as i32);
+        let a = Runner::new(config(), image.clone()).run();
+        let b = Runner::new_with_roi(image, roi, config()).run();
+        assert!(a.cluster_indices == b.cluster_indices);
+        assert!(a.clusters_output == b.clusters_output);
+        assert_eq!(a.clusters.len(), b.clusters.len());
+        for (ca, cb) in a.clusters.iter().zip(b.clusters.iter()) {
+            assert_eq!(ca.indices, cb.indices);
+            assert_eq!(ca.rect, cb.rect);
+        }
+    }
+
+    #[test]
+    fn runner_roi_subregion() {
+        let image = test_image();
+        let width = image.width;
+        let roi = BoundingRect::new_x_y_w_h(3, 2, 14, 11);
+        let clusters = Runner::new_with_roi(image, roi, config()).run();
+        let view = clusters.view();
+        let mut count = 0;
+        for &index in view.clusters_output.iter() {
+            let cluster = view.get_cluster(index);
+            assert!(cluster.rect.width() > 0);
+            let rect = cluster.rect;
+            assert!(roi.left <= rect.left && rect.right <= roi.right);
+            assert!(roi.top <= rect.top && rect.bottom <= roi.bottom);
+            count += 1;
+        }
+        assert!(count > 0);
+        for (i, &index) in clusters.cluster_indices.iter().enumerate() {
+            let (x, y) = ((i % width) as i32, (i / width) as i32);
+            if x < roi.left || x >= roi.right || y < roi.top || y >= roi.bottom {
+                assert!(index == ZERO);
+            }
+        }
+    }
+
+    #[test]
+    fn runner_roi_off_image() {
+        let image = test_image();
+        let roi = BoundingRect::new_x_y_w_h(image.width as i32 + 5, 0, 10, 10);
+        let mut builder = Runner::new_with_roi(image, roi, config()).start();
+        assert_eq!(builder.progress(), 0);
+        while !builder.tick() {
+            assert!(builder.progress() <= 100);
+        }
+        assert_eq!(builder.progress(), 100);
+        assert_eq!(builder.result().output_len(), 0);
+    }
+
+    fn nested_squares() -> ColorImage {
+        let mut image = ColorImage::new_w_h(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                let outer = (8..24).contains(&x) && (8..24).contains(&y);
+                let inner = (12..20).contains(&x) && (12..20).contains(&y);
+                let c = if outer && !inner { 255 } else { 0 };
+                image.set_pixel(x, y, &Color::new(c, c, c));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn coverage_default_closures() {
+        let clusters = Runner::new(RunnerConfig::default(), nested_squares()).run();
+        assert_eq!(clusters.output_len(), 3);
+        let report = clusters.check_output_coverage();
+        assert_eq!(report.covered_once, 32 * 32);
+        assert_eq!(report.covered_multiple, 0);
+        assert_eq!(report.uncovered, 0);
+        assert!(report.offenders.is_empty());
+    }
+
+    #[test]
+    fn coverage_deepen_without_hollow() {
+        let clusters = Runner::new(RunnerConfig::default(), nested_squares())
+            .builder()
+            .deepen(|_, _, _| true)
+            .hollow(|_, _, _| false)
+            .run();
+        let report = clusters.check_output_coverage();
+        assert!(report.covered_multiple > 0);
+        assert_eq!(report.uncovered, 0);
+        assert_eq!(report.covered_once + report.covered_multiple, 32 * 32);
+        assert!(!report.offenders.is_empty());
+    }
+
+    #[test]
+    fn label_image_two_regions() {
+        let mut image = ColorImage::new_w_h(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                let color = if x < 8 { Color::new(255, 0, 0) } else { Color::new(0, 0, 255) };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let clusters = Runner::new(RunnerConfig::default(), image).run();
+        let labels = clusters.label_image().unwrap();
+        let left = labels.get_pixel(0, 0);
+        let right = labels.get_pixel(15, 7);
+        assert!(left != 0 && right != 0 && left != right);
+        for y in 0..8 {
+            for x in 0..16 {
+                assert_eq!(labels.get_pixel(x, y), if x < 8 { left } else { right });
+            }
+        }
+    }
+
+    #[test]
+    fn label_image_too_many_outputs() {
+        let outputs = u16::MAX as usize + 1;
+        let clusters = Clusters {
+            width: 1,
+            height: 1,
+            diagonal: false,
+            pixels: vec![0; 4],
+            clusters: vec![Cluster::new(); outputs + 1],
+            cluster_indices: vec![ZERO],
+            clusters_output: (1..=outputs).map(|i| ClusterIndex::new(i as u32).unwrap()).collect(),
+            keyed_pixels: 0,
+            clustered_pixels: 1,
+            warnings: Vec::new(),
+        };
+        assert!(clusters.label_image().is_none());
+    }
+
+    #[test]
+    fn builder_max_clusters() {
+        let mut image = ColorImage::new_w_h(24, 24);
+        let squares = [
+            (1, 1, 10, Color::new(255, 255, 255)),
+            (13, 1, 6, Color::new(255, 0, 0)),
+            (13, 13, 5, Color::new(0, 255, 0)),
+            (2, 15, 4, Color::new(0, 0, 255)),
+            (19, 8, 3, Color::new(255, 255, 0)),
+        ];
+        for &(left, top, size, color) in squares.iter() {
+            for y in top..top + size {
+                for x in left..left + size {
+                    image.set_pixel(x, y, &color);
+                }
+            }
+        }
+
+        let visible_areas = |clusters: &Clusters| {
+            let labels = clusters.label_image().unwrap();
+            let mut areas = vec![0; clusters.output_len()];
+            for y in 0..labels.height() {
+                for x in 0..labels.width() {
+                    areas[labels.get_pixel(x, y) as usize - 1] += 1;
+                }
+            }
+            areas
+        };
+
+        let all = Runner::new(RunnerConfig::default(), image.clone()).run();
+        assert!(all.output_len() > 3);
+        let areas = visible_areas(&all);
+        let mut largest: Vec<_> = all.clusters_output.iter().copied().zip(areas).collect();
+        largest.sort_by_key(|&(_, area)| std::cmp::Reverse(area));
+        let mut largest: Vec<_> = largest.into_iter().take(3).map(|(index, _)| index).collect();
+        largest.sort();
+
+        let limited = Runner::new(RunnerConfig::default(), image).builder().max_clusters(3).run();
+        assert_eq!(limited.output_len(), 3);
+        let mut kept = limited.clusters_output.clone();
+        kept.sort();
+        assert_eq!(kept, largest);
+        // no pixel is left labeled with a removed cluster
+        let removed: Vec<_> = all.clusters_output.iter().filter(|index| !kept.contains(index)).collect();
+        assert!(!removed.is_empty());
+        assert!(limited.cluster_indices.iter().all(|index| !removed.contains(&index)));
+        let report = limited.check_output_coverage();
+        assert_eq!(report.covered_once, 24 * 24);
+        assert_eq!(visible_areas(&limited).iter().sum::<usize>(), 24 * 24);
+    }
+
+    #[test]
+    fn diagonal_neighbours() {
+        // two squares of similar (but not the same) color touching only at a corner,
+        // so that they are not merged in stage 1 and can only merge as neighbours in stage 2
+        let mut image = ColorImage::new_w_h(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let color = if (7..12).contains(&x) && (2..7).contains(&y) {
+                    Color::new(255, 255, 255)
+                } else if (2..7).contains(&x) && (7..12).contains(&y) {
+                    Color::new(255, 255, 215)
+                } else {
+                    Color::new(0, 0, 0)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        for &diagonal in [true, false].iter() {
+            let config = RunnerConfig { diagonal, ..Default::default() };
+            let clusters = Runner::new(config, image.clone()).run();
+            let labels = clusters.label_image().unwrap();
+            let (a, b, background) = (labels.get_pixel(7, 6), labels.get_pixel(6, 7), labels.get_pixel(0, 0));
+            assert!(a != background && b != background);
+            assert_eq!(a == b, diagonal);
+            let view = clusters.view();
+            let cluster = view.get_cluster(view.clusters_output[a as usize - 1]);
+            assert_eq!(cluster.area() == 50, diagonal);
+        }
+    }
+
+    #[test]
+    fn color_image_on_background() {
+        let mut image = ColorImage::new_w_h(8, 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                image.set_pixel(x, y, &Color::new_rgba(255, 0, 0, 128));
+            }
+        }
+        let clusters = Runner::new(RunnerConfig::default(), image).run();
+        let white = clusters.to_color_image_on_background(Color::new(255, 255, 255));
+        let black = clusters.to_color_image_on_background(Color::new(0, 0, 0));
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(white.get_pixel(x, y), Color::new(255, 127, 127));
+                assert_eq!(black.get_pixel(x, y), Color::new(128, 0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn render_order_nested_squares() {
+        let image = nested_squares();
+        let clusters = Runner::new(RunnerConfig::default(), image.clone())
+            .builder()
+            .deepen(|_, _, _| true)
+            .hollow(|_, _, _| false)
+            .run();
+        let render = |order: &[ClusterIndex]| {
+            let view = clusters.view();
+            let mut canvas = ColorImage::new_w_h(image.width, image.height);
+            for &index in order {
+                let cluster = view.get_cluster(index);
+                for &i in cluster.iter() {
+                    canvas.set_pixel_at(i as usize, &cluster.residue_color());
+                }
+            }
+            canvas
+        };
+
+        let order = clusters.output_in_render_order();
+        assert_eq!(order.len(), clusters.output_len());
+        assert_eq!(render(&order).pixels, image.pixels);
+
+        let hit_test = clusters.output_in_hit_test_order();
+        assert_eq!(hit_test, order.iter().rev().copied().collect::<Vec<_>>());
+        assert!(render(&hit_test).pixels != image.pixels);
+    }
+
+    #[test]
+    fn render_antialiased_circle() {
+        let mut image = ColorImage::new_w_h(24, 24);
+        for y in 0..24 {
+            for x in 0..24 {
+                let (dx, dy) = (x as f64 - 11.5, y as f64 - 11.5);
+                let c = if dx * dx + dy * dy < 64.0 { 255 } else { 0 };
+                image.set_pixel(x, y, &Color::new(c, c, c));
+            }
+        }
+        let clusters = Runner::new(RunnerConfig::default(), image).run();
+        let view = clusters.view();
+        let circle = view.iter().find(|c| c.residue_color().r == 255).unwrap();
+        let coverage = circle.coverage(&view);
+
+        let mut canvas = ColorImage::new_w_h(24, 24);
+        for i in 0..24 * 24 {
+            canvas.set_pixel_at(i, &Color::new(0, 0, 0));
+        }
+        circle.render_antialiased(&mut canvas, &coverage);
+
+        assert_eq!(canvas.get_pixel(12, 12), Color::new(255, 255, 255));
+        assert_eq!(canvas.get_pixel(0, 0), Color::new(0, 0, 0));
+        let blended = (0..24 * 24)
+            .map(|i| canvas.get_pixel_at(i).r)
+            .filter(|&r| 0 < r && r < 255)
+            .count();
+        assert!(blended > 8);
+    }
+
+    #[test]
+    fn cluster_index_checked() {
+        assert!(ClusterIndex::new(0).is_none());
+        assert!(ClusterIndex::zero().is_reserved());
+        assert_eq!(ClusterIndex::new(5).unwrap().as_u32(), 5);
+
+        let clusters = Runner::new(config(), test_image()).run();
+        let max = clusters.max_index().unwrap();
+        assert!(clusters.get_cluster_checked(max).is_some());
+        assert!(clusters.get_cluster_checked(ClusterIndex::new(max.as_u32() + 1).unwrap()).is_none());
+        assert!(clusters.get_cluster_checked(ClusterIndex::zero()).is_none());
+
+        let view = clusters.view();
+        assert!(view.get_cluster_checked(ClusterIndex::zero()).is_none());
+        assert!(view.get_cluster_checked(ClusterIndex::new(u32::MAX).unwrap()).is_none());
+        let outputs: Vec<ClusterIndex> = clusters.output_indices().collect();
+        assert_eq!(outputs.len(), clusters.output_len());
+        for index in outputs {
+            assert!(!index.is_reserved());
+            assert!(index <= max);
+            assert!(view.get_cluster_checked(index).is_some());
+        }
+    }
+
+    #[test]
+    fn iter_output_visits_each_once() {
+        let clusters = Runner::new(config(), test_image()).run();
+        let visited: Vec<ClusterIndex> = clusters.iter_output().map(|(index, _)| index).collect();
+        assert_eq!(visited, clusters.output_indices().collect::<Vec<_>>());
+        let unique: std::collections::HashSet<_> = visited.iter().collect();
+        assert_eq!(unique.len(), clusters.output_len());
+
+        let view = clusters.view();
+        let total: usize = clusters.iter_output().map(|(index, cluster)| {
+            assert!(std::ptr::eq(cluster, view.get_cluster(index)));
+            cluster.area()
+        }).sum();
+        assert_eq!(total, view.iter().map(|cluster| cluster.area()).sum::<usize>());
+    }
+
+    #[test]
+    fn builder_alpha() {
+        let mut image = ColorImage::new_w_h(20, 20);
+        for y in 0..20 {
+            for x in 0..20 {
+                let alpha = if (6..14).contains(&x) && (6..14).contains(&y) { 128 } else { 255 };
+                image.set_pixel(x, y, &Color::new_rgba(200, 40, 40, alpha));
+            }
+        }
+
+        // alpha is ignored by default
+        let clusters = Runner::new(RunnerConfig::default(), image.clone()).run();
+        assert_eq!(clusters.output_len(), 1);
+
+        let clusters = Runner::new(RunnerConfig::default(), image.clone()).builder().ignore_alpha(false).run();
+        let view = clusters.view();
+        let alphas: Vec<u8> = clusters.output_indices()
+            .map(|index| view.get_cluster(index).residue_color().a)
+            .collect();
+        assert!(alphas.iter().any(|&a| (127..=129).contains(&a)), "{:?}", alphas);
+        let rendered = view.to_color_image();
+        assert_eq!(rendered.get_pixel(10, 10).a, 128);
+        assert_eq!(rendered.get_pixel(2, 2).a, 255);
+
+        let clusters = Runner::new(RunnerConfig::default(), image).builder().ignore_alpha(false).alpha_tolerance(127).run();
+        assert_eq!(clusters.output_len(), 1);
+    }
+
+    fn mostly_white() -> ColorImage {
+        let mut image = ColorImage::new_w_h(20, 20);
+        for y in 0..20 {
+            for x in 0..20 {
+                let color = if (4..10).contains(&x) && (4..10).contains(&y) {
+                    Color::new(0, 0, 255)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn keyed_fraction_warning() {
+        let white = Runner::new(RunnerConfig { key_color: Color::new(255, 255, 255), ..Default::default() }, mostly_white())
+            .builder()
+            .max_keyed_fraction(Some(0.5));
+        let clusters = white.run();
+        assert_eq!(clusters.keyed_pixel_count(), 400 - 36);
+        assert!((clusters.keyed_fraction() - 0.91).abs() < 1e-9);
+        assert_eq!(clusters.warnings(), &[BuilderWarning::KeyedFractionExceeded { fraction: 0.91, max: 0.5 }]);
+
+        let blue = Runner::new(RunnerConfig { key_color: Color::new(0, 0, 255), ..Default::default() }, mostly_white())
+            .builder()
+            .max_keyed_fraction(Some(0.5));
+        let clusters = blue.run();
+        assert_eq!(clusters.keyed_pixel_count(), 36);
+        assert!(clusters.warnings().is_empty());
+    }
+
+    #[test]
+    fn keyed_fraction_abort() {
+        let config = || RunnerConfig { key_color: Color::new(255, 255, 255), ..Default::default() };
+        let result = Runner::new(config(), mostly_white())
+            .builder()
+            .max_keyed_fraction(Some(0.9))
+            .abort_on_max_keyed(true)
+            .try_run();
+        assert_eq!(result.err(), Some(BuilderError::KeyedFractionExceeded { fraction: 0.91, max: 0.9 }));
+
+        let mut builder = Runner::new(config(), mostly_white()).start();
+        while !builder.tick() {}
+        assert_eq!(builder.progress_detail(), ProgressDetail { progress: 100, keyed_pixels: 364 });
+        assert!(builder.try_result().is_ok());
+    }
+
+    /// Checks that `rects` cover the pixels of `cluster` exactly once
+    fn assert_exact_cover(cluster: &Cluster, view: &ClustersView, rects: &[BoundingRect]) {
+        let mut covered = vec![0; (view.width * view.height) as usize];
+        for rect in rects {
+            for y in rect.top..rect.bottom {
+                for x in rect.left..rect.right {
+                    covered[(y as u32 * view.width + x as u32) as usize] += 1;
+                }
+            }
+        }
+        let mut pixels = vec![0; covered.len()];
+        for &i in cluster.iter() {
+            pixels[i as usize] = 1;
+        }
+        assert_eq!(covered, pixels);
+    }
+
+    #[test]
+    fn cluster_to_rects() {
+        // an L of 6 pixels
+        let image = ColorImage::new_w_h(6, 6);
+        let mut labels = ScalerField::<u32>::new_w_h(6, 6);
+        for y in 0..6 {
+            for x in 0..6 {
+                let l = (x == 1 && (1..5).contains(&y)) || (y == 4 && (1..4).contains(&x));
+                labels.set_pixel(x, y, if l { 1 } else { 2 });
+            }
+        }
+        let clusters = Clusters::from_labels(&image, &labels);
+        let view = clusters.view();
+        let l = view.get_cluster(clusters.output_indices().next().unwrap());
+        let rects = l.to_rects(&view);
+        assert_eq!(rects, vec![BoundingRect::new_x_y_w_h(1, 1, 1, 3), BoundingRect::new_x_y_w_h(1, 4, 3, 1)]);
+        assert_exact_cover(l, &view, &rects);
+
+        // a checkerboard labelled as one cluster
+        let image = ColorImage::new_w_h(5, 4);
+        let mut labels = ScalerField::<u32>::new_w_h(5, 4);
+        for y in 0..4 {
+            for x in 0..5 {
+                labels.set_pixel(x, y, if (x + y) % 2 == 0 { 1 } else { 2 });
+            }
+        }
+        let clusters = Clusters::from_labels(&image, &labels);
+        let view = clusters.view();
+        let board = view.get_cluster(clusters.output_indices().next().unwrap());
+        let rects = board.to_rects(&view);
+        assert_eq!(rects.len(), board.area());
+        assert!(rects.iter().all(|rect| rect.width() == 1 && rect.height() == 1));
+        assert_exact_cover(board, &view, &rects);
+    }
+
+    #[test]
+    fn cluster_to_full_frame_image() {
+        let image = ColorImage::new_w_h(7, 5);
+        let mut labels = ScalerField::<u32>::new_w_h(7, 5);
+        for y in 0..5 {
+            for x in 0..7 {
+                labels.set_pixel(x, y, if (3..6).contains(&x) && (1..3).contains(&y) { 1 } else { 2 });
+            }
+        }
+        let clusters = Clusters::from_labels(&image, &labels);
+        let view = clusters.view();
+        let cluster = view.get_cluster(clusters.output_indices().next().unwrap());
+        assert_eq!(cluster.area(), 6);
+        let full = cluster.to_full_frame_image(&view);
+        assert_eq!((full.width, full.height), (7, 5));
+        for y in 0..5 {
+            for x in 0..7 {
+                assert_eq!(full.get_pixel(x, y), (3..6).contains(&x) && (1..3).contains(&y));
+            }
+        }
+        assert_eq!(full.crop_with_rect(cluster.rect).to_string(), cluster.to_image(&view).to_string());
+    }
+}
@@ -0,0 +1,240 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use crate::{Color, ColorImage, ColorSum, MonoImage, MonoImageItem, PointI32, ScalerField};
+use super::{Cluster, ClusterIndex, Clusters, ZERO, color_diff};
+
+/// Pixels next to a region, as (diff, order of insertion, x, y, label);
+/// the insertion order makes ties first come first served
+type Queue = BinaryHeap<Reverse<(i32, usize, i32, i32, u32)>>;
+
+/// Grows labelled regions from seed pixels, e.g. user scribbles, best first.
+///
+/// Each label keeps the running average color of its region. Pixels next to a region are queued
+/// by the `diff` of their color to that average, and the closest pixel is claimed first.
+/// A pixel can only be reached from an adjacent (4-connected) pixel of the same color by `same`,
+/// and if `max_distance` is given, only while its `diff` to the region average is within it.
+///
+/// Returns the label of every pixel, 0 for pixels not reached from any seed.
+/// Seeds with label 0 or outside the image are ignored; seeds sharing a label grow one region.
+pub fn grow_from_seeds(
+    image: &ColorImage,
+    seeds: &[(PointI32, u32)],
+    same: impl Fn(Color, Color) -> bool,
+    max_distance: Option<i32>,
+    diff: impl Fn(Color, Color) -> i32,
+) -> ScalerField<u32> {
+    let (width, height) = (image.width as i32, image.height as i32);
+    let mut labels = ScalerField::<u32>::new_w_h(image.width, image.height);
+    let mut sums = BTreeMap::<u32, ColorSum>::new();
+    let mut queue = Queue::new();
+    let mut order = 0;
+
+    let mut claim = |labels: &mut ScalerField<u32>, sums: &mut BTreeMap<u32, ColorSum>,
+            queue: &mut Queue, p: PointI32, label: u32| {
+        let color = image.get_pixel(p.x as usize, p.y as usize);
+        labels.set_pixel(p.x as usize, p.y as usize, label);
+        let sum = sums.entry(label).or_default();
+        sum.add(&color);
+        let average = sum.average();
+        for n in [PointI32::new(0, -1), PointI32::new(-1, 0), PointI32::new(1, 0), PointI32::new(0, 1)] {
+            let q = p + n;
+            if q.x < 0 || q.y < 0 || q.x >= width || q.y >= height
+                || labels.get_pixel(q.x as usize, q.y as usize) != 0 {
+                continue;
+            }
+            let neighbour = image.get_pixel(q.x as usize, q.y as usize);
+            if same(color, neighbour) {
+                queue.push(Reverse((diff(average, neighbour), order, q.x, q.y, label)));
+                order += 1;
+            }
+        }
+    };
+
+    for &(p, label) in seeds {
+        if label == 0 || p.x < 0 || p.y < 0 || p.x >= width || p.y >= height
+            || labels.get_pixel(p.x as usize, p.y as usize) != 0 {
+            continue;
+        }
+        claim(&mut labels, &mut sums, &mut queue, p, label);
+    }
+
+    while let Some(Reverse((_, _, x, y, label))) = queue.pop() {
+        if labels.get_pixel(x as usize, y as usize) != 0 {
+            continue;
+        }
+        if let Some(max_distance) = max_distance {
+            let color = image.get_pixel(x as usize, y as usize);
+            if diff(sums[&label].average(), color) > max_distance {
+                continue;
+            }
+        }
+        claim(&mut labels, &mut sums, &mut queue, PointI32::new(x, y), label);
+    }
+
+    labels
+}
+
+impl ColorImage {
+    /// Segments the image by growing a region from each of `seeds`, see `grow_from_seeds`.
+    /// A region spreads to adjacent pixels differing by at most `tolerance`
+    /// in the sum of the absolute channel differences (`color_diff`).
+    ///
+    /// Returns the index of the seed plus one at every pixel, 0 for pixels not reached from any seed.
+    /// Seeds past the range of `MonoImageItem` are ignored.
+    pub fn region_grow(&self, seeds: &[PointI32], tolerance: u32) -> MonoImage {
+        let seeds: Vec<(PointI32, u32)> = seeds.iter()
+            .take(MonoImageItem::MAX as usize)
+            .enumerate()
+            .map(|(i, &p)| (p, i as u32 + 1))
+            .collect();
+        let tolerance = std::cmp::min(tolerance, i32::MAX as u32) as i32;
+        let labels = grow_from_seeds(self, &seeds, |a, b| color_diff(a, b) <= tolerance, None, color_diff);
+        let mut image = MonoImage::new_w_h(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                image.set_pixel(x, y, labels.get_pixel(x, y) as MonoImageItem);
+            }
+        }
+        image
+    }
+}
+
+impl Clusters {
+    /// Builds a clustering from a label field, such as returned by `grow_from_seeds`,
+    /// so that the usual cluster and path extraction methods apply.
+    ///
+    /// Each non-zero label becomes an output cluster, in ascending label order;
+    /// pixels labelled 0 go to the reserved cluster `ZERO`.
+    pub fn from_labels(image: &ColorImage, labels: &ScalerField<u32>) -> Clusters {
+        assert_eq!((image.width, image.height), (labels.width(), labels.height()));
+        let mut indices = BTreeMap::new();
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let label = labels.get_pixel(x, y);
+                if label != 0 {
+                    indices.insert(label, ZERO);
+                }
+            }
+        }
+        for (i, index) in indices.values_mut().enumerate() {
+            *index = ClusterIndex(i as u32 + 1);
+        }
+
+        let mut clusters = vec![Cluster::new(); indices.len() + 1];
+        let mut cluster_indices = vec![ZERO; image.width * image.height];
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let label = labels.get_pixel(x, y);
+                let index = if label != 0 { indices[&label] } else { ZERO };
+                let i = y * image.width + x;
+                cluster_indices[i] = index;
+                clusters[index.0 as usize].add(i as u32, &image.get_pixel(x, y), x as i32, y as i32);
+            }
+        }
+        for cluster in clusters.iter_mut() {
+            cluster.residue_sum = cluster.sum;
+        }
+
+        Clusters {
+            width: image.width as u32,
+            height: image.height as u32,
+            diagonal: false,
+            pixels: image.pixels.clone(),
+            clusters,
+            cluster_indices,
+            clusters_output: indices.into_values().collect(),
+            keyed_pixels: 0,
+            clustered_pixels: (image.width * image.height) as u32,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PathSimplifyMode;
+    use crate::color_clusters::{color_diff, color_same};
+
+    #[test]
+    fn grow_two_regions() {
+        // two flat regions separated by a black border column
+        let mut image = ColorImage::new_w_h(9, 4);
+        for y in 0..4 {
+            for x in 0..9 {
+                let color = match x {
+                    0..=3 => Color::new(200, 50, 50),
+                    4 => Color::new(0, 0, 0),
+                    _ => Color::new(50, 50, 200),
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let seeds = [(PointI32::new(1, 1), 1), (PointI32::new(7, 2), 2)];
+        let labels = grow_from_seeds(&image, &seeds, |a, b| color_same(a, b, 4, 1), None, color_diff);
+        for y in 0..4 {
+            for x in 0..9 {
+                let expected = match x {
+                    0..=3 => 1,
+                    4 => 0,
+                    _ => 2,
+                };
+                assert_eq!(labels.get_pixel(x, y), expected, "at ({}, {})", x, y);
+            }
+        }
+
+        let clusters = Clusters::from_labels(&image, &labels);
+        assert_eq!(clusters.output_len(), 2);
+        let view = clusters.view();
+        let first = view.get_cluster(clusters.output_indices().next().unwrap());
+        assert_eq!(first.area(), 16);
+        assert_eq!(first.color(), Color::new(200, 50, 50));
+        let paths = first.to_compound_path(&view, false, PathSimplifyMode::Polygon, 0.0, 0.0, 0, 0.0);
+        assert_eq!(paths.paths.len(), 1);
+    }
+
+    #[test]
+    fn grow_gradient() {
+        // a gentle gradient up to x = 9, then steep steps
+        let mut image = ColorImage::new_w_h(16, 3);
+        for y in 0..3 {
+            for x in 0..16 {
+                let v = if x < 10 { x * 4 } else { 36 + (x - 9) * 30 };
+                image.set_pixel(x, y, &Color::new(v as u8, v as u8, v as u8));
+            }
+        }
+        let same = |a: Color, b: Color| (a.r as i32 - b.r as i32).abs() <= 8;
+        let labels = grow_from_seeds(&image, &[(PointI32::new(0, 1), 5)], same, None, color_diff);
+        for x in 0..16 {
+            assert_eq!(labels.get_pixel(x, 0), if x < 10 { 5 } else { 0 });
+        }
+
+        // the region average stays near the seed within a small max_distance
+        let labels = grow_from_seeds(&image, &[(PointI32::new(0, 1), 5)], same, Some(40), color_diff);
+        assert_eq!(labels.get_pixel(2, 1), 5);
+        assert_eq!(labels.get_pixel(9, 1), 0);
+    }
+
+    #[test]
+    fn region_grow_two_tone() {
+        // two tones with a little texture, meeting along a diagonal
+        let mut image = ColorImage::new_w_h(12, 8);
+        for y in 0..8 {
+            for x in 0..12 {
+                let grain = ((x * 7 + y * 3) % 4) as u8;
+                let color = if x > y + 2 { Color::new(40 + grain, 120, 40) } else { Color::new(220, 200 + grain, 180) };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let labels = image.region_grow(&[PointI32::new(11, 0), PointI32::new(0, 7)], 10);
+        for y in 0..8 {
+            for x in 0..12 {
+                assert_eq!(labels.get_pixel(x, y), if x > y + 2 { 1 } else { 2 }, "at ({}, {})", x, y);
+            }
+        }
+        // too strict to spread over the texture
+        let labels = image.region_grow(&[PointI32::new(11, 0)], 0);
+        let grown = (0..8).flat_map(|y| (0..12).map(move |x| (x, y))).filter(|&(x, y)| labels.get_pixel(x, y) == 1).count();
+        assert!((1..10).contains(&grown), "{}", grown);
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::{BoundingRect, Color, ColorImage, ColorSum};
+use super::{ClusterIndex, Clusters, ClustersView, ZERO};
+
+/// The part of a `Cluster` kept in a `ClustersSnapshot`
+#[derive(Clone, Debug)]
+pub struct ClusterSummary {
+    pub color: Color,
+    pub residue_color: Color,
+    pub rect: BoundingRect,
+    pub area: usize,
+    pub depth: u32,
+    pub merged_into: ClusterIndex,
+}
+
+/// Cluster index of every `scale`-th pixel in both directions
+#[derive(Clone, Debug)]
+pub struct PreviewMap {
+    pub scale: u32,
+    pub width: u32,
+    pub height: u32,
+    pub indices: Vec<ClusterIndex>,
+}
+
+/// An owned copy of the state of a clustering, which can be sent to another thread
+/// while the `IncrementalBuilder` keeps running. Cloning is cheap as the data is shared.
+///
+/// The per-pixel cluster indices are not kept, unless a preview scale is requested,
+/// in which case a downsampled `PreviewMap` is.
+#[derive(Clone)]
+pub struct ClustersSnapshot {
+    pub width: u32,
+    pub height: u32,
+    /// whether clustering has finished, in which case the preview shows the output clusters
+    pub complete: bool,
+    pub pixels: Arc<Vec<u8>>,
+    pub clusters: Arc<Vec<ClusterSummary>>,
+    pub clusters_output: Arc<Vec<ClusterIndex>>,
+    pub preview: Option<Arc<PreviewMap>>,
+}
+
+fn average(sum: &ColorSum) -> Color {
+    if sum.counter > 0 { sum.average() } else { Color::default() }
+}
+
+impl ClustersSnapshot {
+    pub(crate) fn new(view: &ClustersView, complete: bool, preview_scale: Option<u32>) -> Self {
+        let clusters = view.clusters
+            .iter()
+            .map(|c| ClusterSummary {
+                color: average(&c.sum),
+                residue_color: average(&c.residue_sum),
+                rect: c.rect,
+                area: c.area(),
+                depth: c.depth,
+                merged_into: c.merged_into,
+            })
+            .collect();
+
+        let preview = preview_scale.map(|scale| {
+            let scale = std::cmp::max(scale, 1);
+            let width = view.width / scale + (view.width % scale).min(1);
+            let height = view.height / scale + (view.height % scale).min(1);
+            let owner = if complete { Some(topmost_outputs(view)) } else { None };
+            let mut indices = Vec::with_capacity((width * height) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let i = (y * scale * view.width + x * scale) as usize;
+                    indices.push(match &owner {
+                        Some(owner) => owner[i],
+                        None => view.cluster_indices[i],
+                    });
+                }
+            }
+            Arc::new(PreviewMap { scale, width, height, indices })
+        });
+
+        Self {
+            width: view.width,
+            height: view.height,
+            complete,
+            pixels: Arc::new(view.pixels.to_vec()),
+            clusters: Arc::new(clusters),
+            clusters_output: Arc::new(view.clusters_output.to_vec()),
+            preview,
+        }
+    }
+
+    pub fn get_cluster(&self, index: ClusterIndex) -> &ClusterSummary {
+        &self.clusters[index.0 as usize]
+    }
+
+    /// Renders the preview map, at its scale. A complete snapshot renders like `ClustersView::to_color_image`,
+    /// otherwise each pixel takes the average color of the cluster it currently belongs to.
+    ///
+    /// Without a preview map, only the bounding rects of the output clusters can be drawn, at full scale.
+    pub fn render_preview(&self) -> ColorImage {
+        let preview = match &self.preview {
+            Some(preview) => preview,
+            None => return self.render_rects(),
+        };
+        let mut image = ColorImage::new_w_h(preview.width as usize, preview.height as usize);
+        for (i, &index) in preview.indices.iter().enumerate() {
+            if index == ZERO {
+                continue;
+            }
+            image.set_pixel_at(i, &self.preview_color(index));
+        }
+        image
+    }
+
+    fn preview_color(&self, index: ClusterIndex) -> Color {
+        let cluster = self.get_cluster(index);
+        if self.complete { cluster.residue_color } else { cluster.color }
+    }
+
+    fn render_rects(&self) -> ColorImage {
+        let mut image = ColorImage::new_w_h(self.width as usize, self.height as usize);
+        for &index in self.clusters_output.iter().rev() {
+            let cluster = self.get_cluster(index);
+            let color = self.preview_color(index);
+            for y in cluster.rect.top..cluster.rect.bottom {
+                for x in cluster.rect.left..cluster.rect.right {
+                    image.set_pixel(x as usize, y as usize, &color);
+                }
+            }
+        }
+        image
+    }
+}
+
+/// The topmost output cluster claiming each pixel, as rendered by `ClustersView::to_color_image`
+fn topmost_outputs(view: &ClustersView) -> Vec<ClusterIndex> {
+    let mut owner = vec![ZERO; view.cluster_indices.len()];
+    for &index in view.clusters_output.iter().rev() {
+        let cluster = view.get_cluster(index);
+        let holes: HashSet<u32> = cluster.holes.iter().copied().collect();
+        for &i in cluster.iter().filter(|i| !holes.contains(i)) {
+            owner[i as usize] = index;
+        }
+    }
+    owner
+}
+
+impl Clusters {
+    /// Takes a complete snapshot of the clustering result, see `IncrementalBuilder::snapshot`
+    pub fn snapshot(&self, preview_scale: Option<u32>) -> ClustersSnapshot {
+        ClustersSnapshot::new(&self.view(), true, preview_scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_clusters::{Runner, RunnerConfig};
+
+    fn test_image() -> ColorImage {
+        let (width, height) = (24, 20);
+        let mut image = ColorImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = (((x / 5) * 7 + (y / 4) * 13) % 4) as u8 * 60;
+                image.set_pixel(x, y, &Color::new(c, 255 - c, c / 2));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn snapshot_while_running() {
+        let config = RunnerConfig {
+            good_min_area: 4,
+            batch_size: 64,
+            ..Default::default()
+        };
+        let mut builder = Runner::new(config, test_image()).start();
+        let mut snapshots = Vec::new();
+        let mut ticks = 0;
+        while !builder.tick() {
+            ticks += 1;
+            if ticks % 3 == 1 {
+                snapshots.push(builder.snapshot(Some(2)));
+            }
+        }
+        assert!(snapshots.len() > 2);
+        let handle = std::thread::spawn(move || {
+            snapshots.iter().map(|s| s.render_preview()).collect::<Vec<_>>()
+        });
+        for preview in handle.join().unwrap() {
+            assert_eq!((preview.width, preview.height), (12, 10));
+        }
+
+        let last = builder.snapshot(Some(2));
+        assert!(last.complete);
+        assert!(last.clone().clusters.len() > 1);
+        let preview = last.render_preview();
+        let full = builder.result().view().to_color_image();
+        for y in 0..10 {
+            for x in 0..12 {
+                assert_eq!(preview.get_pixel(x, y), full.get_pixel(x * 2, y * 2));
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_without_preview() {
+        let clusters = Runner::new(RunnerConfig::default(), test_image()).run();
+        let snapshot = clusters.snapshot(None);
+        assert!(snapshot.preview.is_none());
+        let image = snapshot.render_preview();
+        assert_eq!((image.width, image.height), (24, 20));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::ColorImage;
+use super::{BuilderConfig, BuilderImpl, Builder, Clusters, Runner, RunnerConfig};
+
+/// A point on the curve of `threshold_sweep`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepPoint {
+    pub threshold: i32,
+    /// Number of output clusters
+    pub output_clusters: usize,
+    /// Mean area of the output clusters, 0 if there are none
+    pub mean_cluster_area: f64,
+}
+
+/// Clusters `image` as `color_image_to_svg` does with `base_config`, once per threshold, for e.g. a slider with live feedback.
+///
+/// The threshold is the `deepen_diff` of `RunnerConfig`: a cluster of good size is kept as an output
+/// if its closest neighbour differs by more than it, so the number of clusters falls as the threshold rises.
+///
+/// Stage 1 (labeling) runs once and its result is shared; stage 2 (merging) runs per threshold,
+/// as the merges depend on the colors left by earlier outputs.
+pub fn threshold_sweep(image: &ColorImage, thresholds: &[i32], base_config: &BuilderConfig) -> Vec<SweepPoint> {
+    let first = match thresholds.first() {
+        Some(&first) => first,
+        None => return Vec::new(),
+    };
+    let mut stage_1 = BuilderImpl::from(sweep_builder(image.clone(), first, base_config));
+    while !stage_1.is_past_stage_1() {
+        stage_1.tick();
+    }
+
+    thresholds.iter().map(|&threshold| {
+        let mut bimpl = stage_1.fork(sweep_builder(ColorImage::new(), threshold, base_config));
+        while !bimpl.tick() {}
+        sweep_point(threshold, &bimpl.result())
+    }).collect()
+}
+
+fn sweep_builder(image: ColorImage, threshold: i32, base_config: &BuilderConfig) -> Builder {
+    let mut builder = Runner::new(RunnerConfig { deepen_diff: threshold, ..RunnerConfig::default() }, image).builder();
+    builder.conf = base_config.clone();
+    builder
+}
+
+fn sweep_point(threshold: i32, clusters: &Clusters) -> SweepPoint {
+    let areas: Vec<usize> = clusters.iter_output().map(|(_, cluster)| cluster.area()).collect();
+    SweepPoint {
+        threshold,
+        output_clusters: areas.len(),
+        mean_cluster_area: if areas.is_empty() { 0.0 } else { areas.iter().sum::<usize>() as f64 / areas.len() as f64 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn threshold_sweep_matches_full_runs() {
+        // 16x16 squares with neighbours differing by 12 to 180 in the sum of channel differences
+        let mut image = ColorImage::new_w_h(64, 64);
+        for y in 0..64 {
+            for x in 0..64 {
+                let (i, j) = (x / 16, y / 16);
+                let color = Color::new((i * 60) as u8, (j * 4 + i * 4) as u8, (255 - j * 30) as u8);
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let config = BuilderConfig::default();
+        let thresholds = [0, 10, 20, 40, 60, 100, 200, 800];
+        let curve = threshold_sweep(&image, &thresholds, &config);
+
+        assert_eq!(curve.iter().map(|p| p.threshold).collect::<Vec<_>>(), thresholds.to_vec());
+        assert!(curve.windows(2).all(|pair| pair[0].output_clusters >= pair[1].output_clusters));
+        assert!(curve[0].output_clusters > curve[thresholds.len() - 1].output_clusters);
+
+        for &k in [1, 3, 6].iter() {
+            let clusters = sweep_builder(image.clone(), thresholds[k], &config).run();
+            assert_eq!(sweep_point(thresholds[k], &clusters), curve[k]);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use crate::{BoundingRect, PointF64};
+use super::{Cluster, ClusterIndex, ClustersView, ZERO};
+
+/// Matches the output clusters of `next` to those of `prev`, two clusterings of consecutive frames of the same size,
+/// e.g. to keep colors or ids stable across an animation.
+///
+/// A pair is a candidate if the IoU (intersection over union) of their bounding rects is at least `min_iou`,
+/// their centroids are at most `max_centroid_distance` apart and they share pixels.
+/// Candidates are then matched greedily by descending pixel overlap, each cluster at most once.
+///
+/// Returns every output cluster of `next` in output order, with its match in `prev` if any.
+pub fn match_clusters(
+    prev: &ClustersView,
+    next: &ClustersView,
+    max_centroid_distance: f64,
+    min_iou: f64,
+) -> Vec<(Option<ClusterIndex>, ClusterIndex)> {
+    assert_eq!((prev.width, prev.height), (next.width, next.height));
+    let centroids = |view: &ClustersView| -> Vec<PointF64> {
+        view.clusters_output.iter().map(|&index| centroid(view.get_cluster(index), view.width)).collect()
+    };
+    let (prev_centroids, next_centroids) = (centroids(prev), centroids(next));
+    let (prev_owners, next_owners) = (owners(prev), owners(next));
+
+    let mut candidates = Vec::new();
+    for (n, &next_index) in next.clusters_output.iter().enumerate() {
+        let next_cluster = next.get_cluster(next_index);
+        for (p, &prev_index) in prev.clusters_output.iter().enumerate() {
+            let prev_cluster = prev.get_cluster(prev_index);
+            if iou(prev_cluster.rect, next_cluster.rect) < min_iou ||
+                prev_centroids[p].distance_to(next_centroids[n]) > max_centroid_distance {
+                continue;
+            }
+            // count the pixels of the smaller cluster shown by the other one
+            let overlap = if next_cluster.area() <= prev_cluster.area() {
+                next_cluster.iter().filter(|&&i| prev_owners[i as usize] == prev_index).count()
+            } else {
+                prev_cluster.iter().filter(|&&i| next_owners[i as usize] == next_index).count()
+            };
+            if overlap > 0 {
+                candidates.push((overlap, p, n));
+            }
+        }
+    }
+    candidates.sort_by_key(|&(overlap, p, n)| (std::cmp::Reverse(overlap), p, n));
+
+    let mut matches = vec![None; next.clusters_output.len()];
+    let mut taken = vec![false; prev.clusters_output.len()];
+    for (_, p, n) in candidates {
+        if !taken[p] && matches[n].is_none() {
+            taken[p] = true;
+            matches[n] = Some(prev.clusters_output[p]);
+        }
+    }
+    matches.into_iter().zip(next.clusters_output.iter().copied()).collect()
+}
+
+/// The output cluster shown at each pixel, the topmost one where outputs are stacked
+/// (as in `ClustersView::to_color_image`), holes respected.
+/// `cluster_indices` cannot be used, as the pixels of deepened clusters are also indexed to their parent.
+fn owners(view: &ClustersView) -> Vec<ClusterIndex> {
+    let mut owners = vec![ZERO; (view.width * view.height) as usize];
+    for &index in view.clusters_output.iter().rev() {
+        let cluster = view.get_cluster(index);
+        let holes: HashSet<u32> = cluster.holes.iter().copied().collect();
+        for &i in cluster.iter().filter(|i| !holes.contains(i)) {
+            owners[i as usize] = index;
+        }
+    }
+    owners
+}
+
+fn centroid(cluster: &Cluster, width: u32) -> PointF64 {
+    let mut sum = PointF64::default();
+    for &i in cluster.iter() {
+        sum += PointF64::new((i % width) as f64, (i / width) as f64);
+    }
+    sum * (1.0 / std::cmp::max(cluster.area(), 1) as f64)
+}
+
+fn iou(a: BoundingRect, b: BoundingRect) -> f64 {
+    if !a.is_valid() || !b.is_valid() {
+        return 0.0;
+    }
+    let width = std::cmp::min(a.right, b.right) - std::cmp::max(a.left, b.left);
+    let height = std::cmp::min(a.bottom, b.bottom) - std::cmp::max(a.top, b.top);
+    let intersection = if width > 0 && height > 0 { (width * height) as f64 } else { 0.0 };
+    let union = (a.width() * a.height() + b.width() * b.height()) as f64 - intersection;
+    if union > 0.0 { intersection / union } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, ColorImage};
+    use crate::color_clusters::{Runner, RunnerConfig};
+
+    #[test]
+    fn iou_invalid_rect() {
+        let rect = BoundingRect::new_x_y_w_h(0, 0, 4, 4);
+        assert_eq!(iou(BoundingRect::INVALID, rect), 0.0);
+        assert_eq!(iou(BoundingRect::INVALID, BoundingRect::INVALID), 0.0);
+        assert_eq!(iou(rect, rect), 1.0);
+    }
+
+    fn scene(offset: usize, extra: bool) -> ColorImage {
+        let mut image = ColorImage::new_w_h(48, 32);
+        for y in 0..32 {
+            for x in 0..48 {
+                let color = if (4 + offset..14 + offset).contains(&x) && (4..14).contains(&y) {
+                    Color::new(255, 0, 0)
+                } else if (20 + offset..30 + offset).contains(&x) && (8..24).contains(&y) {
+                    Color::new(0, 0, 255)
+                } else if extra && (36..44).contains(&x) && (20..28).contains(&y) {
+                    Color::new(255, 255, 0)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn match_translated_scene() {
+        let prev = Runner::new(RunnerConfig::default(), scene(0, false)).run();
+        let next = Runner::new(RunnerConfig::default(), scene(2, true)).run();
+        let (prev_view, next_view) = (prev.view(), next.view());
+        let matches = match_clusters(&prev_view, &next_view, 4.0, 0.3);
+        assert_eq!(matches.len(), 4);
+        assert_eq!(matches.iter().filter(|(prev_index, _)| prev_index.is_none()).count(), 1);
+        for (prev_index, next_index) in matches {
+            let color = next_view.get_cluster(next_index).residue_color();
+            match prev_index {
+                Some(prev_index) => assert_eq!(prev_view.get_cluster(prev_index).residue_color(), color),
+                None => assert_eq!(color, Color::new(255, 255, 0)),
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::ColorImage;
+use super::{Cluster, Clusters, container::ClusterIndex, builder::Area};
+
+/// Buffers that can be reused across `Builder::run_with_workspace` calls,
+/// e.g. when clustering every frame of a video at the same resolution.
+///
+/// Each run moves the buffers out into the returned `Clusters`; `recycle` moves them back.
+#[derive(Default)]
+pub struct ClusterWorkspace {
+    pub(crate) cluster_indices: Vec<ClusterIndex>,
+    pub(crate) clusters: Vec<Cluster>,
+    pub(crate) spare: Vec<Cluster>,
+    pub(crate) cluster_areas: Vec<Area>,
+    pub(crate) clusters_output: Vec<ClusterIndex>,
+}
+
+impl ClusterWorkspace {
+    /// Creates a workspace with buffers sized for frames of `width` x `height`.
+    /// Frames of other sizes still work, but the buffers are reallocated.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            cluster_indices: Vec::with_capacity(width * height),
+            ..Default::default()
+        }
+    }
+
+    /// Takes back the buffers of `clusters` for the next run, returning the clustered image
+    /// so that its pixel buffer can be reused for the next frame as well.
+    pub fn recycle(&mut self, mut clusters: Clusters) -> ColorImage {
+        self.cluster_indices = std::mem::take(&mut clusters.cluster_indices);
+        self.clusters_output = std::mem::take(&mut clusters.clusters_output);
+        self.spare.append(&mut clusters.clusters);
+        self.clusters = std::mem::take(&mut clusters.clusters);
+        clusters.take_image()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Color;
+    use super::*;
+    use super::super::{Runner, RunnerConfig};
+
+    fn frame(seed: u32) -> ColorImage {
+        let (width, height) = (32, 24);
+        let mut image = ColorImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let v = ((x as u32 / 6 + seed) * 5 + (y as u32 / 5) * 3 + seed) % 4;
+                let c = (v * 80) as u8;
+                image.set_pixel(x, y, &Color::new(c, 255 - c, (c / 2) ^ (seed as u8 * 40)));
+            }
+        }
+        image
+    }
+
+    fn assert_same(a: &Clusters, b: &Clusters) {
+        assert!(a.cluster_indices == b.cluster_indices);
+        assert!(a.clusters_output == b.clusters_output);
+        assert_eq!(a.clusters.len(), b.clusters.len());
+        for (ca, cb) in a.clusters.iter().zip(b.clusters.iter()) {
+            assert_eq!(ca.indices, cb.indices);
+            assert_eq!(ca.holes, cb.holes);
+            assert_eq!(ca.rect, cb.rect);
+            assert_eq!(ca.sum.counter, cb.sum.counter);
+            if ca.sum.counter > 0 {
+                assert_eq!(ca.color(), cb.color());
+            }
+        }
+    }
+
+    #[test]
+    fn workspace_matches_standalone() {
+        let mut ws = ClusterWorkspace::new(32, 24);
+        for seed in 0..3 {
+            let expected = Runner::new(RunnerConfig::default(), frame(seed)).run();
+            let actual = Runner::new(RunnerConfig::default(), frame(seed)).builder().run_with_workspace(&mut ws);
+            assert_same(&expected, &actual);
+            ws.recycle(actual);
+        }
+    }
+}
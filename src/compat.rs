@@ -0,0 +1,63 @@
+//! Pinning the behaviours that affect output, for results reproducible across upgrades
+
+/// A set of behaviours kept across upgrades, see `CompatFlags`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompatLevel {
+    /// The behaviours of the 0.8 releases, for exactly the fields of `CompatFlags`:
+    /// packed sort keys, alpha ignored when clustering, traced shapes in slot order and no `fill-opacity`.
+    ///
+    /// Nothing else is pinned. Seam placement, the reuse of cluster slots while merging, the render order
+    /// of color clusters and the default tolerances follow the current release, and so do fixes made since 0.8, e.g. to the simplification
+    /// of degenerate paths, to `Path::reduce` where extreme points coincide and to the tie-break of
+    /// `Cluster::break_cluster`.
+    Legacy,
+    /// The current behaviours, which may change with new releases
+    #[default]
+    Latest,
+}
+
+/// The behaviours differing between releases, resolved from a `CompatLevel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatFlags {
+    /// Whether output clusters and merge candidates are sorted by keys packed into one integer,
+    /// which collide beyond 65535 clusters, instead of by tuples
+    pub packed_sort_keys: bool,
+    /// Whether clusters of colors differing in alpha are kept apart, see `Builder::ignore_alpha`
+    pub compare_alpha: bool,
+    /// Whether the shapes traced by `binary_image_to_svg` and `bands_to_svg` are in raster order of their first pixel,
+    /// instead of in the order of the slots left by merging clusters
+    pub raster_order: bool,
+    /// Whether svg documents give translucent colors a `fill-opacity`, instead of only their rgb hex
+    pub fill_opacity: bool,
+}
+
+impl CompatLevel {
+    pub fn flags(self) -> CompatFlags {
+        match self {
+            Self::Legacy => CompatFlags {
+                packed_sort_keys: true,
+                compare_alpha: false,
+                raster_order: false,
+                fill_opacity: false,
+            },
+            Self::Latest => CompatFlags {
+                packed_sort_keys: false,
+                compare_alpha: true,
+                raster_order: true,
+                fill_opacity: true,
+            },
+        }
+    }
+}
+
+impl From<CompatLevel> for CompatFlags {
+    fn from(level: CompatLevel) -> Self {
+        level.flags()
+    }
+}
+
+impl Default for CompatFlags {
+    fn default() -> Self {
+        CompatLevel::default().flags()
+    }
+}
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Write;
 
 pub use bit_vec::BitVec;
 
-use crate::{BoundingRect, Color, ColorName, ColorType, Field, PointF32, PointF64, PointI32};
+use crate::{BoundingRect, Color, ColorName, ColorType, Field, PathI32, PointF32, PointF64, PointI32};
+use crate::rasterizer::bresenham;
 
 /// Image with 1 bit per pixel
 #[derive(Debug, Clone, Default)]
@@ -24,6 +26,14 @@ pub type MonoImageItem = u16;
 /// Image with grayscale values
 pub type MonoImage = ScalerField<MonoImageItem>;
 
+/// Image with 1 byte (gray level) per pixel
+#[derive(Debug, Clone, Default)]
+pub struct GrayImage {
+    pub pixels: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+}
+
 /// Image with 4 bytes per pixel
 #[derive(Clone, Default)]
 pub struct ColorImage {
@@ -32,6 +42,30 @@ pub struct ColorImage {
     pub height: usize,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors from accessing a malformed or too small image
+pub enum ImageError {
+    /// The pixel buffer does not hold 4 bytes for each of the `width * height` pixels
+    BufferSizeMismatch { width: usize, height: usize, len: usize },
+    /// The pixel index is not less than `width * height`
+    IndexOutOfBounds { index: usize, size: usize },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BufferSizeMismatch { width, height, len } => write!(
+                f, "Invalid image! A {}x{} image needs {} bytes, got {}.", width, height, width * height * 4, len
+            ),
+            Self::IndexOutOfBounds { index, size } => write!(
+                f, "Pixel index {} out of bounds for an image of {} pixels.", index, size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
 /// Iterate over each pixel of ColorImage
 pub struct ColorImageIter<'a> {
     im: &'a ColorImage,
@@ -82,6 +116,20 @@ impl BinaryImage {
         self.pixels.set(i, v);
     }
 
+    /// Sets all `points` to `v`; the points must lie within the image
+    pub fn set_pixels(&mut self, points: &[PointI32], v: bool) {
+        for p in points {
+            self.pixels.set(p.y as usize * self.width + p.x as usize, v);
+        }
+    }
+
+    /// Creates an image with only `points` set, e.g. to reconstruct a mask from a point list
+    pub fn from_points(width: usize, height: usize, points: &[PointI32]) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(width, height);
+        image.set_pixels(points, true);
+        image
+    }
+
     pub fn set_pixel_safe(&mut self, x: i32, y: i32, v: bool) -> bool {
         if  x >= 0 && x < self.width as i32 &&
             y >= 0 && y < self.height as i32 {
@@ -180,7 +228,7 @@ impl BinaryImage {
                 let rotated = PointF64::new(x as f64, y as f64).rotate(origin, -angle).translate(-offset);
                 rotated_image.set_pixel(
                     x, y,
-                    self.get_pixel_safe(rotated.x.round() as i32, rotated.y.round() as i32)
+                    self.get_pixel_at_safe(rotated.round())
                 );
             }
         }
@@ -202,6 +250,31 @@ impl BinaryImage {
         }
     }
 
+    /// Returns `levels` images starting with a copy of this one, each half the size of the previous (rounded up),
+    /// where a pixel is set if at least half of the 2x2 block (within the image) it covers is set
+    pub fn pyramid(&self, levels: usize) -> Vec<BinaryImage> {
+        let mut pyramid: Vec<BinaryImage> = Vec::with_capacity(levels);
+        for level in 0..levels {
+            pyramid.push(if level == 0 { self.clone() } else { pyramid[level - 1].half() });
+        }
+        pyramid
+    }
+
+    fn half(&self) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(self.width / 2 + self.width % 2, self.height / 2 + self.height % 2);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let (mut count, mut set) = (0, 0);
+                for (xx, yy) in half_block(x, y, self.width, self.height) {
+                    count += 1;
+                    set += self.get_pixel(xx, yy) as usize;
+                }
+                image.set_pixel(x, y, 2 * set >= count);
+            }
+        }
+        image
+    }
+
     pub fn to_color_image(&self) -> ColorImage {
         let mut image = ColorImage::new_w_h(self.width, self.height);
         let black = Color::color(&ColorName::Black);
@@ -239,6 +312,16 @@ impl<T> ScalerField<T> where T: Default {
     }
 }
 
+impl<T> ScalerField<T> {
+    pub fn width(&self) -> usize {
+        self.field.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.field.height()
+    }
+}
+
 impl<T> ScalerField<T> where T: Clone {
     pub fn get_pixel(&self, x: usize, y: usize) -> T {
         self.field.get(self.field.index_at(x, y)).unwrap()
@@ -249,6 +332,36 @@ impl<T> ScalerField<T> where T: Clone {
     }
 }
 
+impl MonoImage {
+    /// Pixels with value >= `t` are set in the returned image
+    pub fn threshold(&self, t: MonoImageItem) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(self.width(), self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                if self.get_pixel(x, y) >= t {
+                    image.set_pixel(x, y, true);
+                }
+            }
+        }
+        image
+    }
+
+    /// Returns (x, y, value) of the pixel with the maximum value; the first one in raster order wins ties.
+    /// Returns (0, 0, 0) for an empty image.
+    pub fn max_value_pixel(&self) -> (usize, usize, MonoImageItem) {
+        let mut max = (0, 0, 0);
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let v = self.get_pixel(x, y);
+                if v > max.2 {
+                    max = (x, y, v);
+                }
+            }
+        }
+        max
+    }
+}
+
 impl Iterator for ColorImageIter<'_> {
     type Item = Color;
 
@@ -263,6 +376,117 @@ impl Iterator for ColorImageIter<'_> {
     }
 }
 
+impl GrayImage {
+    /// ITU-R BT.601 luma weights of red, green and blue, for `from_color_image`
+    pub const LUMA_WEIGHTS: [f64; 3] = [0.299, 0.587, 0.114];
+
+    pub fn new_w_h(width: usize, height: usize) -> Self {
+        Self {
+            pixels: vec![0; width * height],
+            width,
+            height,
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn get_pixel_safe(&self, x: i32, y: i32) -> Option<u8> {
+        if  x >= 0 && x < self.width as i32 &&
+            y >= 0 && y < self.height as i32 {
+            return Some(self.get_pixel(x as usize, y as usize));
+        }
+        None
+    }
+
+    pub fn get_pixel_at(&self, index: usize) -> u8 {
+        self.pixels[index]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, v: u8) {
+        self.pixels[y * self.width + x] = v;
+    }
+
+    pub fn set_pixel_at(&mut self, index: usize, v: u8) {
+        self.pixels[index] = v;
+    }
+
+    /// Sets the pixel if it lies inside the image, otherwise does nothing
+    pub fn set_pixel_safe(&mut self, x: i32, y: i32, v: u8) {
+        if  x >= 0 && x < self.width as i32 &&
+            y >= 0 && y < self.height as i32 {
+            self.set_pixel(x as usize, y as usize, v);
+        }
+    }
+
+    /// Converts to gray levels as the weighted sum of red, green and blue, e.g. with `GrayImage::LUMA_WEIGHTS`.
+    /// Alpha is ignored.
+    pub fn from_color_image(image: &ColorImage, weights: [f64; 3]) -> Self {
+        let mut gray = Self::new_w_h(image.width, image.height);
+        for (i, c) in image.iter().enumerate() {
+            let v = weights[0] * c.r as f64 + weights[1] * c.g as f64 + weights[2] * c.b as f64;
+            gray.pixels[i] = v.round().clamp(0.0, 255.0) as u8;
+        }
+        gray
+    }
+
+    /// Converts to an opaque color image with equal red, green and blue
+    pub fn to_color_image(&self) -> ColorImage {
+        let mut image = ColorImage::new_w_h(self.width, self.height);
+        for (i, &v) in self.pixels.iter().enumerate() {
+            image.set_pixel_at(i, &Color::new(v, v, v));
+        }
+        image
+    }
+
+    /// Pixels with value >= `t` are set in the returned image
+    pub fn to_binary_image(&self, t: u8) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(self.width, self.height);
+        for (i, &v) in self.pixels.iter().enumerate() {
+            if v >= t {
+                image.set_pixel_index(i, true);
+            }
+        }
+        image
+    }
+
+    /// crop a specific area from image; `rect` must lie within the image
+    pub fn crop_with_rect(&self, rect: BoundingRect) -> GrayImage {
+        let mut image = GrayImage::new_w_h(rect.width() as usize, rect.height() as usize);
+        let row = rect.width() as usize;
+        for y in 0..rect.height() as usize {
+            let src = (rect.top as usize + y) * self.width + rect.left as usize;
+            image.pixels[y * row..(y + 1) * row].copy_from_slice(&self.pixels[src..src + row]);
+        }
+        image
+    }
+
+    /// Resizes to `width` x `height` by bilinear interpolation, aligning the pixel centers of both images
+    pub fn resize_bilinear(&self, width: usize, height: usize) -> GrayImage {
+        let mut image = GrayImage::new_w_h(width, height);
+        if self.width == 0 || self.height == 0 {
+            return image;
+        }
+        // the source coordinate of destination pixel `i` along an axis, and the blend weight
+        let map = |i: usize, from: usize, to: usize| {
+            let p = ((i as f64 + 0.5) * from as f64 / to as f64 - 0.5).clamp(0.0, (from - 1) as f64);
+            let p0 = p.floor() as usize;
+            (p0, std::cmp::min(p0 + 1, from - 1), p - p0 as f64)
+        };
+        for y in 0..height {
+            let (y0, y1, fy) = map(y, self.height, height);
+            for x in 0..width {
+                let (x0, x1, fx) = map(x, self.width, width);
+                let top = self.get_pixel(x0, y0) as f64 * (1.0 - fx) + self.get_pixel(x1, y0) as f64 * fx;
+                let bottom = self.get_pixel(x0, y1) as f64 * (1.0 - fx) + self.get_pixel(x1, y1) as f64 * fx;
+                image.set_pixel(x, y, (top * (1.0 - fy) + bottom * fy).round() as u8);
+            }
+        }
+        image
+    }
+}
+
 impl ColorImage {
     pub fn new() -> Self {
         Default::default()
@@ -301,6 +525,49 @@ impl ColorImage {
         None
     }
 
+    /// Creates an image from pixels in BGRA byte order, as used by e.g. Windows GDI,
+    /// reordering them into RGBA
+    pub fn from_bgra(bytes: &[u8], width: usize, height: usize) -> Result<Self, ImageError> {
+        let image = Self {
+            pixels: bytes.to_vec(),
+            width,
+            height,
+        };
+        image.validate()?;
+        Ok(image.swap_rb())
+    }
+
+    /// Returns a copy with the red and blue channels swapped, converting between RGBA and BGRA byte order
+    pub fn swap_rb(&self) -> ColorImage {
+        let mut image = self.clone();
+        for pixel in image.pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        image
+    }
+
+    /// Checks that the pixel buffer holds exactly 4 bytes per pixel
+    pub fn validate(&self) -> Result<(), ImageError> {
+        if self.pixels.len() != self.width * self.height * 4 {
+            return Err(ImageError::BufferSizeMismatch {
+                width: self.width,
+                height: self.height,
+                len: self.pixels.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Same as `get_pixel_at`, but returns an error instead of panicking
+    /// if the image is malformed (see `validate`) or `index` is out of bounds
+    pub fn try_get_pixel_at(&self, index: usize) -> Result<Color, ImageError> {
+        self.validate()?;
+        if index >= self.width * self.height {
+            return Err(ImageError::IndexOutOfBounds { index, size: self.width * self.height });
+        }
+        Ok(self.get_pixel_at(index))
+    }
+
     pub fn get_pixel_at(&self, index: usize) -> Color {
         let index = index * 4;
         let r = self.pixels[index];
@@ -324,6 +591,127 @@ impl ColorImage {
         self.pixels[index + 3] = color.a;
     }
 
+    /// Sets the pixel if it lies inside the image, otherwise does nothing
+    pub fn set_pixel_safe(&mut self, x: i32, y: i32, color: &Color) {
+        if  x >= 0 && x < self.width as i32 &&
+            y >= 0 && y < self.height as i32 {
+            self.set_pixel(x as usize, y as usize, color);
+        }
+    }
+
+    /// Returns the tight bounding rect of the pixels with alpha above `alpha_threshold`,
+    /// e.g. the visible extent of content with soft edges; invalid if there is none
+    pub fn content_bounds(&self, alpha_threshold: u8) -> BoundingRect {
+        let mut rect = BoundingRect::default();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.pixels[(y * self.width + x) * 4 + 3] > alpha_threshold {
+                    rect.add_x_y(x as i32, y as i32);
+                }
+            }
+        }
+        rect
+    }
+
+    /// Composites the image over `bg` (whose alpha is ignored), leaving every pixel opaque,
+    /// e.g. before clustering an image with transparency meant for a known background
+    pub fn flatten_transparency(&self, bg: Color) -> ColorImage {
+        let mut image = self.clone();
+        for pixel in image.pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            let blend = |src: u8, dst: u8| ((src as u32 * alpha + dst as u32 * (255 - alpha) + 127) / 255) as u8;
+            pixel[0] = blend(pixel[0], bg.r);
+            pixel[1] = blend(pixel[1], bg.g);
+            pixel[2] = blend(pixel[2], bg.b);
+            pixel[3] = 255;
+        }
+        image
+    }
+
+    /// Counts the pixels in a coarse RGB histogram keeping the top `bits_per_channel` (1 to 8) bits of each channel,
+    /// e.g. to seed palette algorithms cheaply. The bucket of a color is `r << 2 * bits | g << bits | b`
+    /// with each channel shifted right to `bits`; alpha is ignored.
+    pub fn color_histogram_3d(&self, bits_per_channel: u32) -> HashMap<u32, u32> {
+        assert!((1..=8).contains(&bits_per_channel), "bits_per_channel must be within 1 to 8");
+        let bits = bits_per_channel;
+        let shift = 8 - bits;
+        let mut histogram = HashMap::new();
+        for pixel in self.pixels.chunks_exact(4) {
+            let (r, g, b) = ((pixel[0] >> shift) as u32, (pixel[1] >> shift) as u32, (pixel[2] >> shift) as u32);
+            *histogram.entry(r << (2 * bits) | g << bits | b).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// crop a specific area from image; `rect` must lie within the image
+    pub fn crop_with_rect(&self, rect: BoundingRect) -> ColorImage {
+        let mut image = ColorImage::new_w_h(rect.width() as usize, rect.height() as usize);
+        let row = rect.width() as usize * 4;
+        for y in 0..rect.height() as usize {
+            let src = ((rect.top as usize + y) * self.width + rect.left as usize) * 4;
+            image.pixels[y * row..(y + 1) * row].copy_from_slice(&self.pixels[src..src + row]);
+        }
+        image
+    }
+
+    /// Draws a line from `a` to `b` (inclusive); pixels outside the image are clipped.
+    /// The line is clipped before it is rasterized, so endpoints far outside the image are cheap.
+    pub fn draw_line(&mut self, a: PointI32, b: PointI32, color: Color) {
+        let (a, b) = match clip_line(a, b, self.width, self.height) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        for p in bresenham(a, b) {
+            self.set_pixel_safe(p.x, p.y, &color);
+        }
+    }
+
+    /// Draws the outline of `rect`, i.e. the outermost pixels inside it
+    pub fn draw_rect(&mut self, rect: BoundingRect, color: Color) {
+        if rect.width() <= 0 || rect.height() <= 0 {
+            return;
+        }
+        let (left, top) = (rect.left, rect.top);
+        let (right, bottom) = (rect.right - 1, rect.bottom - 1);
+        self.draw_line(PointI32::new(left, top), PointI32::new(right, top), color);
+        self.draw_line(PointI32::new(left, bottom), PointI32::new(right, bottom), color);
+        self.draw_line(PointI32::new(left, top), PointI32::new(left, bottom), color);
+        self.draw_line(PointI32::new(right, top), PointI32::new(right, bottom), color);
+    }
+
+    /// Draws lines connecting consecutive points of `path`, and the last point to the first if `close`
+    pub fn draw_path(&mut self, path: &PathI32, color: Color, close: bool) {
+        for pair in path.path.windows(2) {
+            self.draw_line(pair[0], pair[1], color);
+        }
+        if let [first, .., last] = path.path[..] {
+            if close {
+                self.draw_line(last, first, color);
+            }
+        } else if let [only] = path.path[..] {
+            self.set_pixel_safe(only.x, only.y, &color);
+        }
+    }
+
+    /// Blends `color` with opacity `alpha` onto the pixels set in `mask`, aligned at the top left corner.
+    /// The alpha channel of the image is left untouched.
+    pub fn draw_binary_overlay(&mut self, mask: &BinaryImage, color: Color, alpha: u8) {
+        let blend = |dst: u8, src: u8| {
+            ((src as u32 * alpha as u32 + dst as u32 * (255 - alpha as u32) + 127) / 255) as u8
+        };
+        for y in 0..std::cmp::min(self.height, mask.height) {
+            for x in 0..std::cmp::min(self.width, mask.width) {
+                if mask.get_pixel(x, y) {
+                    let c = self.get_pixel(x, y);
+                    let blended = Color::new_rgba(
+                        blend(c.r, color.r), blend(c.g, color.g), blend(c.b, color.b), c.a
+                    );
+                    self.set_pixel(x, y, &blended);
+                }
+            }
+        }
+    }
+
     pub fn to_binary_image<F>(&self, f: F) -> BinaryImage
         where F: Fn(Color) -> bool {
         let mut image = BinaryImage::new_w_h(self.width, self.height);
@@ -335,6 +723,37 @@ impl ColorImage {
         image
     }
 
+    /// Returns `levels` images starting with a copy of this one, each half the size of the previous (rounded up),
+    /// where a pixel is the average (per channel, rounded) of the 2x2 block (within the image) it covers
+    pub fn pyramid(&self, levels: usize) -> Vec<ColorImage> {
+        let mut pyramid: Vec<ColorImage> = Vec::with_capacity(levels);
+        for level in 0..levels {
+            pyramid.push(if level == 0 { self.clone() } else { pyramid[level - 1].half() });
+        }
+        pyramid
+    }
+
+    fn half(&self) -> ColorImage {
+        let mut image = ColorImage::new_w_h(self.width / 2 + self.width % 2, self.height / 2 + self.height % 2);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let (mut count, mut sums) = (0, [0u32; 4]);
+                for (xx, yy) in half_block(x, y, self.width, self.height) {
+                    count += 1;
+                    let i = (yy * self.width + xx) * 4;
+                    for (sum, &v) in sums.iter_mut().zip(&self.pixels[i..i + 4]) {
+                        *sum += v as u32;
+                    }
+                }
+                let average = |sum: u32| ((sum + count / 2) / count) as u8;
+                image.set_pixel(x, y, &Color::new_rgba(
+                    average(sums[0]), average(sums[1]), average(sums[2]), average(sums[3])
+                ));
+            }
+        }
+        image
+    }
+
     pub fn sample_pixel_at(&self, p: PointF32) -> Color {
         bilinear_interpolate(self, p)
     }
@@ -342,6 +761,18 @@ impl ColorImage {
     pub fn sample_pixel_at_safe(&self, p:PointF32) -> Option<Color> {
         bilinear_interpolate_safe(self, p)
     }
+
+    /// Like `sample_pixel_at`, but interpolates in premultiplied alpha,
+    /// so transparent neighbours do not darken the result
+    pub fn sample_pixel_at_premultiplied(&self, p: PointF32) -> Color {
+        bilinear_interpolate_premultiplied(self, p)
+    }
+}
+
+/// The pixels of a `width` x `height` image within the 2x2 block covered by pixel (x, y) of its half-sized image
+fn half_block(x: usize, y: usize, width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    (2 * y..std::cmp::min(2 * y + 2, height))
+        .flat_map(move |yy| (2 * x..std::cmp::min(2 * x + 2, width)).map(move |xx| (xx, yy)))
 }
 
 pub fn bilinear_interpolate_safe(im: &ColorImage, p: PointF32) -> Option<Color> {
@@ -384,10 +815,92 @@ pub fn bilinear_interpolate(im: &ColorImage, p: PointF32) -> Color {
     )
 }
 
+/// Bilinear interpolation on premultiplied alpha; a fully transparent result is transparent black
+pub fn bilinear_interpolate_premultiplied(im: &ColorImage, p: PointF32) -> Color {
+    let x = p.x - p.x.floor();
+    let y = p.y - p.y.floor();
+    let neighbours = [
+        (im.get_pixel(p.x.floor() as usize, p.y.floor() as usize), (1.0 - x) * (1.0 - y)),
+        (im.get_pixel(p.x.ceil() as usize, p.y.floor() as usize), x * (1.0 - y)),
+        (im.get_pixel(p.x.floor() as usize, p.y.ceil() as usize), (1.0 - x) * y),
+        (im.get_pixel(p.x.ceil() as usize, p.y.ceil() as usize), x * y),
+    ];
+
+    let mut sums = [0.0f32; 4];
+    for (color, weight) in neighbours.iter() {
+        let alpha = color.a as f32 / 255.0;
+        sums[0] += color.r as f32 * alpha * weight;
+        sums[1] += color.g as f32 * alpha * weight;
+        sums[2] += color.b as f32 * alpha * weight;
+        sums[3] += color.a as f32 * weight;
+    }
+
+    let a = sums[3] as u8;
+    if a == 0 {
+        return Color::new_rgba(0, 0, 0, 0);
+    }
+    let unpremultiply = |sum: f32| (sum * 255.0 / sums[3]).round().min(255.0) as u8;
+    Color::new_rgba(unpremultiply(sums[0]), unpremultiply(sums[1]), unpremultiply(sums[2]), a)
+}
+
+/// Clips the line from `a` to `b` to the pixels of a `width` x `height` image (Liang-Barsky),
+/// rounding the endpoints moved onto its border. Returns `None` if the line misses the image.
+fn clip_line(a: PointI32, b: PointI32, width: usize, height: usize) -> Option<(PointI32, PointI32)> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let (ax, ay) = (a.x as f64, a.y as f64);
+    let (dx, dy) = (b.x as f64 - ax, b.y as f64 - ay);
+    // the outer edges of the border pixels
+    let (min, max_x, max_y) = (-0.5, width as f64 - 0.5, height as f64 - 0.5);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for (p, q) in [(-dx, ax - min), (dx, max_x - ax), (-dy, ay - min), (dy, max_y - ay)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| PointI32::new((ax + t * dx).round() as i32, (ay + t * dy).round() as i32);
+    Some((if t0 > 0.0 { at(t0) } else { a }, if t1 < 1.0 { at(t1) } else { b }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn crop_with_invalid_rect() {
+        let inverted = BoundingRect { left: 3, top: 3, right: 1, bottom: 1 };
+        for rect in [BoundingRect::INVALID, inverted].iter() {
+            let binary = BinaryImage::new_w_h(4, 4).crop_with_rect(*rect);
+            assert_eq!((binary.width, binary.height), (0, 0));
+            let gray = GrayImage::new_w_h(4, 4).crop_with_rect(*rect);
+            assert_eq!((gray.width, gray.height), (0, 0));
+            let color = ColorImage::new_w_h(4, 4).crop_with_rect(*rect);
+            assert_eq!((color.width, color.height), (0, 0));
+        }
+    }
+
+    #[test]
+    fn sample_pixel_at_premultiplied() {
+        let mut image = ColorImage::new_w_h(2, 2);
+        image.set_pixel(0, 0, &Color::new(255, 0, 0));
+        image.set_pixel(1, 0, &Color::new_rgba(0, 0, 0, 0));
+        let mid = PointF32::new(0.5, 0.0);
+        assert_eq!(image.sample_pixel_at_premultiplied(mid), Color::new_rgba(255, 0, 0, 127));
+        assert_eq!(image.sample_pixel_at(mid), Color::new_rgba(127, 0, 0, 127));
+        assert_eq!(image.sample_pixel_at_premultiplied(PointF32::new(0.5, 1.0)), Color::new_rgba(0, 0, 0, 0));
+        assert_eq!(image.sample_pixel_at_premultiplied(PointF32::new(0.0, 0.0)), Color::new(255, 0, 0));
+    }
+
     #[test]
     fn binary_image_crop() {
         let mut image = BinaryImage::new_w_h(4, 4);
@@ -402,6 +915,121 @@ mod tests {
         assert_eq!(crop.get_pixel(1, 1), true);
     }
 
+    #[test]
+    fn binary_image_from_points() {
+        let points = [PointI32::new(0, 0), PointI32::new(3, 1), PointI32::new(2, 2)];
+        let mut image = BinaryImage::from_points(4, 3, &points);
+        assert_eq!(image.to_string(),
+            "*---\n".to_owned()+
+            "---*\n"+
+            "--*-\n");
+        image.set_pixels(&points[1..], false);
+        assert_eq!(image.area(), 1);
+        assert!(image.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn color_image_validate() {
+        let mut image = ColorImage::new_w_h(3, 2);
+        image.set_pixel(2, 1, &Color::new(1, 2, 3));
+        assert_eq!(image.validate(), Ok(()));
+        assert_eq!(image.try_get_pixel_at(5), Ok(Color::new(1, 2, 3)));
+        assert_eq!(image.try_get_pixel_at(6), Err(ImageError::IndexOutOfBounds { index: 6, size: 6 }));
+
+        image.width = 4;
+        assert_eq!(image.validate(), Err(ImageError::BufferSizeMismatch { width: 4, height: 2, len: 24 }));
+        assert!(image.try_get_pixel_at(7).is_err());
+    }
+
+    #[test]
+    fn gray_image_luminance() {
+        let mut color = ColorImage::new_w_h(4, 1);
+        color.set_pixel(0, 0, &Color::new(255, 0, 0));
+        color.set_pixel(1, 0, &Color::new(0, 255, 0));
+        color.set_pixel(2, 0, &Color::new(0, 0, 255));
+        color.set_pixel(3, 0, &Color::new(255, 255, 255));
+        let gray = GrayImage::from_color_image(&color, GrayImage::LUMA_WEIGHTS);
+        assert_eq!(gray.pixels, vec![76, 150, 29, 255]);
+
+        let color = gray.to_color_image();
+        assert_eq!(color.get_pixel(1, 0), Color::new(150, 150, 150));
+        assert_eq!(gray.to_binary_image(100).to_string(), "-*-*\n");
+        assert_eq!(gray.get_pixel_safe(4, 0), None);
+    }
+
+    #[test]
+    fn gray_image_crop_resize() {
+        let mut image = GrayImage::new_w_h(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                image.set_pixel(x, y, (y * 4 + x) as u8 * 10);
+            }
+        }
+        let crop = image.crop_with_rect(BoundingRect::new_x_y_w_h(1, 2, 2, 2));
+        assert_eq!((crop.width, crop.height), (2, 2));
+        assert_eq!(crop.pixels, vec![90, 100, 130, 140]);
+
+        assert_eq!(image.resize_bilinear(4, 4).pixels, image.pixels);
+        let half = image.resize_bilinear(2, 2);
+        // each pixel is the average of a 2x2 block
+        assert_eq!(half.pixels, vec![25, 45, 105, 125]);
+        let double = crop.resize_bilinear(4, 4);
+        assert_eq!(double.get_pixel(0, 0), 90);
+        assert_eq!(double.get_pixel(3, 3), 140);
+        assert_eq!(double.get_pixel(1, 0), 93);
+    }
+
+    #[test]
+    fn color_image_from_bgra() {
+        let blue_bgra = [255, 0, 0, 255, 255, 0, 0, 255];
+        let image = ColorImage::from_bgra(&blue_bgra, 2, 1).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Color::new(0, 0, 255));
+        assert_eq!(image.get_pixel(1, 0), Color::new(0, 0, 255));
+        assert_eq!(image.swap_rb().pixels, blue_bgra);
+        assert!(ColorImage::from_bgra(&blue_bgra, 3, 1).is_err());
+    }
+
+    #[test]
+    fn color_image_pyramid() {
+        let mut image = ColorImage::new_w_h(8, 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                image.set_pixel(x, y, &Color::new((x * 30) as u8, (y * 30) as u8, ((x + y) * 10) as u8));
+            }
+        }
+        let pyramid = image.pyramid(3);
+        let sizes: Vec<_> = pyramid.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, vec![(8, 8), (4, 4), (2, 2)]);
+        assert_eq!(pyramid[0].pixels, image.pixels);
+        for level in 1..3 {
+            let (previous, current) = (&pyramid[level - 1], &pyramid[level]);
+            for y in 0..current.height {
+                for x in 0..current.width {
+                    let block = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| previous.get_pixel(2 * x + dx, 2 * y + dy));
+                    let average = |channel: fn(&Color) -> u8| {
+                        ((block.iter().map(|c| channel(c) as u32).sum::<u32>() + 2) / 4) as u8
+                    };
+                    assert_eq!(current.get_pixel(x, y), Color::new_rgba(
+                        average(|c| c.r), average(|c| c.g), average(|c| c.b), average(|c| c.a)
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn binary_image_pyramid() {
+        let image = BinaryImage::from_string(&(
+            "**-*-\n".to_owned()+
+            "*----\n"+
+            "---**\n"));
+        let pyramid = image.pyramid(3);
+        assert_eq!(pyramid[1].to_string(),
+            "*--\n".to_owned()+
+            "-**\n");
+        assert_eq!(pyramid[2].to_string(), "**\n");
+    }
+
     #[test]
     fn image_as_string() {
         let mut image = BinaryImage::new_w_h(2,2);
@@ -420,6 +1048,79 @@ mod tests {
         }
     }
 
+    #[test]
+    fn mono_image_threshold() {
+        let mut image = MonoImage::new_w_h(3, 2);
+        image.set_pixel(0, 0, 1);
+        image.set_pixel(1, 0, 5);
+        image.set_pixel(2, 0, 3);
+        image.set_pixel(0, 1, 7);
+        image.set_pixel(2, 1, 7);
+        assert_eq!(image.threshold(3).to_string(),
+            "-**\n".to_owned()+
+            "*-*\n");
+        assert_eq!(image.threshold(0).to_string(),
+            "***\n".to_owned()+
+            "***\n");
+        assert_eq!(image.max_value_pixel(), (0, 1, 7));
+        assert_eq!(MonoImage::new_w_h(0, 0).max_value_pixel(), (0, 0, 0));
+    }
+
+    fn drawn(image: &ColorImage, color: Color) -> BinaryImage {
+        image.to_binary_image(|c| c == color)
+    }
+
+    #[test]
+    fn color_image_draw_rect_and_line() {
+        let red = Color::new(255, 0, 0);
+        let mut image = ColorImage::new_w_h(5, 4);
+        image.draw_rect(BoundingRect::new_x_y_w_h(1, 0, 3, 3), red);
+        assert_eq!(drawn(&image, red).to_string(),
+            "-***-\n".to_owned()+
+            "-*-*-\n"+
+            "-***-\n"+
+            "-----\n");
+
+        let mut image = ColorImage::new_w_h(5, 4);
+        image.draw_line(PointI32::new(0, 0), PointI32::new(3, 3), red);
+        assert_eq!(drawn(&image, red).to_string(),
+            "*----\n".to_owned()+
+            "-*---\n"+
+            "--*--\n"+
+            "---*-\n");
+    }
+
+    #[test]
+    fn color_image_draw_clipped() {
+        let red = Color::new(255, 0, 0);
+        let mut image = ColorImage::new_w_h(4, 3);
+        image.draw_line(PointI32::new(-2, 1), PointI32::new(10, 1), red);
+        image.draw_rect(BoundingRect::new_x_y_w_h(2, -1, 5, 5), red);
+        image.draw_path(&PathI32::from_points(vec![PointI32::new(-5, -5), PointI32::new(0, 0)]), red, true);
+        assert_eq!(drawn(&image, red).to_string(),
+            "*-*-\n".to_owned()+
+            "****\n"+
+            "--*-\n");
+
+        let mut image = ColorImage::new_w_h(4, 3);
+        image.draw_line(PointI32::new(i32::MIN, i32::MIN), PointI32::new(i32::MAX, i32::MAX), red);
+        image.draw_line(PointI32::new(-1_000_000_000, 2), PointI32::new(1_000_000_000, 2), red);
+        image.draw_line(PointI32::new(i32::MAX, i32::MIN), PointI32::new(i32::MAX, i32::MAX), red);
+        image.draw_line(PointI32::new(i32::MIN, 5), PointI32::new(i32::MAX, 5), red);
+        assert_eq!(drawn(&image, red).to_string(),
+            "*---\n".to_owned()+
+            "-*--\n"+
+            "****\n");
+
+        let mut mask = BinaryImage::new_w_h(10, 10);
+        mask.set_pixel(3, 2, true);
+        mask.set_pixel(9, 9, true);
+        let mut image = ColorImage::new_w_h(4, 3);
+        image.draw_binary_overlay(&mask, Color::new(255, 255, 255), 128);
+        assert_eq!(image.get_pixel(3, 2), Color::new_rgba(128, 128, 128, 0));
+        assert_eq!(image.get_pixel(2, 2), Color::new_rgba(0, 0, 0, 0));
+    }
+
     #[test]
     fn rotate_test() {
         assert_eq!(
@@ -488,4 +1189,61 @@ mod tests {
             "-----------------------------\n"
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn color_image_content_bounds() {
+        // an opaque disk of radius 5 at (12, 9), fading out over 3 pixels, on transparent
+        let mut image = ColorImage::new_w_h(30, 20);
+        for y in 0..20 {
+            for x in 0..30 {
+                let distance = (x as f64 - 12.0).hypot(y as f64 - 9.0);
+                let alpha = (255.0 * (1.0 - (distance - 5.0) / 3.0)).clamp(0.0, 255.0) as u8;
+                image.set_pixel(x, y, &Color::new_rgba(0, 0, 255, alpha));
+            }
+        }
+        assert_eq!(image.content_bounds(0), BoundingRect::new_x_y_w_h(5, 2, 15, 15));
+        assert_eq!(image.content_bounds(254), BoundingRect::new_x_y_w_h(7, 4, 11, 11));
+        assert!(!ColorImage::new_w_h(4, 4).content_bounds(0).is_valid());
+    }
+
+    #[test]
+    fn flatten_transparency_checkerboard() {
+        let mut image = ColorImage::new_w_h(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                let color = if (x + y) % 2 == 0 { Color::new(255, 0, 0) } else { Color::new_rgba(0, 0, 0, 0) };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        image.set_pixel(3, 0, &Color::new_rgba(255, 0, 0, 128));
+        let flat = image.flatten_transparency(Color::new_rgba(255, 255, 255, 0));
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = if (x, y) == (3, 0) {
+                    Color::new(255, 127, 127)
+                } else if (x + y) % 2 == 0 {
+                    Color::new(255, 0, 0)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                assert_eq!(flat.get_pixel(x, y), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn color_histogram_two_colors() {
+        let mut image = ColorImage::new_w_h(10, 6);
+        for y in 0..6 {
+            for x in 0..10 {
+                // colors differing only below the top 5 bits share a bucket
+                let color = if x < 3 { Color::new(200 + (y as u8 & 7), 16, 5) } else { Color::new(8, 100, 255) };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let histogram = image.color_histogram_3d(5);
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram[&(25 << 10 | 2 << 5)], 18);
+        assert_eq!(histogram[&(1 << 10 | 12 << 5 | 31)], 42);
+    }
+}
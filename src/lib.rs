@@ -6,6 +6,7 @@ pub mod bound;
 pub mod clusters;
 mod color;
 mod color_stat;
+mod compat;
 pub mod disjoint_sets;
 mod field;
 mod image;
@@ -14,7 +15,10 @@ mod polar;
 mod sampler;
 mod sat;
 mod statistic;
+mod template;
+mod tile;
 mod transform;
+mod vectorize;
 
 // pub use color_clusters;
 pub use numeric::*;
@@ -24,6 +28,7 @@ pub use bound::{Bound, BoundingRect, BoundingRectF64, BoundStat};
 //pub use clusters;
 pub use color::*;
 pub use color_stat::*;
+pub use compat::*;
 pub use disjoint_sets::Forests;
 pub use field::*;
 pub use image::*;
@@ -32,4 +37,6 @@ pub use polar::*;
 pub use sampler::*;
 pub use sat::*;
 pub use statistic::*;
-pub use transform::*;
\ No newline at end of file
+pub use tile::*;
+pub use transform::*;
+pub use vectorize::*;
\ No newline at end of file
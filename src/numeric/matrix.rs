@@ -1,4 +1,5 @@
 use std::fmt::{Debug};
+use std::ops::Mul;
 
 /// Matrix operations adapted from https://github.com/sloisel/numeric
 #[derive(Clone, PartialEq)]
@@ -19,6 +20,19 @@ impl<const I: usize, const J: usize> Matrix<I, J> {
         Self { m }
     }
 
+    /// Constructs a matrix from `I` rows, or returns `None` if `rows` does not yield exactly `I` rows
+    pub fn from_rows(rows: impl IntoIterator<Item = [f64; J]>) -> Option<Self> {
+        let mut m = [[0.; J]; I];
+        let mut rows = rows.into_iter();
+        for row in m.iter_mut() {
+            *row = rows.next()?;
+        }
+        if rows.next().is_some() {
+            return None;
+        }
+        Some(Self { m })
+    }
+
     pub fn dim(&self) -> [usize; 2] {
         return [I, J];
     }
@@ -92,6 +106,71 @@ impl<const I: usize> Matrix<I, I> {
         }
         Some(ret)
     }
+
+    /// LU decomposition with partial pivoting, packed into a single matrix (the unit diagonal of L is implied).
+    /// Returns the decomposition, the row permutation and the sign of the permutation,
+    /// or `None` if the matrix is singular.
+    fn lu(&self) -> Option<([[f64; I]; I], [usize; I], f64)> {
+        let mut lu = self.m;
+        let mut perm = [0; I];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = i;
+        }
+        let mut sign = 1.0;
+        for j in 0..I {
+            let mut i0 = j;
+            for i in j+1..I {
+                if lu[i][j].abs() > lu[i0][j].abs() {
+                    i0 = i;
+                }
+            }
+            if lu[i0][j] == 0.0 {
+                return None;
+            }
+            if i0 != j {
+                lu.swap(i0, j);
+                perm.swap(i0, j);
+                sign = -sign;
+            }
+            let pivot = lu[j];
+            for row in lu[j+1..].iter_mut() {
+                let x = row[j] / pivot[j];
+                row[j] = x;
+                for (v, p) in row[j+1..].iter_mut().zip(pivot[j+1..].iter()) {
+                    *v -= x * p;
+                }
+            }
+        }
+        Some((lu, perm, sign))
+    }
+
+    /// Solves `self * x = b` for x, or returns `None` if the matrix is singular
+    pub fn solve(&self, b: &[f64; I]) -> Option<[f64; I]> {
+        let (lu, perm, _) = self.lu()?;
+        let mut x = [0.0; I];
+        for i in 0..I {
+            let mut v = b[perm[i]];
+            for k in 0..i {
+                v -= lu[i][k] * x[k];
+            }
+            x[i] = v;
+        }
+        for i in (0..I).rev() {
+            let mut v = x[i];
+            for k in i+1..I {
+                v -= lu[i][k] * x[k];
+            }
+            x[i] = v / lu[i][i];
+        }
+        Some(x)
+    }
+
+    pub fn determinant(&self) -> f64 {
+        match self.lu() {
+            Some((lu, _, sign)) => (0..I).fold(sign, |det, i| det * lu[i][i]),
+            None => 0.0,
+        }
+    }
 }
 
 impl<const I: usize, const J: usize> Matrix<I, J> {
@@ -156,6 +235,13 @@ impl<const I: usize, const J: usize> Matrix<I, J> {
         true
     }
 
+    /// Finds x minimizing the squared error of `self * x = b`, by solving the normal equations.
+    /// Returns `None` if the columns are linearly dependent.
+    pub fn least_squares(&self, b: &[f64; I]) -> Option<[f64; J]> {
+        let t = self.transpose();
+        t.dot_mm_small(self).solve(&t.dot_mv(b))
+    }
+
     pub fn scale(&mut self, s: f64) {
         let x = &mut self.m;
         for i in 0..I {
@@ -166,6 +252,22 @@ impl<const I: usize, const J: usize> Matrix<I, J> {
     }
 }
 
+impl<const I: usize, const J: usize, const K: usize> Mul<&Matrix<J, K>> for &Matrix<I, J> {
+    type Output = Matrix<I, K>;
+
+    fn mul(self, rhs: &Matrix<J, K>) -> Self::Output {
+        self.dot_mm_small(rhs)
+    }
+}
+
+impl<const I: usize, const J: usize, const K: usize> Mul<Matrix<J, K>> for Matrix<I, J> {
+    type Output = Matrix<I, K>;
+
+    fn mul(self, rhs: Matrix<J, K>) -> Self::Output {
+        self.dot_mm_small(&rhs)
+    }
+}
+
 impl<const I: usize, const J: usize> Debug for Matrix<I, J> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Matrix([")?;
@@ -220,4 +322,92 @@ mod test {
         m2.scale(1./18.);
         assert!(m1.inv().unwrap().eq(&m2, 1e-7));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_matrix_solve() {
+        let m = Matrix::new([
+            [2., 1., -1.],
+            [-3., -1., 2.],
+            [-2., 1., 2.],
+        ]);
+        let x = m.solve(&[8., -11., -3.]).unwrap();
+        for (a, b) in x.iter().zip([2., 3., -1.].iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        let m = Matrix::new([
+            [0., 2., 1.],
+            [1., 0., 0.],
+            [0., 0., 4.],
+        ]);
+        let x = m.solve(&[5., 7., 8.]).unwrap();
+        for (a, b) in x.iter().zip([7., 1.5, 2.].iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        let singular = Matrix::new([
+            [1., 2., 3.],
+            [2., 4., 6.],
+            [1., 0., 1.],
+        ]);
+        assert!(singular.solve(&[1., 2., 3.]).is_none());
+        assert_eq!(singular.determinant(), 0.);
+    }
+
+    #[test]
+    fn test_matrix_least_squares() {
+        // y = 0.5 x - 2, sampled with symmetric noise that cancels out
+        let noise = [0.1, -0.1, -0.1, 0.1, 0.1, -0.1, -0.1, 0.1];
+        let xs = [-3., -1., 0., 2., 3., 5., 6., 8.];
+        let m = Matrix::<8, 2>::from_rows(xs.iter().map(|&x| [x, 1.])).unwrap();
+        let mut b = [0.; 8];
+        for i in 0..8 {
+            b[i] = 0.5 * xs[i] - 2.;
+        }
+        let [slope, intercept] = m.least_squares(&b).unwrap();
+        assert!((slope - 0.5).abs() < 1e-9);
+        assert!((intercept + 2.).abs() < 1e-9);
+
+        for i in 0..8 {
+            b[i] += noise[i];
+        }
+        let [slope, intercept] = m.least_squares(&b).unwrap();
+        assert!((slope - 0.5).abs() < 0.05);
+        assert!((intercept + 2.).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_matrix_determinant() {
+        let m1 = Matrix::new([
+            [1., 0., 0.],
+            [3., 5., 0.],
+            [2., 1., 8.],
+        ]);
+        assert!((m1.determinant() - 40.).abs() < 1e-9);
+        let m2 = Matrix::new([
+            [2., 0., 5.],
+            [3., 1., 3.],
+            [9., 5., 9.],
+        ]);
+        assert!((m2.determinant() - 18.).abs() < 1e-9);
+        assert!((Matrix::<4, 4>::identity().determinant() - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_matrix_from_rows_and_mul() {
+        assert!(Matrix::<2, 2>::from_rows([[1., 2.]]).is_none());
+        assert!(Matrix::<2, 2>::from_rows([[1., 2.], [3., 4.], [5., 6.]]).is_none());
+        let a = Matrix::<2, 3>::from_rows([[1., 2., 3.], [4., 5., 6.]]).unwrap();
+        let b = Matrix::new([
+            [1., 0.],
+            [0., 1.],
+            [1., 1.],
+        ]);
+        let expected = Matrix::new([
+            [4., 5.],
+            [10., 11.],
+        ]);
+        assert!((&a * &b).eq(&expected, 1e-9));
+        assert!((a * b).eq(&expected, 1e-9));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{BinaryImage, BoundingRect, CompoundPath, CompoundPathElement, PathSimplifyMode, PointF64, PointI32};
+use crate::clusters::Cluster;
+
+/// Number of line segments each Bezier curve is flattened into for rasterization
+const CURVE_SEGMENTS: usize = 16;
+
+impl CompoundPath {
+    /// Rasterizes the closed paths into a mask covering `rect`, where pixel (x, y) of the mask
+    /// is pixel (rect.left + x, rect.top + y) of the path coordinate system.
+    ///
+    /// A pixel is set if its center lies inside, by the even-odd rule, so holes are subtracted
+    /// regardless of their orientation. Paths are treated as closed.
+    pub fn rasterize(&self, rect: &BoundingRect) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(rect.width() as usize, rect.height() as usize);
+        let polygons: Vec<Vec<PointF64>> = self.paths.iter().map(flatten).collect();
+
+        let mut crossings = Vec::new();
+        for y in 0..rect.height() {
+            let cy = (rect.top + y) as f64 + 0.5;
+            crossings.clear();
+            for polygon in polygons.iter() {
+                for (i, &a) in polygon.iter().enumerate() {
+                    let b = polygon[(i + 1) % polygon.len()];
+                    if (a.y <= cy) != (b.y <= cy) {
+                        crossings.push(a.x + (cy - a.y) / (b.y - a.y) * (b.x - a.x));
+                    }
+                }
+            }
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for span in crossings.chunks_exact(2) {
+                // pixels whose centers lie within the span
+                let from = std::cmp::max((span[0] - 0.5).ceil() as i32 - rect.left, 0);
+                let to = std::cmp::min((span[1] - 0.5).ceil() as i32 - rect.left, rect.width());
+                for x in from..to {
+                    image.set_pixel(x as usize, y as usize, true);
+                }
+            }
+        }
+        image
+    }
+
+    /// Returns the region covered by either `self` or `other`, see `CompoundPath::boolean`
+    pub fn union(&self, other: &CompoundPath) -> CompoundPath {
+        self.boolean(other, |a, b| a.union(b))
+    }
+
+    /// Returns the region covered by both `self` and `other`, see `CompoundPath::boolean`
+    pub fn intersection(&self, other: &CompoundPath) -> CompoundPath {
+        self.boolean(other, |a, b| a.intersect(b))
+    }
+
+    /// Returns the region covered by `self` but not `other`, see `CompoundPath::boolean`
+    pub fn difference(&self, other: &CompoundPath) -> CompoundPath {
+        self.boolean(other, |a, b| a.intersect(&b.negative()))
+    }
+
+    /// Combines two regions by rasterizing both (see `CompoundPath::rasterize`), applying `operator`
+    /// to the masks and tracing the result into polygons.
+    /// The result is thus snapped to the pixel grid of the path coordinate system.
+    pub fn boolean(
+        &self,
+        other: &CompoundPath,
+        operator: impl Fn(&BinaryImage, &BinaryImage) -> BinaryImage,
+    ) -> CompoundPath {
+        let mut bounds = self.bounding_rect_f64();
+        bounds.merge(other.bounding_rect_f64());
+        if bounds.is_empty() {
+            return CompoundPath::new();
+        }
+        let left = bounds.left_top.x.floor() as i32;
+        let top = bounds.left_top.y.floor() as i32;
+        let rect = BoundingRect::new_x_y_w_h(
+            left,
+            top,
+            bounds.right_bottom.x.ceil() as i32 - left,
+            bounds.right_bottom.y.ceil() as i32 - top,
+        );
+
+        let result = operator(&self.rasterize(&rect), &other.rasterize(&rect));
+        let mut paths = CompoundPath::new();
+        for cluster in result.to_clusters(false).iter() {
+            let offset = PointI32::new(rect.left + cluster.rect.left, rect.top + cluster.rect.top);
+            paths.append(Cluster::image_to_compound_path(
+                &offset, &cluster.to_binary_image(), PathSimplifyMode::Polygon, 0.0, 0.0, 0, 0.0,
+            ));
+        }
+        paths
+    }
+}
+
+/// The vertices of an element as a polygon, with curves flattened
+fn flatten(element: &CompoundPathElement) -> Vec<PointF64> {
+    match element {
+        CompoundPathElement::PathI32(path) => path.iter().map(|p| p.to_point_f64()).collect(),
+        CompoundPathElement::PathF64(path) => path.path.clone(),
+        CompoundPathElement::Spline(spline) => {
+            let mut points = spline.points.iter().take(1).copied().collect::<Vec<_>>();
+            for i in 0..spline.num_curves() {
+                for k in 1..=CURVE_SEGMENTS {
+                    points.push(spline.point_at(i, k as f64 / CURVE_SEGMENTS as f64));
+                }
+            }
+            points
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PathI32;
+
+    fn square(left: i32, top: i32, size: i32) -> CompoundPath {
+        let mut paths = CompoundPath::new();
+        paths.add_path_i32(PathI32::from_points(vec![
+            PointI32::new(left, top),
+            PointI32::new(left + size, top),
+            PointI32::new(left + size, top + size),
+            PointI32::new(left, top + size),
+            PointI32::new(left, top),
+        ]));
+        paths
+    }
+
+    #[test]
+    fn boolean_difference_hole() {
+        let result = square(0, 0, 20).difference(&square(5, 5, 5));
+        assert_eq!(result.paths.len(), 2);
+
+        let rect = BoundingRect::new_x_y_w_h(0, 0, 20, 20);
+        let mask = result.rasterize(&rect);
+        assert!(mask.get_pixel(2, 2));
+        assert!(mask.get_pixel(12, 12));
+        assert!(!mask.get_pixel(5, 5));
+        assert!(!mask.get_pixel(9, 9));
+        assert_eq!(mask.area(), 20 * 20 - 5 * 5);
+    }
+
+    #[test]
+    fn boolean_union_intersection() {
+        let a = square(0, 0, 10);
+        let b = square(5, 5, 10);
+        let rect = BoundingRect::new_x_y_w_h(0, 0, 15, 15);
+        assert_eq!(a.union(&b).rasterize(&rect).area(), 100 + 100 - 25);
+        assert_eq!(a.intersection(&b).rasterize(&rect).area(), 25);
+        assert!(square(0, 0, 4).intersection(&square(10, 10, 4)).is_empty());
+    }
+}
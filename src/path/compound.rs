@@ -1,4 +1,4 @@
-use crate::{PathI32, PathF64, PointType, Spline};
+use crate::{BoundingRect, BoundingRectF64, PathI32, PathF64, PointF64, PointI32, PointType, Spline, SplineError};
 
 #[derive(Debug, Clone)]
 /// A collection of `Path` and `Spline` that represents a shape with holes
@@ -14,6 +14,42 @@ pub enum CompoundPathElement {
     Spline(Spline),
 }
 
+impl CompoundPathElement {
+    /// The area enclosed by the element (for splines, by their control polygon),
+    /// positive if clockwise with the origin at the top left corner
+    pub fn signed_area(&self) -> f64 {
+        match self {
+            CompoundPathElement::PathI32(path) => polygon_signed_area(path.path.iter().map(|p| p.to_point_f64())),
+            CompoundPathElement::PathF64(path) => polygon_signed_area(path.path.iter().copied()),
+            CompoundPathElement::Spline(spline) => polygon_signed_area(spline.points.iter().copied()),
+        }
+    }
+}
+
+/// The points of a polygon without repeats of the previous point, nor of the first point at the end
+fn distinct_polygon<T: Copy + PartialEq>(points: &[T]) -> Vec<T> {
+    let mut distinct: Vec<T> = Vec::with_capacity(points.len());
+    for &p in points {
+        if distinct.last() != Some(&p) {
+            distinct.push(p);
+        }
+    }
+    while distinct.len() > 1 && distinct.first() == distinct.last() {
+        distinct.pop();
+    }
+    distinct
+}
+
+/// Shoelace formula over the polygon through `points`, closed implicitly
+fn polygon_signed_area(points: impl Iterator<Item = PointF64>) -> f64 {
+    let points: Vec<PointF64> = points.collect();
+    let len = points.len();
+    (0..len).map(|i| {
+        let (p, q) = (points[i], points[(i + 1) % len]);
+        p.x * q.y - q.x * p.y
+    }).sum::<f64>() / 2.0
+}
+
 impl Default for CompoundPath {
     fn default() -> Self {
         Self::new()
@@ -52,56 +88,194 @@ impl CompoundPath {
         self.paths.push(CompoundPathElement::Spline(path));
     }
 
-    /// returns a single svg path string in relative path syntax and offset
+    /// returns a single svg path string in relative path syntax and offset.
+    /// Panic if the length of a spline is not valid (see `Spline::try_to_svg_string`)
     pub fn to_svg_string<P>(&self, close: bool, offset: P, precision: Option<u32>) -> (String, P)
         where P: PointType + std::ops::Sub<Output = P> {
-        let origin = if !self.paths.is_empty() {
-            match &self.paths[0] {
-                CompoundPathElement::PathI32(p) => P::default() - p.path[0].to::<P>(),
-                CompoundPathElement::PathF64(p) => P::default() - p.path[0].to::<P>(),
-                CompoundPathElement::Spline(p) => P::default() - p.points[0].to::<P>(),
+        for p in self.paths.iter() {
+            if let CompoundPathElement::Spline(spline) = p {
+                if !spline.points.is_empty() && !spline.is_valid() {
+                    panic!("{}", SplineError::InvalidLength(spline.len()));
+                }
             }
-        } else {
-            P::default()
-        };
+        }
+        let mut string = String::with_capacity(self.estimate_svg_len(close, precision));
+        let offset = self.write_svg(&mut string, close, offset, precision).unwrap();
+        (string, offset)
+    }
 
-        let string = self.paths.iter().map(|p| {
+    /// Same as `to_svg_string`, but writes the path into `w` and returns the offset.
+    /// Returns an error if the length of a spline is not valid
+    pub fn write_svg<P>(&self, w: &mut impl std::fmt::Write, close: bool, offset: P, precision: Option<u32>) -> Result<P, std::fmt::Error>
+        where P: PointType + std::ops::Sub<Output = P> {
+        let origin: P = self.svg_origin();
+        for p in self.paths.iter() {
             match p {
-                CompoundPathElement::PathI32(p) => p.to_svg_string(close, &origin.to_point_i32(), precision),
-                CompoundPathElement::PathF64(p) => p.to_svg_string(close, &origin.to_point_f64(), precision),
-                CompoundPathElement::Spline(p) => p.to_svg_string(close, &origin.to_point_f64(), precision),
+                CompoundPathElement::PathI32(p) => p.write_svg(w, close, &origin.to_point_i32(), precision)?,
+                CompoundPathElement::PathF64(p) => p.write_svg(w, close, &origin.to_point_f64(), precision)?,
+                CompoundPathElement::Spline(p) => p.write_svg(w, close, &origin.to_point_f64(), precision)?,
             }
-        }).collect::<String>();
+        }
+        Ok(offset - origin)
+    }
 
-        (string, offset - origin)
+    /// Estimates the length of the string written by `to_svg_string`, see `Path::estimate_svg_len`
+    pub fn estimate_svg_len(&self, close: bool, precision: Option<u32>) -> usize {
+        let origin: PointF64 = self.svg_origin();
+        self.paths.iter().map(|p| {
+            match p {
+                CompoundPathElement::PathI32(p) => p.estimate_svg_len(close, &origin.to_point_i32(), precision),
+                CompoundPathElement::PathF64(p) => p.estimate_svg_len(close, &origin, precision),
+                CompoundPathElement::Spline(p) => p.estimate_svg_len(close, &origin, precision),
+            }
+        }).sum()
     }
 
+    /// The offset moving the first point to the origin, so the path is written in relative coordinates
+    fn svg_origin<P>(&self) -> P
+        where P: PointType + std::ops::Sub<Output = P> {
+        match self.paths.first() {
+            Some(CompoundPathElement::PathI32(p)) => P::default() - p.path[0].to::<P>(),
+            Some(CompoundPathElement::PathF64(p)) => P::default() - p.path[0].to::<P>(),
+            Some(CompoundPathElement::Spline(p)) => P::default() - p.points[0].to::<P>(),
+            None => P::default(),
+        }
+    }
+
+    /// Simplifies every element, dropping those reduced to nothing.
+    /// If the outer path (see `outer_path_index`) is dropped, the holes are dropped as well, leaving the path empty.
     pub fn reduce(&self, tolerance: f64) -> Self {
-        CompoundPath {
-            paths: self.paths.iter().filter_map(|path| {
-                match path {
-                    CompoundPathElement::PathI32(path) => {
-                        if let Some(path) = path.reduce(tolerance)
-                        { Some(CompoundPathElement::PathI32(path)) } else { None }
-                    },
-                    CompoundPathElement::PathF64(path) => {
-                        if let Some(path) = path.reduce(tolerance)
-                        { Some(CompoundPathElement::PathF64(path)) } else { None }
-                    },
-                    CompoundPathElement::Spline(_) => panic!("unimplemented!()"),
-                }
-            }).collect()
+        let outer = self.outer_path_index();
+        let mut paths = Vec::with_capacity(self.paths.len());
+        for (i, path) in self.paths.iter().enumerate() {
+            let reduced = match path {
+                CompoundPathElement::PathI32(path) => path.reduce(tolerance).map(CompoundPathElement::PathI32),
+                CompoundPathElement::PathF64(path) => path.reduce(tolerance).map(CompoundPathElement::PathF64),
+                CompoundPathElement::Spline(_) => panic!("unimplemented!()"),
+            };
+            match reduced {
+                Some(reduced) => paths.push(reduced),
+                None if Some(i) == outer => return Self::new(),
+                None => (),
+            }
+        }
+        CompoundPath { paths }
+    }
+
+    /// Like `reduce`, with the tolerance `relative_tolerance` times the diagonal of `rect`,
+    /// typically the bounds of the traced cluster, so that a shape is simplified alike at any scale
+    pub fn reduce_relative(&self, relative_tolerance: f64, rect: BoundingRect) -> Self {
+        let diagonal = (rect.width() as f64).hypot(rect.height() as f64);
+        self.reduce(relative_tolerance * diagonal)
+    }
+
+    /// Prepares the path for strict svg consumers: with coordinates first rounded to `precision` decimal places (if any),
+    /// repeated points and zero-length curves are removed, and elements are dropped if they have fewer than
+    /// `min_points` (at least 3) distinct points or enclose no area or less than `min_area`.
+    /// Every remaining polygon ends with its first point, to be written closed.
+    ///
+    /// The areas of splines are those of their control polygons.
+    pub fn sanitize(&self, min_points: usize, min_area: f64, precision: Option<u32>) -> Self {
+        let min_points = std::cmp::max(min_points, 3);
+        let round = |p: PointF64| match precision {
+            Some(precision) => {
+                let scale = 10f64.powi(precision as i32);
+                PointF64::new((p.x * scale).round() / scale, (p.y * scale).round() / scale)
+            },
+            None => p,
+        };
+        let mut paths = Vec::with_capacity(self.paths.len());
+        for path in self.paths.iter() {
+            let element = match path {
+                CompoundPathElement::PathI32(path) => CompoundPathElement::PathI32(PathI32::from_points(distinct_polygon(&path.path))),
+                CompoundPathElement::PathF64(path) => CompoundPathElement::PathF64(PathF64::from_points(
+                    distinct_polygon(&path.path.iter().map(|&p| round(p)).collect::<Vec<_>>())
+                )),
+                CompoundPathElement::Spline(spline) => {
+                    let points: Vec<PointF64> = spline.points.iter().map(|&p| round(p)).collect();
+                    let mut sanitized = Spline { points: points.iter().take(1).copied().collect() };
+                    for curve in points.get(1..).unwrap_or_default().chunks(3) {
+                        let start = *sanitized.points.last().unwrap();
+                        if curve.iter().any(|&p| p != start) {
+                            sanitized.points.extend_from_slice(curve);
+                        }
+                    }
+                    CompoundPathElement::Spline(sanitized)
+                },
+            };
+            let distinct = match &element {
+                CompoundPathElement::PathI32(path) => path.len(),
+                CompoundPathElement::PathF64(path) => path.len(),
+                CompoundPathElement::Spline(spline) => distinct_polygon(&spline.points).len(),
+            };
+            let area = element.signed_area().abs();
+            if distinct < min_points || area == 0.0 || area < min_area {
+                continue;
+            }
+            paths.push(match element {
+                CompoundPathElement::PathI32(mut path) => { path.add(path[0]); CompoundPathElement::PathI32(path) },
+                CompoundPathElement::PathF64(mut path) => { path.add(path[0]); CompoundPathElement::PathF64(path) },
+                spline => spline,
+            });
+        }
+        CompoundPath { paths }
+    }
+
+    /// Keeps only the outer path (see `outer_path_index`), returning the number of elements removed
+    pub fn remove_holes(&mut self) -> usize {
+        let removed = self.paths.len().saturating_sub(1);
+        if let Some(outer) = self.outer_path_index() {
+            let outer = self.paths.swap_remove(outer);
+            self.paths = vec![outer];
+        }
+        removed
+    }
+
+    /// Returns the index of the element enclosing the largest area, which holes lie within,
+    /// or `None` if the path is empty. Of elements with equal areas, the first is returned.
+    pub fn outer_path_index(&self) -> Option<usize> {
+        let mut outer: Option<(usize, f64)> = None;
+        for (i, path) in self.paths.iter().enumerate() {
+            let area = path.signed_area().abs();
+            let larger = match outer {
+                Some((_, largest)) => area > largest,
+                None => true,
+            };
+            if larger {
+                outer = Some((i, area));
+            }
         }
+        outer.map(|(i, _)| i)
     }
 
-    pub fn remove_holes(&mut self) {
-        self.paths.truncate(1);
+    /// Applies an offset to all points of all elements
+    pub fn offset(&mut self, o: PointI32) {
+        for path in self.paths.iter_mut() {
+            match path {
+                CompoundPathElement::PathI32(path) => path.offset(&o),
+                CompoundPathElement::PathF64(path) => path.offset(&o.to_point_f64()),
+                CompoundPathElement::Spline(spline) => spline.offset(&o.to_point_f64()),
+            }
+        }
     }
 
     pub fn is_empty(&self) -> bool {
         self.paths.is_empty()
     }
 
+    /// Returns the union of the bounding rects of all elements
+    pub fn bounding_rect_f64(&self) -> BoundingRectF64 {
+        let mut rect = BoundingRectF64::default();
+        for path in self.paths.iter() {
+            rect.merge(match path {
+                CompoundPathElement::PathI32(path) => path.to_path_f64().bounding_rect_f64(),
+                CompoundPathElement::PathF64(path) => path.bounding_rect_f64(),
+                CompoundPathElement::Spline(spline) => spline.exact_bounding_rect(),
+            });
+        }
+        rect
+    }
+
     const DEFAULT_MAX_ITERATIONS: usize = 10;
 
     pub fn smooth(&self, corner_threshold: f64, outset_ratio: f64, segment_length: f64) -> Self {
@@ -163,4 +337,135 @@ mod tests {
         assert_eq!("M0,0 L1,0 L1,1 Z M2,2 L3,2 L3,3 Z ", string);
         assert_eq!(offset, PointF64 { x: 2.0, y: 2.0 });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reduce_relative_scale_invariant() {
+        let disk = |radius: f64| {
+            let size = (2.0 * radius) as usize + 2;
+            let mut image = crate::BinaryImage::new_w_h(size, size);
+            for y in 0..size {
+                for x in 0..size {
+                    let c = size as f64 / 2.0;
+                    image.set_pixel(x, y, (x as f64 + 0.5 - c).hypot(y as f64 + 0.5 - c) <= radius);
+                }
+            }
+            let paths = crate::clusters::Cluster::image_to_compound_path(
+                &PointI32::default(), &image, crate::PathSimplifyMode::Polygon, 0.0, 0.0, 0, 0.0
+            );
+            (paths, BoundingRect::new_x_y_w_h(0, 0, size as i32, size as i32))
+        };
+        let count = |paths: &CompoundPath| paths.iter().map(|path| match path {
+            CompoundPathElement::PathI32(path) => path.len(),
+            _ => unreachable!(),
+        }).sum::<usize>();
+        let ((small, small_rect), (large, large_rect)) = (disk(8.0), disk(48.0));
+
+        let (small_count, large_count) = (count(&small.reduce(1.0)), count(&large.reduce(1.0)));
+        assert!(large_count > 2 * small_count, "{} {}", small_count, large_count);
+        let small_count = count(&small.reduce_relative(0.05, small_rect));
+        let large_count = count(&large.reduce_relative(0.05, large_rect));
+        assert!((small_count as f64 / large_count as f64 - 1.0).abs() < 0.35, "{} {}", small_count, large_count);
+    }
+
+    fn square(left: i32, top: i32, size: i32) -> PathI32 {
+        let mut path = PathI32::new();
+        for (x, y) in [(0, 0), (size, 0), (size, size), (0, size), (0, 0)] {
+            path.add(PointI32 { x: left + x, y: top + y });
+        }
+        path
+    }
+
+    #[test]
+    fn remove_holes_keeps_outer() {
+        let mut paths = CompoundPath::new();
+        let mut hole = square(4, 4, 2);
+        hole.path.reverse();
+        paths.add_path_i32(hole);
+        paths.add_path_i32(square(0, 0, 10));
+        paths.add_path_i32(square(2, 2, 1));
+        assert_eq!(paths.paths[0].signed_area(), -4.0);
+        assert_eq!(paths.paths[1].signed_area(), 100.0);
+        assert_eq!(paths.outer_path_index(), Some(1));
+
+        assert_eq!(paths.remove_holes(), 2);
+        assert_eq!(paths.paths.len(), 1);
+        assert_eq!(paths.paths[0].signed_area(), 100.0);
+        assert_eq!(paths.remove_holes(), 0);
+
+        let mut empty = CompoundPath::new();
+        assert_eq!(empty.outer_path_index(), None);
+        assert_eq!(empty.remove_holes(), 0);
+    }
+
+    #[test]
+    fn reduce_drops_holes_with_outer() {
+        let mut paths = CompoundPath::new();
+        paths.add_path_i32(square(0, 0, 1));
+        paths.add_path_i32(square(0, 0, 2));
+        paths.add_path_i32(square(0, 0, 1));
+        // the outer square survives, the small ones do not
+        let reduced = paths.reduce(1.5);
+        assert_eq!(reduced.paths.len(), 1);
+        assert_eq!(reduced.paths[0].signed_area(), 4.0);
+        // the outer square reduces to nothing too
+        assert!(paths.reduce(3.0).is_empty());
+    }
+
+    #[test]
+    fn sanitize_drops_degenerate_paths() {
+        let mut paths = CompoundPath::new();
+        paths.add_path_f64(PathF64::from_points(vec![PointF64::new(0.0, 0.0), PointF64::new(5.0, 5.0)]));
+        // a sliver folding back on itself
+        paths.add_path_f64(PathF64::from_points(vec![
+            PointF64::new(0.0, 0.0), PointF64::new(4.0, 0.0), PointF64::new(8.0, 0.0), PointF64::new(0.0, 0.0),
+        ]));
+        // a square with points equal at one decimal place
+        paths.add_path_f64(PathF64::from_points(vec![
+            PointF64::new(1.0, 1.0), PointF64::new(3.0, 1.0), PointF64::new(3.01, 1.02), PointF64::new(3.0, 3.0),
+            PointF64::new(1.0, 3.0), PointF64::new(1.0, 1.0), PointF64::new(1.0, 1.0),
+        ]));
+        let sanitized = paths.sanitize(3, 0.5, Some(1));
+        assert_eq!(sanitized.paths.len(), 1);
+        assert_eq!(sanitized.paths[0].signed_area(), 4.0);
+
+        let (string, _) = sanitized.to_svg_string(true, PointF64::default(), Some(1));
+        assert_eq!(string, "M0,0 L2,0 L2,2 L0,2 Z ");
+        let (string, _) = paths.to_svg_string(true, PointF64::default(), Some(1));
+        assert!(string.contains("L3,1 L3,1 "), "{}", string);
+    }
+
+    #[test]
+    fn write_svg_matches_string() {
+        let mut traced = CompoundPath::new();
+        traced.add_path_i32(square(10, 10, 200));
+        let mut hole = square(50, 60, 7);
+        hole.path.reverse();
+        traced.add_path_i32(hole);
+
+        let mut circle = CompoundPath::new();
+        circle.add_path_f64(PathF64::from_points((0..=64).map(|i| {
+            let angle = i as f64 / 64.0 * std::f64::consts::TAU;
+            PointF64::new(100.0 + 80.0 * angle.cos(), 100.0 + 80.0 * angle.sin())
+        }).collect()));
+
+        let mut curves = CompoundPath::new();
+        let mut spline = Spline::new(PointF64::new(3.5, 7.25));
+        for i in 0..20 {
+            let x = i as f64 * 13.7;
+            spline.add(PointF64::new(x + 4.1, 20.3), PointF64::new(x + 9.6, 0.4), PointF64::new(x + 13.7, 7.25));
+        }
+        curves.add_spline(spline);
+
+        for paths in [traced, circle, curves].iter() {
+            for &precision in [None, Some(0), Some(2)].iter() {
+                let (string, offset) = paths.to_svg_string(true, PointF64::new(1.0, 2.0), precision);
+                let mut written = String::new();
+                assert_eq!(paths.write_svg(&mut written, true, PointF64::new(1.0, 2.0), precision), Ok(offset));
+                assert_eq!(written, string);
+
+                let estimate = paths.estimate_svg_len(true, precision);
+                assert!(estimate * 2 >= string.len() && estimate <= string.len() * 2, "{} {}", estimate, string.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Measures how closely a traced outline follows the shape it was traced from
+
+use crate::{BinaryImage, CompoundPath, CompoundPathElement, PointF64, Shape};
+use super::reduce::get_sq_seg_dist;
+
+/// Side length in pixels of the grid cells bucketing the segments
+const GRID_CELL_SIZE: f64 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// Distances from the boundary pixels of a shape to its traced outline, see `deviation`
+pub struct Deviation {
+    /// The largest distance, in pixels
+    pub max: f64,
+    /// The average distance, in pixels
+    pub mean: f64,
+    /// Number of boundary pixels measured
+    pub boundary_points: usize,
+    /// Number of vertices of the flattened outline
+    pub path_points: usize,
+}
+
+/// The largest distance from a boundary pixel of `original` to `traced`, see `deviation`
+pub fn max_deviation(original: &BinaryImage, traced: &CompoundPath, flatten_tolerance: f64) -> f64 {
+    deviation(original, traced, flatten_tolerance).max
+}
+
+/// The average distance from a boundary pixel of `original` to `traced`, see `deviation`
+pub fn mean_deviation(original: &BinaryImage, traced: &CompoundPath, flatten_tolerance: f64) -> f64 {
+    deviation(original, traced, flatten_tolerance).mean
+}
+
+/// Measures the distance from the center of each boundary pixel of `original` to the nearest segment of `traced`,
+/// with curves flattened to within `flatten_tolerance`.
+///
+/// Paths traced from an image run along pixel edges, so a perfect trace deviates by 0.5 pixels.
+/// The deviation is infinite if `original` has pixels but `traced` has no segments, and zero if `original` is empty.
+pub fn deviation(original: &BinaryImage, traced: &CompoundPath, flatten_tolerance: f64) -> Deviation {
+    let boundary = Shape::image_boundary_list(original);
+    let polylines: Vec<Vec<PointF64>> = traced.iter().map(|element| flatten(element, flatten_tolerance)).collect();
+    let path_points = polylines.iter().map(|polyline| polyline.len()).sum();
+    if boundary.is_empty() {
+        return Deviation { path_points, ..Default::default() };
+    }
+
+    let grid = SegmentGrid::new(&polylines);
+    let (mut max, mut sum) = (0.0f64, 0.0);
+    for p in boundary.iter() {
+        let distance = grid.nearest_sq_dist(PointF64::new(p.x as f64 + 0.5, p.y as f64 + 0.5)).sqrt();
+        max = max.max(distance);
+        sum += distance;
+    }
+    Deviation {
+        max,
+        mean: sum / boundary.len() as f64,
+        boundary_points: boundary.len(),
+        path_points,
+    }
+}
+
+/// The closed polyline of an element, with curves flattened
+fn flatten(element: &CompoundPathElement, tolerance: f64) -> Vec<PointF64> {
+    let mut points = match element {
+        CompoundPathElement::PathI32(path) => path.iter().map(|p| p.to_point_f64()).collect(),
+        CompoundPathElement::PathF64(path) => path.path.clone(),
+        CompoundPathElement::Spline(spline) => spline.flatten(tolerance).path,
+    };
+    if points.len() > 1 && points.first() != points.last() {
+        points.push(points[0]);
+    }
+    points
+}
+
+/// Segments bucketed by the grid cells their bounding boxes overlap
+struct SegmentGrid {
+    segments: Vec<(PointF64, PointF64)>,
+    cells: Vec<Vec<usize>>,
+    origin: PointF64,
+    columns: i32,
+    rows: i32,
+}
+
+impl SegmentGrid {
+    fn new(polylines: &[Vec<PointF64>]) -> Self {
+        let segments: Vec<(PointF64, PointF64)> = polylines.iter()
+            .flat_map(|polyline| polyline.windows(2).map(|pair| (pair[0], pair[1])))
+            .collect();
+        let (mut min, mut max) = (PointF64::new(f64::MAX, f64::MAX), PointF64::new(f64::MIN, f64::MIN));
+        for &(a, b) in segments.iter() {
+            min = PointF64::new(min.x.min(a.x).min(b.x), min.y.min(a.y).min(b.y));
+            max = PointF64::new(max.x.max(a.x).max(b.x), max.y.max(a.y).max(b.y));
+        }
+        if segments.is_empty() {
+            return Self { segments, cells: Vec::new(), origin: PointF64::default(), columns: 0, rows: 0 };
+        }
+        let columns = ((max.x - min.x) / GRID_CELL_SIZE) as i32 + 1;
+        let rows = ((max.y - min.y) / GRID_CELL_SIZE) as i32 + 1;
+        let mut grid = Self { segments: Vec::new(), cells: vec![Vec::new(); (columns * rows) as usize], origin: min, columns, rows };
+        for (s, &(a, b)) in segments.iter().enumerate() {
+            let (x0, y0) = grid.cell_of(PointF64::new(a.x.min(b.x), a.y.min(b.y)));
+            let (x1, y1) = grid.cell_of(PointF64::new(a.x.max(b.x), a.y.max(b.y)));
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    grid.cells[(y * columns + x) as usize].push(s);
+                }
+            }
+        }
+        grid.segments = segments;
+        grid
+    }
+
+    /// The cell containing `p`, which may lie outside the grid
+    fn cell_of(&self, p: PointF64) -> (i32, i32) {
+        (
+            ((p.x - self.origin.x) / GRID_CELL_SIZE).floor() as i32,
+            ((p.y - self.origin.y) / GRID_CELL_SIZE).floor() as i32,
+        )
+    }
+
+    /// Squared distance from `p` to the nearest segment, searching rings of cells outwards from that of `p`
+    fn nearest_sq_dist(&self, p: PointF64) -> f64 {
+        let (cx, cy) = self.cell_of(p);
+        // the ring from which every cell of the grid has been searched
+        let last_ring = [cx, self.columns - 1 - cx, cy, self.rows - 1 - cy].iter()
+            .map(|d| d.abs())
+            .max()
+            .unwrap_or(0);
+        let mut best = f64::INFINITY;
+        for r in 0..=last_ring {
+            for y in cy - r..=cy + r {
+                if y < 0 || y >= self.rows {
+                    continue;
+                }
+                // the whole rows at the top and bottom of the ring, only the ends of the others
+                let step = if y == cy - r || y == cy + r { 1 } else { std::cmp::max(2 * r, 1) };
+                for x in (cx - r..=cx + r).step_by(step as usize) {
+                    if x < 0 || x >= self.columns {
+                        continue;
+                    }
+                    for &s in self.cells[(y * self.columns + x) as usize].iter() {
+                        let (a, b) = self.segments[s];
+                        best = best.min(get_sq_seg_dist(p, a, b));
+                    }
+                }
+            }
+            // segments not seen yet lie outside this ring, at least r cells away
+            let reach = r as f64 * GRID_CELL_SIZE;
+            if best <= reach * reach {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clusters::Cluster, PathSimplifyMode, PointI32};
+
+    fn trace(image: &BinaryImage, mode: PathSimplifyMode, segment_length: f64) -> CompoundPath {
+        Cluster::image_to_compound_path(
+            &PointI32::default(), image, mode, std::f64::consts::PI / 3.0, segment_length, 10, std::f64::consts::PI / 4.0,
+        )
+    }
+
+    fn star(size: usize) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(size, size);
+        let c = size as f64 / 2.0;
+        for y in 0..size {
+            for x in 0..size {
+                let (dx, dy) = (x as f64 + 0.5 - c, y as f64 + 0.5 - c);
+                // five points, reaching from 0.4 to 0.95 of the radius
+                let angle = dy.atan2(dx);
+                let wave = ((angle * 5.0).cos() + 1.0) / 2.0;
+                let radius = c * (0.4 + 0.55 * wave.powi(3));
+                image.set_pixel(x, y, dx.hypot(dy) <= radius);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn rectangle_polygon_deviation() {
+        let mut image = BinaryImage::new_w_h(20, 14);
+        for y in 3..11 {
+            for x in 4..16 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let paths = trace(&image, PathSimplifyMode::Polygon, 4.0);
+        let deviation = deviation(&image, &paths, 0.1);
+        assert!(deviation.max < 1.0, "{:?}", deviation);
+        assert!((deviation.mean - 0.5).abs() < 1e-9, "{:?}", deviation);
+        assert_eq!(deviation.boundary_points, 2 * 12 + 2 * 6);
+
+        assert_eq!(max_deviation(&image, &CompoundPath::new(), 0.1), f64::INFINITY);
+        assert_eq!(mean_deviation(&BinaryImage::new_w_h(4, 4), &paths, 0.1), 0.0);
+    }
+
+    #[test]
+    fn star_spline_deviation() {
+        let image = star(96);
+        let coarse = max_deviation(&image, &trace(&image, PathSimplifyMode::Spline, 12.0), 0.1);
+        let fine = max_deviation(&image, &trace(&image, PathSimplifyMode::Spline, 2.0), 0.1);
+        assert!(coarse > 1.0 && coarse < 10.0, "coarse {}", coarse);
+        assert!(fine < coarse, "fine {} coarse {}", fine, coarse);
+    }
+}
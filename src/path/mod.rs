@@ -1,9 +1,12 @@
+mod boolean;
 mod compound;
+pub mod fidelity;
 mod paths;
 pub mod reduce;
 mod simplify;
 mod smooth;
 mod spline;
+mod svg;
 mod walker;
 mod util;
 
@@ -13,5 +16,6 @@ pub use paths::*;
 pub use simplify::*;
 //pub use smooth::*;
 pub use spline::*;
+pub use svg::*;
 pub use walker::*;
 pub use util::*;
\ No newline at end of file
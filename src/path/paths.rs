@@ -1,7 +1,7 @@
 use std::fmt::{Debug, Write};
 use std::ops::{Add, AddAssign, Index, IndexMut, Mul, Range, RangeFrom, RangeInclusive, Sub};
 
-use crate::{BinaryImage, Point2, PointF64, PointI32, Shape, ToSvgString};
+use crate::{BinaryImage, BoundingRectF64, Point2, PointF64, PointI32, Shape, ToSvgString};
 use super::{PathSimplify, PathSimplifyMode, PathWalker, smooth::SubdivideSmooth, reduce::reduce};
 
 #[derive(Clone, Debug, Default)]
@@ -148,6 +148,13 @@ where
             point.add_assign(*o);
         }
     }
+
+    /// Returns a copy of the path with an offset applied to all points
+    pub fn translated(&self, o: T) -> Self {
+        let mut path = self.clone();
+        path.offset(&o);
+        path
+    }
 }
 
 impl<T> Path<T>
@@ -162,25 +169,43 @@ where
     /// 
     /// If `close` is true, assume the last point of the path repeats the first point
     pub fn to_svg_string(&self, close: bool, offset: &T, precision: Option<u32>) -> String {
+        let mut string = String::with_capacity(self.estimate_svg_len(close, offset, precision));
+        self.write_svg(&mut string, close, offset, precision).unwrap();
+        string
+    }
+
+    /// Same as `to_svg_string`, but writes into `w`
+    pub fn write_svg(&self, w: &mut impl Write, close: bool, offset: &T, precision: Option<u32>) -> std::fmt::Result {
         let o = *offset;
-        let mut string = String::new();
 
-        self.path
-            .iter()
-            .take(1)
-            .for_each(|p| write!(&mut string, "M{} ", (*p+o).to_svg_string(precision)).unwrap());
+        if let Some(p) = self.path.first() {
+            write!(w, "M{} ", (*p+o).to_svg_string(precision))?;
+        }
 
-        self.path
-            .iter()
-            .skip(1)
-            .take(self.path.len() - if close { 2 } else { 1 })
-            .for_each(|p| write!(&mut string, "L{} ", (*p+o).to_svg_string(precision)).unwrap());
+        for p in self.path.iter().skip(1).take(self.path.len() - if close { 2 } else { 1 }) {
+            write!(w, "L{} ", (*p+o).to_svg_string(precision))?;
+        }
 
         if close {
-            write!(&mut string, "Z ").unwrap();
+            write!(w, "Z ")?;
         }
 
-        string
+        Ok(())
+    }
+
+    /// Estimates the length of `to_svg_string` from the points a quarter, half and three quarters of the way along
+    pub fn estimate_svg_len(&self, close: bool, offset: &T, precision: Option<u32>) -> usize {
+        let len = self.path.len();
+        if len == 0 {
+            return if close { 2 } else { 0 };
+        }
+        let o = *offset;
+        let sample: usize = [len / 4, len / 2, len * 3 / 4].iter()
+            .map(|&i| (self.path[i] + o).to_svg_string(precision).len())
+            .sum();
+        // "L" and " " around each point
+        let points = if close { len - 1 } else { len };
+        points * (sample / 3 + 2) + if close { 2 } else { 0 }
     }
 }
 
@@ -193,15 +218,31 @@ where T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> +
     /// and reduce each section individually.
     /// Thus the most simplified path consists of at least 4 points.
     /// This function assumes the last point of the path repeats the first point.
+    /// Returns `None` for paths of fewer than 4 distinct points, as these have no 4 sections to reduce.
+    ///
+    /// If some extreme points coincide (e.g. one vertex is both the max-x and max-y point),
+    /// the largest sections are split in half instead, so that paths of at least 4 points have 4 sections.
     pub fn reduce(&self, tolerance: f64) -> Option<Self> {
-        if !self.path.is_empty() {
-            assert!(self.path[0] == self.path[self.path.len() - 1]);
+        if self.path.is_empty() {
+            return None;
         }
-        let mut corners = [(0, self.path[0]); 4];
-        for (i, p) in self.path.iter().enumerate() {
-            if i == self.path.len() - 1 {
-                break;
+        assert!(self.path[0] == self.path[self.path.len() - 1]);
+        let n = self.path.len() - 1;
+        let mut distinct: Vec<Point2<T>> = Vec::with_capacity(4);
+        for p in self.path[..n].iter() {
+            if !distinct.contains(p) {
+                distinct.push(*p);
+                if distinct.len() == 4 {
+                    break;
+                }
             }
+        }
+        if distinct.len() < 4 {
+            return None;
+        }
+
+        let mut corners = [(0, self.path[0]); 4];
+        for (i, p) in self.path[..n].iter().enumerate() {
             if p.x < corners[0].1.x { corners[0] = (i, *p); }
             if p.y <= corners[1].1.y { corners[1] = (i, *p); }
             if p.x >= corners[2].1.x { corners[2] = (i, *p); }
@@ -212,20 +253,26 @@ where T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> +
             abs(corners[1].1.y - corners[3].1.y) < tolerance {
             return None;
         }
-        corners.sort_by_key(|c| c.0);
-        let mut sections = [
-            &self.path[corners[0].0..=corners[1].0],
-            &self.path[corners[1].0..=corners[2].0],
-            &self.path[corners[2].0..=corners[3].0],
-            &[],
-        ];
-        let mut last = self.path[corners[3].0..self.path.len()-1].to_vec();
-        last.append(&mut self.path[0..=corners[0].0].to_vec());
-        sections[3] = &last.as_slice();
+        let mut cuts: Vec<usize> = corners.iter().map(|c| c.0).collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        while n >= 4 && cuts.len() < 4 {
+            // split the longest section, going around from the last cut back to the first
+            let gap = |k: usize| if k + 1 < cuts.len() { cuts[k + 1] - cuts[k] } else { cuts[0] + n - cuts[k] };
+            let k = (0..cuts.len()).max_by_key(|&k| (gap(k), std::cmp::Reverse(k))).unwrap();
+            let cut = (cuts[k] + gap(k) / 2) % n;
+            cuts.insert(k + 1, cut);
+            cuts.sort_unstable();
+        }
+
+        let mut last = self.path[cuts[cuts.len() - 1]..n].to_vec();
+        last.extend_from_slice(&self.path[0..=cuts[0]]);
+        let mut sections: Vec<&[Point2<T>]> = cuts.windows(2).map(|w| &self.path[w[0]..=w[1]]).collect();
+        sections.push(last.as_slice());
         let mut combined = Vec::new();
         for (i, path) in sections.iter().enumerate() {
             let mut reduced = reduce::<T>(path, tolerance);
-            if i != 3 {
+            if i != sections.len() - 1 {
                 reduced.pop();
             }
             combined.append(&mut reduced);
@@ -248,12 +295,22 @@ impl PathI32 {
     /// `segment_length` is specified in pixels (length unit in path coordinate system).
     pub fn smooth(
         &self, corner_threshold: f64, outset_ratio: f64, segment_length: f64, max_iterations: usize
+    ) -> PathF64 {
+        self.smooth_with(corner_threshold, outset_ratio, |_| segment_length, max_iterations)
+    }
+
+    /// Same as `smooth`, but the segment length is given per point, so that e.g. high-curvature
+    /// regions can be subdivided more finely than straight runs.
+    /// 
+    /// A segment is not divided if it is no longer than `segment_length` at either of its end points.
+    pub fn smooth_with(
+        &self, corner_threshold: f64, outset_ratio: f64, segment_length: impl Fn(&PointF64) -> f64, max_iterations: usize
     ) -> PathF64 {
         assert!(max_iterations > 0);
         let mut corners = SubdivideSmooth::find_corners(self, corner_threshold);
         let mut path = self.to_path_f64();
         for _i in 0..max_iterations {
-            let result = SubdivideSmooth::subdivide_keep_corners(&path, &corners, outset_ratio, segment_length);
+            let result = SubdivideSmooth::subdivide_keep_corners_with(&path, &corners, outset_ratio, &segment_length);
             path = result.0;
             corners = result.1;
             if result.2 { // Can terminate early
@@ -281,6 +338,63 @@ impl PathF64 {
         }
         path
     }
+
+    /// Returns a copy of the path with all points scaled by `factor` about the origin
+    pub fn scaled(&self, factor: f64) -> PathF64 {
+        Self::from_points(self.path.iter().map(|&p| p * factor).collect())
+    }
+
+    /// Returns a copy of the path with all points scaled by `factor` about `center`
+    pub fn scaled_about(&self, center: PointF64, factor: f64) -> PathF64 {
+        Self::from_points(self.path.iter().map(|&p| center + (p - center) * factor).collect())
+    }
+
+    /// Returns the tight bounding rect of all points, or an empty rect for an empty path
+    pub fn bounding_rect_f64(&self) -> BoundingRectF64 {
+        let mut rect = BoundingRectF64::default();
+        for &p in self.path.iter() {
+            rect.add_point(p);
+        }
+        rect
+    }
+
+    const REDUCE_BUDGET_ITERATIONS: usize = 32;
+
+    /// Returns a copy of the path reduced to at most `max_points` points (counting the repeated last point),
+    /// by binary searching the tolerance of `reduce` for the smallest one meeting the budget.
+    ///
+    /// If no tolerance meets the budget, e.g. below the 5 points `reduce` keeps at least,
+    /// the path reduced to the fewest points found is returned instead.
+    pub fn reduce_to_budget(&self, max_points: usize) -> PathF64 {
+        if self.len() <= max_points {
+            return self.clone();
+        }
+        let rect = self.bounding_rect_f64();
+        let (mut lo, mut hi) = (0.0, rect.width().max(rect.height()));
+        let mut fewest: Option<PathF64> = None;
+        for _ in 0..Self::REDUCE_BUDGET_ITERATIONS {
+            let tolerance = (lo + hi) / 2.0;
+            match self.reduce(tolerance) {
+                Some(reduced) if reduced.len() <= max_points => {
+                    hi = tolerance;
+                    fewest = Some(reduced);
+                },
+                Some(reduced) => {
+                    lo = tolerance;
+                    let fewer = match &fewest {
+                        Some(fewest) => fewest.len() > max_points && reduced.len() < fewest.len(),
+                        None => true,
+                    };
+                    if fewer {
+                        fewest = Some(reduced);
+                    }
+                },
+                // too coarse to keep a shape
+                None => hi = tolerance,
+            }
+        }
+        fewest.unwrap_or_else(|| self.clone())
+    }
 }
 
 impl PathI32 {
@@ -298,13 +412,21 @@ impl PathI32 {
     /// Takes an enum PathSimplifyMode which indicates the required operation:
     /// 
     /// - Polygon - Walk path and simplify it
+    /// - Corner - Walk path, simplify it and keep only the corners
     /// - Otherwise - Walk path only
+    ///
+    /// Panics if the boundary touches itself at a corner, e.g. where a hole meets the outline diagonally.
+    /// `Cluster::image_to_paths` fills the holes and traces them separately, so it accepts any image.
     pub fn image_to_path(image: &BinaryImage, clockwise: bool, mode: PathSimplifyMode) -> PathI32 {
         match mode {
             PathSimplifyMode::Polygon => {
                 let path = Self::image_to_path_baseline(image, clockwise);
                 path.simplify(clockwise)
             },
+            PathSimplifyMode::Corner { angle_threshold, min_segment } => {
+                let path = Self::image_to_path_baseline(image, clockwise);
+                PathSimplify::keep_corners(&path, clockwise, angle_threshold, min_segment)
+            },
             // Otherwise
             PathSimplifyMode::None | PathSimplifyMode::Spline => {
                 Self::image_to_path_baseline(image, clockwise)
@@ -334,6 +456,34 @@ impl PathI32 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_smooth_with() {
+        // a 12x12 square with a point every 3 pixels
+        let mut path = PathI32::new();
+        for i in 0..4 { path.add(PointI32::new(i * 3, 0)); }
+        for i in 0..4 { path.add(PointI32::new(12, i * 3)); }
+        for i in 0..4 { path.add(PointI32::new(12 - i * 3, 12)); }
+        for i in 0..4 { path.add(PointI32::new(0, 12 - i * 3)); }
+        path.add(PointI32::new(0, 0));
+        let no_corners = std::f64::consts::PI;
+
+        for &length in [1.0, 2.5, 10.0].iter() {
+            let expected = path.smooth(no_corners, 8.0, length, 10);
+            let actual = path.smooth_with(no_corners, 8.0, |_| length, 10);
+            assert_eq!(expected.path, actual.path);
+        }
+
+        let corners = [PointF64::new(0.0, 0.0), PointF64::new(12.0, 0.0), PointF64::new(12.0, 12.0), PointF64::new(0.0, 12.0)];
+        let near_corner = |p: &PointF64| corners.iter().any(|c| (*p - *c).norm() <= 3.0);
+        let uniform = path.smooth(no_corners, 8.0, 4.0, 10);
+        let adaptive = path.smooth_with(no_corners, 8.0, |p| if near_corner(p) { 1.0 } else { 4.0 }, 10);
+        assert_eq!(uniform.len(), path.len());
+        let count_near = |path: &PathF64| path.iter().filter(|p| near_corner(p)).count();
+        assert!(count_near(&adaptive) > count_near(&uniform));
+        // straight runs are left coarser than uniformly fine smoothing
+        assert!(adaptive.len() < path.smooth(no_corners, 8.0, 1.0, 10).len());
+    }
+
     #[test]
     fn test_to_svg_string() {
         let mut path = PathI32::new();
@@ -390,6 +540,70 @@ mod tests {
         assert!(path.reduce(2.0).is_none());
     }
 
+    #[test]
+    fn test_reduce_degenerate() {
+        assert!(PathI32::new().reduce(0.5).is_none());
+        let point = Path { path: vec![PointI32 { x: 3, y: 4 }] };
+        assert!(point.reduce(0.5).is_none());
+        let segment = Path {
+            path: vec![
+                PointI32 { x: 0, y: 0 },
+                PointI32 { x: 10, y: 0 },
+                PointI32 { x: 0, y: 0 },
+            ]
+        };
+        assert!(segment.reduce(0.5).is_none());
+        let triangle = Path {
+            path: vec![
+                PointI32 { x: 0, y: 0 },
+                PointI32 { x: 10, y: 0 },
+                PointI32 { x: 5, y: 8 },
+                PointI32 { x: 0, y: 0 },
+            ]
+        };
+        assert!(triangle.reduce(0.5).is_none());
+    }
+
+    #[test]
+    fn test_reduce_coinciding_extremes() {
+        // (10, 10) is both the max-x and max-y point, and (0, 0) both the min-x and min-y point
+        let vertices = [PointI32 { x: 0, y: 0 }, PointI32 { x: 8, y: 2 }, PointI32 { x: 10, y: 10 }, PointI32 { x: 2, y: 8 }];
+        let mut path = PathI32::new();
+        for i in 0..4 {
+            let (a, b) = (vertices[i], vertices[(i + 1) % 4]);
+            for t in 0..4 {
+                path.add(PointI32 { x: a.x + (b.x - a.x) * t / 4, y: a.y + (b.y - a.y) * t / 4 });
+            }
+        }
+        path.add(vertices[0]);
+
+        let reduced = path.reduce(0.5).unwrap().path;
+        assert_eq!(reduced.first(), reduced.last());
+        assert!(reduced.len() >= 5);
+        for vertex in vertices.iter() {
+            assert!(reduced.contains(vertex));
+        }
+        for p in reduced.iter() {
+            assert!(path.path.contains(p));
+        }
+    }
+
+    #[test]
+    fn test_reduce_to_budget() {
+        let points: Vec<PointF64> = (0..100)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * (i % 99) as f64 / 99.0;
+                PointF64::new(50.0 * angle.cos(), 30.0 * angle.sin())
+            })
+            .collect();
+        let path = PathF64::from_points(points);
+        let reduced = path.reduce_to_budget(10);
+        assert!(reduced.len() <= 10 && reduced.len() >= 5, "{}", reduced.len());
+        assert_eq!(reduced[0], path[0]);
+        assert_eq!(reduced[reduced.len() - 1], path[path.len() - 1]);
+        assert_eq!(path.reduce_to_budget(100).path, path.path);
+    }
+
     #[test]
     fn test_reduce_noop_2() {
         let path = Path {
@@ -497,7 +711,7 @@ mod tests {
     }
 
     #[test]
-    fn test_reduce_triangle_noop() {
+    fn test_reduce_triangle_too_few_points() {
         let path = Path {
             path: vec![
                 PointI32 { x: 0, y: 0 },
@@ -506,7 +720,7 @@ mod tests {
                 PointI32 { x: 0, y: 0 },
             ]
         };
-        assert_eq!(path.reduce(0.5).unwrap().path, path.path);
+        assert!(path.reduce(0.5).is_none());
     }
 
     #[test]
@@ -563,4 +777,39 @@ mod tests {
             "M2,3 L4,3 L0,0 ".to_owned()
         );
     }
-}
\ No newline at end of file
+
+    fn unit_square() -> PathF64 {
+        PathF64::from_points(vec![
+            PointF64::new(0.0, 0.0),
+            PointF64::new(1.0, 0.0),
+            PointF64::new(1.0, 1.0),
+            PointF64::new(0.0, 1.0),
+        ])
+    }
+
+    #[test]
+    fn test_scaled() {
+        let square = unit_square();
+        assert_eq!(square.scaled(2.0).path, vec![
+            PointF64::new(0.0, 0.0),
+            PointF64::new(2.0, 0.0),
+            PointF64::new(2.0, 2.0),
+            PointF64::new(0.0, 2.0),
+        ]);
+        assert_eq!(square.scaled_about(PointF64::new(0.5, 0.5), 2.0).path, vec![
+            PointF64::new(-0.5, -0.5),
+            PointF64::new(1.5, -0.5),
+            PointF64::new(1.5, 1.5),
+            PointF64::new(-0.5, 1.5),
+        ]);
+        assert_eq!(square.path, unit_square().path);
+    }
+
+    #[test]
+    fn test_translated() {
+        let path = PathI32::from_points(vec![PointI32::new(1, 2), PointI32::new(3, 4)]);
+        let moved = path.translated(PointI32::new(10, -1));
+        assert_eq!(moved.path, vec![PointI32::new(11, 1), PointI32::new(13, 3)]);
+        assert_eq!(path.path, vec![PointI32::new(1, 2), PointI32::new(3, 4)]);
+    }
+}
@@ -16,7 +16,7 @@ where T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Copy + Into<Float
 }
 
 /// square distance from a point to a segment
-fn get_sq_seg_dist<T>(p: Point2<T>, p1: Point2<T>, p2: Point2<T>) -> Float
+pub(crate) fn get_sq_seg_dist<T>(p: Point2<T>, p1: Point2<T>, p2: Point2<T>) -> Float
 where T: Add<Output = T> + Sub<Output = T> + Mul<Output = T> + Copy + Into<Float> {
 
     let mut x = p1.x.into();
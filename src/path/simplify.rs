@@ -5,10 +5,15 @@ use super::util::signed_area;
 pub(crate) struct PathSimplify;
 
 #[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
 pub enum PathSimplifyMode {
     None,
     Polygon,
     Spline,
+    /// Keep only corners (turning by at least `angle_threshold` radians, measured
+    /// `min_segment` pixels along the path on either side) connected by straight lines.
+    /// Falls back to `Polygon` if that changes the enclosed area too much.
+    Corner { angle_threshold: f64, min_segment: f64 },
 }
 
 #[derive(Copy, Clone)]
@@ -27,11 +32,21 @@ impl Default for PathSimplifyMode {
 
 impl PathSimplify {
 
+    /// Paths with this many points or fewer are returned unchanged by the simplification functions
+    const MIN_LEN: usize = 4;
+
     /// Returns a copy of a path after removing 1-pixel staircases.
     /// 
     /// Clockwiseness of path must be indicated to perform outset
+    /// 
+    /// Paths of `MIN_LEN` points or fewer are returned unchanged. Consecutive duplicate points are skipped,
+    /// and the result never has fewer than 3 distinct points unless the input had fewer.
     pub fn remove_staircase(path: &PathI32, clockwise: bool) -> PathI32 {
-        let path = &path.path;
+        if path.len() <= Self::MIN_LEN {
+            return path.clone();
+        }
+        let input = Self::remove_duplicates(path);
+        let path = &input.path;
         let len = path.len();
 
         let segment_length = |i: usize, j: usize| -> i32 {
@@ -57,12 +72,19 @@ impl PathSimplify {
                 result.add(path[i]);
             }
         }
-        result
+        Self::keep_distinct(result, input)
     }
 
+    /// Returns a copy of a path after removing points that deviate less than 1 pixel from a straight line.
+    /// 
+    /// The same guarantees as `remove_staircase` apply to degenerate input.
     pub fn limit_penalties(path: &PathI32) -> PathI32 {
+        if path.len() <= Self::MIN_LEN {
+            return path.clone();
+        }
+        let input = Self::remove_duplicates(path);
         let tolerance = 1.0;
-        let path = &path.path;
+        let path = &input.path;
         let len = path.len();
         let past_delta = |from: usize, to: usize| -> f64 {
             (from..to).skip(1).map(|i| {
@@ -88,7 +110,30 @@ impl PathSimplify {
                 result.add(path[i]);
             }
         }
-        result
+        Self::keep_distinct(result, input)
+    }
+
+    /// Returns a copy of a path with consecutive duplicate points skipped
+    fn remove_duplicates(path: &PathI32) -> PathI32 {
+        let mut points = path.path.clone();
+        points.dedup();
+        PathI32::from_points(points)
+    }
+
+    /// Falls back to `input` if simplification collapsed a shape into fewer than 3 distinct points
+    fn keep_distinct(result: PathI32, input: PathI32) -> PathI32 {
+        if Self::num_distinct(&result) < 3 && Self::num_distinct(&input) >= 3 {
+            input
+        } else {
+            result
+        }
+    }
+
+    fn num_distinct(path: &PathI32) -> usize {
+        let mut points = path.path.clone();
+        points.sort_by_key(|p| (p.x, p.y));
+        points.dedup();
+        points.len()
     }
 
     fn evaluate_penalty(a: PointI32, b: PointI32, c: PointI32) -> f64 {
@@ -100,4 +145,254 @@ impl PathSimplify {
         let area = (p * (p - l1) * (p - l2) * (p - l3)).sqrt();
         area * area / l3
     }
-}
\ No newline at end of file
+
+    /// Maximum relative difference in enclosed area between the output of `keep_corners`
+    /// and the polygon it is derived from
+    const CORNER_MAX_AREA_ERROR: f64 = 0.1;
+
+    /// Returns the polygon simplified path reduced to its corners, see `PathSimplifyMode::Corner`.
+    /// If fewer than 3 corners are found or the area error is above `CORNER_MAX_AREA_ERROR`,
+    /// the polygon is returned instead.
+    pub fn keep_corners(path: &PathI32, clockwise: bool, angle_threshold: f64, min_segment: f64) -> PathI32 {
+        let polygon = Self::limit_penalties(&Self::remove_staircase(path, clockwise));
+        let mut points = &polygon.path[..];
+        if points.len() > 1 && points.first() == points.last() {
+            points = &points[..points.len() - 1];
+        }
+        let len = points.len();
+        if len < 3 {
+            return polygon;
+        }
+
+        let next = |i: usize| (i + 1) % len;
+        let prev = |i: usize| (i + len - 1) % len;
+        let length = |i: usize, j: usize| {
+            let d = points[j] - points[i];
+            ((d.x * d.x + d.y * d.y) as f64).sqrt()
+        };
+        // walks at least `min_segment` along the path (but never all the way around)
+        let walk = |i: usize, step: &dyn Fn(usize) -> usize| {
+            let (mut j, mut distance) = (i, 0.0);
+            for _ in 1..len {
+                if distance >= min_segment {
+                    break;
+                }
+                let k = step(j);
+                distance += length(j, k);
+                j = k;
+            }
+            j
+        };
+
+        let turns: Vec<f64> = (0..len).map(|i| {
+            let a = points[i] - points[walk(i, &prev)];
+            let b = points[walk(i, &next)] - points[i];
+            let cross = (a.x * b.y - a.y * b.x) as f64;
+            let dot = (a.x * b.x + a.y * b.y) as f64;
+            cross.atan2(dot).abs()
+        }).collect();
+
+        // non-maximum suppression within `min_segment` on either side
+        let is_corner = |i: usize| {
+            if turns[i] < angle_threshold {
+                return false;
+            }
+            let beats = |j: usize| turns[j] > turns[i] || (turns[j] == turns[i] && j < i);
+            for step in [&prev as &dyn Fn(usize) -> usize, &next] {
+                let (mut j, mut distance) = (i, 0.0);
+                for _ in 1..len {
+                    let k = step(j);
+                    distance += length(j, k);
+                    if distance >= min_segment {
+                        break;
+                    }
+                    if beats(k) {
+                        return false;
+                    }
+                    j = k;
+                }
+            }
+            true
+        };
+
+        let mut corners: Vec<PointI32> = (0..len).filter(|&i| is_corner(i)).map(|i| points[i]).collect();
+        if corners.len() < 3 {
+            return polygon;
+        }
+
+        let expected = Self::area(points);
+        let actual = Self::area(&corners);
+        if expected == 0 || (actual - expected).abs() as f64 > expected.abs() as f64 * Self::CORNER_MAX_AREA_ERROR {
+            return polygon;
+        }
+
+        corners.push(corners[0]);
+        PathI32::from_points(corners)
+    }
+
+    /// Twice the signed area enclosed by a polygon (shoelace formula)
+    fn area(points: &[PointI32]) -> i64 {
+        let len = points.len();
+        (0..len).map(|i| {
+            let (p, q) = (points[i], points[(i + 1) % len]);
+            p.x as i64 * q.y as i64 - q.x as i64 * p.y as i64
+        }).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BinaryImage, CompoundPathElement, Spline};
+    use crate::clusters::Cluster;
+
+    /// xorshift32; deterministic so failures are reproducible
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            let mut x = self.0;
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            self.0 = x;
+            x
+        }
+    }
+
+    fn random_image(rng: &mut Rng) -> BinaryImage {
+        let width = 1 + (rng.next() % 8) as usize;
+        let height = 1 + (rng.next() % 8) as usize;
+        let mut image = BinaryImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, rng.next() & 1 == 0);
+            }
+        }
+        image
+    }
+
+    fn path(points: &[(i32, i32)]) -> PathI32 {
+        PathI32::from_points(points.iter().map(|&(x, y)| PointI32::new(x, y)).collect())
+    }
+
+    #[test]
+    fn simplify_short_paths_unchanged() {
+        for points in [
+            vec![],
+            vec![(0, 0)],
+            vec![(0, 0), (0, 0)],
+            vec![(0, 0), (1, 0), (0, 0)],
+            vec![(0, 0), (1, 0), (1, 1), (0, 0)],
+        ].iter() {
+            let p = path(points);
+            assert_eq!(PathSimplify::remove_staircase(&p, true).path, p.path);
+            assert_eq!(PathSimplify::remove_staircase(&p, false).path, p.path);
+            assert_eq!(PathSimplify::limit_penalties(&p).path, p.path);
+            // must not panic
+            p.simplify(true).smooth(1.0, 8.0, 1.0, 10);
+        }
+    }
+
+    #[test]
+    fn simplify_skip_duplicates() {
+        let p = path(&[(0, 0), (0, 0), (1, 0), (1, 0), (1, 1), (0, 1), (0, 0)]);
+        let result = PathSimplify::remove_staircase(&p, true);
+        assert!(result.path.windows(2).all(|w| w[0] != w[1]));
+        assert!(PathSimplify::num_distinct(&result) >= 3);
+        let result = PathSimplify::limit_penalties(&p);
+        assert!(result.path.windows(2).all(|w| w[0] != w[1]));
+        assert!(PathSimplify::num_distinct(&result) >= 3);
+    }
+
+    #[test]
+    fn simplify_keep_distinct() {
+        // a unit square walked counter-clockwise used to collapse into a single point
+        let p = path(&[(0, 0), (0, 1), (1, 1), (1, 0), (0, 0)]);
+        assert!(PathSimplify::num_distinct(&PathSimplify::remove_staircase(&p, true)) >= 3);
+        assert!(PathSimplify::num_distinct(&PathSimplify::limit_penalties(&p)) >= 3);
+        // collinear content is kept rather than collapsed
+        let p = path(&[(0, 0), (1, 0), (2, 0), (3, 0), (2, 0), (1, 0), (0, 0)]);
+        assert!(PathSimplify::num_distinct(&PathSimplify::remove_staircase(&p, true)) >= 3);
+        assert!(PathSimplify::num_distinct(&PathSimplify::limit_penalties(&p)) >= 3);
+    }
+
+    fn rasterize(width: usize, height: usize, inside: impl Fn(f64, f64) -> bool) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, inside(x as f64 + 0.5, y as f64 + 0.5));
+            }
+        }
+        image
+    }
+
+    const CORNER: PathSimplifyMode = PathSimplifyMode::Corner {
+        angle_threshold: std::f64::consts::PI / 4.0,
+        min_segment: 4.0,
+    };
+
+    #[test]
+    fn simplify_corner_rotated_rectangle() {
+        let (sin, cos) = 10f64.to_radians().sin_cos();
+        let image = rasterize(64, 64, |x, y| {
+            let (x, y) = (x - 32.0, y - 32.0);
+            let (u, v) = (x * cos + y * sin, -x * sin + y * cos);
+            u.abs() <= 20.0 && v.abs() <= 12.0
+        });
+        for &clockwise in [true, false].iter() {
+            let polygon = PathI32::image_to_path(&image, clockwise, PathSimplifyMode::Polygon);
+            assert!(polygon.len() > 5);
+            let path = PathI32::image_to_path(&image, clockwise, CORNER);
+            // 4 corners and the closing point
+            assert_eq!(path.len(), 5);
+            assert_eq!(path[0], path[4]);
+        }
+    }
+
+    #[test]
+    fn simplify_corner_circle_fallback() {
+        let image = rasterize(48, 48, |x, y| (x - 24.0).powi(2) + (y - 24.0).powi(2) <= 20.0 * 20.0);
+        let polygon = PathI32::image_to_path(&image, true, PathSimplifyMode::Polygon);
+        let path = PathI32::image_to_path(&image, true, CORNER);
+        assert_eq!(path.path, polygon.path);
+    }
+
+    #[test]
+    fn simplify_random_images() {
+        let mut rng = Rng(0x9E3779B9);
+        for _ in 0..2000 {
+            // raw bitmaps, including diagonal pinches and checkerboards
+            let image = random_image(&mut rng);
+            let margin = 2.0;
+            let check = |points: &[crate::PointF64]| {
+                for p in points {
+                    assert!(p.x.is_finite() && p.y.is_finite(), "{}", image);
+                    assert!(-margin <= p.x && p.x <= image.width as f64 + margin, "{}", image);
+                    assert!(-margin <= p.y && p.y <= image.height as f64 + margin, "{}", image);
+                }
+            };
+            for cluster in image.to_clusters(false).iter() {
+                let cluster_image = cluster.to_binary_image();
+                // holes are filled and traced on their own, so the walker never meets a pinch
+                for mut path in Cluster::image_to_paths(&cluster_image, PathSimplifyMode::Polygon) {
+                    path.offset(&cluster.rect.left_top());
+                    let smooth = path.smooth(std::f64::consts::PI / 3.0, 8.0, 1.0, 10);
+                    let spline = Spline::from_path_f64(&smooth, std::f64::consts::PI / 4.0);
+                    check(&smooth.path);
+                    check(&spline.points);
+                }
+                for mode in [PathSimplifyMode::None, PathSimplifyMode::Spline, PathSimplifyMode::Corner { angle_threshold: 1.0, min_segment: 2.0 }] {
+                    let paths = cluster.to_compound_path(mode, std::f64::consts::PI / 3.0, 4.0, 10, std::f64::consts::PI / 4.0);
+                    for path in paths.iter() {
+                        match path {
+                            CompoundPathElement::PathI32(path) => check(&path.to_path_f64().path),
+                            CompoundPathElement::PathF64(path) => check(&path.path),
+                            CompoundPathElement::Spline(spline) => check(&spline.points),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -4,7 +4,7 @@ use flo_curves::{bezier, BezierCurveFactory};
 /// Handles Path Smoothing
 pub(crate) struct SubdivideSmooth;
 
-use super::util::{angle, find_intersection, find_mid_point, norm, normalize, signed_angle_difference};
+use super::util::{angle, find_intersection, norm, normalize, signed_angle_difference};
 
 impl SubdivideSmooth {
 
@@ -16,7 +16,7 @@ impl SubdivideSmooth {
     pub fn find_corners<T>(path: &Path<Point2<T>>, threshold: f64) -> Vec<bool>
     where T: std::ops::Add<Output = T> + std::ops::Sub<Output = T> + std::ops::Mul<Output = T> + Copy + Into<f64> {
 
-        let path = &path.path[0..(path.path.len()-1)];
+        let path = &path.path[0..path.path.len().saturating_sub(1)];
         let len = path.len();
         if len == 0 {
             return vec![];
@@ -50,7 +50,7 @@ impl SubdivideSmooth {
     /// because the last point of the original path is always equal to the first point for paths of walked polygons (closed path).
     pub fn find_splice_points(path: &PathF64, threshold: f64) -> Vec<bool> {
 
-        let path = &path.path[0..(path.path.len()-1)];
+        let path = &path.path[0..path.path.len().saturating_sub(1)];
         let len = path.len();
         if len == 0 {
             return vec![];
@@ -129,7 +129,20 @@ impl SubdivideSmooth {
     pub fn subdivide_keep_corners(
         path: &PathF64, corners: &[bool], outset_ratio: f64, segment_length: f64
     ) -> (PathF64, Vec<bool>, bool) {
+        Self::subdivide_keep_corners_with(path, corners, outset_ratio, &|_| segment_length)
+    }
+
+    /// Same as `subdivide_keep_corners`, but `segment_length` is evaluated per point.
+    /// A segment is compared against the smaller of the lengths at its two end points.
+    pub fn subdivide_keep_corners_with(
+        path: &PathF64, corners: &[bool], outset_ratio: f64, segment_length: &dyn Fn(&PointF64) -> f64
+    ) -> (PathF64, Vec<bool>, bool) {
+        let segment_length = |a: &PointF64, b: &PointF64| segment_length(a).min(segment_length(b));
 
+        if path.len() <= 1 {
+            // nothing to subdivide
+            return (path.clone(), corners.to_vec(), true);
+        }
         let path = &path.path[0..(path.path.len()-1)];
         let len = path.len();
 
@@ -151,7 +164,7 @@ impl SubdivideSmooth {
 
             // Apply threshold on length of current segment
             let length_curr = norm(&(path[i] - path[j]));
-            if length_curr <= segment_length {
+            if length_curr <= segment_length(&path[i], &path[j]) {
                 continue;
             }
 
@@ -183,7 +196,8 @@ impl SubdivideSmooth {
                 new_path.push(new_point);
                 new_corners.push(false); // new point will never be corner
                 // If any of the new segments is still bigger than the length threshold, further iterations will be needed
-                if norm(&(path[i] - new_point)) > segment_length || norm(&(path[j] - new_point)) > segment_length {
+                if norm(&(path[i] - new_point)) > segment_length(&path[i], &new_point) ||
+                    norm(&(path[j] - new_point)) > segment_length(&path[j], &new_point) {
                     can_terminate_iteration = false;
                 }
             }
@@ -200,8 +214,8 @@ impl SubdivideSmooth {
     fn find_new_point_from_4_point_scheme(
         p_i: &PointF64, p_j: &PointF64, p_1: &PointF64, p_2: &PointF64, outset_ratio: f64
     ) -> PointF64 {
-        let mid_out = find_mid_point(p_i, p_j);
-        let mid_in = find_mid_point(p_1, p_2);
+        let mid_out = p_i.midpoint(*p_j);
+        let mid_in = p_1.midpoint(*p_2);
 
         let vector_out = mid_out - mid_in;
         let new_magnitude = vector_out.norm() / outset_ratio;
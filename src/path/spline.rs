@@ -1,7 +1,27 @@
 use std::{cmp::Ordering};
-use crate::{BinaryImage, PathF64, PointF64, PathSimplifyMode};
+use crate::{BinaryImage, BoundingRectF64, Color, ColorImage, PathF64, PointF64, PathSimplifyMode, ToSvgString, bilinear_interpolate, bilinear_interpolate_safe};
 use super::{PathI32, smooth::SubdivideSmooth};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors from operating on a malformed `Spline`
+pub enum SplineError {
+    /// The number of points is not 1+3n for some integer n
+    InvalidLength(usize),
+}
+
+impl std::fmt::Display for SplineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLength(len) => write!(f, "Invalid spline! Length must be 1+3n, got {}.", len),
+        }
+    }
+}
+
+impl std::error::Error for SplineError {}
+
+/// Number of color samples per curve taken by `Spline::split_by_color_change`
+const COLOR_SAMPLES_PER_CURVE: usize = 16;
+
 #[derive(Debug, Default, Clone)]
 /// Series of connecting 2D Bezier Curves
 pub struct Spline {
@@ -51,6 +71,20 @@ impl Spline {
         self.points.len() <= 3
     }
 
+    /// Returns true if the number of points is 1+3n for some integer n
+    pub fn is_valid(&self) -> bool {
+        self.points.len() % 3 == 1
+    }
+
+    /// Drops the points of a trailing incomplete curve, so that the spline becomes valid.
+    /// An empty spline is left empty.
+    pub fn repair(&mut self) {
+        if !self.points.is_empty() {
+            let len = self.points.len() - (self.points.len() - 1) % 3;
+            self.points.truncate(len);
+        }
+    }
+
     /// Applies an offset to all points on the spline
     pub fn offset(&mut self, offset: &PointF64) {
         for path in self.points.iter_mut() {
@@ -59,6 +93,153 @@ impl Spline {
         }
     }
 
+    /// Evaluates the `curve_index`-th curve at parameter `t` (0.0 to 1.0)
+    pub fn point_at(&self, curve_index: usize, t: f64) -> PointF64 {
+        let p = &self.points[curve_index * 3..curve_index * 3 + 4];
+        let s = 1.0 - t;
+        p[0] * (s * s * s) + p[1] * (3.0 * s * s * t) + p[2] * (3.0 * s * t * t) + p[3] * (t * t * t)
+    }
+
+    /// Returns the (unnormalized) derivative of the `curve_index`-th curve at parameter `t`
+    pub fn tangent_at(&self, curve_index: usize, t: f64) -> PointF64 {
+        let p = &self.points[curve_index * 3..curve_index * 3 + 4];
+        let s = 1.0 - t;
+        (p[1] - p[0]) * (3.0 * s * s) + (p[2] - p[1]) * (6.0 * s * t) + (p[3] - p[2]) * (3.0 * t * t)
+    }
+
+    /// Approximates the spline by a polyline, subdividing each curve until its control points
+    /// are within `tolerance` of the chord. Returns just the points of an empty spline.
+    pub fn flatten(&self, tolerance: f64) -> PathF64 {
+        let mut path = PathF64::new();
+        if let Some(&first) = self.points.first() {
+            path.add(first);
+        }
+        for i in 0..self.num_curves() {
+            let curve = [0, 1, 2, 3].map(|c| self.points[i * 3 + c]);
+            flatten_curve(&curve, tolerance.max(1e-6), SPLINE_FLATTEN_MAX_DEPTH, &mut path);
+        }
+        path
+    }
+
+    /// Samples the colors of `image` along the spline, at `samples_per_curve` evenly spaced `t` values of each curve
+    /// (starting at 0.0) plus the end point, so `num_curves() * samples_per_curve + 1` colors in order.
+    /// Points outside the image are clamped to its border. Returns no colors if the spline has no curve
+    /// or the image has no pixel.
+    pub fn sample_colors_along(&self, image: &ColorImage, samples_per_curve: usize) -> Vec<Color> {
+        if self.is_empty() || image.width == 0 || image.height == 0 {
+            return Vec::new();
+        }
+        let samples_per_curve = samples_per_curve.max(1);
+        let sample = |p: PointF64| {
+            let p = p.to_point_f32();
+            bilinear_interpolate_safe(image, p).unwrap_or_else(|| {
+                let mut p = p;
+                p.x = p.x.clamp(0.0, (image.width - 1) as f32);
+                p.y = p.y.clamp(0.0, (image.height - 1) as f32);
+                bilinear_interpolate(image, p)
+            })
+        };
+        let mut colors = Vec::with_capacity(self.num_curves() * samples_per_curve + 1);
+        for i in 0..self.num_curves() {
+            for k in 0..samples_per_curve {
+                colors.push(sample(self.point_at(i, k as f64 / samples_per_curve as f64)));
+            }
+        }
+        colors.push(sample(self.points[self.num_curves() * 3]));
+        colors
+    }
+
+    /// Cuts the spline where the color of `image` sampled along it (see `sample_colors_along`) changes,
+    /// i.e. at each sample point whose `diff` to the previous sample exceeds `threshold`,
+    /// so that each piece can be stroked with a constant color. Returns the pieces in order.
+    pub fn split_by_color_change(
+        &self,
+        image: &ColorImage,
+        threshold: i32,
+        diff: impl Fn(Color, Color) -> i32,
+    ) -> Vec<Spline> {
+        let colors = self.sample_colors_along(image, COLOR_SAMPLES_PER_CURVE);
+        if colors.is_empty() {
+            return vec![self.clone()];
+        }
+        let cut_at = |j: usize| diff(colors[j - 1], colors[j]) > threshold;
+
+        let mut pieces = Vec::new();
+        let mut current = Spline::new(self.points[0]);
+        for i in 0..self.num_curves() {
+            if i > 0 && cut_at(i * COLOR_SAMPLES_PER_CURVE) {
+                let start = self.points[i * 3];
+                pieces.push(std::mem::replace(&mut current, Spline::new(start)));
+            }
+            let mut curve = [0, 1, 2, 3].map(|c| self.points[i * 3 + c]);
+            // the parameter of the original curve at which `curve` starts
+            let mut start = 0.0;
+            for k in 1..COLOR_SAMPLES_PER_CURVE {
+                if !cut_at(i * COLOR_SAMPLES_PER_CURVE + k) {
+                    continue;
+                }
+                let t = k as f64 / COLOR_SAMPLES_PER_CURVE as f64;
+                let (left, right) = split_curve(&curve, (t - start) / (1.0 - start));
+                current.add(left[1], left[2], left[3]);
+                pieces.push(std::mem::replace(&mut current, Spline::new(left[3])));
+                curve = right;
+                start = t;
+            }
+            current.add(curve[1], curve[2], curve[3]);
+        }
+        pieces.push(current);
+        pieces
+    }
+
+    /// Returns the tight bounding rect of the spline, taking the extrema inside each curve into account
+    /// (as opposed to the bounds of the control points, which may over-estimate).
+    pub fn exact_bounding_rect(&self) -> BoundingRectF64 {
+        let mut rect = BoundingRectF64::default();
+        if let Some(&first) = self.points.first() {
+            rect.add_point(first);
+        }
+        for i in 0..self.num_curves() {
+            let p = &self.points[i * 3..i * 3 + 4];
+            rect.add_point(p[3]);
+            if p.iter().any(|p| !p.x.is_finite() || !p.y.is_finite()) {
+                // degenerate curve, fall back to the control polygon
+                p.iter().for_each(|&p| rect.add_point(p));
+                continue;
+            }
+            let roots_x = Self::derivative_roots(p[0].x, p[1].x, p[2].x, p[3].x);
+            let roots_y = Self::derivative_roots(p[0].y, p[1].y, p[2].y, p[3].y);
+            for t in roots_x.into_iter().chain(roots_y).flatten() {
+                rect.add_point(self.point_at(i, t));
+            }
+        }
+        rect
+    }
+
+    /// Returns the roots within (0, 1) of the derivative of a 1D cubic Bezier
+    fn derivative_roots(p0: f64, p1: f64, p2: f64, p3: f64) -> [Option<f64>; 2] {
+        // B'(t) / 3 = a t^2 + b t + c
+        let a = p3 - 3.0 * p2 + 3.0 * p1 - p0;
+        let b = 2.0 * (p2 - 2.0 * p1 + p0);
+        let c = p1 - p0;
+        let within = |t: f64| if t > 0.0 && t < 1.0 { Some(t) } else { None };
+
+        let scale = a.abs().max(b.abs()).max(c.abs());
+        if scale < f64::EPSILON {
+            // constant
+            return [None, None];
+        }
+        if a.abs() < scale * 1e-12 {
+            // quadratic term vanishes, linear
+            return [within(-c / b), None];
+        }
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return [None, None];
+        }
+        let sqrt = discriminant.sqrt();
+        [within((-b + sqrt) / (2.0 * a)), within((-b - sqrt) / (2.0 * a))]
+    }
+
     /// Returns a spline created from image.
     /// The following steps are performed:
     /// 1. Convert pixels into path
@@ -82,7 +263,7 @@ impl Spline {
     pub fn from_path_f64(path: &PathF64, splice_threshold: f64) -> Self {
         // First locate all the splice points
         let splice_points = SubdivideSmooth::find_splice_points(&path, splice_threshold);
-        let path = &path.path[0..path.len()-1];
+        let path = &path.path[0..path.len().saturating_sub(1)];
         let len = path.len();
         if len<=1 {
             return Self::new(PointF64 {x:0.0,y:0.0});
@@ -130,37 +311,67 @@ impl Spline {
 
     /// Converts spline to svg path. Panic if the length of spline is not valid (not 1+3n for some integer n)
     pub fn to_svg_string(&self, close: bool, offset: &PointF64, precision: Option<u32>) -> String {
+        match self.try_to_svg_string(close, offset, precision) {
+            Ok(string) => string,
+            Err(error) => panic!("{}", error),
+        }
+    }
 
-        let o = offset;
+    /// Converts spline to svg path. Returns an error if the length of spline is not valid (not 1+3n for some integer n)
+    pub fn try_to_svg_string(&self, close: bool, offset: &PointF64, precision: Option<u32>) -> Result<String, SplineError> {
+        if !self.points.is_empty() && !self.is_valid() {
+            return Err(SplineError::InvalidLength(self.len()));
+        }
 
-        if self.is_empty() {
-            return String::from("");
+        let mut string = String::with_capacity(self.estimate_svg_len(close, offset, precision));
+        self.write_svg(&mut string, close, offset, precision).unwrap();
+        Ok(string)
+    }
+
+    /// Same as `to_svg_string`, but writes into `w`. Returns an error without writing anything
+    /// if the length of spline is not valid (not 1+3n for some integer n)
+    pub fn write_svg(&self, w: &mut impl std::fmt::Write, close: bool, offset: &PointF64, precision: Option<u32>) -> std::fmt::Result {
+        if !self.points.is_empty() && !self.is_valid() {
+            return Err(std::fmt::Error);
         }
 
-        if (self.len() - 1) % 3 != 0 {
-            panic!("Invalid spline! Length must be 1+3n.");
+        if self.is_empty() {
+            return Ok(());
         }
 
+        let o = offset;
+        let number = |n: f64| PointF64::number_format(n, precision);
         let points = &self.points;
         let len = points.len();
-        let mut result: Vec<String> = vec![format!("M{} {} ", PointF64::number_format(points[0].x + o.x, precision), PointF64::number_format(points[0].y + o.y, precision))];
+        write!(w, "M{} {} ", number(points[0].x + o.x), number(points[0].y + o.y))?;
 
         let mut i = 1;
         while i < len {
-            result.push(
-                format!("C{} {} {} {} {} {} ",
-                PointF64::number_format(points[i].x + o.x, precision), PointF64::number_format(points[i].y + o.y, precision),
-                PointF64::number_format(points[i+1].x + o.x, precision), PointF64::number_format(points[i+1].y + o.y, precision),
-                PointF64::number_format(points[i+2].x + o.x, precision), PointF64::number_format(points[i+2].y + o.y, precision))
-            );
+            write!(w, "C{} {} {} {} {} {} ",
+                number(points[i].x + o.x), number(points[i].y + o.y),
+                number(points[i+1].x + o.x), number(points[i+1].y + o.y),
+                number(points[i+2].x + o.x), number(points[i+2].y + o.y))?;
             i += 3;
         }
 
         if close {
-            result.push(String::from("Z "));
+            write!(w, "Z ")?;
         }
 
-        result.concat()
+        Ok(())
+    }
+
+    /// Estimates the length of `to_svg_string` from the points a quarter, half and three quarters of the way along
+    pub fn estimate_svg_len(&self, close: bool, offset: &PointF64, precision: Option<u32>) -> usize {
+        let len = self.points.len();
+        if len == 0 {
+            return 0;
+        }
+        let sample: usize = [len / 4, len / 2, len * 3 / 4].iter()
+            .map(|&i| (self.points[i] + *offset).to_svg_string(precision).len())
+            .sum();
+        // " " after each point, "M" and a "C" per curve
+        len * (sample / 3 + 1) + 1 + len / 3 + if close { 2 } else { 0 }
     }
 
     fn get_circular_subpath(path: &[PointF64], from: usize, to: usize) -> Vec<PointF64> {
@@ -184,6 +395,31 @@ impl Spline {
 
 }
 
+/// Maximum number of halvings of a curve in `Spline::flatten`
+const SPLINE_FLATTEN_MAX_DEPTH: usize = 16;
+
+/// Appends the points after the first of the flattened curve
+fn flatten_curve(p: &[PointF64; 4], tolerance: f64, depth: usize, path: &mut PathF64) {
+    let flat = super::reduce::get_sq_seg_dist(p[1], p[0], p[3]).max(super::reduce::get_sq_seg_dist(p[2], p[0], p[3]))
+        <= tolerance * tolerance;
+    if flat || depth == 0 {
+        path.add(p[3]);
+    } else {
+        let (left, right) = split_curve(p, 0.5);
+        flatten_curve(&left, tolerance, depth - 1, path);
+        flatten_curve(&right, tolerance, depth - 1, path);
+    }
+}
+
+/// Splits a cubic Bezier curve at parameter `t` by de Casteljau's algorithm
+fn split_curve(p: &[PointF64; 4], t: f64) -> ([PointF64; 4], [PointF64; 4]) {
+    let lerp = |a: PointF64, b: PointF64| a.lerp(b, t);
+    let (p01, p12, p23) = (lerp(p[0], p[1]), lerp(p[1], p[2]), lerp(p[2], p[3]));
+    let (p012, p123) = (lerp(p01, p12), lerp(p12, p23));
+    let mid = lerp(p012, p123);
+    ([p[0], p01, p012, mid], [mid, p123, p23, p[3]])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -211,4 +447,172 @@ mod tests {
             "M2 3 C4 3 4 5 6 5 ".to_owned()
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_spline_repair() {
+        let degenerate = PathF64::from_points(vec![PointF64::new(1.0, 1.0), PointF64::new(2.0, 3.0), PointF64::new(1.0, 1.0)]);
+        let spline = Spline::from_path_f64(&degenerate, 1.0);
+        assert!(spline.is_valid());
+        assert_eq!(spline.num_curves(), 1);
+
+        let mut spline = Spline::new(PointF64::new(0.0, 0.0));
+        assert!(spline.is_valid());
+        spline.add(PointF64::new(1.0, 0.0), PointF64::new(2.0, 0.0), PointF64::new(3.0, 0.0));
+        spline.points.push(PointF64::new(4.0, 0.0));
+        spline.points.push(PointF64::new(5.0, 0.0));
+        assert!(!spline.is_valid());
+        spline.repair();
+        assert!(spline.is_valid());
+        assert_eq!(spline.len(), 4);
+        assert_eq!(spline.points[3], PointF64::new(3.0, 0.0));
+
+        let mut empty = Spline::default();
+        assert!(!empty.is_valid());
+        empty.repair();
+        assert!(empty.points.is_empty());
+    }
+
+    #[test]
+    fn test_spline_try_to_svg_invalid() {
+        let spline = Spline {
+            points: vec![PointF64::new(0.0, 0.0), PointF64::new(1.0, 1.0), PointF64::new(2.0, 2.0)]
+        };
+        let origin = PointF64::new(0.0, 0.0);
+        assert_eq!(spline.try_to_svg_string(false, &origin, None), Err(SplineError::InvalidLength(3)));
+        assert_eq!(Spline::new(origin).try_to_svg_string(false, &origin, None), Ok(String::new()));
+        assert_eq!(Spline::default().try_to_svg_string(false, &origin, None), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_spline_write_svg_invalid() {
+        let spline = Spline {
+            points: vec![PointF64::new(0.0, 0.0); 5]
+        };
+        let mut string = String::new();
+        assert_eq!(spline.write_svg(&mut string, false, &PointF64::new(0.0, 0.0), None), Err(std::fmt::Error));
+        assert!(string.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Length must be 1+3n")]
+    fn test_spline_to_svg_invalid_panics() {
+        let spline = Spline {
+            points: vec![PointF64::new(0.0, 0.0); 5]
+        };
+        spline.to_svg_string(false, &PointF64::new(0.0, 0.0), None);
+    }
+
+    fn control_polygon_rect(spline: &Spline) -> BoundingRectF64 {
+        let mut rect = BoundingRectF64::default();
+        spline.iter().for_each(|&p| rect.add_point(p));
+        rect
+    }
+
+    #[test]
+    fn test_spline_exact_bounding_rect_arc() {
+        // cubic approximation of the upper half of the unit circle, peaking at (0, 1)
+        let k = 4.0 / 3.0;
+        let spline = Spline {
+            points: vec![
+                PointF64::new(-1.0, 0.0),
+                PointF64::new(-1.0, k),
+                PointF64::new(1.0, k),
+                PointF64::new(1.0, 0.0),
+            ]
+        };
+        let rect = spline.exact_bounding_rect();
+        assert!((rect.left_top.x + 1.0).abs() < 1e-9);
+        assert!((rect.left_top.y - 0.0).abs() < 1e-9);
+        assert!((rect.right_bottom.x - 1.0).abs() < 1e-9);
+        assert!((rect.right_bottom.y - 1.0).abs() < 1e-9);
+        assert!(rect.height() < control_polygon_rect(&spline).height());
+        assert!((spline.point_at(0, 0.5) - PointF64::new(0.0, 1.0)).norm() < 1e-9);
+        assert!(spline.tangent_at(0, 0.5).y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spline_exact_bounding_rect_sampled() {
+        let mut spline = Spline::new(PointF64::new(0.0, 0.0));
+        spline.add(PointF64::new(3.0, -2.0), PointF64::new(-2.0, 4.0), PointF64::new(1.0, 1.0));
+        spline.add(PointF64::new(4.0, -2.0), PointF64::new(5.0, 3.0), PointF64::new(2.0, 2.0));
+        let rect = spline.exact_bounding_rect();
+        let mut sampled = BoundingRectF64::default();
+        for i in 0..spline.num_curves() {
+            for s in 0..=1000 {
+                sampled.add_point(spline.point_at(i, s as f64 / 1000.0));
+            }
+        }
+        for (a, b) in [
+            (rect.left_top.x, sampled.left_top.x), (rect.left_top.y, sampled.left_top.y),
+            (rect.right_bottom.x, sampled.right_bottom.x), (rect.right_bottom.y, sampled.right_bottom.y),
+        ] {
+            assert!((a - b).abs() < 1e-4);
+        }
+        assert!(rect.left_top.x <= sampled.left_top.x && rect.right_bottom.x >= sampled.right_bottom.x);
+        assert!(rect.width() < control_polygon_rect(&spline).width());
+    }
+
+    #[test]
+    fn split_by_color_change_red_blue() {
+        // red on the left, blue on the right
+        let mut image = ColorImage::new_w_h(40, 10);
+        for y in 0..10 {
+            for x in 0..40 {
+                image.set_pixel(x, y, &if x < 17 { Color::new(255, 0, 0) } else { Color::new(0, 0, 255) });
+            }
+        }
+        // a straight line across the boundary, running beyond the image at both ends
+        let mut spline = Spline::new(PointF64::new(-4.0, 5.0));
+        spline.add(PointF64::new(4.0, 6.0), PointF64::new(14.0, 4.0), PointF64::new(20.0, 5.0));
+        spline.add(PointF64::new(26.0, 6.0), PointF64::new(36.0, 4.0), PointF64::new(44.0, 5.0));
+
+        let colors = spline.sample_colors_along(&image, 4);
+        assert_eq!(colors.len(), 9);
+        assert_eq!(colors[0], Color::new(255, 0, 0));
+        assert_eq!(colors[2], Color::new(255, 0, 0));
+        assert_eq!(colors[6], Color::new(0, 0, 255));
+        assert_eq!(colors[8], Color::new(0, 0, 255));
+
+        let diff = |a: Color, b: Color| (a.r as i32 - b.r as i32).abs() + (a.b as i32 - b.b as i32).abs();
+        let pieces = spline.split_by_color_change(&image, 128, diff);
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces.iter().all(|piece| piece.is_valid() && !piece.is_empty()));
+        let cut = *pieces[0].points.last().unwrap();
+        assert!((cut.x - 16.5).abs() < 2.0, "cut at {:?}", cut);
+        assert_eq!(cut, pieces[1].points[0]);
+        assert_eq!(pieces[0].points[0], spline.points[0]);
+        assert_eq!(*pieces[1].points.last().unwrap(), *spline.points.last().unwrap());
+        for (piece, color) in pieces.iter().zip([Color::new(255, 0, 0), Color::new(0, 0, 255)]) {
+            assert_eq!(piece.sample_colors_along(&image, 2)[1], color);
+        }
+    }
+
+    #[test]
+    fn sample_colors_along_empty_image() {
+        let mut spline = Spline::new(PointF64::new(0.0, 0.0));
+        spline.add(PointF64::new(1.0, 0.0), PointF64::new(2.0, 0.0), PointF64::new(3.0, 0.0));
+        let image = ColorImage::new_w_h(0, 0);
+        assert!(spline.sample_colors_along(&image, 4).is_empty());
+        let pieces = spline.split_by_color_change(&image, 0, |_, _| 1);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].points, spline.points);
+    }
+
+    #[test]
+    fn spline_flatten_tolerance() {
+        let mut spline = Spline::new(PointF64::new(0.0, 0.0));
+        spline.add(PointF64::new(0.0, 10.0), PointF64::new(10.0, 10.0), PointF64::new(10.0, 0.0));
+        let coarse = spline.flatten(1.0);
+        let fine = spline.flatten(0.01);
+        assert!(coarse.len() > 2 && fine.len() > coarse.len());
+        assert_eq!(fine.path[0], spline.points[0]);
+        assert_eq!(*fine.path.last().unwrap(), spline.points[3]);
+        // the vertices lie on the curve
+        for p in fine.iter() {
+            let t = (0..=1000).map(|k| k as f64 / 1000.0)
+                .min_by(|&a, &b| spline.point_at(0, a).distance_to(*p).partial_cmp(&spline.point_at(0, b).distance_to(*p)).unwrap())
+                .unwrap();
+            assert!(spline.point_at(0, t).distance_to(*p) < 0.05);
+        }
+    }
+}
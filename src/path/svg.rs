@@ -0,0 +1,327 @@
+use crate::{CompoundPathElement, PathF64, PointF64, Spline};
+
+#[derive(Debug, Clone, PartialEq)]
+/// Errors from parsing SVG path data
+pub enum SvgParseError {
+    /// A command other than M, L, C, Q, Z (in either case)
+    UnsupportedCommand(char),
+    /// A token that is neither a command nor a number
+    InvalidNumber(String),
+    /// A command is not followed by enough numbers
+    MissingArguments(char),
+    /// The path data does not start with a moveto
+    MissingMoveTo,
+}
+
+impl std::fmt::Display for SvgParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedCommand(c) => write!(f, "unsupported path command '{}'", c),
+            Self::InvalidNumber(s) => write!(f, "invalid number '{}'", s),
+            Self::MissingArguments(c) => write!(f, "missing arguments to path command '{}'", c),
+            Self::MissingMoveTo => write!(f, "path data must start with a moveto"),
+        }
+    }
+}
+
+impl std::error::Error for SvgParseError {}
+
+enum Token {
+    Command(char),
+    Number(f64),
+}
+
+fn tokenize(d: &str) -> Result<Vec<Token>, SvgParseError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = d.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() || c == ',' {
+            i += 1;
+        } else if c.is_ascii_alphabetic() && c != 'e' && c != 'E' {
+            tokens.push(Token::Command(c));
+            i += 1;
+        } else {
+            let start = i;
+            i += 1;
+            let mut dot = c == '.';
+            while i < chars.len() {
+                let n = chars[i];
+                let exponent = i > start && (chars[i - 1] == 'e' || chars[i - 1] == 'E');
+                if n.is_ascii_digit() || n == 'e' || n == 'E' || ((n == '-' || n == '+') && exponent) {
+                    i += 1;
+                } else if n == '.' && !dot {
+                    dot = true;
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            let token: String = chars[start..i].iter().collect();
+            match token.parse::<f64>() {
+                Ok(number) => tokens.push(Token::Number(number)),
+                Err(_) => return Err(SvgParseError::InvalidNumber(token)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A subpath being parsed. Straight segments are kept as a polyline until a curve shows up,
+/// after which the subpath becomes a spline.
+struct Subpath {
+    points: Vec<PointF64>,
+    curved: bool,
+}
+
+impl Subpath {
+    fn new(start: PointF64) -> Self {
+        Self { points: vec![start], curved: false }
+    }
+
+    fn last(&self) -> PointF64 {
+        self.points[self.points.len() - 1]
+    }
+
+    fn line_to(&mut self, p: PointF64) {
+        if self.curved {
+            let a = self.last();
+            self.points.push(a + (p - a) / 3.0);
+            self.points.push(a + (p - a) * (2.0 / 3.0));
+        }
+        self.points.push(p);
+    }
+
+    fn curve_to(&mut self, c1: PointF64, c2: PointF64, p: PointF64) {
+        if !self.curved {
+            // convert the polyline so far into straight cubic segments
+            let polyline = std::mem::take(&mut self.points);
+            self.points.push(polyline[0]);
+            self.curved = true;
+            for &q in polyline.iter().skip(1) {
+                self.line_to(q);
+            }
+        }
+        self.points.push(c1);
+        self.points.push(c2);
+        self.points.push(p);
+    }
+
+    fn into_element(self) -> CompoundPathElement {
+        if self.curved {
+            CompoundPathElement::Spline(Spline { points: self.points })
+        } else {
+            CompoundPathElement::PathF64(PathF64::from_points(self.points))
+        }
+    }
+}
+
+impl PathF64 {
+    /// Parses SVG path data, such as generated by `CompoundPath::to_svg_string`, into one element per subpath.
+    ///
+    /// M, L, C, Q and Z commands are supported, in absolute (upper case) and relative (lower case) forms.
+    /// Subpaths with curves become `Spline`s (with quadratic curves and lines elevated to cubic),
+    /// others become `PathF64`s. A closed subpath repeats its first point at the end.
+    /// A command other than a moveto after a closepath starts a new subpath at the start of the closed one.
+    pub fn from_svg_string(d: &str) -> Result<Vec<CompoundPathElement>, SvgParseError> {
+        let tokens = tokenize(d)?;
+        let mut elements = Vec::new();
+        let mut subpath: Option<Subpath> = None;
+        let mut current = PointF64::default();
+        let mut command = None;
+        let mut i = 0;
+
+        while i < tokens.len() {
+            let c = match tokens[i] {
+                Token::Command(c) => {
+                    i += 1;
+                    c
+                },
+                // repeated arguments of the previous command; those of a moveto are implicit linetos
+                Token::Number(_) => match command {
+                    Some('M') => 'L',
+                    Some('m') => 'l',
+                    Some('Z') | Some('z') => return Err(SvgParseError::MissingMoveTo),
+                    Some(c) => c,
+                    None => return Err(SvgParseError::MissingMoveTo),
+                },
+            };
+            let relative = c.is_ascii_lowercase();
+            let arguments = match c.to_ascii_uppercase() {
+                'M' | 'L' => 2,
+                'Q' => 4,
+                'C' => 6,
+                'Z' => 0,
+                _ => return Err(SvgParseError::UnsupportedCommand(c)),
+            };
+            let mut numbers = Vec::with_capacity(arguments);
+            for _ in 0..arguments {
+                match tokens.get(i) {
+                    Some(Token::Number(n)) => numbers.push(*n),
+                    _ => return Err(SvgParseError::MissingArguments(c)),
+                }
+                i += 1;
+            }
+            let base = if relative { current } else { PointF64::default() };
+            let point = |k: usize| base + PointF64::new(numbers[2 * k], numbers[2 * k + 1]);
+
+            if !c.eq_ignore_ascii_case(&'M') && subpath.is_none() {
+                match command {
+                    // closing again draws nothing
+                    Some(_) if c.eq_ignore_ascii_case(&'Z') => {
+                        command = Some(c);
+                        continue;
+                    },
+                    // after a closepath, the next subpath starts at the start of the closed one
+                    Some(_) => subpath = Some(Subpath::new(current)),
+                    None => return Err(SvgParseError::MissingMoveTo),
+                }
+            }
+            match c.to_ascii_uppercase() {
+                'M' => {
+                    if let Some(s) = subpath.take() {
+                        elements.push(s.into_element());
+                    }
+                    current = point(0);
+                    subpath = Some(Subpath::new(current));
+                },
+                'L' => {
+                    current = point(0);
+                    subpath.as_mut().unwrap().line_to(current);
+                },
+                'Q' => {
+                    let (q, p) = (point(0), point(1));
+                    let c1 = current + (q - current) * (2.0 / 3.0);
+                    let c2 = p + (q - p) * (2.0 / 3.0);
+                    subpath.as_mut().unwrap().curve_to(c1, c2, p);
+                    current = p;
+                },
+                'C' => {
+                    let (c1, c2, p) = (point(0), point(1), point(2));
+                    subpath.as_mut().unwrap().curve_to(c1, c2, p);
+                    current = p;
+                },
+                _ => {
+                    let mut s = subpath.take().unwrap();
+                    let start = s.points[0];
+                    if s.last() != start || s.points.len() == 1 {
+                        s.line_to(start);
+                    }
+                    elements.push(s.into_element());
+                    current = start;
+                },
+            }
+            command = Some(c);
+        }
+
+        if let Some(s) = subpath {
+            elements.push(s.into_element());
+        }
+        Ok(elements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompoundPath;
+
+    fn assert_points_eq(a: &[PointF64], b: &[PointF64]) {
+        assert_eq!(a.len(), b.len());
+        for (p, q) in a.iter().zip(b.iter()) {
+            assert!((p.x - q.x).abs() < 1e-9 && (p.y - q.y).abs() < 1e-9, "{:?} != {:?}", p, q);
+        }
+    }
+
+    #[test]
+    fn svg_round_trip() {
+        let square = PathF64::from_points(vec![
+            PointF64::new(2.0, 3.0),
+            PointF64::new(12.0, 3.0),
+            PointF64::new(12.5, 13.25),
+            PointF64::new(2.0, 13.0),
+            PointF64::new(2.0, 3.0),
+        ]);
+        let spline = Spline {
+            points: vec![
+                PointF64::new(20.0, 20.0),
+                PointF64::new(25.0, 18.0),
+                PointF64::new(30.0, 22.0),
+                PointF64::new(30.0, 30.0),
+                PointF64::new(26.0, 34.0),
+                PointF64::new(21.0, 31.0),
+                PointF64::new(20.0, 20.0),
+            ],
+        };
+        let mut paths = CompoundPath::new();
+        paths.add_path_f64(square.clone());
+        paths.add_spline(spline.clone());
+
+        let (string, offset) = paths.to_svg_string(true, PointF64::default(), None);
+        let parsed = PathF64::from_svg_string(&string).unwrap();
+        assert_eq!(parsed.len(), 2);
+        match &parsed[0] {
+            CompoundPathElement::PathF64(p) => assert_points_eq(&p.translated(offset).path, &square.path),
+            _ => panic!("expected PathF64"),
+        }
+        match &parsed[1] {
+            CompoundPathElement::Spline(s) => {
+                let points: Vec<PointF64> = s.points.iter().map(|&p| p + offset).collect();
+                assert_points_eq(&points, &spline.points);
+            },
+            _ => panic!("expected Spline"),
+        }
+    }
+
+    #[test]
+    fn svg_relative_and_quadratic() {
+        let parsed = PathF64::from_svg_string("m1,1 l2,0 0,2 z M0 0 q2 2 4 0").unwrap();
+        assert_eq!(parsed.len(), 2);
+        match &parsed[0] {
+            CompoundPathElement::PathF64(p) => assert_points_eq(&p.path, &[
+                PointF64::new(1.0, 1.0),
+                PointF64::new(3.0, 1.0),
+                PointF64::new(3.0, 3.0),
+                PointF64::new(1.0, 1.0),
+            ]),
+            _ => panic!("expected PathF64"),
+        }
+        match &parsed[1] {
+            CompoundPathElement::Spline(s) => assert_points_eq(&s.points, &[
+                PointF64::new(0.0, 0.0),
+                PointF64::new(4.0 / 3.0, 4.0 / 3.0),
+                PointF64::new(8.0 / 3.0, 4.0 / 3.0),
+                PointF64::new(4.0, 0.0),
+            ]),
+            _ => panic!("expected Spline"),
+        }
+    }
+
+    #[test]
+    fn svg_after_closepath() {
+        let parsed = PathF64::from_svg_string("M1 1 L2 2 Z L3 3 z l1 0 Z Z").unwrap();
+        assert_eq!(parsed.len(), 3);
+        let expected = [
+            vec![PointF64::new(1.0, 1.0), PointF64::new(2.0, 2.0), PointF64::new(1.0, 1.0)],
+            vec![PointF64::new(1.0, 1.0), PointF64::new(3.0, 3.0), PointF64::new(1.0, 1.0)],
+            vec![PointF64::new(1.0, 1.0), PointF64::new(2.0, 1.0), PointF64::new(1.0, 1.0)],
+        ];
+        for (element, expected) in parsed.iter().zip(expected.iter()) {
+            match element {
+                CompoundPathElement::PathF64(p) => assert_points_eq(&p.path, expected),
+                _ => panic!("expected PathF64"),
+            }
+        }
+    }
+
+    #[test]
+    fn svg_errors() {
+        assert_eq!(PathF64::from_svg_string("L1 1").unwrap_err(), SvgParseError::MissingMoveTo);
+        assert_eq!(PathF64::from_svg_string("M1 1 L2 2 Z 3 3").unwrap_err(), SvgParseError::MissingMoveTo);
+        assert_eq!(PathF64::from_svg_string("Z").unwrap_err(), SvgParseError::MissingMoveTo);
+        assert_eq!(PathF64::from_svg_string("M1 1 A1 1 0 0 1 2 2").unwrap_err(), SvgParseError::UnsupportedCommand('A'));
+        assert_eq!(PathF64::from_svg_string("M1 1 C2 2").unwrap_err(), SvgParseError::MissingArguments('C'));
+        assert_eq!(PathF64::from_svg_string("M1 1 L2 .").unwrap_err(), SvgParseError::InvalidNumber(".".into()));
+    }
+}
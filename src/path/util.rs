@@ -32,7 +32,7 @@ pub fn find_intersection(p1: &PointF64, p2: &PointF64, p3: &PointF64, p4: &Point
 
     if negligible(denom) && negligible(numera) && negligible(numerb) {
         // the two lines coincide
-        return Some((find_mid_point(p1, p2), Intersection { mua: NAN, mub: NAN }));
+        return Some((p1.midpoint(*p2), Intersection { mua: NAN, mub: NAN }));
     }
 
     if negligible(denom) {
@@ -87,12 +87,6 @@ fn negligible(v: f64) -> bool {
     -EPSILON < v && v < EPSILON
 }
 
-pub(super) fn find_mid_point(p1: &PointF64, p2: &PointF64) -> PointF64 {
-    let x = (p1.x + p2.x) / 2.0;
-    let y = (p1.y + p2.y) / 2.0;
-    PointF64 {x, y}
-}
-
 pub(super) fn norm<T>(p: &Point2<T>) -> f64
 where T: std::ops::Add<Output = T> + std::ops::Mul<Output = T> + Copy + Into<f64> {
     let n: f64 = (p.x*p.x + p.y*p.y).into();
@@ -1,7 +1,7 @@
 use flo_curves::{Coordinate, Coordinate2D};
 use num_traits::Float;
 use std::{convert::{From, Into}, fmt::Display, ops::*};
-use crate::{Polar2, PolarF64};
+use crate::{BoundingRect, Polar2, PolarF64};
 
 /// Generic point in 2D space
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -381,6 +381,14 @@ impl PointI32 {
     pub fn to_point_f64(&self) -> PointF64 {
         PointF64 { x: self.x as f64, y: self.y as f64 }
     }
+
+    /// The nearest point within `rect`, whose right and bottom are exclusive
+    pub fn clamp(self, rect: BoundingRect) -> PointI32 {
+        PointI32 {
+            x: self.x.min(rect.right - 1).max(rect.left),
+            y: self.y.min(rect.bottom - 1).max(rect.top),
+        }
+    }
 }
 
 impl PointF64 {
@@ -398,6 +406,33 @@ impl PointF64 {
     pub fn to_polar_f64(&self) -> PolarF64 {
         self.to_polar()
     }
+
+    /// The nearest pixel, rounding half away from zero
+    #[inline]
+    pub fn round(self) -> PointI32 {
+        PointI32 { x: self.x.round() as i32, y: self.y.round() as i32 }
+    }
+
+    #[inline]
+    pub fn floor(self) -> PointI32 {
+        PointI32 { x: self.x.floor() as i32, y: self.y.floor() as i32 }
+    }
+
+    #[inline]
+    pub fn ceil(self) -> PointI32 {
+        PointI32 { x: self.x.ceil() as i32, y: self.y.ceil() as i32 }
+    }
+
+    /// Linear interpolation: `self` at `t` = 0, `other` at `t` = 1
+    #[inline]
+    pub fn lerp(self, other: Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+
+    #[inline]
+    pub fn midpoint(self, other: Self) -> Self {
+        PointF64 { x: (self.x + other.x) / 2.0, y: (self.y + other.y) / 2.0 }
+    }
 }
 
 impl PointType for PointI32 {
@@ -446,6 +481,35 @@ mod tests {
         assert!(1.0 - 0.000000001 < r.y && r.y < 1.0 + 0.000000001);
     }
 
+    #[test]
+    fn pointf64_round() {
+        let p = PointF64::new(-1.5, 2.5);
+        assert_eq!(p.round(), PointI32::new(-2, 3));
+        assert_eq!(p.floor(), PointI32::new(-2, 2));
+        assert_eq!(p.ceil(), PointI32::new(-1, 3));
+        let q = PointF64::new(-0.4, 0.6);
+        assert_eq!(q.round(), PointI32::new(0, 1));
+        assert_eq!(q.floor(), PointI32::new(-1, 0));
+        assert_eq!(q.ceil(), PointI32::new(0, 1));
+    }
+
+    #[test]
+    fn pointi32_clamp() {
+        let rect = BoundingRect::new_x_y_w_h(2, 3, 10, 5);
+        assert_eq!(PointI32::new(-5, 20).clamp(rect), PointI32::new(2, 7));
+        assert_eq!(PointI32::new(50, 0).clamp(rect), PointI32::new(11, 3));
+        assert_eq!(PointI32::new(4, 4).clamp(rect), PointI32::new(4, 4));
+    }
+
+    #[test]
+    fn pointf64_lerp() {
+        let (a, b) = (PointF64::new(1.0, -2.0), PointF64::new(5.0, 4.0));
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 0.5), PointF64::new(3.0, 1.0));
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.midpoint(b), PointF64::new(3.0, 1.0));
+    }
+
     #[test]
     fn test_round_i32() {
         let z = PointI32 { x: 0, y: 2 };
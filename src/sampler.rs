@@ -1,38 +1,92 @@
 use crate::{BinaryImage, BoundingRect};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors from constructing a `Sampler` with invalid parameters
+pub enum SamplerError {
+    /// The sampler size is 0
+    ZeroSize,
+    /// The crop is not square
+    NonSquareCrop { width: i32, height: i32 },
+    /// The crop does not lie within the image
+    CropOutOfBounds { crop: BoundingRect, width: usize, height: usize },
+}
+
+impl std::fmt::Display for SamplerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroSize => write!(f, "Invalid sampler! Size must be positive."),
+            Self::NonSquareCrop { width, height } => write!(f, "Invalid sampler! Crop must be square, got {}x{}.", width, height),
+            Self::CropOutOfBounds { crop, width, height } => write!(
+                f, "Invalid sampler! Crop {:?} exceeds the {}x{} image.", crop, width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SamplerError {}
+
 /// For sampling and resizing binary images
 pub struct Sampler {
     pub image: BinaryImage,
 }
 
 impl Sampler {
+    /// Samples the whole image into a square of its longer side; a 0x0 image gives a 0x0 sampler
     pub fn new(image: &BinaryImage) -> Sampler {
         let size = std::cmp::max(image.width, image.height);
         Self::new_with_size(image, size)
     }
 
+    /// Panics if `sampler_size` is 0 and the image is not 0x0, see `try_new_with_size_crop`
     pub fn new_with_size(image: &BinaryImage, sampler_size: usize) -> Sampler {
         Self::new_with_size_crop(image, sampler_size, Default::default())
     }
 
+    /// Panics if `sampler_size` is 0 or `crop` is invalid, see `try_new_with_size_crop`
     pub fn new_with_size_crop(
         image: &BinaryImage,
         sampler_size: usize,
         crop: BoundingRect,
     ) -> Sampler {
-        let new_image;
-        assert_eq!(crop.width(), crop.height());
+        match Self::try_new_with_size_crop(image, sampler_size, crop) {
+            Ok(sampler) => sampler,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Samples `crop` of `image` (the whole image if `crop` is empty) into a square of `sampler_size`.
+    ///
+    /// Returns an error if `crop` is not square or exceeds the image, or if `sampler_size` is 0,
+    /// unless the image is already a square of that size (i.e. 0x0) and `crop` is empty.
+    pub fn try_new_with_size_crop(
+        image: &BinaryImage,
+        sampler_size: usize,
+        crop: BoundingRect,
+    ) -> Result<Sampler, SamplerError> {
+        if crop.width() != crop.height() {
+            return Err(SamplerError::NonSquareCrop { width: crop.width(), height: crop.height() });
+        }
+        if !crop.is_empty() && (crop.left < 0 || crop.top < 0 ||
+            crop.right > image.width as i32 || crop.bottom > image.height as i32) {
+            return Err(SamplerError::CropOutOfBounds { crop, width: image.width, height: image.height });
+        }
+
         if crop.is_empty() && image.width == image.height && image.width == sampler_size {
-            new_image = image.clone();
-        } else if !crop.is_empty()
+            return Ok(Sampler { image: image.clone() });
+        }
+        if sampler_size == 0 {
+            return Err(SamplerError::ZeroSize);
+        }
+
+        let new_image = if !crop.is_empty()
             && crop.width() as usize == sampler_size
             && crop.height() as usize == sampler_size
         {
-            new_image = image.crop_with_rect(crop);
+            image.crop_with_rect(crop)
         } else {
-            new_image = Self::resample_square_image(&image, crop, sampler_size);
-        }
-        Sampler { image: new_image }
+            Self::resample_square_image(image, crop, sampler_size)
+        };
+        Ok(Sampler { image: new_image })
     }
 
     /// Resize an image of any size into a square image while keeping the aspect ratio of content.
@@ -82,6 +136,7 @@ impl Sampler {
         new_image
     }
 
+    /// Panics if `dst_rect` does not lie within `dst`
     pub fn resample_image_with_crop_to_image(
         src: &BinaryImage,
         src_rect: BoundingRect,
@@ -91,6 +146,7 @@ impl Sampler {
         Self::resample_image_with_crop_to_image_overlay(src, src_rect, dst, dst_rect, false);
     }
 
+    /// Panics if `dst_rect` does not lie within `dst`
     pub fn resample_image_with_crop_to_image_overlay(
         src: &BinaryImage,
         src_rect: BoundingRect,
@@ -98,6 +154,11 @@ impl Sampler {
         dst_rect: BoundingRect,
         overlay: bool,
     ) {
+        assert!(
+            dst_rect.is_empty() || (dst_rect.left >= 0 && dst_rect.top >= 0 &&
+                dst_rect.right <= dst.width as i32 && dst_rect.bottom <= dst.height as i32),
+            "dst_rect {:?} exceeds the {}x{} dst image", dst_rect, dst.width, dst.height
+        );
         let src_rect = if !src_rect.is_empty() {
             src_rect
         } else {
@@ -286,6 +347,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sampler_errors() {
+        let image = BinaryImage::new_w_h(4, 4);
+        assert_eq!(Sampler::try_new_with_size_crop(&image, 0, Default::default()).err(), Some(SamplerError::ZeroSize));
+        assert_eq!(
+            Sampler::try_new_with_size_crop(&image, 2, BoundingRect::new_x_y_w_h(0, 0, 2, 3)).err(),
+            Some(SamplerError::NonSquareCrop { width: 2, height: 3 })
+        );
+        let crop = BoundingRect::new_x_y_w_h(3, 3, 2, 2);
+        assert_eq!(
+            Sampler::try_new_with_size_crop(&image, 2, crop).err(),
+            Some(SamplerError::CropOutOfBounds { crop, width: 4, height: 4 })
+        );
+        assert!(Sampler::try_new_with_size_crop(&image, 2, BoundingRect::new_x_y_w_h(2, 2, 2, 2)).is_ok());
+    }
+
+    #[test]
+    fn sampler_empty_image() {
+        let empty = BinaryImage::new_w_h(0, 0);
+        let sampler = Sampler::new(&empty);
+        assert_eq!((sampler.image.width, sampler.image.height), (0, 0));
+        assert!(Sampler::try_new_with_size_crop(&empty, 0, Default::default()).is_ok());
+        assert_eq!(Sampler::try_new_with_size_crop(&BinaryImage::new_w_h(3, 0), 0, Default::default()).err(), Some(SamplerError::ZeroSize));
+    }
+
+    #[test]
+    #[should_panic(expected = "exceeds the 4x4 dst image")]
+    fn resample_dst_rect_out_of_bounds() {
+        let image = BinaryImage::new_w_h(2, 2);
+        let mut new_image = BinaryImage::new_w_h(4, 4);
+        Sampler::resample_image_with_crop_to_image(
+            &image, Default::default(), &mut new_image, BoundingRect::new_x_y_w_h(2, 2, 4, 2),
+        );
+    }
+
     #[test]
     fn resample_image_2x2_to_3x2() {
         let image = BinaryImage::from_string(&(
@@ -1,4 +1,4 @@
-use crate::{ColorImage, PointI32};
+use crate::{ColorImage, GrayImage, PointI32};
 
 /// A data structure to efficiently compute summed pixel values over regions in an image (repeatedly).
 pub struct SummedAreaTable {
@@ -48,6 +48,28 @@ impl SummedAreaTable {
         }
     }
 
+    /// Creates an SAT of the gray levels of image, as `from_color_image` does for the mean of red, green and blue.
+    ///
+    /// This construction takes 1 pass through the pixels in image, keeping a running sum of the current row.
+    pub fn from_gray_image(image: &GrayImage) -> Self {
+        let (width, height) = (image.width, image.height);
+        let mut sums = vec![0; width * height];
+        for y in 0..height {
+            let mut row = 0;
+            for x in 0..width {
+                row += image.get_pixel(x, y) as u32;
+                let up = if y > 0 { sums[(y - 1) * width + x] } else { 0 };
+                sums[y * width + x] = up + row;
+            }
+        }
+
+        Self {
+            sums,
+            width,
+            height
+        }
+    }
+
     /// Returns the entry in the SAT.
     ///
     /// If the input point is out of boundary, this function returns 0.
@@ -136,6 +158,21 @@ mod tests {
         assert_eq!(sat.get_bot_right_sum(4, 2), 254);
     }
 
+    #[test]
+    fn sat_from_gray_image() {
+        let pixels = vec![
+            31, 2, 4, 33, 5, 36,
+            12, 26, 9, 10, 29, 25,
+            13, 17, 21, 22, 20, 18,
+            24, 23, 15, 16, 14, 19,
+            30, 8, 28, 27, 11, 7,
+            1, 35, 34, 3, 32, 6,
+        ];
+        let image = create_color_image_helper(6, 6, pixels);
+        let gray = GrayImage::from_color_image(&image, GrayImage::LUMA_WEIGHTS);
+        assert_eq!(SummedAreaTable::from_gray_image(&gray).sums, SummedAreaTable::from_color_image(&image).sums);
+    }
+
     #[test]
     fn sat_region_sum() {
         // Example from wikipedia
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use crate::{BinaryImage, PointF64, PointI32};
+use super::rasterizer::bresenham;
+
+/// Number of skeleton steps walked back from an endpoint to estimate its tangent
+const TANGENT_STEPS: usize = 3;
+/// Two endpoints are only bridged if the dot product of their (normalized) tangents is below this,
+/// i.e. the strokes are heading towards each other, more than 90 degrees apart
+const TANGENT_DOT_THRESHOLD: f64 = 0.0;
+
+/// The 8-neighbourhood in clockwise order
+const NEIGHBOURS: [(i32, i32); 8] = [(-1, -1), (0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0)];
+
+/// A line segment added by `BinaryImage::bridge_gaps` to close a gap between two stroke endpoints
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GapBridge {
+    pub from: PointI32,
+    pub to: PointI32,
+}
+
+impl BinaryImage {
+    /// Closes gaps of at most `max_gap` pixels between stroke endpoints, see `BinaryImage::find_gap_bridges`
+    pub fn bridge_gaps(&self, max_gap: usize) -> BinaryImage {
+        let mut image = self.clone();
+        for bridge in self.find_gap_bridges(max_gap) {
+            for p in bresenham(bridge.from, bridge.to) {
+                image.set_pixel_at_safe(p, true);
+            }
+        }
+        image
+    }
+
+    /// Finds pairs of stroke endpoints to be connected by `BinaryImage::bridge_gaps`.
+    ///
+    /// Endpoints are skeleton pixels with exactly one 8-neighbour, or two adjacent ones
+    /// (where the stroke staircases into its tip). Two endpoints are paired
+    /// if at most `max_gap` pixels lie between them and the strokes are heading towards each other,
+    /// which avoids bridging parallel strokes. Nearest pairs are taken first, and each endpoint is used once.
+    pub fn find_gap_bridges(&self, max_gap: usize) -> Vec<GapBridge> {
+        let skeleton = self.to_skeleton().image;
+        let endpoints: Vec<(PointI32, PointF64)> = (0..skeleton.height as i32)
+            .flat_map(|y| (0..skeleton.width as i32).map(move |x| PointI32::new(x, y)))
+            .filter(|&p| skeleton.get_pixel_at(p) && is_endpoint(&skeleton, p))
+            .map(|p| (p, endpoint_tangent(&skeleton, p)))
+            .collect();
+
+        let mut candidates = Vec::new();
+        for (i, &(a, ta)) in endpoints.iter().enumerate() {
+            for &(b, tb) in endpoints[i + 1..].iter() {
+                let (dx, dy) = (b.x - a.x, b.y - a.y);
+                let gap = std::cmp::max(dx.abs(), dy.abs()) as usize;
+                if gap == 0 || gap - 1 > max_gap {
+                    continue;
+                }
+                let ab = (b - a).to_point_f64();
+                if ta.dot(ab) <= 0.0 || tb.dot(-ab) <= 0.0 || ta.dot(tb) >= TANGENT_DOT_THRESHOLD {
+                    continue;
+                }
+                candidates.push((dx * dx + dy * dy, a, b));
+            }
+        }
+        candidates.sort_by_key(|&(distance, a, b)| (distance, a.y, a.x, b.y, b.x));
+
+        let mut used = HashSet::new();
+        let mut bridges = Vec::new();
+        for (_, a, b) in candidates {
+            if used.contains(&a) || used.contains(&b) {
+                continue;
+            }
+            used.insert(a);
+            used.insert(b);
+            bridges.push(GapBridge { from: a, to: b });
+        }
+        bridges
+    }
+}
+
+fn skeleton_neighbours(skeleton: &BinaryImage, p: PointI32) -> Vec<PointI32> {
+    NEIGHBOURS
+        .iter()
+        .map(|&(dx, dy)| PointI32::new(p.x + dx, p.y + dy))
+        .filter(|&n| skeleton.get_pixel_at_safe(n))
+        .collect()
+}
+
+fn is_endpoint(skeleton: &BinaryImage, p: PointI32) -> bool {
+    let ring: Vec<bool> = NEIGHBOURS
+        .iter()
+        .map(|&(dx, dy)| skeleton.get_pixel_at_safe(PointI32::new(p.x + dx, p.y + dy)))
+        .collect();
+    let count = ring.iter().filter(|&&v| v).count();
+    let runs = (0..ring.len()).filter(|&i| ring[i] && !ring[(i + ring.len() - 1) % ring.len()]).count();
+    (1..=2).contains(&count) && runs == 1
+}
+
+/// Normalized direction pointing out of the stroke at `endpoint`
+fn endpoint_tangent(skeleton: &BinaryImage, endpoint: PointI32) -> PointF64 {
+    let mut visited = vec![endpoint];
+    let mut current = endpoint;
+    for _ in 0..TANGENT_STEPS {
+        match skeleton_neighbours(skeleton, current).into_iter().find(|n| !visited.contains(n)) {
+            Some(next) => {
+                visited.push(next);
+                current = next;
+            },
+            None => break,
+        }
+    }
+    (endpoint - current).to_point_f64().get_normalized()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circle_outline(radius: f64, gaps: &[(i32, i32)]) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(32, 32);
+        for step in 0..720 {
+            let angle = step as f64 / 720.0 * std::f64::consts::PI * 2.0;
+            let p = PointI32::new(
+                (16.0 + radius * angle.cos()).round() as i32,
+                (16.0 + radius * angle.sin()).round() as i32,
+            );
+            image.set_pixel_at(p, true);
+        }
+        for &(x, y) in gaps {
+            image.set_pixel(x as usize, y as usize, false);
+        }
+        image
+    }
+
+    #[test]
+    fn bridge_circle_gaps() {
+        // two 2px gaps, at the right and at the left of the circle
+        let image = circle_outline(10.0, &[(26, 16), (26, 17), (6, 15), (6, 16)]);
+        assert_eq!(image.to_clusters(true).len(), 2);
+
+        let bridges = image.find_gap_bridges(3);
+        assert_eq!(bridges.len(), 2);
+        let bridged = image.bridge_gaps(3);
+        assert_eq!(bridged.to_clusters(true).len(), 1);
+    }
+
+    #[test]
+    fn bridge_parallel_strokes() {
+        let mut image = BinaryImage::new_w_h(24, 24);
+        for x in 4..20 {
+            image.set_pixel(x, 10, true);
+            image.set_pixel(x, 13, true);
+        }
+        assert!(image.find_gap_bridges(3).is_empty());
+        assert_eq!(image.bridge_gaps(3).to_clusters(true).len(), 2);
+    }
+}
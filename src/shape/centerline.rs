@@ -0,0 +1,372 @@
+use crate::{BinaryImage, CompatFlags, CompoundPath, Field, NumberFormat, PhysicalScale, ToSvgString, PathF64, PathSimplifyMode, PointF64, PointI32};
+use crate::reduce::reduce;
+use super::distance::stroke_width;
+use super::rasterizer::bresenham;
+use super::skeleton::skeleton_chains;
+
+/// An open (or closed) path with a stroke width at each point, see `BinaryImage::to_centerline_paths`
+#[derive(Clone, Debug)]
+pub struct WidthPath {
+    pub path: PathF64,
+    /// The stroke width at each point of `path`
+    pub widths: Vec<f64>,
+}
+
+impl WidthPath {
+    /// Mean of `widths`, 0 if there are none
+    pub fn mean_width(&self) -> f64 {
+        if self.widths.is_empty() {
+            return 0.0;
+        }
+        self.widths.iter().sum::<f64>() / self.widths.len() as f64
+    }
+
+    /// A `polyline` element stroked with the mean width, see `PointF64::to_svg_string` for `precision`
+    pub fn to_svg_stroke_string(&self, precision: Option<u32>) -> String {
+        let points: Vec<String> = self.path.iter().map(|p| p.to_svg_string(precision)).collect();
+        format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>",
+            points.join(" "), f64::number_format(self.mean_width(), precision)
+        )
+    }
+}
+
+/// Parameters of `BinaryImage::trace_centerline`, and of the outline tracing done by
+/// `color_image_to_svg` and `binary_image_to_svg`
+#[derive(Clone, Copy, Debug)]
+pub struct TraceParams {
+    /// How outlines are simplified
+    pub mode: PathSimplifyMode,
+    /// Minimum turning angle (in radians) of a corner, in `PathSimplifyMode::Spline`
+    pub corner_threshold: f64,
+    /// Maximum length of a subdivided segment, in `PathSimplifyMode::Spline`
+    pub segment_length: f64,
+    /// Maximum number of smoothing iterations, in `PathSimplifyMode::Spline`
+    pub max_iterations: usize,
+    /// Minimum turning angle (in radians) at which a spline is spliced, in `PathSimplifyMode::Spline`
+    pub splice_threshold: f64,
+    /// Outlines of shapes or clusters of fewer pixels than this are left out
+    pub min_area: usize,
+    /// Each point is averaged with this many neighbours on either side along the chain
+    pub smooth_radius: usize,
+    /// Tolerance (in pixels) of the path reduction after smoothing
+    pub reduce_tolerance: f64,
+    /// Branches shorter than this are pruned, see `Skeleton::prune`
+    pub min_branch_length: usize,
+    /// If positive, traced outlines are reduced with this fraction of the diagonal of each cluster as tolerance,
+    /// see `CompoundPath::reduce_relative`; not applied in `PathSimplifyMode::Spline`
+    pub relative_tolerance: f64,
+    /// If set, traced outlines are written without subpaths of fewer than 3 distinct points or no area,
+    /// nor repeated points at the output precision, see `CompoundPath::sanitize`
+    pub sanitize: bool,
+    /// Behaviours pinned across upgrades, see `CompatLevel`
+    pub compat: CompatFlags,
+    /// If set, svg documents declare their width and height in millimeters at this scale, see `PhysicalScale`
+    pub physical_scale: Option<PhysicalScale>,
+}
+
+impl Default for TraceParams {
+    fn default() -> Self {
+        Self {
+            mode: PathSimplifyMode::Spline,
+            corner_threshold: 60f64.to_radians(),
+            segment_length: 4.0,
+            max_iterations: 10,
+            splice_threshold: 45f64.to_radians(),
+            min_area: 0,
+            smooth_radius: 2,
+            reduce_tolerance: 1.0,
+            min_branch_length: 4,
+            relative_tolerance: 0.0,
+            sanitize: false,
+            compat: CompatFlags::default(),
+            physical_scale: None,
+        }
+    }
+}
+
+impl TraceParams {
+    /// Returns `base` for images scanned at `dpi`, with the thresholds measured in pixels
+    /// (`segment_length`, `reduce_tolerance` and `min_branch_length`) multiplied by `dpi` over the dpi of `base`,
+    /// taken from its `physical_scale` (96, the CSS pixel, if none), and `min_area` by the square of that.
+    /// The result declares `dpi` as its `physical_scale`.
+    pub fn scaled_for_dpi(base: &TraceParams, dpi: f64) -> TraceParams {
+        let base_dpi = base.physical_scale.map_or(PhysicalScale::CSS_DPI, |scale| scale.dpi);
+        let factor = dpi / base_dpi;
+        TraceParams {
+            segment_length: base.segment_length * factor,
+            min_area: (base.min_area as f64 * factor * factor).round() as usize,
+            reduce_tolerance: base.reduce_tolerance * factor,
+            min_branch_length: (base.min_branch_length as f64 * factor).round() as usize,
+            physical_scale: Some(PhysicalScale { dpi }),
+            ..*base
+        }
+    }
+}
+
+impl BinaryImage {
+    /// Traces the centerline (medial axis) of the shapes instead of their outline.
+    ///
+    /// The image is skeletonized, breaks where strokes meet are bridged as in `to_centerline_paths`,
+    /// short spurs are pruned, the skeleton is split into chains of pixels running between
+    /// end points and junctions, and each chain is smoothed and reduced into an open path.
+    /// Closed loops without any junction become closed paths.
+    pub fn trace_centerline(&self, params: &TraceParams) -> CompoundPath {
+        let mut paths = CompoundPath::new();
+        for chain in self.centerline_chains(&self.distance_transform(), params.min_branch_length) {
+            let closed = chain.len() > 2 && chain[0] == chain[chain.len() - 1];
+            let smoothed = smooth_chain(&chain, params.smooth_radius, closed);
+            paths.add_path_f64(PathF64::from_points(reduce(&smoothed, params.reduce_tolerance)));
+        }
+        paths
+    }
+}
+
+impl BinaryImage {
+    /// Traces the centerline as `trace_centerline` does, after pruning branches shorter than `prune_length`,
+    /// but keeps every smoothed point of each chain together with the stroke width at its skeleton pixel
+    /// (see `BinaryImage::estimate_stroke_width`).
+    ///
+    /// Chains run between end points and junctions, so the paths of a junction all end at it.
+    /// As the skeleton may break up where strokes meet, an end point is first joined to the nearest pixel
+    /// of another piece of the skeleton within the stroke width, if the line between stays within the shape.
+    /// Paths are ordered by their first point, top to bottom then left to right.
+    pub fn to_centerline_paths(&self, prune_length: usize) -> Vec<WidthPath> {
+        const SMOOTH_RADIUS: usize = 2;
+        let distances = self.distance_transform();
+        let mut chains = self.centerline_chains(&distances, prune_length);
+        chains.sort_by_key(|chain| (chain[0].y, chain[0].x));
+        chains.into_iter().map(|chain| {
+            let closed = chain.len() > 2 && chain[0] == chain[chain.len() - 1];
+            WidthPath {
+                path: PathF64::from_points(smooth_chain(&chain, SMOOTH_RADIUS, closed)),
+                widths: chain.iter().map(|&p| stroke_width(&distances, p)).collect(),
+            }
+        }).collect()
+    }
+
+    /// The chains of the skeleton with its breaks bridged by `reconnect_skeleton`,
+    /// after pruning branches shorter than `prune_length`
+    fn centerline_chains(&self, distances: &Field<f64>, prune_length: usize) -> Vec<Vec<PointI32>> {
+        let mut skeleton = self.to_skeleton();
+        skeleton.image = reconnect_skeleton(&skeleton.image, self, distances);
+        let mut chains = skeleton_chains(&skeleton.prune(prune_length).image);
+        // a loop around a 2x2 block of skeleton pixels encloses nothing
+        chains.retain(|chain| {
+            let closed = chain.len() > 2 && chain[0] == chain[chain.len() - 1];
+            !closed || chain.iter().any(|p| (p.x - chain[0].x).abs() > 1 || (p.y - chain[0].y).abs() > 1)
+        });
+        chains
+    }
+}
+
+/// Joins each end point of `skeleton` to the nearest pixel of another 8-connected piece within the stroke width
+/// at the end point, along a line within `shape`
+fn reconnect_skeleton(skeleton: &BinaryImage, shape: &BinaryImage, distances: &Field<f64>) -> BinaryImage {
+    let (width, height) = (skeleton.width as i32, skeleton.height as i32);
+    let mut pieces = vec![usize::MAX; skeleton.width * skeleton.height];
+    for (i, cluster) in skeleton.to_clusters(true).clusters.iter().enumerate() {
+        for p in cluster.iter() {
+            pieces[(p.y * width + p.x) as usize] = i;
+        }
+    }
+    let piece = |p: PointI32| if p.x < 0 || p.y < 0 || p.x >= width || p.y >= height {
+        usize::MAX
+    } else {
+        pieces[(p.y * width + p.x) as usize]
+    };
+
+    let mut bridged = skeleton.clone();
+    for y in 0..height {
+        for x in 0..width {
+            let end = PointI32::new(x, y);
+            let mine = piece(end);
+            if mine == usize::MAX {
+                continue;
+            }
+            let neighbours = (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .filter(|&(dx, dy)| (dx, dy) != (0, 0) && piece(end + PointI32::new(dx, dy)) == mine)
+                .count();
+            if neighbours != 1 {
+                continue;
+            }
+            let radius = stroke_width(distances, end).ceil().max(1.0) as i32;
+            let mut nearest: Option<(i32, PointI32)> = None;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let other = end + PointI32::new(dx, dy);
+                    let d = dx * dx + dy * dy;
+                    let other_piece = piece(other);
+                    if other_piece == usize::MAX || other_piece == mine || d > radius * radius
+                        || matches!(nearest, Some((nd, _)) if nd <= d) {
+                        continue;
+                    }
+                    if bresenham(end, other).all(|p| shape.get_pixel_at_safe(p)) {
+                        nearest = Some((d, other));
+                    }
+                }
+            }
+            if let Some((_, other)) = nearest {
+                for p in bresenham(end, other) {
+                    bridged.set_pixel_at(p, true);
+                }
+            }
+        }
+    }
+    bridged
+}
+
+/// Moving average along the chain; the end points of an open chain are kept in place
+fn smooth_chain(chain: &[PointI32], radius: usize, closed: bool) -> Vec<PointF64> {
+    let points: Vec<PointF64> = chain.iter().map(|p| p.to_point_f64()).collect();
+    // the repeated point of a loop is excluded from the average and restored afterwards
+    let len = if closed { points.len() - 1 } else { points.len() };
+    let mut smoothed = points.clone();
+    for (i, point) in smoothed.iter_mut().enumerate().take(len) {
+        if !closed && (i == 0 || i == len - 1) {
+            continue;
+        }
+        let r = if closed { radius } else { radius.min(i).min(len - 1 - i) };
+        let mut sum = PointF64::default();
+        for k in 0..=2 * r {
+            let j = (i + len - r + k) % len;
+            sum += points[j];
+        }
+        *point = sum / (2 * r + 1) as f64;
+    }
+    if closed {
+        smoothed[len] = smoothed[0];
+    }
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompoundPathElement;
+
+    #[test]
+    fn centerline_l_stroke() {
+        // 5px wide L: vertical bar x in 5..10, y in 4..30; horizontal bar y in 25..30, x in 5..32
+        let mut image = BinaryImage::new_w_h(40, 36);
+        for y in 4..30 {
+            for x in 5..10 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        for y in 25..30 {
+            for x in 5..32 {
+                image.set_pixel(x, y, true);
+            }
+        }
+
+        let paths = image.trace_centerline(&TraceParams::default());
+        let paths: Vec<&PathF64> = paths.iter().map(|p| match p {
+            CompoundPathElement::PathF64(p) => p,
+            _ => panic!("expected PathF64"),
+        }).collect();
+        assert_eq!(paths.len(), 1);
+        let path = paths[0];
+        assert_eq!(path.len(), 3);
+
+        let (mut a, corner, mut b) = (path[0], path[1], path[2]);
+        if a.y > b.y {
+            std::mem::swap(&mut a, &mut b);
+        }
+        // top of the vertical bar, the corner, and the end of the horizontal bar, all along the middle
+        assert!((a.x - 7.0).abs() <= 1.0 && a.y < 8.0);
+        assert!((corner.x - 7.0).abs() <= 1.5 && (corner.y - 27.0).abs() <= 1.5);
+        assert!((b.y - 27.0).abs() <= 1.0 && b.x > 27.0);
+    }
+
+    #[test]
+    fn centerline_t_junction() {
+        let mut image = BinaryImage::new_w_h(64, 56);
+        for y in 10..15 {
+            for x in 4..60 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        for y in 15..52 {
+            for x in 30..35 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let paths = image.trace_centerline(&TraceParams::default());
+        let ends: Vec<PointF64> = paths.iter().flat_map(|p| match p {
+            CompoundPathElement::PathF64(p) => vec![p[0], p[p.len() - 1]],
+            _ => panic!("expected PathF64"),
+        }).collect();
+        assert_eq!(ends.len(), 6, "{:?}", ends);
+        // one end of each of the three paths is at the junction
+        let junction = PointF64::new(32.0, 12.0);
+        assert_eq!(ends.iter().filter(|p| p.distance_to(junction) <= 2.0).count(), 3, "{:?}", ends);
+    }
+
+    #[test]
+    fn scaled_for_dpi_doubles() {
+        let base = TraceParams { min_area: 10, physical_scale: Some(PhysicalScale { dpi: 300.0 }), ..Default::default() };
+        let scaled = TraceParams::scaled_for_dpi(&base, 600.0);
+        assert_eq!(scaled.segment_length, 2.0 * base.segment_length);
+        assert_eq!(scaled.min_area, 4 * base.min_area);
+        assert_eq!(scaled.reduce_tolerance, 2.0 * base.reduce_tolerance);
+        assert_eq!(scaled.min_branch_length, 2 * base.min_branch_length);
+        assert_eq!(scaled.corner_threshold, base.corner_threshold);
+        assert_eq!(scaled.physical_scale, Some(PhysicalScale { dpi: 600.0 }));
+    }
+
+    #[test]
+    fn centerline_paths_widths() {
+        let mut image = BinaryImage::new_w_h(64, 25);
+        for y in 10..15 {
+            for x in 4..60 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let paths = image.to_centerline_paths(4);
+        assert_eq!(paths.len(), 1);
+        let path = &paths[0];
+        assert_eq!(path.widths.len(), path.path.len());
+        assert!(path.path.len() > 40);
+        // away from the rounded-off ends
+        for (p, &width) in path.path.iter().zip(path.widths.iter()) {
+            if p.x > 8.0 && p.x < 55.0 {
+                assert!((width - 5.0).abs() < 1e-9, "{:?} {}", p, width);
+                assert!((p.y - 12.0).abs() <= 0.5);
+            }
+        }
+        let svg = path.to_svg_stroke_string(Some(1));
+        assert!(svg.starts_with("<polyline points=\""));
+        assert!(path.mean_width() > 4.5 && path.mean_width() <= 5.0);
+        assert!(svg.contains(&format!("stroke-width=\"{}\"", f64::number_format(path.mean_width(), Some(1)))), "{}", svg);
+    }
+
+    #[test]
+    fn centerline_paths_t_junction() {
+        let mut image = BinaryImage::new_w_h(64, 56);
+        for y in 10..15 {
+            for x in 4..60 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        for y in 15..52 {
+            for x in 30..35 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let paths = image.to_centerline_paths(4);
+        assert_eq!(paths.len(), 3);
+
+        // the end of each path nearest to the junction
+        let junction = PointF64::new(32.0, 12.0);
+        let ends: Vec<PointF64> = paths.iter().map(|p| {
+            let (first, last) = (p.path[0], p.path[p.path.len() - 1]);
+            if first.distance_to(junction) < last.distance_to(junction) { first } else { last }
+        }).collect();
+        for a in ends.iter() {
+            for b in ends.iter() {
+                assert!(a.distance_to(*b) <= 1.5, "{:?}", ends);
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{BinaryImage, Field, GrayImage, PathF64, PointI32, ScalerField};
+use super::rasterizer::bresenham;
+
+impl BinaryImage {
+    /// Euclidean distance from the center of each pixel to the center of the nearest background pixel,
+    /// 0 for background pixels. Pixels outside the image count as background.
+    ///
+    /// Computed exactly in linear time by Felzenszwalb & Huttenlocher's separable algorithm.
+    pub fn distance_transform(&self) -> Field<f64> {
+        let squared = self.squared_distances_to(false, true);
+        let mut field = Field::with_initial(self.width, self.height, 0.0);
+        for (i, d) in squared.iter().enumerate() {
+            field.set(i, &d.sqrt());
+        }
+        field
+    }
+
+    /// Signed distance field: the euclidean distance from the center of each pixel to the outline,
+    /// positive inside and negative outside, clamped to `[-spread, spread]`.
+    ///
+    /// The outline lies halfway between set and unset pixels, so pixels along it are at about +-0.5.
+    /// Pixels outside the image count as unset. Computed exactly in linear time, see `distance_transform`.
+    pub fn to_sdf(&self, spread: f64) -> ScalerField<f32> {
+        let inside = self.squared_distances_to(false, true);
+        let outside = self.squared_distances_to(true, false);
+        let mut field = ScalerField::new_w_h(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let distance = if self.get_pixel(x, y) {
+                    inside[i].sqrt() - 0.5
+                } else {
+                    0.5 - outside[i].sqrt()
+                };
+                field.set_pixel(x, y, distance.clamp(-spread, spread) as f32);
+            }
+        }
+        field
+    }
+
+    /// Squared distance from the center of each pixel to the center of the nearest pixel valued `target`,
+    /// in row-major order; infinite if there is none. Pixels outside the image count if `border`.
+    fn squared_distances_to(&self, target: bool, border: bool) -> Vec<f64> {
+        // pad by one pixel on each side
+        let (width, height) = (self.width + 2, self.height + 2);
+        let mut squared = vec![if border { 0.0 } else { f64::INFINITY }; width * height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                squared[(y + 1) * width + x + 1] = if self.get_pixel(x, y) == target { 0.0 } else { f64::INFINITY };
+            }
+        }
+
+        let mut f = vec![0.0; std::cmp::max(width, height)];
+        for x in 0..width {
+            for y in 0..height {
+                f[y] = squared[y * width + x];
+            }
+            let d = distance_transform_1d(&f[..height]);
+            for y in 0..height {
+                squared[y * width + x] = d[y];
+            }
+        }
+        for y in 0..height {
+            let d = distance_transform_1d(&squared[y * width..(y + 1) * width]);
+            squared[y * width..(y + 1) * width].copy_from_slice(&d);
+        }
+
+        let mut unpadded = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            unpadded.extend_from_slice(&squared[(y + 1) * width + 1..(y + 1) * width + 1 + self.width]);
+        }
+        unpadded
+    }
+
+    /// Estimates the width of the stroke passing through `at`, which should lie on its centerline
+    /// (e.g. a point traced by `BinaryImage::trace_centerline`). Returns 0 for background.
+    ///
+    /// This computes the distance transform of the whole image; use `estimate_stroke_widths` for many points.
+    pub fn estimate_stroke_width(&self, at: PointI32) -> f64 {
+        self.estimate_stroke_widths(&[at])[0]
+    }
+
+    /// Stroke width at each of `points` (see `BinaryImage::estimate_stroke_width`),
+    /// computing the distance transform only once.
+    pub fn estimate_stroke_widths(&self, points: &[PointI32]) -> Vec<f64> {
+        let distances = self.distance_transform();
+        points.iter().map(|&p| stroke_width(&distances, p)).collect()
+    }
+
+    /// Average stroke width along `path` (see `BinaryImage::estimate_stroke_width`),
+    /// sampled at every pixel the path passes through. Returns 0 if the path does not touch the foreground.
+    pub fn estimate_path_stroke_width(&self, path: &PathF64) -> f64 {
+        let points: Vec<PointI32> = path.iter().map(|p| p.to_point_i32()).collect();
+        let mut samples = points.first().copied().into_iter().collect::<Vec<_>>();
+        for pair in points.windows(2) {
+            samples.extend(bresenham(pair[0], pair[1]).skip(1));
+        }
+
+        let widths: Vec<f64> = self
+            .estimate_stroke_widths(&samples)
+            .into_iter()
+            .filter(|&w| w > 0.0)
+            .collect();
+        if widths.is_empty() {
+            return 0.0;
+        }
+        widths.iter().sum::<f64>() / widths.len() as f64
+    }
+}
+
+impl ScalerField<f32> {
+    /// Quantizes to gray levels `value * scale + bias`, rounded and clamped to 0..=255,
+    /// e.g. `to_gray_u8(128.0, 127.0 / spread)` to export a signed distance field (see `BinaryImage::to_sdf`)
+    pub fn to_gray_u8(&self, bias: f64, scale: f64) -> GrayImage {
+        let mut image = GrayImage::new_w_h(self.width(), self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let value = (self.get_pixel(x, y) as f64 * scale + bias).round().clamp(0.0, 255.0);
+                image.set_pixel(x, y, value as u8);
+            }
+        }
+        image
+    }
+}
+
+/// The distance to the nearest background pixel center is half a pixel more than the distance to the edge,
+/// and the stroke extends that far on both sides of the centerline
+pub(super) fn stroke_width(distances: &Field<f64>, at: PointI32) -> f64 {
+    if at.x < 0 || at.y < 0 || at.x as usize >= distances.width() || at.y as usize >= distances.height() {
+        return 0.0;
+    }
+    let d = distances.get(distances.index_at(at.x as usize, at.y as usize)).unwrap_or(0.0);
+    if d > 0.0 { 2.0 * d - 1.0 } else { 0.0 }
+}
+
+/// Squared distance transform of a sampled function in one dimension
+fn distance_transform_1d(f: &[f64]) -> Vec<f64> {
+    let n = f.len();
+    let mut d = vec![0.0; n];
+    // locations of the parabolas in the lower envelope, and the boundaries between them
+    let mut v = vec![0; n];
+    let mut z = vec![0.0; n + 1];
+    let mut k = 0;
+    let intersect = |q: usize, p: usize| -> f64 {
+        let (q2, p2) = ((q * q) as f64, (p * p) as f64);
+        ((f[q] + q2) - (f[p] + p2)) / (2.0 * q as f64 - 2.0 * p as f64)
+    };
+
+    let first = match f.iter().position(|x| x.is_finite()) {
+        Some(first) => first,
+        None => return vec![f64::INFINITY; n],
+    };
+    v[0] = first;
+    z[0] = f64::NEG_INFINITY;
+    z[1] = f64::INFINITY;
+    for (q, fq) in f.iter().enumerate().skip(first + 1) {
+        if !fq.is_finite() {
+            continue;
+        }
+        let mut s = intersect(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersect(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, dq) in d.iter_mut().enumerate() {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let dx = q as f64 - v[k] as f64;
+        *dq = dx * dx + f[v[k]];
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PointF64;
+
+    #[test]
+    fn distance_transform_square() {
+        let mut image = BinaryImage::new_w_h(7, 7);
+        for y in 1..6 {
+            for x in 1..6 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let d = image.distance_transform();
+        assert_eq!(d.get(d.index_at(0, 0)), Some(0.0));
+        assert_eq!(d.get(d.index_at(1, 1)), Some(1.0));
+        assert_eq!(d.get(d.index_at(3, 3)), Some(3.0));
+        assert_eq!(d.get(d.index_at(2, 3)), Some(2.0));
+    }
+
+    #[test]
+    fn stroke_width_bar() {
+        let mut image = BinaryImage::new_w_h(30, 12);
+        for y in 4..7 {
+            for x in 2..28 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        assert!((image.estimate_stroke_width(PointI32::new(15, 5)) - 3.0).abs() < 1e-9);
+        assert_eq!(image.estimate_stroke_width(PointI32::new(15, 9)), 0.0);
+        let points = [PointI32::new(15, 5), PointI32::new(15, 9), PointI32::new(2, 4), PointI32::new(-1, 5)];
+        let widths: Vec<f64> = points.iter().map(|&p| image.estimate_stroke_width(p)).collect();
+        assert_eq!(image.estimate_stroke_widths(&points), widths);
+        assert_eq!(widths[3], 0.0);
+
+        let path = PathF64::from_points(vec![PointF64::new(5.0, 5.0), PointF64::new(24.0, 5.0)]);
+        assert!((image.estimate_path_stroke_width(&path) - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sdf_circle() {
+        let (size, radius, spread) = (48, 14.0, 6.0);
+        let c = size as f64 / 2.0;
+        let mut image = BinaryImage::new_w_h(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                image.set_pixel(x, y, (x as f64 + 0.5 - c).hypot(y as f64 + 0.5 - c) <= radius);
+            }
+        }
+        let sdf = image.to_sdf(spread);
+        for y in (0..size).step_by(3) {
+            for x in (0..size).step_by(3) {
+                let expected = radius - (x as f64 + 0.5 - c).hypot(y as f64 + 0.5 - c);
+                let d = sdf.get_pixel(x, y) as f64;
+                assert!((d - expected.clamp(-spread, spread)).abs() <= 1.0, "({}, {}) {} {}", x, y, d, expected);
+                assert_eq!(d > 0.0, image.get_pixel(x, y), "({}, {})", x, y);
+                assert!(d.abs() <= spread);
+            }
+        }
+        assert_eq!(sdf.get_pixel(0, 0), -spread as f32);
+        assert_eq!(sdf.get_pixel(size / 2, size / 2), spread as f32);
+
+        let gray = sdf.to_gray_u8(128.0, 127.0 / spread);
+        assert_eq!(gray.get_pixel(0, 0), 1);
+        assert_eq!(gray.get_pixel(size / 2, size / 2), 255);
+        assert_eq!(BinaryImage::new_w_h(3, 3).to_sdf(2.0).get_pixel(1, 1), -2.0);
+    }
+}
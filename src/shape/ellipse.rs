@@ -0,0 +1,224 @@
+use crate::{Matrix, PointF64, Shape};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// An ellipse fitted to the boundary of a shape, see `Shape::fit_ellipse`
+pub struct EllipseFit {
+    pub center: PointF64,
+    pub semi_major: f64,
+    pub semi_minor: f64,
+    /// Angle of the major axis from the x axis, in radians in [0, PI), turning from x towards y (clockwise on screen)
+    pub rotation: f64,
+    /// Root mean square distance from the boundary points to the ellipse, in pixels
+    pub rms_error: f64,
+}
+
+impl Shape {
+    /// Fits an ellipse to the centers of the boundary pixels, by direct least squares fitting of a conic
+    /// constrained to be an ellipse (Fitzgibbon et al., in the numerically stable form of Halir & Flusser).
+    ///
+    /// The fit is in the coordinates of `self.image`. As boundary pixels lie just inside the outline,
+    /// the axes come out about half a pixel shorter than those of the outline.
+    /// Returns `None` for shapes with fewer than 6 boundary pixels or on which the fit degenerates, e.g. a straight line.
+    pub fn fit_ellipse(&self) -> Option<EllipseFit> {
+        let boundary = Self::image_boundary_list(&self.image);
+        if boundary.len() < 6 {
+            return None;
+        }
+        // centered and scaled to unit spread to keep the scatter matrices well conditioned
+        let points: Vec<PointF64> = boundary.iter().map(|p| PointF64::new(p.x as f64 + 0.5, p.y as f64 + 0.5)).collect();
+        let n = points.len() as f64;
+        let mean = points.iter().fold(PointF64::default(), |sum, &p| sum + p) * (1.0 / n);
+        let scale = (points.iter().map(|&p| (p - mean).dot(p - mean)).sum::<f64>() / n).sqrt();
+        if scale <= f64::EPSILON {
+            return None;
+        }
+        let points: Vec<PointF64> = points.iter().map(|&p| (p - mean) * (1.0 / scale)).collect();
+
+        // scatter matrices of the quadratic terms [x^2, xy, y^2] and the linear terms [x, y, 1]
+        let (mut s1, mut s2, mut s3) = (Matrix::<3, 3>::default(), Matrix::<3, 3>::default(), Matrix::<3, 3>::default());
+        for p in points.iter() {
+            let quadratic = [p.x * p.x, p.x * p.y, p.y * p.y];
+            let linear = [p.x, p.y, 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    s1.m[i][j] += quadratic[i] * quadratic[j];
+                    s2.m[i][j] += quadratic[i] * linear[j];
+                    s3.m[i][j] += linear[i] * linear[j];
+                }
+            }
+        }
+        // the linear coefficients as a function of the quadratic ones
+        let mut t = &s3.inv()? * &s2.transpose();
+        t.scale(-1.0);
+        let mut reduced = &s2 * &t;
+        for i in 0..3 {
+            for j in 0..3 {
+                reduced.m[i][j] += s1.m[i][j];
+            }
+        }
+        // premultiplied by the inverse of the ellipse constraint 4ac - b^2
+        let m = Matrix::new([
+            [reduced.m[2][0] / 2.0, reduced.m[2][1] / 2.0, reduced.m[2][2] / 2.0],
+            [-reduced.m[1][0], -reduced.m[1][1], -reduced.m[1][2]],
+            [reduced.m[0][0] / 2.0, reduced.m[0][1] / 2.0, reduced.m[0][2] / 2.0],
+        ]);
+
+        let quadratic = eigenvectors(&m).into_iter()
+            .filter(|v| 4.0 * v[0] * v[2] - v[1] * v[1] > 0.0)
+            .max_by(|a, b| constraint(a).partial_cmp(&constraint(b)).unwrap())?;
+        let linear = t.dot_mv(&quadratic);
+        let conic = [quadratic[0], quadratic[1], quadratic[2], linear[0], linear[1], linear[2]];
+
+        let (center, semi_major, semi_minor, rotation) = conic_to_ellipse(&conic)?;
+        let rms_error = (points.iter().map(|&p| sampson_distance(&conic, p).powi(2)).sum::<f64>() / n).sqrt();
+        Some(EllipseFit {
+            center: center * scale + mean,
+            semi_major: semi_major * scale,
+            semi_minor: semi_minor * scale,
+            rotation,
+            rms_error: rms_error * scale,
+        })
+    }
+}
+
+/// `4ac - b^2` of a unit vector along `v`
+fn constraint(v: &[f64; 3]) -> f64 {
+    (4.0 * v[0] * v[2] - v[1] * v[1]) / Matrix::<3, 3>::dot_vv(v, v)
+}
+
+/// The eigenvectors of the real eigenvalues of `m`
+fn eigenvectors(m: &Matrix<3, 3>) -> Vec<[f64; 3]> {
+    let m = &m.m;
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    let minors = m[0][0] * m[1][1] - m[0][1] * m[1][0]
+        + m[0][0] * m[2][2] - m[0][2] * m[2][0]
+        + m[1][1] * m[2][2] - m[1][2] * m[2][1];
+    let determinant = Matrix::new(*m).determinant();
+    cubic_roots(-trace, minors, -determinant).into_iter()
+        .filter_map(|lambda| {
+            let mut rows = *m;
+            for (i, row) in rows.iter_mut().enumerate() {
+                row[i] -= lambda;
+            }
+            // the eigenvector is orthogonal to every row; take the best conditioned cross product
+            [(0, 1), (0, 2), (1, 2)].iter()
+                .map(|&(a, b)| cross(&rows[a], &rows[b]))
+                .max_by(|a, b| Matrix::<3, 3>::dot_vv(a, a).partial_cmp(&Matrix::<3, 3>::dot_vv(b, b)).unwrap())
+                .filter(|v| Matrix::<3, 3>::dot_vv(v, v) > 0.0)
+        })
+        .collect()
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+/// The real roots of `x^3 + a x^2 + b x + c`
+fn cubic_roots(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (3.0 * b - a * a) / 9.0;
+    let r = (9.0 * a * b - 27.0 * c - 2.0 * a * a * a) / 54.0;
+    let discriminant = q * q * q + r * r;
+    if discriminant > 0.0 {
+        let s = discriminant.sqrt();
+        vec![(r + s).cbrt() + (r - s).cbrt() - a / 3.0]
+    } else if q == 0.0 {
+        vec![-a / 3.0]
+    } else {
+        let theta = (r / (-q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        (0..3)
+            .map(|k| 2.0 * (-q).sqrt() * ((theta + 2.0 * std::f64::consts::PI * k as f64) / 3.0).cos() - a / 3.0)
+            .collect()
+    }
+}
+
+/// Center, semi-major and semi-minor axes and rotation of the ellipse `ax^2 + bxy + cy^2 + dx + ey + f = 0`
+fn conic_to_ellipse(conic: &[f64; 6]) -> Option<(PointF64, f64, f64, f64)> {
+    let [a, b, c, d, e, f] = *conic;
+    let denominator = 4.0 * a * c - b * b;
+    if denominator <= 0.0 {
+        return None;
+    }
+    let center = PointF64::new((b * e - 2.0 * c * d) / denominator, (b * d - 2.0 * a * e) / denominator);
+    let f0 = f + (d * center.x + e * center.y) / 2.0;
+    // eigenvalues of the quadratic form, the larger one along the minor axis
+    let mid = (a + c) / 2.0;
+    let spread = (((a - c) / 2.0).powi(2) + (b / 2.0).powi(2)).sqrt();
+    let (large, small) = (mid + spread, mid - spread);
+    let (semi_minor, semi_major) = ((-f0 / large).sqrt(), (-f0 / small).sqrt());
+    if !(semi_minor.is_finite() && semi_major.is_finite() && semi_minor > 0.0) {
+        return None;
+    }
+    let rotation = (0.5 * b.atan2(a - c) + std::f64::consts::FRAC_PI_2).rem_euclid(std::f64::consts::PI);
+    Some((center, semi_major, semi_minor, rotation))
+}
+
+/// First order approximation of the distance from `p` to the conic
+fn sampson_distance(conic: &[f64; 6], p: PointF64) -> f64 {
+    let [a, b, c, d, e, f] = *conic;
+    let value = a * p.x * p.x + b * p.x * p.y + c * p.y * p.y + d * p.x + e * p.y + f;
+    let gradient = PointF64::new(2.0 * a * p.x + b * p.y + d, b * p.x + 2.0 * c * p.y + e);
+    let norm = gradient.dot(gradient).sqrt();
+    if norm > 0.0 { value.abs() / norm } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BinaryImage;
+
+    fn rotated_ellipse(a: f64, b: f64, degrees: f64) -> Shape {
+        let mut image = BinaryImage::new_w_h(90, 90);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        for y in 0..90 {
+            for x in 0..90 {
+                let (dx, dy) = (x as f64 + 0.5 - 45.0, y as f64 + 0.5 - 45.0);
+                let (u, v) = (dx * cos + dy * sin, -dx * sin + dy * cos);
+                image.set_pixel(x, y, (u / a).powi(2) + (v / b).powi(2) <= 1.0);
+            }
+        }
+        Shape { image }
+    }
+
+    #[test]
+    fn fit_rotated_ellipses() {
+        for degrees in [0.0, 30.0, 60.0] {
+            let fit = rotated_ellipse(36.0, 18.0, degrees).fit_ellipse().unwrap();
+            assert!((fit.center.x - 45.0).abs() < 0.5 && (fit.center.y - 45.0).abs() < 0.5, "{:?}", fit);
+            assert!((fit.semi_major / 36.0 - 1.0).abs() < 0.05, "{:?}", fit);
+            assert!((fit.semi_minor / 18.0 - 1.0).abs() < 0.05, "{:?}", fit);
+            assert!((fit.rotation.to_degrees() - degrees).abs() < 3.0, "{:?}", fit);
+            assert!(fit.rms_error < 0.5, "{:?}", fit);
+        }
+    }
+
+    #[test]
+    fn fit_square() {
+        let mut image = BinaryImage::new_w_h(40, 40);
+        for y in 5..35 {
+            for x in 5..35 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let square = Shape { image };
+        assert!(!square.is_ellipse_with(Some(1.0)));
+        let square = square.fit_ellipse().unwrap();
+        let circle = rotated_ellipse(17.0, 17.0, 0.0).fit_ellipse().unwrap();
+        assert!(square.rms_error > 1.0 && square.rms_error > 4.0 * circle.rms_error, "{:?} {:?}", square, circle);
+
+        let mut line = BinaryImage::new_w_h(20, 1);
+        for x in 0..20 {
+            line.set_pixel(x, 0, true);
+        }
+        assert_eq!(Shape { image: line }.fit_ellipse(), None);
+    }
+
+    #[test]
+    fn is_ellipse_by_fit() {
+        // rotated, so unlike the axis-aligned template
+        let shape = rotated_ellipse(36.0, 18.0, 30.0);
+        assert!(shape.is_ellipse_with(Some(0.5)));
+        assert!(!shape.is_ellipse());
+        assert!(!shape.is_circle_with(Some(0.5)));
+        assert!(rotated_ellipse(30.0, 28.0, 0.0).is_circle_with(Some(0.5)));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{BinaryImage, BoundingRect, clusters::Cluster, CompoundPathElement, PathSimplifyMode, PointI32};
+use crate::{BinaryImage, BoundingRect, clusters::Cluster, CompoundPath, CompoundPathElement, PathF64, PathSimplifyMode, PointF64, PointI32};
 use super::rasterizer::rasterize_triangle;
 
 /// A conceptual object represented by an image
@@ -67,6 +67,23 @@ impl Shape {
         boundary
     }
 
+    /// Traces every cluster of set pixels in `image` into closed contours along the pixel edges, each
+    /// paired with whether it is an outer boundary (`true`) or that of a hole (`false`). Outer contours
+    /// run clockwise and holes anticlockwise, as in `PathI32::image_to_path`, ready for `PathF64::smooth`.
+    ///
+    /// `PathSimplifyMode::Spline` traces as `PathSimplifyMode::None`.
+    pub fn trace_contours(image: &BinaryImage, mode: PathSimplifyMode) -> Vec<(PathF64, bool)> {
+        let mut contours = Vec::new();
+        for cluster in image.to_clusters(false).iter() {
+            let offset = PointI32::new(cluster.rect.left, cluster.rect.top);
+            for (i, mut path) in Cluster::image_to_paths(&cluster.to_binary_image(), mode).into_iter().enumerate() {
+                path.offset(&offset);
+                contours.push((path.to_path_f64(), i == 0));
+            }
+        }
+        contours
+    }
+
     pub fn rect(&self) -> BoundingRect {
         BoundingRect {
             left: 0,
@@ -116,15 +133,32 @@ impl Shape {
     }
 
     pub fn is_circle(&self) -> bool {
+        self.is_circle_with(None)
+    }
+
+    /// Like `is_circle`, but with `Some(max_rms_error)` tests the fit of `fit_ellipse` instead of the template diff,
+    /// see `is_ellipse_with`, and compares the fitted axes rather than the image size
+    pub fn is_circle_with(&self, max_rms_error: Option<f64>) -> bool {
+        if max_rms_error.is_some() {
+            return self.is_ellipse_with(max_rms_error) &&
+                matches!(self.fit_ellipse(), Some(fit) if fit.semi_major - fit.semi_minor <= fit.semi_major / 4.0);
+        }
         if std::cmp::max(self.image.width, self.image.height) - 
             std::cmp::min(self.image.width, self.image.height) >
             std::cmp::max(self.image.width, self.image.height) / 4 {
             return false;
         }
-        self.is_ellipse()
+        self.is_ellipse_with(max_rms_error)
     }
 
     pub fn is_ellipse(&self) -> bool {
+        self.is_ellipse_with(None)
+    }
+
+    /// Like `is_ellipse`, but with `Some(max_rms_error)` the shape is an ellipse if `fit_ellipse` succeeds
+    /// with an error of at most `max_rms_error` pixels, which also accepts rotated ellipses.
+    /// With `None` the shape is compared to the axis-aligned ellipse filling its image.
+    pub fn is_ellipse_with(&self, max_rms_error: Option<f64>) -> bool {
         if self.image.width <= 4 && self.image.height <= 4 {
             return false;
         }
@@ -136,6 +170,9 @@ impl Shape {
         if corners > 1 {
             return false;
         }
+        if let Some(max_rms_error) = max_rms_error {
+            return matches!(self.fit_ellipse(), Some(fit) if fit.rms_error <= max_rms_error);
+        }
         let area = self.image.width * self.image.height;
         let threshold = area / 2;
         let diff = self.image.diff(&Self::ellipse(self.image.width, self.image.height).image);
@@ -155,34 +192,74 @@ impl Shape {
         true
     }
 
+    /// Whether the shape is close to the quadrilateral spanned by its extreme points.
+    ///
+    /// Two candidates are considered, one with vertices extreme along the axes (fitting diamonds),
+    /// the other along the diagonals (fitting upright rectangles), and the larger one is compared.
+    /// Outline vertices lie on pixel corners, and a pixel belongs to the quadrilateral if its center lies inside.
     pub fn is_quadrilateral(&self) -> bool {
+        self.quadrilateral_corners().is_some()
+    }
+
+    /// The corners of the quadrilateral if the shape is one (see `is_quadrilateral`), rounded to the nearest pixel corner.
+    /// They are clockwise on screen, starting from the top, or from the top right if the quadrilateral stands on its sides.
+    pub fn quadrilateral_corners(&self) -> Option<[PointI32; 4]> {
+        if self.image.width < 5 || self.image.height < 5 {
+            return None;
+        }
+        let quad = self.extreme_quadrilateral()?;
+        let mut paths = CompoundPath::new();
+        paths.add_path_f64(quad.clone());
+        let reduced = paths.rasterize(&self.rect());
+        let diff = self.image.diff(&reduced);
+        if (diff.area() as f64) < self.image.area() as f64 * Self::QUADRILATERAL_MAX_DIFF {
+            let corner = |i: usize| PointI32::new(quad.path[i].x.round() as i32, quad.path[i].y.round() as i32);
+            Some([corner(0), corner(1), corner(2), corner(3)])
+        } else {
+            None
+        }
+    }
+
+    /// Derived from the fixtures in the tests: rotated squares and rectangles stay below 0.22,
+    /// circles, ellipses and blobs stay above 0.25
+    const QUADRILATERAL_MAX_DIFF: f64 = 0.235;
+
+    /// The quadrilateral used by `is_quadrilateral`, closed, or `None` if it degenerates into a triangle or less
+    fn extreme_quadrilateral(&self) -> Option<PathF64> {
         let mut paths = Cluster::image_to_compound_path(
-            &PointI32::default(),
-            &self.image,
-            PathSimplifyMode::None,
-            0.0,
-            0.0,
-            0,
-            0.0
+            &PointI32::default(), &self.image, PathSimplifyMode::None, 0.0, 0.0, 0, 0.0
         );
-        paths.paths.truncate(1);
-        let paths = paths.reduce(std::cmp::min(self.image.width, self.image.height) as f64);
-        // the path is reduced to a quadrilateral bound by the north most, east most, south most and west most point
-        let mut reduced = BinaryImage::new_w_h(self.image.width, self.image.height);
-        let path = &match &paths.paths[0] {
-            CompoundPathElement::PathI32(path) => path,
-            _ => unreachable!(),
-        }.path;
-        let p0 = PointI32::new(path[0].x-1, path[0].y);
-        let p2 = PointI32::new(path[2].x, path[2].y-1);
-        rasterize_triangle(&[p0, PointI32::new(path[1].x-1, path[1].y-1), p2], &mut reduced);
-        rasterize_triangle(&[p0, p2, PointI32::new(path[3].x, path[1].y-1)], &mut reduced);
-        // panic!("\n{}", reduced.to_string());
-        let diff = self.image.diff(&reduced);
-        let threshold = self.image.width * self.image.height / 6;
-        Self::clustered_diff(&diff, threshold)
+        let outline = match paths.paths.drain(..).next() {
+            Some(CompoundPathElement::PathI32(path)) => path.path,
+            _ => return None,
+        };
+        // the average of the outline points furthest along `dir`, i.e. the middle of a flat extreme
+        let extreme = |dir: PointI32| {
+            let max = outline.iter().map(|p| p.dot(dir)).max().unwrap();
+            let tied: Vec<PointF64> = outline.iter().filter(|p| p.dot(dir) == max).map(|p| p.to_point_f64()).collect();
+            tied.iter().fold(PointF64::default(), |sum, &p| sum + p) / tied.len() as f64
+        };
+        let quad = |dirs: [(i32, i32); 4]| -> Vec<PointF64> {
+            dirs.iter().map(|&(x, y)| extreme(PointI32::new(x, y))).collect()
+        };
+        let area = |quad: &[PointF64]| -> f64 {
+            (0..4).map(|i| quad[i].x * quad[(i + 1) % 4].y - quad[(i + 1) % 4].x * quad[i].y).sum::<f64>().abs() / 2.0
+        };
+        let axes = quad([(0, -1), (1, 0), (0, 1), (-1, 0)]);
+        let diagonals = quad([(1, -1), (1, 1), (-1, 1), (-1, -1)]);
+        let mut quad = if area(&diagonals) > area(&axes) { diagonals } else { axes };
+
+        let perimeter: f64 = (0..4).map(|i| quad[i].distance_to(quad[(i + 1) % 4])).sum();
+        if (0..4).any(|i| quad[i].distance_to(quad[(i + 1) % 4]) < perimeter * Self::MIN_SIDE_RATIO) {
+            return None;
+        }
+        quad.push(quad[0]);
+        Some(PathF64::from_points(quad))
     }
 
+    /// Sides shorter than this fraction of the perimeter are the cut-off tip of a triangle
+    const MIN_SIDE_RATIO: f64 = 1.0 / 16.0;
+
     pub fn is_isosceles_triangle(&self) -> bool {
         if self.image.width < 3 && self.image.height < 3 {
             return false;
@@ -210,6 +287,24 @@ impl From<BinaryImage> for Shape {
 mod tests {
     use super::*;
 
+    #[test]
+    fn trace_contours_ring() {
+        let ring = BinaryImage::from_string(&(
+            "-------\n".to_owned()+
+            "-*****-\n"+
+            "-*---*-\n"+
+            "-*---*-\n"+
+            "-*****-\n"));
+        let contours = Shape::trace_contours(&ring, PathSimplifyMode::Polygon);
+        assert_eq!(contours.iter().map(|(_, outer)| *outer).collect::<Vec<_>>(), [true, false]);
+        for (path, _) in contours.iter() {
+            assert_eq!(path[0], path[path.len() - 1]);
+        }
+        let (outer, hole) = (contours[0].0.bounding_rect_f64(), contours[1].0.bounding_rect_f64());
+        assert_eq!((outer.left_top, outer.right_bottom), (PointF64::new(1.0, 1.0), PointF64::new(6.0, 5.0)));
+        assert_eq!((hole.left_top, hole.right_bottom), (PointF64::new(2.0, 2.0), PointF64::new(5.0, 4.0)));
+    }
+
     #[test]
     fn shape_circle_3() {
         let image = Shape::circle(3, 3).image;
@@ -418,4 +513,82 @@ mod tests {
         assert!(!shape.is_circle());
         assert!(!shape.is_quadrilateral());
     }
+
+    /// Rasterizes a polygon and crops it to its bounds
+    fn polygon_shape(points: &[(f64, f64)]) -> Shape {
+        let mut path: Vec<PointF64> = points.iter().map(|&(x, y)| PointF64::new(x, y)).collect();
+        path.push(path[0]);
+        let mut paths = CompoundPath::new();
+        paths.add_path_f64(PathF64::from_points(path));
+        let image = paths.rasterize(&BoundingRect::new_x_y_w_h(0, 0, 100, 100));
+        Shape::from(image.crop_with_rect(image.bounding_rect()))
+    }
+
+    fn rotated_rect(width: f64, height: f64, degrees: f64) -> Shape {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let corners = [(-width, -height), (width, -height), (width, height), (-width, height)];
+        polygon_shape(&corners.map(|(x, y)| (50.0 + (x * cos - y * sin) / 2.0, 50.0 + (x * sin + y * cos) / 2.0)))
+    }
+
+    /// A circle with `lobes` bumps of relative height `k`
+    fn blob(radius: f64, k: f64, lobes: usize) -> Shape {
+        let points: Vec<(f64, f64)> = (0..64).map(|i| {
+            let t = i as f64 / 64.0 * std::f64::consts::TAU;
+            let r = radius * (1.0 + k * (lobes as f64 * t).sin());
+            (50.0 + r * t.cos(), 50.0 + r * t.sin())
+        }).collect();
+        polygon_shape(&points)
+    }
+
+    #[test]
+    fn is_quadrilateral_fixtures() {
+        for size in [9, 12, 16, 24, 32, 40] {
+            let s = size as f64;
+            for degrees in [0.0, 5.0, 10.0, 20.0, 30.0, 45.0, 60.0, 75.0, 85.0] {
+                assert!(rotated_rect(s, s, degrees).is_quadrilateral(), "square {} at {}", size, degrees);
+                assert!(rotated_rect(s, (s * 0.6).round(), degrees).is_quadrilateral(), "rectangle {} at {}", size, degrees);
+            }
+            assert!(!Shape::circle(size, size).is_quadrilateral(), "circle {}", size);
+            assert!(!Shape::ellipse(size, size * 2 / 3 + 1).is_quadrilateral(), "ellipse {}", size);
+            assert!(!blob(s / 2.0, 0.2, 2).is_quadrilateral(), "blob2 {}", size);
+            assert!(!blob(s / 2.0, 0.25, 5).is_quadrilateral(), "blob5 {}", size);
+            assert!(!polygon_shape(&[(0.0, 0.0), (s, s / 2.0), (0.0, s)]).is_quadrilateral(), "triangle {}", size);
+        }
+    }
+
+    #[test]
+    fn is_quadrilateral_regressions() {
+        // rejected by the previous rasterization
+        assert!(Shape::from(BinaryImage::from_string(&(
+            "**********\n".to_owned() +
+            "**********\n" +
+            "**********\n" +
+            "**********\n" +
+            "**********\n" +
+            "**********\n"
+        ))).is_quadrilateral());
+        assert!(rotated_rect(20.0, 20.0, 10.0).is_quadrilateral());
+        assert!(rotated_rect(16.0, 10.0, 5.0).is_quadrilateral());
+        assert!(rotated_rect(30.0, 18.0, 60.0).is_quadrilateral());
+        // panicked, as the reduced path was empty
+        assert!(!Shape::circle(10, 10).is_quadrilateral());
+    }
+
+    #[test]
+    fn quadrilateral_corners_diamond() {
+        let n = 8;
+        let mut image = BinaryImage::new_w_h(2 * n, 2 * n);
+        for y in 0..2 * n {
+            let half = if y < n { y + 1 } else { 2 * n - y };
+            for x in n - half..n + half {
+                image.set_pixel(x, y, true);
+            }
+        }
+        let n = n as i32;
+        assert_eq!(
+            Shape::from(image).quadrilateral_corners(),
+            Some([PointI32::new(n, 0), PointI32::new(2 * n, n), PointI32::new(n, 2 * n), PointI32::new(0, n)])
+        );
+        assert_eq!(Shape::circle(16, 16).quadrilateral_corners(), None);
+    }
 }
@@ -0,0 +1,85 @@
+use crate::{BinaryImage, GrayImage, ScalerField};
+
+/// Two-threshold (hysteresis) thresholding: pixels >= `high` are set, and so are pixels >= `low`
+/// that are 8-connected, directly or through other such pixels, to a pixel >= `high`.
+/// Faint parts of strong strokes are kept while isolated faint noise is dropped.
+///
+/// If `low > high`, the two are swapped.
+pub fn hysteresis_threshold(field: &ScalerField<u16>, low: u16, high: u16) -> BinaryImage {
+    hysteresis(field.width(), field.height(), |x, y| field.get_pixel(x, y), low, high)
+}
+
+impl GrayImage {
+    /// Two-threshold thresholding of the gray levels, see `hysteresis_threshold`
+    pub fn hysteresis_threshold(&self, low: u8, high: u8) -> BinaryImage {
+        hysteresis(self.width, self.height, |x, y| self.get_pixel(x, y), low, high)
+    }
+}
+
+fn hysteresis<T: PartialOrd + Copy>(
+    width: usize,
+    height: usize,
+    get: impl Fn(usize, usize) -> T,
+    low: T,
+    high: T,
+) -> BinaryImage {
+    let (low, high) = if low > high { (high, low) } else { (low, high) };
+    let mut image = BinaryImage::new_w_h(width, height);
+    let mut stack = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if get(x, y) >= high && !image.get_pixel(x, y) {
+                image.set_pixel(x, y, true);
+                stack.push((x, y));
+            }
+            // flood fill over the pixels >= low
+            while let Some((x, y)) = stack.pop() {
+                for ny in y.saturating_sub(1)..std::cmp::min(y + 2, height) {
+                    for nx in x.saturating_sub(1)..std::cmp::min(x + 2, width) {
+                        if !image.get_pixel(nx, ny) && get(nx, ny) >= low {
+                            image.set_pixel(nx, ny, true);
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hysteresis_tail_and_noise() {
+        // a strong stroke fading diagonally into a faint tail, and a faint blob
+        let mut field = ScalerField::<u16>::new_w_h(12, 6);
+        for (x, v) in [(0, 200), (1, 200), (2, 150), (3, 90), (4, 60), (5, 40)] {
+            field.set_pixel(x, x.min(4), v);
+        }
+        field.set_pixel(6, 4, 20);
+        for (x, y) in [(9, 1), (10, 1), (9, 2), (10, 2)] {
+            field.set_pixel(x, y, 90);
+        }
+
+        let image = hysteresis_threshold(&field, 30, 120);
+        assert_eq!(image.to_string(),
+            "*-----------\n".to_owned()+
+            "-*----------\n"+
+            "--*---------\n"+
+            "---*--------\n"+
+            "----**------\n"+
+            "------------\n");
+        assert_eq!(hysteresis_threshold(&field, 120, 30).to_string(), image.to_string());
+
+        let mut gray = GrayImage::new_w_h(12, 6);
+        for y in 0..6 {
+            for x in 0..12 {
+                gray.set_pixel(x, y, field.get_pixel(x, y) as u8);
+            }
+        }
+        assert_eq!(gray.hysteresis_threshold(30, 120).to_string(), image.to_string());
+    }
+}
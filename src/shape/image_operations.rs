@@ -49,11 +49,20 @@ impl BinaryImage {
 
     /// early return if diff >= threshold, so maximum return value is equal to threshold
     pub fn significance(&self, area: u64, threshold: u32) -> u32 {
+        self.significance_until(area, Some(threshold))
+    }
+
+    /// the complete value of `significance` without early return, for comparing or ranking
+    pub fn significance_full(&self, area: u64) -> u32 {
+        self.significance_until(area, None)
+    }
+
+    fn significance_until(&self, area: u64, threshold: Option<u32>) -> u32 {
         let clusters = self.to_clusters(false);
         let mut diff: u64 = 0;
         let scale = 4 * 128 * 128;
         let divisor = area * self.width as u64;
-        let threshold_u64 = threshold as u64 * divisor;
+        let threshold_u64 = threshold.map(|threshold| threshold as u64 * divisor);
         for cluster in clusters.iter() {
             let size = cluster.size() as u64;
             let cluster_image = cluster.to_binary_image();
@@ -63,7 +72,7 @@ impl BinaryImage {
                 skeleton.stat.mean as u64 *
                 skeleton.stat.count as u64 /
                 boundary.len() as u64;
-            if diff >= threshold_u64 {
+            if matches!(threshold_u64, Some(threshold) if diff >= threshold) {
                 break;
             }
         }
@@ -164,4 +173,23 @@ mod tests {
             )).to_string()
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn significance_full_no_cutoff() {
+        let image = BinaryImage::from_string(&(
+            "****----------\n".to_owned() +
+            "****----***---\n" +
+            "****----***---\n" +
+            "--------***---\n" +
+            "-----------**-\n" +
+            "-***-------**-\n" +
+            "-***----------\n"));
+        let area = image.area();
+        let full = image.significance_full(area);
+        assert!(full > 0);
+        assert_eq!(full, image.significance_full(area));
+        assert_eq!(full, image.significance(area, u32::MAX));
+        // the thresholded version stops early
+        assert!(image.significance(area, 1) < full);
+    }
+}
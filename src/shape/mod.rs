@@ -4,9 +4,20 @@ mod processor;
 pub mod rasterizer;
 mod skeleton;
 mod arc;
+mod bridge;
+mod centerline;
+mod distance;
+mod ellipse;
+mod morphology;
+mod hysteresis;
+mod watershed;
 
 pub use geometry::*;
 pub use image_operations::*;
 pub use processor::*;
 pub use skeleton::*;
-pub use arc::*;
\ No newline at end of file
+pub use arc::*;
+pub use bridge::*;
+pub use centerline::*;
+pub use ellipse::*;
+pub use hysteresis::*;
@@ -0,0 +1,189 @@
+use crate::BinaryImage;
+
+impl BinaryImage {
+    /// Erodes with a horizontal line of `length` pixels centered at each pixel:
+    /// a pixel stays set only if all the pixels of the line around it are set
+    pub fn erode_h(&self, length: usize) -> BinaryImage {
+        self.erode_line(length, false)
+    }
+
+    /// Erodes with a vertical line of `length` pixels, see `erode_h`
+    pub fn erode_v(&self, length: usize) -> BinaryImage {
+        self.erode_line(length, true)
+    }
+
+    /// Opens with a horizontal line of `length` pixels,
+    /// i.e. keeps only the horizontal runs at least `length` pixels long
+    pub fn open_h(&self, length: usize) -> BinaryImage {
+        self.open_line(length, false)
+    }
+
+    /// Opens with a vertical line of `length` pixels, see `open_h`
+    pub fn open_v(&self, length: usize) -> BinaryImage {
+        self.open_line(length, true)
+    }
+
+    /// Separates horizontal and vertical lines at least `min_length` pixels long, e.g. the rules of a scanned form,
+    /// from the rest of the image. Returns (image without lines, extracted lines).
+    ///
+    /// Strokes crossing or touching a line are not amputated: the line is grown by one pixel across its thickness,
+    /// and where that reaches remaining ink on either side, the line pixels in between are kept in the image without lines.
+    pub fn remove_long_lines(&self, min_length: usize) -> (BinaryImage, BinaryImage) {
+        let horizontal = self.open_h(min_length);
+        let vertical = self.open_v(min_length);
+        let lines = horizontal.union(&vertical);
+        let remaining = self.intersect(&lines.negative());
+
+        let mut image = remaining.clone();
+        // a horizontal line is crossed along a column, and a vertical line along a row
+        for (mask, across) in [(&horizontal, true), (&vertical, false)] {
+            for (line, start, end) in mask.runs(across) {
+                let before = start > 0 && remaining.get_pixel_along(across, line, start - 1);
+                let after = end < remaining.line_length(across) && remaining.get_pixel_along(across, line, end);
+                if before || after {
+                    for i in start..end {
+                        image.set_pixel_along(across, line, i, true);
+                    }
+                }
+            }
+        }
+        (image, lines)
+    }
+
+    fn erode_line(&self, length: usize, vertical: bool) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(self.width, self.height);
+        let before = length / 2;
+        let after = length.max(1) - 1 - before;
+        for (line, start, end) in self.runs(vertical) {
+            for i in start + before..end.saturating_sub(after) {
+                image.set_pixel_along(vertical, line, i, true);
+            }
+        }
+        image
+    }
+
+    fn open_line(&self, length: usize, vertical: bool) -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(self.width, self.height);
+        for (line, start, end) in self.runs(vertical) {
+            if end - start >= length {
+                for i in start..end {
+                    image.set_pixel_along(vertical, line, i, true);
+                }
+            }
+        }
+        image
+    }
+
+    /// Runs of set pixels as (row, start, end) with `end` exclusive, or (column, start, end) if `vertical`
+    fn runs(&self, vertical: bool) -> Vec<(usize, usize, usize)> {
+        let (lines, length) = if vertical { (self.width, self.height) } else { (self.height, self.width) };
+        let mut runs = Vec::new();
+        for line in 0..lines {
+            let mut i = 0;
+            while i < length {
+                if !self.get_pixel_along(vertical, line, i) {
+                    i += 1;
+                    continue;
+                }
+                let start = i;
+                while i < length && self.get_pixel_along(vertical, line, i) {
+                    i += 1;
+                }
+                runs.push((line, start, i));
+            }
+        }
+        runs
+    }
+
+    fn line_length(&self, vertical: bool) -> usize {
+        if vertical { self.height } else { self.width }
+    }
+
+    fn get_pixel_along(&self, vertical: bool, line: usize, i: usize) -> bool {
+        if vertical { self.get_pixel(line, i) } else { self.get_pixel(i, line) }
+    }
+
+    fn set_pixel_along(&mut self, vertical: bool, line: usize, i: usize, v: bool) {
+        if vertical { self.set_pixel(line, i, v) } else { self.set_pixel(i, line, v) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erode_open_directional() {
+        let image = BinaryImage::from_string(&(
+            "-*****-\n".to_owned() +
+            "-*-----\n" +
+            "-*-**--\n"));
+        assert_eq!(image.erode_h(3).to_string(), BinaryImage::from_string(&(
+            "--***--\n".to_owned() +
+            "-------\n" +
+            "-------\n")).to_string());
+        assert_eq!(image.erode_v(3).to_string(), BinaryImage::from_string(&(
+            "-------\n".to_owned() +
+            "-*-----\n" +
+            "-------\n")).to_string());
+        assert_eq!(image.open_h(3).to_string(), BinaryImage::from_string(&(
+            "-*****-\n".to_owned() +
+            "-------\n" +
+            "-------\n")).to_string());
+        assert_eq!(image.open_v(3).to_string(), BinaryImage::from_string(&(
+            "-*-----\n".to_owned() +
+            "-*-----\n" +
+            "-*-----\n")).to_string());
+    }
+
+    #[test]
+    fn remove_long_lines_form() {
+        // a two pixel thick rule through a stroke crossing it, a glyph resting on it and a glyph hanging below
+        let form = BinaryImage::from_string(&(
+            "------------------------------\n".to_owned() +
+            "---*-------***----------------\n" +
+            "---*-------*-*----------------\n" +
+            "---*-------***----------------\n" +
+            "******************************\n" +
+            "******************************\n" +
+            "---*-----------------**-------\n" +
+            "---*------------------*-------\n" +
+            "---*------------------*-------\n" +
+            "------------------------------\n"));
+        let mut rule = BinaryImage::new_w_h(form.width, form.height);
+        for y in 4..6 {
+            for x in 0..form.width {
+                rule.set_pixel(x, y, true);
+            }
+        }
+
+        let (glyphs, lines) = form.remove_long_lines(10);
+        assert_eq!(lines.to_string(), rule.to_string());
+        // every glyph pixel is kept, and the crossing stroke stays in one piece
+        assert_eq!(glyphs.intersect(&rule.negative()).to_string(), form.intersect(&rule.negative()).to_string());
+        assert_eq!(glyphs.to_clusters(false).len(), 3);
+        assert!(glyphs.get_pixel(3, 4) && glyphs.get_pixel(3, 5));
+        // the glyphs touching the rule from one side keep the rule pixels under their strokes
+        assert!((11..14).all(|x| glyphs.get_pixel(x, 4) && glyphs.get_pixel(x, 5)));
+        assert!(glyphs.get_pixel(21, 5) && glyphs.get_pixel(22, 4));
+        assert!(!glyphs.get_pixel(15, 4) && !glyphs.get_pixel(0, 5));
+    }
+
+    #[test]
+    fn remove_long_lines_touching_glyph() {
+        // a stem standing on a rule, whose ink within the rule can not be told apart from the rule
+        let form = BinaryImage::from_string(&(
+            "--------------------\n".to_owned() +
+            "----*---------------\n" +
+            "----*---------------\n" +
+            "----*---------------\n" +
+            "********************\n" +
+            "********************\n" +
+            "--------------------\n"));
+        let (glyphs, lines) = form.remove_long_lines(10);
+        assert!((0..form.width).all(|x| lines.get_pixel(x, 4) && lines.get_pixel(x, 5) && !lines.get_pixel(x, 3)));
+        assert_eq!(glyphs.to_clusters(false).len(), 1);
+        assert!((1..6).all(|y| glyphs.get_pixel(4, y)));
+        assert!(!glyphs.get_pixel(3, 4) && !glyphs.get_pixel(5, 5));
+    }
+}
@@ -1,9 +1,12 @@
-use crate::{BinaryImage, MonoImage, MonoImageItem, SampleStat, SampleStatBuilder, Shape};
+use std::collections::HashSet;
+use crate::{BinaryImage, CompoundPath, MonoImage, MonoImageItem, PathI32, PointI32, SampleStat, SampleStatBuilder, Shape};
+use crate::reduce::reduce;
 
 /// The skeleton of a binary image (aka medial axis)
 pub struct Skeleton {
     pub image: BinaryImage,
     pub stat: SampleStat,
+    pub(crate) span: MonoImage,
 }
 
 impl Shape {
@@ -12,6 +15,75 @@ impl Shape {
     }
 }
 
+impl Skeleton {
+    /// The stroke width sampled at each skeleton pixel, 0 where it is not counted in `stat`
+    pub fn span(&self) -> &MonoImage {
+        &self.span
+    }
+
+    /// Removes branches running from an end point to a junction with fewer than `min_branch_length` pixels,
+    /// not counting the junction, repeating until no such branch is left. All branches of a pass are found
+    /// before erasing any, so both arms of a fork at the end of a stroke are removed. Junction pixels are
+    /// never erased, so the remaining skeleton stays connected and no component disappears entirely;
+    /// pieces without any junction, however short, are kept.
+    ///
+    /// `stat` is recomputed over the surviving pixels.
+    pub fn prune(&self, min_branch_length: usize) -> Skeleton {
+        let mut image = self.image.clone();
+        loop {
+            let mut erase = Vec::new();
+            for end in skeleton_pixels(&image) {
+                let next = neighbours(&image, end);
+                if next.len() != 1 {
+                    continue;
+                }
+                let mut chain = trace_chain(&image, end, next[0]);
+                let junction = chain[chain.len() - 1];
+                if neighbours(&image, junction).len() > 2 && chain.len() - 1 < min_branch_length {
+                    chain.pop();
+                    erase.push(chain);
+                }
+            }
+            if erase.is_empty() {
+                break;
+            }
+            for p in erase.into_iter().flatten() {
+                image.set_pixel_at(p, false);
+            }
+        }
+
+        let mut stat = SampleStatBuilder::new();
+        let mut span = MonoImage::new_w_h(image.width, image.height);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let dd = self.span.get_pixel(x, y);
+                if image.get_pixel(x, y) && dd > 0 {
+                    stat.add(dd as i32);
+                    span.set_pixel(x, y, dd);
+                }
+            }
+        }
+
+        Skeleton {
+            image,
+            stat: stat.build(),
+            span,
+        }
+    }
+
+    /// Splits the skeleton into chains of pixels running between end points and junctions,
+    /// and reduces each with `tolerance` (see `reduce`) into an open path; loops without any junction become closed paths.
+    ///
+    /// The ends of every chain are kept, so the paths still meet at the junctions, and only the points between move.
+    pub fn simplify(&self, tolerance: f64) -> CompoundPath {
+        let mut paths = CompoundPath::new();
+        for chain in skeleton_chains(&self.image) {
+            paths.add_path_i32(PathI32::from_points(reduce(&chain, tolerance)));
+        }
+        paths
+    }
+}
+
 impl BinaryImage {
     /// An extremely fast implementation of skeletonization
     #[allow(clippy::many_single_char_names)]
@@ -33,6 +105,7 @@ impl BinaryImage {
         let mut spanxy = MonoImage::new_w_h(self.width, self.height);
         let mut spanyx = MonoImage::new_w_h(self.width, self.height);
         let mut result = BinaryImage::new_w_h(self.width, self.height);
+        let mut span = MonoImage::new_w_h(self.width, self.height);
 
         // span width for each horizontal scan line
         for y in 0..self.height {
@@ -272,6 +345,7 @@ impl BinaryImage {
                         }
                         if dd > 0 {
                             stat.add(dd as i32);
+                            span.set_pixel(x, y, dd);
                         }
                     } else {
                         result.set_pixel(x as usize, y as usize, false);
@@ -283,6 +357,199 @@ impl BinaryImage {
         Skeleton {
             image: result,
             stat: stat.build(),
+            span,
+        }
+    }
+}
+
+/// 8-neighbours of `p`, skipping a diagonal neighbour when it is also reachable through a 4-neighbour
+/// (m-adjacency), so that staircases do not form spurious junctions
+pub(super) fn neighbours(skeleton: &BinaryImage, p: PointI32) -> Vec<PointI32> {
+    let on = |x: i32, y: i32| skeleton.get_pixel_safe(p.x + x, p.y + y);
+    let mut result = Vec::new();
+    for (x, y) in [(0, -1), (1, 0), (0, 1), (-1, 0)] {
+        if on(x, y) {
+            result.push(PointI32::new(p.x + x, p.y + y));
+        }
+    }
+    for (x, y) in [(1, -1), (1, 1), (-1, 1), (-1, -1)] {
+        if on(x, y) && !on(x, 0) && !on(0, y) {
+            result.push(PointI32::new(p.x + x, p.y + y));
+        }
+    }
+    result
+}
+
+/// Follows the skeleton from `start` through `next` until reaching a node (a pixel not having exactly
+/// 2 neighbours) or coming back to `start`. Both ends are included.
+pub(super) fn trace_chain(skeleton: &BinaryImage, start: PointI32, next: PointI32) -> Vec<PointI32> {
+    let mut chain = vec![start, next];
+    let (mut prev, mut current) = (start, next);
+    while current != start {
+        let neighbours = neighbours(skeleton, current);
+        if neighbours.len() != 2 {
+            break;
+        }
+        let following = if neighbours[0] != prev { neighbours[0] } else { neighbours[1] };
+        chain.push(following);
+        prev = current;
+        current = following;
+    }
+    chain
+}
+
+pub(super) fn skeleton_pixels(skeleton: &BinaryImage) -> Vec<PointI32> {
+    (0..skeleton.height as i32)
+        .flat_map(|y| (0..skeleton.width as i32).map(move |x| PointI32::new(x, y)))
+        .filter(|&p| skeleton.get_pixel_at(p))
+        .collect()
+}
+
+/// Splits the skeleton into chains of pixels between nodes. Loops are returned with the first point repeated at the end.
+pub(super) fn skeleton_chains(skeleton: &BinaryImage) -> Vec<Vec<PointI32>> {
+    let pixels = skeleton_pixels(skeleton);
+    let mut walked: HashSet<(PointI32, PointI32)> = HashSet::new();
+    let mut chains = Vec::new();
+    let mut take = |chain: Vec<PointI32>, walked: &mut HashSet<(PointI32, PointI32)>| {
+        for pair in chain.windows(2) {
+            walked.insert((pair[0], pair[1]));
+            walked.insert((pair[1], pair[0]));
+        }
+        chains.push(chain);
+    };
+
+    for &node in pixels.iter() {
+        let neighbours = neighbours(skeleton, node);
+        if neighbours.len() == 2 {
+            continue;
+        }
+        for next in neighbours {
+            if !walked.contains(&(node, next)) {
+                take(trace_chain(skeleton, node, next), &mut walked);
+            }
+        }
+    }
+
+    // loops without any node
+    for &p in pixels.iter() {
+        let neighbours = neighbours(skeleton, p);
+        if neighbours.len() == 2 && !walked.contains(&(p, neighbours[0])) {
+            take(trace_chain(skeleton, p, neighbours[0]), &mut walked);
+        }
+    }
+
+    chains
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rectangle_with_bump() -> BinaryImage {
+        let mut image = BinaryImage::new_w_h(40, 14);
+        for y in 4..9 {
+            for x in 4..36 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        for y in 1..4 {
+            for x in 18..21 {
+                image.set_pixel(x, y, true);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn skeleton_prune_bump() {
+        let skeleton = rectangle_with_bump().to_skeleton();
+        assert!((0..4).any(|y| (0..40).any(|x| skeleton.image.get_pixel(x, y))));
+
+        let pruned = skeleton.prune(4);
+        // the skeleton of the bump is a separate fork, which is pruned down to its junction
+        assert_eq!((0..4).map(|y| (0..40).filter(|&x| pruned.image.get_pixel(x, y)).count()).sum::<usize>(), 1);
+        assert!((10..30).all(|x| pruned.image.get_pixel(x, 6)));
+        assert_eq!(pruned.image.to_clusters(true).len(), 2);
+        assert!(pruned.stat.count < skeleton.stat.count);
+    }
+
+    fn skeleton_of(image: BinaryImage) -> Skeleton {
+        let span = MonoImage::new_w_h(image.width, image.height);
+        Skeleton { image, stat: SampleStat::default(), span }
+    }
+
+    #[test]
+    fn skeleton_prune_spur() {
+        // an axis with a spur of 3 pixels above (19, 6), and one of 4 pixels below (9, 6)
+        let mut image = BinaryImage::new_w_h(40, 14);
+        for x in 5..35 {
+            image.set_pixel(x, 6, true);
+        }
+        for y in 3..6 {
+            image.set_pixel(19, y, true);
+        }
+        for y in 7..11 {
+            image.set_pixel(9, y, true);
         }
+        let pruned = skeleton_of(image).prune(4);
+        assert!((5..35).all(|x| pruned.image.get_pixel(x, 6)));
+        assert!(!(3..6).any(|y| pruned.image.get_pixel(19, y)));
+        assert!((7..11).all(|y| pruned.image.get_pixel(9, y)));
+        assert_eq!(pruned.image.to_clusters(true).len(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn skeleton_prune_keeps_pieces() {
+        // a short segment, and a plus with arms of 2 pixels
+        let mut image = BinaryImage::new_w_h(20, 10);
+        image.set_pixel(2, 2, true);
+        image.set_pixel(3, 2, true);
+        for i in 10..15 {
+            image.set_pixel(i, 5, true);
+            image.set_pixel(12, i - 7, true);
+        }
+        let pruned = skeleton_of(image).prune(4);
+        assert!(pruned.image.get_pixel(2, 2) && pruned.image.get_pixel(3, 2));
+        assert!(pruned.image.get_pixel(12, 5));
+        assert_eq!(pruned.image.to_clusters(true).len(), 2);
+    }
+
+    #[test]
+    fn skeleton_prune_zero() {
+        let skeleton = rectangle_with_bump().to_skeleton();
+        let pruned = skeleton.prune(0);
+        assert_eq!(pruned.image.to_string(), skeleton.image.to_string());
+        assert_eq!(pruned.stat.count, skeleton.stat.count);
+        assert_eq!(pruned.stat.mean, skeleton.stat.mean);
+    }
+
+    #[test]
+    fn simplify_pins_junction() {
+        // arms from the junction at (10, 10) up left, up right and straight down
+        let mut image = BinaryImage::new_w_h(21, 21);
+        for i in 0..=8 {
+            image.set_pixel(10 - i, 10 - i, true);
+            image.set_pixel(10 + i, 10 - i, true);
+            image.set_pixel(10, 10 + i, true);
+        }
+        let skeleton = skeleton_of(image);
+        let junction = PointI32::new(10, 10);
+        assert_eq!(neighbours(&skeleton.image, junction).len(), 3);
+
+        let paths = skeleton.simplify(1.0);
+        assert_eq!(paths.paths.len(), 3);
+        let mut ends = Vec::new();
+        for path in paths.iter() {
+            let path = match path {
+                crate::CompoundPathElement::PathI32(path) => path,
+                _ => unreachable!(),
+            };
+            // straight arms reduce to their ends
+            assert_eq!(path.len(), 2);
+            assert!(path[0] == junction || path[1] == junction, "{:?}", path);
+            ends.extend(path.iter().copied().filter(|&p| p != junction));
+        }
+        ends.sort_by_key(|p| (p.y, p.x));
+        assert_eq!(ends, vec![PointI32::new(2, 2), PointI32::new(18, 2), PointI32::new(10, 18)]);
+    }
+}
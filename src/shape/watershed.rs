@@ -0,0 +1,92 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use crate::{BinaryImage, MonoImage, MonoImageItem};
+
+impl BinaryImage {
+    /// Splits the set pixels into the catchment basins of `markers` (non-zero labels, e.g. one per blob center),
+    /// by marker-controlled watershed on the inverted distance transform.
+    ///
+    /// Basins are flooded from the markers, claiming pixels far from the background first,
+    /// so touching blobs meet at the neck between them. Every set pixel 4-connected to a marker
+    /// gets the label of the basin reaching it first; other pixels, including markers on the background, are 0.
+    pub fn watershed(&self, markers: &MonoImage) -> MonoImage {
+        assert_eq!((self.width, self.height), (markers.width(), markers.height()));
+        let distance = self.distance_transform();
+        // distances are to pixel centers, so squared they are whole numbers; deepest first
+        let depth = |i: usize| Reverse((distance.get(i).unwrap() * distance.get(i).unwrap()).round() as i64);
+        let mut labels = MonoImage::new_w_h(self.width, self.height);
+        // (depth, order of insertion, index, label), the order making ties first come first served
+        let mut queue = BinaryHeap::<Reverse<(Reverse<i64>, usize, usize, MonoImageItem)>>::new();
+        let mut order = 0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let label = markers.get_pixel(x, y);
+                if label != 0 && self.get_pixel(x, y) {
+                    let i = y * self.width + x;
+                    queue.push(Reverse((depth(i), order, i, label)));
+                    order += 1;
+                }
+            }
+        }
+        while let Some(Reverse((_, _, i, label))) = queue.pop() {
+            let (x, y) = (i % self.width, i / self.width);
+            if labels.get_pixel(x, y) != 0 {
+                continue;
+            }
+            labels.set_pixel(x, y, label);
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < self.width).then(|| i + 1),
+                (y > 0).then(|| i - self.width),
+                (y + 1 < self.height).then(|| i + self.width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                let (nx, ny) = (n % self.width, n / self.width);
+                if self.get_pixel(nx, ny) && labels.get_pixel(nx, ny) == 0 {
+                    queue.push(Reverse((depth(n), order, n, label)));
+                    order += 1;
+                }
+            }
+        }
+        labels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watershed_two_circles() {
+        // circles of radius 10 at x = 14 and x = 30, overlapping by 4 pixels, and a stray dot
+        let mut image = BinaryImage::new_w_h(46, 26);
+        for y in 0..26 {
+            for x in 0..46 {
+                let inside = |cx: f64| (x as f64 - cx).hypot(y as f64 - 12.0) <= 10.0;
+                image.set_pixel(x, y, inside(14.0) || inside(30.0) || (x, y) == (44, 24));
+            }
+        }
+        assert_eq!(image.to_clusters(false).len(), 2);
+
+        let mut markers = MonoImage::new_w_h(46, 26);
+        markers.set_pixel(14, 12, 1);
+        markers.set_pixel(30, 12, 2);
+        markers.set_pixel(0, 0, 3);
+        let labels = image.watershed(&markers);
+        for y in 0..26 {
+            for x in 0..46 {
+                let expected = match (image.get_pixel(x, y), x) {
+                    (false, _) | (true, 44) => 0,
+                    (true, 0..=21) => 1,
+                    (true, 23..) => 2,
+                    // the neck column goes to whichever basin reaches it first
+                    (true, _) => {
+                        assert_ne!(labels.get_pixel(x, y), 0);
+                        continue;
+                    },
+                };
+                assert_eq!(labels.get_pixel(x, y), expected, "at ({}, {})", x, y);
+            }
+        }
+    }
+}
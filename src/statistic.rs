@@ -35,6 +35,7 @@ pub struct SimpleStatBuilder {
 pub struct SampleStatBuilder {
     simple: SimpleStatBuilder,
     sequence: Vec<i32>,
+    sorted: bool,
     histogram: HashMap<i32, i32>,
 }
 
@@ -80,6 +81,7 @@ impl SampleStatBuilder {
         let counter = self.histogram.entry(v).or_insert(0);
         *counter += 1;
         self.sequence.push(v);
+        self.sorted = false;
     }
 
     pub fn build(&mut self) -> SampleStat {
@@ -89,7 +91,7 @@ impl SampleStatBuilder {
             deviation,
         } = self.simple.build();
 
-        self.sequence.sort_unstable();
+        self.sort();
         let max = self.histogram.iter().max_by_key(|x| x.1).unwrap_or((&0, &0)).1;
         let mut maxes: Vec<(&i32, &i32)> = self.histogram.iter().filter(|x| x.1 == max).collect();
         maxes.sort_by_key(|x| x.0);
@@ -114,6 +116,26 @@ impl SampleStatBuilder {
         self.sequence[self.sequence.len() * i as usize / 100]
     }
 
+    /// Returns the sample at fraction `p` (0.0 to 1.0) of the sorted sequence,
+    /// using the nearest-rank method. e.g. `quantile(0.9)` is the 90th percentile;
+    /// unlike `percentile`, it does not need `build` to be called first.
+    pub fn quantile(&mut self, p: f64) -> i32 {
+        if self.sequence.is_empty() {
+            return 0;
+        }
+        self.sort();
+        let len = self.sequence.len();
+        let rank = (p.clamp(0.0, 1.0) * len as f64).ceil() as usize;
+        self.sequence[rank.clamp(1, len) - 1]
+    }
+
+    fn sort(&mut self) {
+        if !self.sorted {
+            self.sequence.sort_unstable();
+            self.sorted = true;
+        }
+    }
+
     pub fn median(sorted_numbers: &[i32]) -> i32 {
         if sorted_numbers.is_empty() {
             return 0;
@@ -193,4 +215,32 @@ mod tests {
             deviation: (2.0 / 3.0f64).sqrt(),
         });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stat_builder_quantile() {
+        let mut builder = SampleStatBuilder::new();
+        // 1..=100 in scrambled order
+        for i in 0..100 {
+            builder.add((i * 37) % 100 + 1);
+        }
+        assert_eq!(builder.quantile(0.9), 90);
+        assert_eq!(builder.quantile(0.5), 50);
+        assert_eq!(builder.quantile(0.0), 1);
+        assert_eq!(builder.quantile(1.0), 100);
+        assert_eq!(builder.build().median, 50);
+        builder.add(1000);
+        assert_eq!(builder.quantile(1.0), 1000);
+        assert_eq!(builder.build().median, 51);
+    }
+
+    #[test]
+    fn test_stat_builder_percentile() {
+        let mut builder = SampleStatBuilder::new();
+        for i in 0..100 {
+            builder.add((i * 37) % 100 + 1);
+        }
+        builder.build();
+        assert_eq!(builder.percentile(90), 91);
+        assert_eq!(builder.percentile(0), 1);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{Color, ColorImage, PointI32, SummedAreaTable};
+
+impl ColorImage {
+    /// Locates `template` in this image by zero-mean normalized cross-correlation of the pixel intensities
+    /// (the mean of red, green and blue, as in `SummedAreaTable`).
+    ///
+    /// Returns the top-left corner of the best match (the first in raster order among ties) and its score,
+    /// from -1.0 to 1.0, where 1.0 is a perfect match up to brightness and contrast.
+    /// Windows or templates of uniform intensity score 0.0, as does a template larger than the image.
+    pub fn match_template(&self, template: &ColorImage) -> (PointI32, f64) {
+        let (tw, th) = (template.width, template.height);
+        if tw == 0 || th == 0 || tw > self.width || th > self.height {
+            return (PointI32::default(), 0.0);
+        }
+        let n = (tw * th) as f64;
+        let image: Vec<f64> = self.iter().map(|c| intensity(c) as f64).collect();
+        let template: Vec<f64> = template.iter().map(|c| intensity(c) as f64).collect();
+        let mean = template.iter().sum::<f64>() / n;
+        let template: Vec<f64> = template.iter().map(|v| v - mean).collect();
+        let template_norm = template.iter().map(|v| v * v).sum::<f64>().sqrt();
+
+        // the window sums and sums of squares for the denominator come from summed area tables
+        let sums = SummedAreaTable::from_color_image(self);
+        let squares = squared_sums(&image, self.width, self.height);
+        let square_sum = |x: usize, y: usize| {
+            let at = |x: usize, y: usize| if x > 0 && y > 0 { squares[(y - 1) * self.width + x - 1] } else { 0.0 };
+            at(x + tw, y + th) - at(x, y + th) - at(x + tw, y) + at(x, y)
+        };
+
+        let mut best = (PointI32::default(), f64::NEG_INFINITY);
+        for y in 0..=self.height - th {
+            for x in 0..=self.width - tw {
+                let sum = sums.get_region_sum_x_y_w_h(x, y, tw, th) as f64;
+                let variance = square_sum(x, y) - sum * sum / n;
+                let score = if variance <= 1e-9 || template_norm <= 1e-9 {
+                    0.0
+                } else {
+                    // the template is zero-mean, so the window mean cancels out of the numerator
+                    let mut correlation = 0.0;
+                    for ty in 0..th {
+                        let row = &image[(y + ty) * self.width + x..][..tw];
+                        correlation += row.iter().zip(&template[ty * tw..][..tw]).map(|(a, b)| a * b).sum::<f64>();
+                    }
+                    correlation / (variance.sqrt() * template_norm)
+                };
+                if score > best.1 {
+                    best = (PointI32::new(x as i32, y as i32), score);
+                }
+            }
+        }
+        best
+    }
+}
+
+fn intensity(c: Color) -> u32 {
+    (c.r as u32 + c.g as u32 + c.b as u32) / 3
+}
+
+/// Summed area table of the squared values, see `SummedAreaTable::from_color_image`
+fn squared_sums(values: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let mut sums = vec![0.0; width * height];
+    for y in 0..height {
+        let mut row = 0.0;
+        for x in 0..width {
+            row += values[y * width + x] * values[y * width + x];
+            let up = if y > 0 { sums[(y - 1) * width + x] } else { 0.0 };
+            sums[y * width + x] = up + row;
+        }
+    }
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BoundingRect;
+
+    #[test]
+    fn match_template_offset() {
+        // deterministic noise
+        let mut seed: u32 = 12345;
+        let mut image = ColorImage::new_w_h(40, 30);
+        for i in 0..40 * 30 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let v = (seed >> 16) as u8;
+            image.set_pixel_at(i, &Color::new(v, v.wrapping_add(40), v / 2));
+        }
+        let template = image.crop_with_rect(BoundingRect::new_x_y_w_h(23, 11, 7, 5));
+
+        let (position, score) = image.match_template(&template);
+        assert_eq!(position, PointI32::new(23, 11));
+        assert!((score - 1.0).abs() < 1e-9, "score {}", score);
+
+        assert_eq!(template.match_template(&image), (PointI32::default(), 0.0));
+    }
+}
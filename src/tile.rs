@@ -0,0 +1,128 @@
+use crate::{BinaryImage, BoundingRect, Color, ColorImage};
+
+/// A rectangular part of an image, read in its own coordinates without copying, see `ColorImage::tiles`
+#[derive(Debug, Clone, Copy)]
+pub struct TileView<'a, T> {
+    /// The part of the image, in image coordinates
+    pub rect: BoundingRect,
+    pub image: &'a T,
+}
+
+impl<T> TileView<'_, T> {
+    pub fn width(&self) -> usize {
+        self.rect.width() as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.rect.height() as usize
+    }
+
+    fn to_image_coordinates(&self, local_x: usize, local_y: usize) -> (usize, usize) {
+        debug_assert!(local_x < self.width() && local_y < self.height());
+        (self.rect.left as usize + local_x, self.rect.top as usize + local_y)
+    }
+}
+
+impl TileView<'_, ColorImage> {
+    pub fn get_pixel(&self, local_x: usize, local_y: usize) -> Color {
+        let (x, y) = self.to_image_coordinates(local_x, local_y);
+        self.image.get_pixel(x, y)
+    }
+}
+
+impl TileView<'_, BinaryImage> {
+    pub fn get_pixel(&self, local_x: usize, local_y: usize) -> bool {
+        let (x, y) = self.to_image_coordinates(local_x, local_y);
+        self.image.get_pixel(x, y)
+    }
+}
+
+impl ColorImage {
+    /// Divides the image into tiles of `tile_w` x `tile_h` pixels, row by row from the top left.
+    /// Tiles at the right and bottom edges are smaller if the size does not divide evenly; no tile is empty.
+    ///
+    /// Panics if `tile_w` or `tile_h` is 0.
+    pub fn tiles(&self, tile_w: usize, tile_h: usize) -> impl Iterator<Item = TileView<'_, ColorImage>> {
+        tile_rects(self.width, self.height, tile_w, tile_h).map(move |rect| TileView { rect, image: self })
+    }
+
+    /// Applies `f` to every tile of `tiles`, returning the results with the tile rects
+    pub fn tile_map<R>(&self, tile_w: usize, tile_h: usize, f: impl Fn(&TileView<ColorImage>) -> R) -> Vec<(BoundingRect, R)> {
+        self.tiles(tile_w, tile_h).map(|tile| (tile.rect, f(&tile))).collect()
+    }
+}
+
+impl BinaryImage {
+    /// Divides the image into tiles, see `ColorImage::tiles`
+    pub fn tiles(&self, tile_w: usize, tile_h: usize) -> impl Iterator<Item = TileView<'_, BinaryImage>> {
+        tile_rects(self.width, self.height, tile_w, tile_h).map(move |rect| TileView { rect, image: self })
+    }
+
+    /// Applies `f` to every tile of `tiles`, returning the results with the tile rects
+    pub fn tile_map<R>(&self, tile_w: usize, tile_h: usize, f: impl Fn(&TileView<BinaryImage>) -> R) -> Vec<(BoundingRect, R)> {
+        self.tiles(tile_w, tile_h).map(|tile| (tile.rect, f(&tile))).collect()
+    }
+}
+
+fn tile_rects(width: usize, height: usize, tile_w: usize, tile_h: usize) -> impl Iterator<Item = BoundingRect> {
+    assert!(tile_w > 0 && tile_h > 0, "tile size must not be 0");
+    (0..height).step_by(tile_h).flat_map(move |top| {
+        (0..width).step_by(tile_w).map(move |left| {
+            let (w, h) = (std::cmp::min(tile_w, width - left), std::cmp::min(tile_h, height - top));
+            BoundingRect::new_x_y_w_h(left as i32, top as i32, w as i32, h as i32)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_partition_image() {
+        let mut image = ColorImage::new_w_h(23, 17);
+        for y in 0..17 {
+            for x in 0..23 {
+                image.set_pixel(x, y, &Color::new(x as u8, y as u8, 0));
+            }
+        }
+        for (tile_w, tile_h) in [(1, 1), (4, 4), (5, 3), (23, 17), (8, 40), (100, 100)] {
+            let tiles = image.tile_map(tile_w, tile_h, |tile| {
+                // local coordinates read the parent pixel
+                assert_eq!(tile.get_pixel(0, 0), Color::new(tile.rect.left as u8, tile.rect.top as u8, 0));
+                let (x, y) = (tile.width() - 1, tile.height() - 1);
+                assert_eq!(tile.get_pixel(x, y), Color::new((tile.rect.left as usize + x) as u8, (tile.rect.top as usize + y) as u8, 0));
+                tile.width() * tile.height()
+            });
+            assert_eq!(tiles.iter().map(|(_, area)| area).sum::<usize>(), 23 * 17);
+            let mut covered = BinaryImage::new_w_h(23, 17);
+            for (rect, _) in tiles.iter() {
+                assert!(!rect.is_empty());
+                for y in rect.top..rect.bottom {
+                    for x in rect.left..rect.right {
+                        assert!(!covered.get_pixel(x as usize, y as usize));
+                        covered.set_pixel(x as usize, y as usize, true);
+                    }
+                }
+            }
+            assert_eq!(covered.area(), 23 * 17);
+        }
+    }
+
+    #[test]
+    fn binary_tiles_count() {
+        let image = BinaryImage::from_string(&(
+            "*-*-*\n".to_owned() +
+            "-----\n" +
+            "*****\n"
+        ));
+        let counts = image.tile_map(2, 2, |tile| {
+            (0..tile.height()).flat_map(|y| (0..tile.width()).map(move |x| (x, y)))
+                .filter(|&(x, y)| tile.get_pixel(x, y))
+                .count()
+        });
+        assert_eq!(counts.iter().map(|(_, count)| count).collect::<Vec<_>>(), vec![&1, &1, &1, &2, &2, &1]);
+        assert_eq!(counts.iter().map(|(_, count)| count).sum::<usize>(), image.area() as usize);
+        assert_eq!(image.tiles(2, 2).count(), 6);
+    }
+}
@@ -0,0 +1,626 @@
+//! One-call conversion of images into SVG documents
+
+use std::fmt::Write;
+use crate::{BinaryImage, BoundingRect, BoundingRectF64, Color, ColorImage, CompoundPath, NumberFormat, PathSimplifyMode, PointF64, TraceParams};
+use crate::color_clusters::{posterize, BuilderConfig, Clusters, Runner, RunnerConfig};
+
+/// Clusters a color image with `config`, and traces every output cluster into a path
+/// filled with its color, stacked in render order. Translucent clusters get a `fill-opacity`,
+/// unless `params.compat` has no `fill_opacity`.
+///
+/// The color and merge criteria are those of `RunnerConfig::default()`.
+/// Coordinates are written with at most `precision` decimal places (all of them if `None`),
+/// and the `viewBox` is the bounding rect of the paths.
+pub fn color_image_to_svg(
+    image: &ColorImage,
+    config: &BuilderConfig,
+    params: &TraceParams,
+    precision: Option<u32>,
+) -> String {
+    color_image_svg_file(image, config, params, precision).finish()
+}
+
+/// Same as `color_image_to_svg`, but writes the document into `w`
+pub fn write_color_image_svg(
+    w: &mut impl Write,
+    image: &ColorImage,
+    config: &BuilderConfig,
+    params: &TraceParams,
+    precision: Option<u32>,
+) -> std::fmt::Result {
+    color_image_svg_file(image, config, params, precision).write(w)
+}
+
+fn color_image_svg_file(image: &ColorImage, config: &BuilderConfig, params: &TraceParams, precision: Option<u32>) -> SvgFile {
+    let mut svg = SvgFile::new(image.width, image.height, precision);
+    svg.sanitize = params.sanitize;
+    svg.fill_opacity = params.compat.fill_opacity;
+    svg.physical_scale = params.physical_scale;
+    for (color, paths) in trace_color_image(image, config, params) {
+        svg.add_path(paths, color);
+    }
+    svg
+}
+
+impl ColorImage {
+    /// Traces only the pixels within `rect` as `color_image_to_svg` does, returning the paths
+    /// with their colors in render order, positioned in the coordinate system of the whole image.
+    ///
+    /// `rect` is clipped to the image; the result is empty if nothing remains.
+    pub fn trace_region(
+        &self,
+        mut rect: BoundingRect,
+        config: &BuilderConfig,
+        params: &TraceParams,
+    ) -> Vec<(Color, CompoundPath)> {
+        rect.clip(BoundingRect::new_x_y_w_h(0, 0, self.width as i32, self.height as i32));
+        if rect.width() <= 0 || rect.height() <= 0 {
+            return Vec::new();
+        }
+        let mut traced = trace_color_image(&self.crop_with_rect(rect), config, params);
+        for (_, paths) in traced.iter_mut() {
+            paths.offset(rect.left_top());
+        }
+        traced
+    }
+}
+
+/// Clusters a color image with `config` as `color_image_to_svg` does, but emits each output cluster
+/// as a path of axis-aligned rectangles (see `Cluster::to_rects`), so that pixel art stays crisp at any zoom
+pub fn color_image_to_pixel_svg(image: &ColorImage, config: &BuilderConfig) -> String {
+    let clusters = cluster_color_image(image, config);
+    let view = clusters.view();
+
+    let mut svg = SvgFile::new(image.width, image.height, None);
+    svg.fill_opacity = config.compat.fill_opacity;
+    for index in clusters.output_in_render_order() {
+        let cluster = view.get_cluster(index);
+        svg.add_rects(cluster.to_rects(&view), cluster.residue_color());
+    }
+    svg.finish()
+}
+
+/// Returns an svg path string with one `M h v h Z` subpath per rect
+pub fn rects_to_svg_path(rects: &[BoundingRect]) -> String {
+    let mut string = String::new();
+    write_rects_svg_path(&mut string, rects).unwrap();
+    string
+}
+
+fn write_rects_svg_path(w: &mut impl Write, rects: &[BoundingRect]) -> std::fmt::Result {
+    for rect in rects {
+        write!(w, "M{},{} h{} v{} h{} Z ", rect.left, rect.top, rect.width(), rect.height(), -rect.width())?;
+    }
+    Ok(())
+}
+
+/// Posterizes a color image into `levels` luminance bands (see `color_clusters::posterize`)
+/// and traces them with `bands_to_svg`
+pub fn posterize_to_svg(image: &ColorImage, levels: usize, params: &TraceParams) -> String {
+    bands_to_svg(&posterize(image, levels), params)
+}
+
+/// Traces the mask of every band into paths filled with the band color, stacked in order,
+/// so that with bands ordered dark to light the lighter ones are on top.
+/// All masks must be of the same size.
+pub fn bands_to_svg(bands: &[(Color, BinaryImage)], params: &TraceParams) -> String {
+    let (width, height) = bands.first().map_or((0, 0), |(_, mask)| (mask.width, mask.height));
+    let mut svg = SvgFile::new(width, height, None);
+    svg.sanitize = params.sanitize;
+    svg.fill_opacity = params.compat.fill_opacity;
+    svg.physical_scale = params.physical_scale;
+    for (color, mask) in bands {
+        let mut paths = CompoundPath::new();
+        for cluster in mask.to_clusters_with(false, params.compat).iter().filter(|cluster| cluster.size() >= params.min_area) {
+            paths.append(reduce_relative(cluster.to_compound_path(
+                params.mode, params.corner_threshold, params.segment_length,
+                params.max_iterations, params.splice_threshold,
+            ), cluster.rect, params));
+        }
+        svg.add_path(paths, *color);
+    }
+    svg.finish()
+}
+
+/// Traces every 4-connected shape of a binary image into a black path,
+/// see `color_image_to_svg` for `precision` and the `viewBox`
+pub fn binary_image_to_svg(image: &BinaryImage, params: &TraceParams, precision: Option<u32>) -> String {
+    let mut svg = SvgFile::new(image.width, image.height, precision);
+    svg.sanitize = params.sanitize;
+    svg.fill_opacity = params.compat.fill_opacity;
+    svg.physical_scale = params.physical_scale;
+    for cluster in image.to_clusters_with(false, params.compat).iter().filter(|cluster| cluster.size() >= params.min_area) {
+        let paths = cluster.to_compound_path(
+            params.mode, params.corner_threshold, params.segment_length,
+            params.max_iterations, params.splice_threshold,
+        );
+        svg.add_path(reduce_relative(paths, cluster.rect, params), Color::new(0, 0, 0));
+    }
+    svg.finish()
+}
+
+/// Clusters `image` with `config` and traces every output cluster, in render order
+fn trace_color_image(image: &ColorImage, config: &BuilderConfig, params: &TraceParams) -> Vec<(Color, CompoundPath)> {
+    let clusters = cluster_color_image(image, config);
+    let view = clusters.view();
+
+    clusters.output_in_render_order().into_iter().filter_map(|index| {
+        let cluster = view.get_cluster(index);
+        if cluster.area() < params.min_area {
+            return None;
+        }
+        let paths = cluster.to_compound_path(
+            &view, false, params.mode, params.corner_threshold, params.segment_length,
+            params.max_iterations, params.splice_threshold,
+        );
+        Some((cluster.residue_color(), reduce_relative(paths, cluster.rect, params)))
+    }).collect()
+}
+
+/// Applies `params.relative_tolerance`, if any, to the paths traced from a cluster bounded by `rect`
+fn reduce_relative(paths: CompoundPath, rect: BoundingRect, params: &TraceParams) -> CompoundPath {
+    if params.relative_tolerance > 0.0 && !matches!(params.mode, PathSimplifyMode::Spline) {
+        paths.reduce_relative(params.relative_tolerance, rect)
+    } else {
+        paths
+    }
+}
+
+fn cluster_color_image(image: &ColorImage, config: &BuilderConfig) -> Clusters {
+    let mut builder = Runner::new(RunnerConfig::default(), image.clone()).builder();
+    builder.conf = config.clone();
+    builder.run()
+}
+
+/// The physical size of a pixel, for svg documents declaring their size in millimeters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhysicalScale {
+    /// Pixels per inch
+    pub dpi: f64,
+}
+
+impl PhysicalScale {
+    /// The resolution of the CSS pixel
+    pub const CSS_DPI: f64 = 96.0;
+
+    /// Converts a length in pixels to millimeters
+    pub fn to_mm(&self, pixels: f64) -> f64 {
+        pixels / self.dpi * 25.4
+    }
+}
+
+/// An svg document being assembled. Shapes are kept until `write`, so that the `viewBox`
+/// is known before the header and each `<path>` are written straight into the output.
+struct SvgFile {
+    width: usize,
+    height: usize,
+    precision: Option<u32>,
+    /// Whether paths are passed through `CompoundPath::sanitize` at `precision`
+    sanitize: bool,
+    /// If set, the width and height are written in millimeters; the `viewBox` stays in pixels
+    physical_scale: Option<PhysicalScale>,
+    /// Whether translucent colors are written with a `fill-opacity`, see `CompatFlags::fill_opacity`
+    fill_opacity: bool,
+    shapes: Vec<(Color, SvgShape)>,
+    bounds: BoundingRectF64,
+}
+
+enum SvgShape {
+    Paths(CompoundPath),
+    Rects(Vec<BoundingRect>),
+}
+
+impl SvgFile {
+    fn new(width: usize, height: usize, precision: Option<u32>) -> Self {
+        Self {
+            width,
+            height,
+            precision,
+            sanitize: false,
+            physical_scale: None,
+            fill_opacity: true,
+            shapes: Vec::new(),
+            bounds: BoundingRectF64::default(),
+        }
+    }
+
+    fn number(&self, num: f64) -> String {
+        f64::number_format(num, self.precision)
+    }
+
+    fn add_path(&mut self, paths: CompoundPath, color: Color) {
+        let paths = if self.sanitize {
+            paths.sanitize(3, 0.0, self.precision)
+        } else {
+            paths
+        };
+        if paths.is_empty() {
+            return;
+        }
+        self.bounds.merge(paths.bounding_rect_f64());
+        self.shapes.push((color, SvgShape::Paths(paths)));
+    }
+
+    fn add_rects(&mut self, rects: Vec<BoundingRect>, color: Color) {
+        if rects.is_empty() {
+            return;
+        }
+        for rect in rects.iter() {
+            self.bounds.merge(BoundingRectF64::new_x_y_w_h(
+                rect.left as f64, rect.top as f64, rect.width() as f64, rect.height() as f64,
+            ));
+        }
+        self.shapes.push((color, SvgShape::Rects(rects)));
+    }
+
+    fn finish(self) -> String {
+        let len: usize = self.shapes.iter().map(|(_, shape)| match shape {
+            SvgShape::Paths(paths) => paths.estimate_svg_len(true, self.precision) + 80,
+            SvgShape::Rects(rects) => rects.len() * 24 + 80,
+        }).sum();
+        let mut string = String::with_capacity(len + 200);
+        self.write(&mut string).unwrap();
+        string
+    }
+
+    fn write(&self, w: &mut impl Write) -> std::fmt::Result {
+        let (left, top, width, height) = if self.bounds.is_empty() {
+            (0.0, 0.0, self.width as f64, self.height as f64)
+        } else {
+            (self.bounds.left_top.x, self.bounds.left_top.y, self.bounds.width(), self.bounds.height())
+        };
+        let (size_width, size_height) = match self.physical_scale {
+            Some(scale) => (
+                format!("{}mm", f64::number_format(scale.to_mm(width), Some(3))),
+                format!("{}mm", f64::number_format(scale.to_mm(height), Some(3))),
+            ),
+            None => (self.number(width), self.number(height)),
+        };
+        write!(
+            w,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <svg version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\" \
+            width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">\n",
+            size_width, size_height, self.number(left), self.number(top), self.number(width), self.number(height)
+        )?;
+        for (color, shape) in self.shapes.iter() {
+            w.write_str("<path d=\"")?;
+            match shape {
+                SvgShape::Paths(paths) => {
+                    let offset = paths.write_svg(w, true, PointF64::default(), self.precision)?;
+                    writeln!(
+                        w,
+                        "\" fill=\"{}\"{} transform=\"translate({},{})\"/>",
+                        color.to_hex_string(), self.opacity(color), self.number(offset.x), self.number(offset.y)
+                    )?;
+                }
+                SvgShape::Rects(rects) => {
+                    write_rects_svg_path(w, rects)?;
+                    writeln!(
+                        w,
+                        "\" fill=\"{}\"{} shape-rendering=\"crispEdges\"/>",
+                        color.to_hex_string(), self.opacity(color)
+                    )?;
+                }
+            }
+        }
+        w.write_str("</svg>\n")
+    }
+
+    fn opacity(&self, color: &Color) -> String {
+        if color.a == 255 || !self.fill_opacity {
+            String::new()
+        } else {
+            format!(" fill-opacity=\"{}\"", f64::number_format(color.a as f64 / 255.0, Some(3)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompatLevel, PathSimplifyMode};
+    use crate::color_clusters::KeyingAction;
+
+    /// Checks that the tags of a document without text content are well-formed and balanced
+    fn assert_well_formed(xml: &str) {
+        let mut stack = Vec::new();
+        let mut rest = xml.trim();
+        while !rest.is_empty() {
+            assert!(rest.starts_with('<'), "unexpected text: {}", rest);
+            let end = rest.find('>').expect("unterminated tag");
+            let tag = &rest[1..end];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(stack.pop(), Some(name.to_owned()));
+            } else if !tag.starts_with('?') && !tag.ends_with('/') {
+                assert_eq!(tag.matches('"').count() % 2, 0);
+                stack.push(tag.split_whitespace().next().unwrap().to_owned());
+            }
+            rest = rest[end + 1..].trim_start();
+        }
+        assert!(stack.is_empty(), "unclosed tags: {:?}", stack);
+    }
+
+    #[test]
+    fn color_image_to_svg_three_colors() {
+        let mut image = ColorImage::new_w_h(40, 30);
+        for y in 0..30 {
+            for x in 0..40 {
+                let color = if (5..15).contains(&x) && (5..20).contains(&y) {
+                    Color::new(255, 0, 0)
+                } else if (22..35).contains(&x) && (8..25).contains(&y) {
+                    Color::new(0, 0, 255)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let svg = color_image_to_svg(&image, &BuilderConfig::default(), &params, Some(2));
+        assert_well_formed(&svg);
+        assert_eq!(svg.matches("<path").count(), 3);
+        assert!(svg.contains("fill=\"#FF0000\""));
+        assert!(svg.contains("fill=\"#0000FF\""));
+        assert!(svg.contains("fill=\"#FFFFFF\""));
+        assert!(!svg.contains("fill-opacity"));
+
+        for y in 5..20 {
+            for x in 5..15 {
+                image.set_pixel(x, y, &Color::new_rgba(255, 0, 0, 128));
+            }
+        }
+        let svg = color_image_to_svg(&image, &BuilderConfig::default(), &params, Some(2));
+        assert!(svg.contains("fill=\"#FF0000\" fill-opacity=\"0.502\""));
+
+        let mut written = String::new();
+        write_color_image_svg(&mut written, &image, &BuilderConfig::default(), &params, Some(2)).unwrap();
+        assert_eq!(written, svg);
+    }
+
+    #[test]
+    fn color_image_to_svg_config() {
+        let mut image = ColorImage::new_w_h(40, 30);
+        for y in 0..30 {
+            for x in 0..40 {
+                let color = if (5..15).contains(&x) && (5..20).contains(&y) {
+                    Color::new(255, 0, 0)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let config = BuilderConfig::default().key(Color::new(255, 255, 255)).keying_action(KeyingAction::Discard);
+        let svg = color_image_to_svg(&image, &config, &params, Some(2));
+        assert_eq!(svg.matches("<path").count(), 1, "{}", svg);
+        assert!(svg.contains("fill=\"#FF0000\""));
+        assert_eq!(color_image_to_pixel_svg(&image, &config).matches("<path").count(), 1);
+    }
+
+    #[test]
+    fn binary_image_to_svg_shapes() {
+        let image = BinaryImage::from_string(&(
+            "**----\n".to_owned()+
+            "**--**\n"+
+            "----**\n"));
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let svg = binary_image_to_svg(&image, &params, Some(2));
+        assert_well_formed(&svg);
+        assert_eq!(svg.matches("<path").count(), 2);
+        let params = TraceParams { min_area: 5, ..params };
+        assert_eq!(binary_image_to_svg(&image, &params, Some(2)).matches("<path").count(), 0);
+    }
+
+    #[test]
+    fn color_image_to_svg_physical_scale() {
+        let mut image = ColorImage::new_w_h(600, 300);
+        for y in 0..300 {
+            for x in 0..600 {
+                let color = if (100..250).contains(&x) && (50..200).contains(&y) { Color::new(255, 0, 0) } else { Color::new(255, 255, 255) };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let params = TraceParams {
+            mode: PathSimplifyMode::Polygon,
+            physical_scale: Some(PhysicalScale { dpi: 300.0 }),
+            ..Default::default()
+        };
+        let svg = color_image_to_svg(&image, &BuilderConfig::default(), &params, Some(2));
+        assert!(svg.contains("width=\"50.8mm\" height=\"25.4mm\" viewBox=\"0 0 600 300\""), "{}", svg);
+    }
+
+    /// Touching red squares on white, the right one translucent, and a U whose clusters merge out of raster order
+    fn compat_fixture() -> (ColorImage, BinaryImage) {
+        let mut image = ColorImage::new_w_h(32, 16);
+        for y in 0..16 {
+            for x in 0..32 {
+                let color = match ((2..14).contains(&y), x) {
+                    (true, 2..=13) => Color::new(255, 0, 0),
+                    (true, 14..=25) => Color::new_rgba(255, 0, 0, 128),
+                    _ => Color::new(255, 255, 255),
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let mask = BinaryImage::from_string(&(
+            "*----**-\n".to_owned() +
+            "*-**-**-\n" +
+            "*-**----\n" +
+            "*-**----\n" +
+            "****----\n" +
+            "--------\n" +
+            "-----**-\n" +
+            "-----**-\n"
+        ));
+        (image, mask)
+    }
+
+    #[test]
+    fn compat_golden_legacy() {
+        let (image, mask) = compat_fixture();
+        let header = |w: u32, h: u32| format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <svg version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", w, h
+        );
+        // recorded from the equivalent calls to the 0.8.8 release: a `Runner` with `diagonal` and tracing
+        // its outputs in reverse, and `BinaryImage::to_clusters(false)`; the fixture avoids degenerate paths,
+        // whose simplification was fixed since
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, compat: CompatLevel::Legacy.into(), ..Default::default() };
+        let config = BuilderConfig::default().ignore_alpha(false).compat(CompatLevel::Legacy);
+        // the squares differing only in alpha are one cluster, even though alpha is not ignored
+        assert_eq!(color_image_to_svg(&image, &config, &params, Some(2)), header(32, 16) +
+            "<path d=\"M0,0 L32,0 L32,16 L0,16 Z \" fill=\"#FF0000\" transform=\"translate(0,0)\"/>\n\
+            <path d=\"M0,0 L32,0 L32,16 L0,16 Z M2,2 L2,14 L26,14 L26,2 Z \" fill=\"#FFFFFF\" transform=\"translate(0,0)\"/>\n\
+            </svg>\n");
+        // the U after the blob, in the slot it merged into
+        assert_eq!(binary_image_to_svg(&mask, &params, Some(2)), header(7, 8) +
+            "<path d=\"M0,0 L2,0 L2,2 L0,2 Z \" fill=\"#000000\" transform=\"translate(5,0)\"/>\n\
+            <path d=\"M0,0 L4,1 L4,5 L0,5 Z \" fill=\"#000000\" transform=\"translate(0,0)\"/>\n\
+            <path d=\"M0,0 L2,0 L2,2 L0,2 Z \" fill=\"#000000\" transform=\"translate(5,6)\"/>\n\
+            </svg>\n");
+
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let config = BuilderConfig::default().ignore_alpha(false);
+        let latest = color_image_to_svg(&image, &config, &params, Some(2));
+        assert_eq!(latest, color_image_to_svg(&image, &config.clone().compat(CompatLevel::Latest), &params, Some(2)));
+        assert_eq!(latest.matches("<path").count(), 3);
+        assert!(latest.contains("fill-opacity"));
+        let latest = binary_image_to_svg(&mask, &params, Some(2));
+        assert_eq!(latest, binary_image_to_svg(&mask, &params, Some(2)));
+        assert!(latest.find("translate(0,0)") < latest.find("translate(5,0)"));
+    }
+
+    #[test]
+    fn binary_image_to_svg_sanitize() {
+        let image = BinaryImage::from_string(&(
+            "*-----\n".to_owned()+
+            "---**-\n"+
+            "---**-\n"));
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, sanitize: true, ..Default::default() };
+        let svg = binary_image_to_svg(&image, &params, Some(0));
+        assert_well_formed(&svg);
+        assert_eq!(svg.matches("<path").count(), 2);
+        assert!(svg.contains("d=\"M0,0 L1,0 L1,1 L0,1 Z \""), "{}", svg);
+    }
+
+    #[test]
+    fn binary_image_to_svg_relative_tolerance() {
+        let mut image = BinaryImage::new_w_h(40, 40);
+        for y in 0..40 {
+            for x in 0..40 {
+                image.set_pixel(x, y, (x as f64 - 19.5).hypot(y as f64 - 19.5) <= 18.0);
+            }
+        }
+        let params = TraceParams { mode: PathSimplifyMode::None, ..Default::default() };
+        let exact = binary_image_to_svg(&image, &params, None);
+        let params = TraceParams { relative_tolerance: 0.02, ..params };
+        let reduced = binary_image_to_svg(&image, &params, None);
+        assert_well_formed(&reduced);
+        assert!(reduced.matches('L').count() < exact.matches('L').count() / 2);
+    }
+
+    #[test]
+    fn color_image_to_pixel_svg_rects() {
+        let mut image = ColorImage::new_w_h(16, 12);
+        for y in 0..12 {
+            for x in 0..16 {
+                let color = if x < 4 || y >= 8 { Color::new(255, 0, 0) } else { Color::new(255, 255, 255) };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let svg = color_image_to_pixel_svg(&image, &BuilderConfig::default());
+        assert_well_formed(&svg);
+        assert!(svg.contains("viewBox=\"0 0 16 12\""));
+        assert!(svg.contains("d=\"M0,0 h4 v8 h-4 Z M0,8 h16 v4 h-16 Z \" fill=\"#FF0000\""));
+        assert!(svg.contains("d=\"M4,0 h12 v8 h-12 Z \" fill=\"#FFFFFF\""));
+    }
+
+    #[test]
+    fn posterize_to_svg_gradient() {
+        let mut image = ColorImage::new_w_h(64, 8);
+        for y in 0..8 {
+            for x in 0..64 {
+                let v = (x * 4) as u8;
+                image.set_pixel(x, y, &Color::new(v, v, v));
+            }
+        }
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let svg = posterize_to_svg(&image, 3, &params);
+        assert_well_formed(&svg);
+        let mut fills: Vec<&str> = svg.match_indices("fill=\"").map(|(i, _)| &svg[i + 6..i + 13]).collect();
+        assert_eq!(fills.len(), 3);
+        fills.dedup();
+        assert_eq!(fills.len(), 3);
+    }
+
+    #[test]
+    fn trace_region_offset() {
+        // a red square at (30, 20) on white, and a blue one outside the region
+        let mut image = ColorImage::new_w_h(60, 40);
+        for y in 0..40 {
+            for x in 0..60 {
+                let color = if (30..38).contains(&x) && (20..28).contains(&y) {
+                    Color::new(255, 0, 0)
+                } else if (2..10).contains(&x) && (2..10).contains(&y) {
+                    Color::new(0, 0, 255)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let rect = BoundingRect::new_x_y_w_h(25, 15, 20, 20);
+        let traced = image.trace_region(rect, &BuilderConfig::default(), &params);
+        assert_eq!(traced.len(), 2);
+
+        let (_, red) = traced.iter().find(|(color, _)| *color == Color::new(255, 0, 0)).unwrap();
+        let bounds = red.bounding_rect_f64();
+        assert_eq!(bounds.left_top, PointF64::new(30.0, 20.0));
+        assert_eq!(bounds.right_bottom, PointF64::new(38.0, 28.0));
+        let (_, white) = traced.iter().find(|(color, _)| *color == Color::new(255, 255, 255)).unwrap();
+        assert_eq!(white.bounding_rect_f64().to_rect(), rect);
+
+        // clipped to the image
+        let rect = BoundingRect::new_x_y_w_h(50, 30, 20, 20);
+        let traced = image.trace_region(rect, &BuilderConfig::default(), &params);
+        assert_eq!(traced.len(), 1);
+        assert_eq!(traced[0].1.bounding_rect_f64().to_rect(), BoundingRect::new_x_y_w_h(50, 30, 10, 10));
+        assert!(image.trace_region(BoundingRect::new_x_y_w_h(70, 0, 5, 5), &BuilderConfig::default(), &params).is_empty());
+    }
+
+    #[test]
+    fn svg_precision_and_view_box() {
+        let mut image = BinaryImage::new_w_h(30, 20);
+        for y in 4..15 {
+            for x in 6..22 {
+                if (x - 14) * (x - 14) + (y - 9) * (y - 9) * 3 < 64 {
+                    image.set_pixel(x as usize, y as usize, true);
+                }
+            }
+        }
+
+        let params = TraceParams { mode: PathSimplifyMode::Polygon, ..Default::default() };
+        let svg = binary_image_to_svg(&image, &params, Some(1));
+        let bounds = image.bounding_rect();
+        assert!(svg.contains(&format!(
+            "width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\"",
+            bounds.width(), bounds.height(), bounds.left, bounds.top, bounds.width(), bounds.height()
+        )));
+
+        let params = TraceParams { mode: PathSimplifyMode::Spline, ..Default::default() };
+        let precise = binary_image_to_svg(&image, &params, None);
+        let rounded = binary_image_to_svg(&image, &params, Some(1));
+        assert_well_formed(&rounded);
+        let decimals = |svg: &str| svg
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .filter_map(|number| number.split_once('.').map(|(_, decimals)| decimals.len()))
+            .max()
+            .unwrap_or(0);
+        assert!(decimals(&precise) > 1);
+        assert_eq!(decimals(&rounded), 1);
+        assert!(rounded.len() < precise.len());
+    }
+}
@@ -0,0 +1,65 @@
+//! Lives in its own test binary, as the counting allocator replaces the global allocator
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use visioncortex::{Color, ColorImage};
+use visioncortex::color_clusters::{ClusterWorkspace, Runner, RunnerConfig};
+
+/// Counts allocations made by the current thread, so that tests running in parallel do not interfere
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|a| a.get())
+}
+
+fn frame(seed: u32) -> ColorImage {
+    let (width, height) = (32, 24);
+    let mut image = ColorImage::new_w_h(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let v = ((x as u32 / 6 + seed) * 5 + (y as u32 / 5) * 3 + seed) % 4;
+            let c = (v * 80) as u8;
+            image.set_pixel(x, y, &Color::new(c, 255 - c, (c / 2) ^ (seed as u8 * 40)));
+        }
+    }
+    image
+}
+
+#[test]
+fn workspace_reuses_allocations() {
+    let mut ws = ClusterWorkspace::new(32, 24);
+    // warm up the workspace
+    let clusters = Runner::new(RunnerConfig::default(), frame(0)).builder().run_with_workspace(&mut ws);
+    ws.recycle(clusters);
+
+    let before = allocations();
+    let clusters = Runner::new(RunnerConfig::default(), frame(1)).run();
+    let standalone = allocations() - before;
+    drop(clusters);
+
+    let before = allocations();
+    let clusters = Runner::new(RunnerConfig::default(), frame(1)).builder().run_with_workspace(&mut ws);
+    let reused = allocations() - before;
+    ws.recycle(clusters);
+
+    // stage 2 still allocates per cluster, but the per-pixel and per-cluster buffers are reused
+    assert!(reused < standalone, "{} >= {}", reused, standalone);
+}